@@ -0,0 +1,39 @@
+//! Loads arbitrary bytes as a ROM and drives the mapper with the rest of the
+//! input. Malformed headers must be rejected with an error, and any
+//! cartridge that loads must survive every read and write the CPU can issue.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_gb::cartridge;
+
+/// Bytes after the ROM image, 3 per access: a selector and a value.
+const ACCESS_LEN: usize = 3;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    // The first two bytes split the input into the ROM and the accesses
+    let rom_len = (u16::from_le_bytes([data[0], data[1]]) as usize).min(data.len() - 2);
+    let (rom, accesses) = data[2..].split_at(rom_len);
+
+    let mut cart = match cartridge::from_bytes(rom.to_vec()) {
+        Ok(cart) => cart,
+        Err(_) => return,
+    };
+
+    for access in accesses.chunks_exact(ACCESS_LEN) {
+        let offset = u16::from_le_bytes([access[0], access[1]]);
+        // Cartridge space as routed by the MMU: ROM and external RAM
+        let addr = if offset & 0x8000 == 0 {
+            offset
+        } else {
+            0xa000 | (offset & 0x1fff)
+        };
+        if access[2] & 1 == 0 {
+            cart.read(addr);
+        } else {
+            cart.write(addr, access[2]);
+        }
+    }
+});
@@ -0,0 +1,26 @@
+//! Runs arbitrary bytes as SM83 code on `FlatBus`. Every opcode, legal or
+//! not, must execute without panicking.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_gb::bus::Bus;
+use rust_gb::cpu::{Cpu, Registers};
+
+/// Upper bound on executed instructions per input.
+const MAX_STEPS: usize = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    let mut cpu = Cpu::with_flat_ram();
+    for (addr, &byte) in data.iter().take(0x10000).enumerate() {
+        cpu.mmu.write_byte(addr as u16, byte);
+    }
+    cpu.set_registers(Registers {
+        pc: 0x0000,
+        sp: 0xfffe,
+        ..Registers::default()
+    });
+
+    for _ in 0..MAX_STEPS {
+        cpu.step();
+    }
+});
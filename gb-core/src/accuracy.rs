@@ -0,0 +1,64 @@
+/// The options that trade accuracy for speed, set together with
+/// `Cpu::set_accuracy`. New options that make emulation slower, or only
+/// matter to test ROMs, belong here so the presets pick them up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccuracyOptions {
+    /// Jump straight to the next timer or PPU event while halted, see
+    /// `Cpu::set_halt_skip`.
+    pub halt_skip: bool,
+    /// Emulate the DMG STAT write bug, see `Ppu::set_stat_write_bug`.
+    pub stat_write_bug: bool,
+    /// Emulate the DMG OAM corruption bug, see `Ppu::set_oam_bug`.
+    pub oam_bug: bool,
+}
+
+impl Default for AccuracyOptions {
+    fn default() -> Self {
+        Accuracy::Balanced.options()
+    }
+}
+
+/// Named sets of `AccuracyOptions`, so that slow devices can give up
+/// accuracy for speed explicitly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Accuracy {
+    /// Leaves out everything games can do without.
+    Fast,
+    /// What a new `Cpu` does: what games need, and nothing only test ROMs
+    /// check.
+    Balanced,
+    /// Everything emulated, whatever it costs.
+    Accurate,
+}
+
+impl Accuracy {
+    pub const ALL: [Accuracy; 3] = [Accuracy::Fast, Accuracy::Balanced, Accuracy::Accurate];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Accuracy::Fast => "fast",
+            Accuracy::Balanced => "balanced",
+            Accuracy::Accurate => "accurate",
+        }
+    }
+
+    pub fn options(self) -> AccuracyOptions {
+        match self {
+            Accuracy::Fast => AccuracyOptions {
+                halt_skip: true,
+                stat_write_bug: false,
+                oam_bug: false,
+            },
+            Accuracy::Balanced => AccuracyOptions {
+                halt_skip: true,
+                stat_write_bug: true,
+                oam_bug: false,
+            },
+            Accuracy::Accurate => AccuracyOptions {
+                halt_skip: false,
+                stat_write_bug: true,
+                oam_bug: true,
+            },
+        }
+    }
+}
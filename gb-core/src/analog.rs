@@ -0,0 +1,96 @@
+use crate::joypad::{ButtonState, Key};
+
+/// Default `StickMapping::deadzone`.
+pub const DEFAULT_DEADZONE: f32 = 0.25;
+/// Default `StickMapping::diagonal_angle`, eight sectors of equal size.
+pub const DEFAULT_DIAGONAL_ANGLE: f32 = 45.0;
+/// Largest `StickMapping::diagonal_angle`.
+pub const MAX_DIAGONAL_ANGLE: f32 = 90.0;
+
+/// Turns an analog stick into D-pad presses.
+///
+/// The stick is split into eight sectors around its center. The four
+/// diagonal ones are `diagonal_angle` degrees wide, so 0 gives a 4-way D-pad
+/// and larger values make diagonals easier to hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StickMapping {
+    /// Part of the full deflection, 0 to 1, that presses nothing.
+    pub deadzone: f32,
+    /// Width of each diagonal sector in degrees, 0 to 90.
+    pub diagonal_angle: f32,
+}
+
+impl Default for StickMapping {
+    fn default() -> Self {
+        StickMapping {
+            deadzone: DEFAULT_DEADZONE,
+            diagonal_angle: DEFAULT_DIAGONAL_ANGLE,
+        }
+    }
+}
+
+impl StickMapping {
+    /// Creates a new `StickMapping`, with both values clamped to their range.
+    pub fn new(deadzone: f32, diagonal_angle: f32) -> Self {
+        StickMapping {
+            deadzone: deadzone.clamp(0.0, 1.0),
+            diagonal_angle: diagonal_angle.clamp(0.0, MAX_DIAGONAL_ANGLE),
+        }
+    }
+
+    /// Directions pressed by a stick at `x`, `y`, with positive values to
+    /// the right and down as SDL reports them.
+    pub fn directions(&self, x: i16, y: i16) -> ButtonState {
+        let (x, y) = (x as f32 / i16::MAX as f32, y as f32 / i16::MAX as f32);
+        let magnitude = libm::hypotf(x, y);
+        let mut buttons = ButtonState::default();
+        if magnitude <= self.deadzone || magnitude == 0.0 {
+            return buttons;
+        }
+        // An axis counts once the stick leaves the cardinal sector of the
+        // other axis, which is half of what the diagonals leave per quadrant
+        let threshold =
+            libm::sinf(((MAX_DIAGONAL_ANGLE - self.diagonal_angle) / 2.0).to_radians()) * magnitude;
+        if x.abs() > threshold {
+            buttons.set(if x < 0.0 { Key::Left } else { Key::Right }, true);
+        }
+        if y.abs() > threshold {
+            buttons.set(if y < 0.0 { Key::Up } else { Key::Down }, true);
+        }
+        buttons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL: i16 = i16::MAX;
+
+    #[test]
+    fn test_deadzone() {
+        let stick = StickMapping::default();
+        assert_eq!(stick.directions(0, 0), ButtonState::default());
+        assert_eq!(stick.directions(FULL / 5, 0), ButtonState::default());
+        assert_eq!(
+            stick.directions(-FULL / 2, 0),
+            ButtonState::default().with(Key::Left)
+        );
+    }
+
+    #[test]
+    fn test_diagonals() {
+        // 30 degrees below the right horizontal
+        let (x, y) = (FULL / 10 * 9, FULL / 2);
+        let right = ButtonState::default().with(Key::Right);
+        let down_right = right.with(Key::Down);
+
+        assert_eq!(StickMapping::new(0.25, 45.0).directions(x, y), down_right);
+        assert_eq!(StickMapping::new(0.25, 0.0).directions(x, y), right);
+        assert_eq!(StickMapping::new(0.25, 30.0).directions(x, y), right);
+        assert_eq!(
+            StickMapping::new(0.25, 90.0).directions(FULL, 100),
+            down_right
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::mmu::Mmu;
+use crate::ppu::PaletteRegister;
+
+/// Something on screen a speedrun split waits for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SplitCondition {
+    /// `tiles` side by side in a row of the tile map at 0x9800, or 0x9c00
+    /// with `high_map`. They start at column and row `at`, or anywhere with
+    /// `None`, and wrap around the right edge like the scrolled map does.
+    Tiles {
+        high_map: bool,
+        at: Option<(u8, u8)>,
+        tiles: Vec<u8>,
+    },
+    /// A DMG palette register holds `value`, e.g. BGP 0x00 at the end of a
+    /// fade to white.
+    Palette {
+        register: PaletteRegister,
+        value: u8,
+    },
+}
+
+impl SplitCondition {
+    fn is_met(&self, mmu: &Mmu) -> bool {
+        match self {
+            SplitCondition::Tiles {
+                high_map,
+                at,
+                tiles,
+            } => {
+                let map = if *high_map { 0x1c00 } else { 0x1800 };
+                let map = &mmu.ppu.vram()[map..map + 0x400];
+                let matches_at = |x: usize, y: usize| {
+                    tiles
+                        .iter()
+                        .enumerate()
+                        .all(|(i, &tile)| map[y * 32 + (x + i) % 32] == tile)
+                };
+                match at {
+                    Some((x, y)) => matches_at(*x as usize % 32, *y as usize % 32),
+                    None => (0..32).any(|y| (0..32).any(|x| matches_at(x, y))),
+                }
+            }
+            SplitCondition::Palette { register, value } => {
+                let addr = match register {
+                    PaletteRegister::Bgp => 0xff47,
+                    PaletteRegister::Obp0 => 0xff48,
+                    PaletteRegister::Obp1 => 0xff49,
+                };
+                mmu.peek(addr) == *value
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Split {
+    pub name: String,
+    pub condition: SplitCondition,
+}
+
+/// Checks registered conditions once a frame for auto-splitter scripts,
+/// which would otherwise poll memory themselves. Each check only reads the
+/// tile maps and palette registers, so it costs little next to a frame.
+#[derive(Default)]
+pub struct AutoSplitter {
+    splits: Vec<Split>,
+    /// Whether each split was met at the last check.
+    met: Vec<bool>,
+}
+
+impl AutoSplitter {
+    /// Creates a new `AutoSplitter` without splits.
+    pub fn new() -> Self {
+        AutoSplitter::default()
+    }
+
+    pub fn add(&mut self, split: Split) {
+        self.splits.push(split);
+        self.met.push(false);
+    }
+
+    pub fn splits(&self) -> &[Split] {
+        &self.splits
+    }
+
+    /// Checks every split, e.g. after each `Cpu::run_frame`, and returns
+    /// those met now but not at the last check. A split still met does not
+    /// fire again until it stops being met.
+    pub fn check(&mut self, mmu: &Mmu) -> Vec<&Split> {
+        let mut fired = Vec::new();
+        for (split, met) in self.splits.iter().zip(&mut self.met) {
+            let now = split.condition.is_met(mmu);
+            if now && !*met {
+                fired.push(split);
+            }
+            *met = now;
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::rom_with_header;
+
+    fn names(fired: Vec<&Split>) -> Vec<&str> {
+        fired.iter().map(|split| split.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_check() {
+        let mut mmu = Mmu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        // Keep VRAM accessible
+        mmu.write_byte(0xff40, 0x00);
+        let mut splitter = AutoSplitter::new();
+        splitter.add(Split {
+            name: "boss".to_string(),
+            condition: SplitCondition::Tiles {
+                high_map: false,
+                at: None,
+                tiles: vec![0x12, 0x13],
+            },
+        });
+        splitter.add(Split {
+            name: "title".to_string(),
+            condition: SplitCondition::Tiles {
+                high_map: true,
+                at: Some((31, 2)),
+                tiles: vec![0x01, 0x02],
+            },
+        });
+        splitter.add(Split {
+            name: "white".to_string(),
+            condition: SplitCondition::Palette {
+                register: PaletteRegister::Bgp,
+                value: 0x00,
+            },
+        });
+        mmu.write_byte(0xff47, 0xe4);
+        assert!(splitter.check(&mmu).is_empty());
+
+        mmu.write_byte(0x9800 + 5 * 32 + 7, 0x12);
+        mmu.write_byte(0x9800 + 5 * 32 + 8, 0x13);
+        // The pair wraps from column 31 to column 0 of row 2
+        mmu.write_byte(0x9c00 + 2 * 32 + 31, 0x01);
+        mmu.write_byte(0x9c00 + 2 * 32, 0x02);
+        assert_eq!(names(splitter.check(&mmu)), ["boss", "title"]);
+        // Still there, so no new split
+        assert!(splitter.check(&mmu).is_empty());
+
+        mmu.write_byte(0x9800 + 5 * 32 + 8, 0x00);
+        mmu.write_byte(0xff47, 0x00);
+        assert_eq!(names(splitter.check(&mmu)), ["white"]);
+        mmu.write_byte(0x9820, 0x12);
+        mmu.write_byte(0x9821, 0x13);
+        assert_eq!(names(splitter.check(&mmu)), ["boss"]);
+    }
+}
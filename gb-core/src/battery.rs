@@ -0,0 +1,181 @@
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::error;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+
+#[cfg(feature = "std")]
+use flate2::read::GzDecoder;
+#[cfg(feature = "std")]
+use flate2::write::GzEncoder;
+#[cfg(feature = "std")]
+use flate2::Compression;
+
+#[cfg(feature = "std")]
+use crate::romdb::crc32;
+
+/// Magic bytes at the start of a compressed battery save.
+const MAGIC: &[u8; 4] = b"RGBB";
+/// Magic, RAM size and CRC32 of the RAM, in front of the gzip stream.
+const HEADER_LEN: usize = 12;
+
+/// How battery saves are written. Both are read back without being told.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveFormat {
+    /// The RAM as is, like a `.sav` file of other emulators.
+    Raw,
+    /// Gzip behind a small header, which suits large and mostly empty RAM
+    /// and carries a checksum to catch truncated files. Needs the `std`
+    /// feature, without which saves are always raw.
+    Compressed,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SaveError {
+    /// The file holds RAM of another size.
+    SizeMismatch { found: usize, expected: usize },
+    /// The compressed data ends early or does not decompress.
+    Truncated,
+    /// The RAM does not match the checksum it was saved with.
+    BadChecksum,
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::SizeMismatch { found, expected } => write!(
+                f,
+                "save data of {} bytes for {} bytes of RAM",
+                found, expected
+            ),
+            SaveError::Truncated => write!(f, "save data is truncated"),
+            SaveError::BadChecksum => write!(f, "save data is corrupt"),
+        }
+    }
+}
+
+impl error::Error for SaveError {}
+
+/// Turns cartridge RAM into the contents of a save file.
+pub fn encode(ram: &[u8], format: SaveFormat) -> Vec<u8> {
+    match format {
+        SaveFormat::Raw => ram.to_vec(),
+        #[cfg(not(feature = "std"))]
+        SaveFormat::Compressed => ram.to_vec(),
+        #[cfg(feature = "std")]
+        SaveFormat::Compressed => {
+            let mut out = MAGIC.to_vec();
+            out.extend_from_slice(&(ram.len() as u32).to_le_bytes());
+            out.extend_from_slice(&crc32(ram).to_le_bytes());
+            let mut encoder = GzEncoder::new(out, Compression::default());
+            encoder
+                .write_all(ram)
+                .and_then(|_| encoder.finish())
+                .expect("writing to a Vec cannot fail")
+        }
+    }
+}
+
+/// Size of the RAM a save file holds: the size in the header of a
+/// compressed save, or the file size for a raw one.
+pub fn ram_len(data: &[u8]) -> usize {
+    if data.starts_with(MAGIC) && data.len() >= HEADER_LEN {
+        u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize
+    } else {
+        data.len()
+    }
+}
+
+/// Reads a save file of either format for `ram_len` bytes of RAM. Without
+/// the `std` feature only raw saves are read.
+pub fn decode(data: &[u8], ram_len: usize) -> Result<Vec<u8>, SaveError> {
+    #[cfg(feature = "std")]
+    if let Some(result) = decode_compressed(data, ram_len) {
+        return result;
+    }
+    if data.len() == ram_len {
+        Ok(data.to_vec())
+    } else {
+        Err(SaveError::SizeMismatch {
+            found: data.len(),
+            expected: ram_len,
+        })
+    }
+}
+
+/// `None` if `data` is not a compressed save, which a raw save of the
+/// right size starting with the magic by chance is not either.
+#[cfg(feature = "std")]
+fn decode_compressed(data: &[u8], ram_len: usize) -> Option<Result<Vec<u8>, SaveError>> {
+    if !data.starts_with(MAGIC) || data.len() < HEADER_LEN {
+        return None;
+    }
+    let field = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+    let (len, checksum) = (field(4) as usize, field(8));
+    if len != ram_len {
+        if data.len() == ram_len {
+            return None;
+        }
+        return Some(Err(SaveError::SizeMismatch {
+            found: len,
+            expected: ram_len,
+        }));
+    }
+
+    let mut ram = Vec::with_capacity(len);
+    let result = GzDecoder::new(&data[HEADER_LEN..]).read_to_end(&mut ram);
+    Some(match result {
+        Err(_) if data.len() == ram_len => return None,
+        Err(_) => Err(SaveError::Truncated),
+        Ok(_) if ram.len() != len => Err(SaveError::Truncated),
+        Ok(_) if crc32(&ram) != checksum => Err(SaveError::BadChecksum),
+        Ok(_) => Ok(ram),
+    })
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut ram = vec![0; 0x20000];
+        ram[0x1234] = 0x56;
+        for format in [SaveFormat::Raw, SaveFormat::Compressed] {
+            assert_eq!(decode(&encode(&ram, format), ram.len()), Ok(ram.clone()));
+        }
+        assert!(encode(&ram, SaveFormat::Compressed).len() < 1024);
+        assert_eq!(ram_len(&encode(&ram, SaveFormat::Compressed)), ram.len());
+        assert_eq!(ram_len(&ram), ram.len());
+        assert_eq!(
+            decode(&ram[..0x100], ram.len()),
+            Err(SaveError::SizeMismatch {
+                found: 0x100,
+                expected: 0x20000
+            })
+        );
+    }
+
+    #[test]
+    fn test_damaged_saves() {
+        let ram: Vec<u8> = (0..0x2000).map(|i| (i * 7) as u8).collect();
+        let data = encode(&ram, SaveFormat::Compressed);
+        assert_eq!(
+            decode(&data[..data.len() - 10], ram.len()),
+            Err(SaveError::Truncated)
+        );
+
+        let mut wrong_checksum = data.clone();
+        wrong_checksum[8] ^= 1;
+        assert_eq!(
+            decode(&wrong_checksum, ram.len()),
+            Err(SaveError::BadChecksum)
+        );
+
+        // A raw save that happens to start with the magic
+        let mut raw = ram.clone();
+        raw[..4].copy_from_slice(MAGIC);
+        assert_eq!(decode(&raw, raw.len()), Ok(raw.clone()));
+    }
+}
@@ -0,0 +1,349 @@
+//! BESS (Best Effort Save State) blocks, the savestate format shared by
+//! SameBoy and other emulators
+//! (<https://github.com/LIJI32/SameBoy/blob/master/BESS.md>).
+//!
+//! A BESS file starts with the emulator's own state and ends with a list of
+//! blocks plus a footer pointing at the first block. The big memory areas
+//! are stored between the two and referenced by offset.
+
+use alloc::vec::Vec;
+
+use crate::cpu::{Cpu, Registers};
+use crate::state::{StateError, StateReader, StateWriter};
+
+const FOOTER_MAGIC: &[u8; 4] = b"BESS";
+const MAJOR_VERSION: u16 = 1;
+const MINOR_VERSION: u16 = 1;
+/// Game Boy family, DMG, revision B.
+const MODEL: &[u8; 4] = b"GDB ";
+/// Memory areas referenced from the CORE block, in this order.
+const NUM_BUFFERS: usize = 7;
+const WRAM: usize = 0;
+const VRAM: usize = 1;
+const MBC_RAM: usize = 2;
+const OAM: usize = 3;
+const HRAM: usize = 4;
+/// Bits of NRx4 that restart a channel, masked when restoring.
+const TRIGGER: u8 = 0x80;
+
+fn write_block(w: &mut StateWriter, id: &[u8; 4], content: &[u8]) {
+    w.write_bytes(id);
+    w.write_vec(content);
+}
+
+/// Appends the memory areas, the blocks and the footer to `data`, which
+/// already holds the native state.
+pub(crate) fn append(cpu: &Cpu, data: &mut Vec<u8>) {
+    let mmu = &cpu.mmu;
+    let wram: Vec<u8> = (0xc000..=0xdfff).map(|addr| mmu.read_byte(addr)).collect();
+    let hram: Vec<u8> = (0xff80..=0xfffe).map(|addr| mmu.read_byte(addr)).collect();
+
+    let mut buffers = [(0u32, 0u32); NUM_BUFFERS];
+    let areas: [(usize, &[u8]); 5] = [
+        (WRAM, &wram),
+        (VRAM, mmu.ppu.vram()),
+        (MBC_RAM, mmu.cartridge.ram()),
+        (OAM, mmu.ppu.oam()),
+        (HRAM, &hram),
+    ];
+    for (i, bytes) in areas {
+        buffers[i] = (bytes.len() as u32, data.len() as u32);
+        data.extend_from_slice(bytes);
+    }
+    let first_block = data.len() as u32;
+
+    let mut w = StateWriter::new();
+    write_block(
+        &mut w,
+        b"NAME",
+        concat!("rust-gb ", env!("CARGO_PKG_VERSION")).as_bytes(),
+    );
+
+    let rom = mmu.cartridge.rom();
+    let mut info = rom[0x134..0x144].to_vec();
+    info.extend_from_slice(&rom[0x14e..0x150]);
+    write_block(&mut w, b"INFO", &info);
+
+    let regs = cpu.registers();
+    let mut core = StateWriter::new();
+    core.write_u16(MAJOR_VERSION);
+    core.write_u16(MINOR_VERSION);
+    core.write_bytes(MODEL);
+    core.write_u16(regs.pc);
+    core.write_u16(u16::from_be_bytes([regs.a, regs.f]));
+    core.write_u16(u16::from_be_bytes([regs.b, regs.c]));
+    core.write_u16(u16::from_be_bytes([regs.d, regs.e]));
+    core.write_u16(u16::from_be_bytes([regs.h, regs.l]));
+    core.write_u16(regs.sp);
+    core.write_bool(cpu.ime());
+    core.write_u8(mmu.interrupt_enable);
+    // Running or halted, STOP is not emulated
+    core.write_u8(u8::from(cpu.halted()));
+    core.write_u8(0);
+    for addr in 0xff00..=0xff7f {
+        core.write_u8(mmu.read_byte(addr));
+    }
+    for (size, offset) in buffers {
+        core.write_u32(size);
+        core.write_u32(offset);
+    }
+    write_block(&mut w, b"CORE", &core.into_bytes());
+
+    let mut mbc = Vec::new();
+    for (addr, value) in mmu.cartridge.mbc_writes() {
+        mbc.extend_from_slice(&addr.to_le_bytes());
+        mbc.push(value);
+    }
+    write_block(&mut w, b"MBC ", &mbc);
+    write_block(&mut w, b"END ", &[]);
+
+    data.extend_from_slice(&w.into_bytes());
+    data.extend_from_slice(&first_block.to_le_bytes());
+    data.extend_from_slice(FOOTER_MAGIC);
+}
+
+/// Contents of the CORE block.
+struct Core<'a> {
+    regs: Registers,
+    ime: bool,
+    ie: u8,
+    halted: bool,
+    io: [u8; 0x80],
+    buffers: [&'a [u8]; NUM_BUFFERS],
+}
+
+fn parse_core<'a>(content: &[u8], data: &'a [u8]) -> Result<Core<'a>, StateError> {
+    let mut r = StateReader::new(content);
+    let major = r.read_u16()?;
+    if major != MAJOR_VERSION {
+        return Err(StateError::UnsupportedVersion(major as u32));
+    }
+    let _minor = r.read_u16()?;
+    let mut model = [0; 4];
+    r.read_bytes(&mut model)?;
+
+    let pc = r.read_u16()?;
+    let [a, f] = r.read_u16()?.to_be_bytes();
+    let [b, c] = r.read_u16()?.to_be_bytes();
+    let [d, e] = r.read_u16()?.to_be_bytes();
+    let [h, l] = r.read_u16()?.to_be_bytes();
+    let sp = r.read_u16()?;
+    let regs = Registers {
+        a,
+        f,
+        b,
+        c,
+        d,
+        e,
+        h,
+        l,
+        sp,
+        pc,
+    };
+    let ime = r.read_bool()?;
+    let ie = r.read_u8()?;
+    let halted = match r.read_u8()? {
+        0 => false,
+        1 => true,
+        // Stopped, closest is running
+        2 => false,
+        _ => return Err(StateError::Corrupt),
+    };
+    let _reserved = r.read_u8()?;
+    let mut io = [0; 0x80];
+    r.read_bytes(&mut io)?;
+
+    let mut buffers = [&data[..0]; NUM_BUFFERS];
+    for buffer in buffers.iter_mut() {
+        let size = r.read_u32()? as usize;
+        let offset = r.read_u32()? as usize;
+        *buffer = offset
+            .checked_add(size)
+            .and_then(|end| data.get(offset..end))
+            .ok_or(StateError::Corrupt)?;
+    }
+
+    Ok(Core {
+        regs,
+        ime,
+        ie,
+        halted,
+        io,
+        buffers,
+    })
+}
+
+/// Restores the state described by the BESS blocks at the end of `data`.
+///
+/// Everything is parsed before the machine is touched, so it is left as is
+/// on error.
+pub(crate) fn load(cpu: &mut Cpu, data: &[u8]) -> Result<(), StateError> {
+    if data.len() < 8 || &data[data.len() - 4..] != FOOTER_MAGIC {
+        return Err(StateError::BadMagic);
+    }
+    let footer = data.len() - 8;
+    let mut first_block = [0; 4];
+    first_block.copy_from_slice(&data[footer..footer + 4]);
+    let blocks = data
+        .get(u32::from_le_bytes(first_block) as usize..footer)
+        .ok_or(StateError::Corrupt)?;
+
+    let mut r = StateReader::new(blocks);
+    let mut core = None;
+    let mut mbc = Vec::new();
+    loop {
+        let mut id = [0; 4];
+        r.read_bytes(&mut id)?;
+        let content = r.read_vec()?;
+        match &id {
+            b"INFO" => {
+                let rom = cpu.mmu.cartridge.rom();
+                if content.len() != 0x12
+                    || content[..0x10] != rom[0x134..0x144]
+                    || content[0x10..] != rom[0x14e..0x150]
+                {
+                    return Err(StateError::RomMismatch);
+                }
+            }
+            b"CORE" => core = Some(parse_core(&content, data)?),
+            b"MBC " => mbc = content,
+            b"END " => break,
+            // NAME and blocks for other hardware
+            _ => {}
+        }
+    }
+    let core = core.ok_or(StateError::Corrupt)?;
+
+    cpu.reset(false);
+    let mmu = &mut cpu.mmu;
+
+    let ram = mmu.cartridge.ram_mut();
+    let len = ram.len().min(core.buffers[MBC_RAM].len());
+    ram[..len].copy_from_slice(&core.buffers[MBC_RAM][..len]);
+    for write in mbc.chunks_exact(3) {
+        let addr = u16::from_le_bytes([write[0], write[1]]);
+        if addr < 0x8000 || (0xa000..=0xbfff).contains(&addr) {
+            mmu.cartridge.write(addr, write[2]);
+        }
+    }
+
+    for (i, &value) in core.buffers[WRAM].iter().take(0x2000).enumerate() {
+        mmu.write_byte(0xc000 + i as u16, value);
+    }
+    for (i, &value) in core.buffers[HRAM].iter().take(0x7f).enumerate() {
+        mmu.write_byte(0xff80 + i as u16, value);
+    }
+    let vram = core.buffers[VRAM];
+    let len = vram.len().min(0x2000);
+    mmu.ppu.vram_mut()[..len].copy_from_slice(&vram[..len]);
+    let oam = core.buffers[OAM];
+    let len = oam.len().min(0xa0);
+    mmu.ppu.oam_mut()[..len].copy_from_slice(&oam[..len]);
+
+    let io = |addr: u16| core.io[(addr - 0xff00) as usize];
+    // Sound needs power before the other registers take writes
+    mmu.write_byte(0xff26, io(0xff26));
+    for addr in (0xff10..=0xff3f).filter(|&addr| addr != 0xff26) {
+        let value = match addr {
+            0xff14 | 0xff19 | 0xff1e | 0xff23 => io(addr) & !TRIGGER,
+            _ => io(addr),
+        };
+        mmu.write_byte(addr, value);
+    }
+    // DIV is skipped, writing it would only reset it
+    for addr in [0xff00, 0xff01, 0xff02, 0xff05, 0xff06, 0xff07, 0xff0f] {
+        mmu.write_byte(addr, io(addr));
+    }
+    // LY is read-only and DMA would copy into OAM again
+    for addr in (0xff40..=0xff4b).filter(|&addr| addr != 0xff44 && addr != 0xff46) {
+        mmu.write_byte(addr, io(addr));
+    }
+    // Replaying STAT, LYC and LCDC can request the STAT interrupt, e.g.
+    // through the STAT write bug, but IF already holds what was pending
+    mmu.ppu.set_irq_lcdc(false);
+    mmu.interrupt_enable = core.ie;
+
+    cpu.set_registers(core.regs);
+    cpu.set_ime(core.ime);
+    cpu.set_halted(core.halted);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::{fix_checksum, rom_with_header};
+
+    /// A 64KB MBC1+RAM ROM.
+    fn test_rom() -> Vec<u8> {
+        let mut rom = rom_with_header(0x03, 0x02);
+        rom.resize(0x10000, 0);
+        rom[0x134..0x138].copy_from_slice(b"BESS");
+        rom[0x148] = 0x01;
+        fix_checksum(&mut rom);
+        rom
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut cpu = Cpu::with_rom(test_rom()).unwrap();
+        cpu.set_registers(Registers {
+            a: 0x12,
+            f: 0xb0,
+            b: 0x34,
+            sp: 0xdff0,
+            pc: 0x4321,
+            ..Registers::default()
+        });
+        cpu.set_ime(true);
+        cpu.mmu.write_byte(0xc123, 0x56);
+        cpu.mmu.write_byte(0xff90, 0x78);
+        cpu.mmu.ppu.vram_mut()[0x10] = 0x9a;
+        cpu.mmu.write_byte(0xff47, 0xe4);
+        cpu.mmu.write_byte(0xffff, 0x05);
+        // Enable and fill cartridge RAM, then select ROM bank 3
+        cpu.mmu.write_byte(0x0000, 0x0a);
+        cpu.mmu.write_byte(0xa010, 0xbc);
+        cpu.mmu.write_byte(0x2000, 0x03);
+        let data = cpu.save_state_bess();
+
+        let mut restored = Cpu::with_rom(test_rom()).unwrap();
+        restored.load_state_bess(&data).unwrap();
+
+        assert_eq!(restored.registers(), cpu.registers());
+        assert!(restored.ime());
+        assert_eq!(restored.mmu.read_byte(0xc123), 0x56);
+        assert_eq!(restored.mmu.read_byte(0xff90), 0x78);
+        assert_eq!(restored.mmu.ppu.vram()[0x10], 0x9a);
+        assert_eq!(restored.mmu.read_byte(0xff47), 0xe4);
+        assert_eq!(restored.mmu.interrupt_enable, 0x05);
+        assert_eq!(restored.mmu.read_byte(0xa010), 0xbc);
+        assert_eq!(
+            restored.mmu.cartridge.mbc_writes(),
+            cpu.mmu.cartridge.mbc_writes()
+        );
+    }
+
+    #[test]
+    fn test_no_spurious_stat_interrupt() {
+        let mut cpu = Cpu::with_rom(test_rom()).unwrap();
+        // Every STAT interrupt source enabled, with LY=LYC on line 0
+        cpu.mmu.write_byte(0xff45, 0x00);
+        cpu.mmu.write_byte(0xff41, 0x78);
+        cpu.mmu.interrupt_flag = 0;
+        let data = cpu.save_state_bess();
+
+        let mut restored = Cpu::with_rom(test_rom()).unwrap();
+        assert!(restored.mmu.ppu.stat_write_bug());
+        restored.load_state_bess(&data).unwrap();
+        assert!(!restored.mmu.ppu.is_irq_lcdc());
+        assert_eq!(restored.mmu.interrupt_flag, 0);
+    }
+
+    #[test]
+    fn test_rejects_native_state() {
+        let mut cpu = Cpu::with_rom(test_rom()).unwrap();
+        let data = cpu.save_state();
+        assert_eq!(cpu.load_state_bess(&data), Err(StateError::BadMagic));
+    }
+}
@@ -0,0 +1,130 @@
+//! A startup animation to show in place of the boot ROM, which this core
+//! does not run: "GAME BOY" scrolls down to the middle of the screen and
+//! stays there a moment. It is drawn here from a built-in font, so no
+//! boot ROM code or logo is needed. The machine still starts at 0x100 as
+//! without it, the animation only delays handing off to the game.
+
+use alloc::vec::Vec;
+
+use crate::ppu::{Rgb, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const TEXT: &[u8] = b"GAME BOY";
+/// Screen pixels per font pixel.
+const SCALE: usize = 3;
+/// Width of a glyph plus the space after it, in font pixels.
+const ADVANCE: usize = 6;
+const TEXT_WIDTH: usize = (TEXT.len() * ADVANCE - 1) * SCALE;
+const TEXT_HEIGHT: usize = 7 * SCALE;
+/// Top of the text once it stopped.
+const REST_Y: usize = (SCREEN_HEIGHT - TEXT_HEIGHT) / 2;
+/// Frames the text scrolls for, a pixel a frame from just above the screen.
+pub const SCROLL_FRAMES: u32 = (REST_Y + TEXT_HEIGHT) as u32;
+/// Frames the text stays still before the game starts.
+pub const HOLD_FRAMES: u32 = 60;
+
+/// Draws the frames of the animation.
+pub struct BootAnimation {
+    /// Background and text colors.
+    shades: [Rgb; 2],
+}
+
+impl BootAnimation {
+    /// Creates a new `BootAnimation` in the lightest and darkest of
+    /// `shades`, e.g. the `Ppu::dmg_palette` the game will use.
+    pub fn new(shades: [Rgb; 4]) -> Self {
+        BootAnimation {
+            shades: [shades[0], shades[3]],
+        }
+    }
+
+    pub fn frames(&self) -> u32 {
+        SCROLL_FRAMES + HOLD_FRAMES
+    }
+
+    /// Frame `frame` of the animation, RGB24 like `Ppu::get_frame`. Frames
+    /// past the end look like the last one.
+    pub fn render(&self, frame: u32) -> Vec<u8> {
+        let mut buf: Vec<u8> = self.shades[0]
+            .iter()
+            .copied()
+            .cycle()
+            .take(SCREEN_WIDTH * SCREEN_HEIGHT * 3)
+            .collect();
+        // Rows of the text scrolled onto the screen so far, from its bottom
+        let shown = (frame.min(SCROLL_FRAMES) as usize).min(TEXT_HEIGHT);
+        let top = (frame.min(SCROLL_FRAMES) as usize) - shown;
+        let left = (SCREEN_WIDTH - TEXT_WIDTH) / 2;
+        for row in 0..shown {
+            let text_row = TEXT_HEIGHT - shown + row;
+            let y = top + row;
+            for (i, c) in TEXT.iter().enumerate() {
+                let bits = glyph(*c)[text_row / SCALE];
+                for dx in 0..5 * SCALE {
+                    if bits >> (4 - dx / SCALE) & 1 == 1 {
+                        let x = left + i * ADVANCE * SCALE + dx;
+                        let offset = (y * SCREEN_WIDTH + x) * 3;
+                        buf[offset..offset + 3].copy_from_slice(&self.shades[1]);
+                    }
+                }
+            }
+        }
+        buf
+    }
+}
+
+/// 5x7 glyph of a character of `TEXT`, a row per byte, the leftmost pixel
+/// in bit 4.
+#[rustfmt::skip]
+fn glyph(c: u8) -> [u8; 7] {
+    match c {
+        b'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        b'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        b'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        b'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        b'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        b'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        b'Y' => [0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100],
+        _ => [0; 7],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADES: [Rgb; 4] = [[0xff; 3], [0xaa; 3], [0x55; 3], [0x00; 3]];
+
+    /// Rows of `frame` with text on them.
+    fn text_rows(frame: &[u8]) -> Vec<usize> {
+        frame
+            .chunks(SCREEN_WIDTH * 3)
+            .enumerate()
+            .filter(|(_, row)| row.contains(&0x00))
+            .map(|(y, _)| y)
+            .collect()
+    }
+
+    #[test]
+    fn test_scroll() {
+        let animation = BootAnimation::new(SHADES);
+        assert_eq!(animation.frames(), SCROLL_FRAMES + HOLD_FRAMES);
+        let first = animation.render(0);
+        assert_eq!(first.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        assert!(first.iter().all(|&b| b == 0xff));
+
+        // The bottom row of the glyphs comes in first
+        assert_eq!(text_rows(&animation.render(SCALE as u32)), [0, 1, 2]);
+        let rest = text_rows(&animation.render(SCROLL_FRAMES));
+        assert_eq!(rest[0], REST_Y);
+        assert_eq!(*rest.last().unwrap(), REST_Y + TEXT_HEIGHT - 1);
+        assert_eq!(
+            animation.render(SCROLL_FRAMES),
+            animation.render(animation.frames() + 10)
+        );
+        // Nothing but the two shades
+        assert!(animation
+            .render(SCROLL_FRAMES)
+            .iter()
+            .all(|&b| b == 0xff || b == 0x00));
+    }
+}
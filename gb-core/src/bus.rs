@@ -0,0 +1,73 @@
+use alloc::boxed::Box;
+use alloc::vec;
+
+use crate::cpu::Interrupt;
+
+/// Memory and peripherals as seen by the CPU.
+///
+/// `Mmu` is the real machine. `FlatBus` is plain RAM for testing the CPU on
+/// its own.
+pub trait Bus {
+    fn read_byte(&self, addr: u16) -> u8;
+    /// Reads like `read_byte` without side effects on the bus, e.g. access
+    /// counting, for checks on the CPU's state from outside.
+    fn peek(&self, addr: u16) -> u8 {
+        self.read_byte(addr)
+    }
+    fn write_byte(&mut self, addr: u16, value: u8);
+    /// Advances the peripherals by `clock` cycles.
+    fn update(&mut self, clock: u16);
+    /// Interrupts both requested and enabled (IF & IE).
+    fn pending_interrupts(&self) -> u8;
+    /// Acknowledges an interrupt once the CPU dispatches it.
+    fn reset_interrupt(&mut self, interrupt: Interrupt);
+    /// Cycles until a peripheral may next raise an interrupt, used to skip
+    /// ahead while halted. `None` if nothing is scheduled.
+    fn cycles_until_next_event(&self) -> Option<u64> {
+        None
+    }
+    /// Called when INC rr or DEC rr puts `addr` on the address bus, which
+    /// can corrupt OAM on a DMG.
+    fn idu_access(&mut self, _addr: u16) {}
+    /// Called by STOP, which clears the system counter behind DIV.
+    fn stop(&mut self) {}
+}
+
+/// 64 KiB of RAM covering the whole address space, with no peripherals and
+/// no interrupts.
+pub struct FlatBus {
+    ram: Box<[u8]>,
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlatBus {
+    /// Creates a new `FlatBus` filled with zeros.
+    pub fn new() -> Self {
+        FlatBus {
+            ram: vec![0; 0x10000].into_boxed_slice(),
+        }
+    }
+}
+
+impl Bus for FlatBus {
+    fn read_byte(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        self.ram[addr as usize] = value;
+    }
+
+    fn update(&mut self, _clock: u16) {}
+
+    fn pending_interrupts(&self) -> u8 {
+        0
+    }
+
+    fn reset_interrupt(&mut self, _interrupt: Interrupt) {}
+}
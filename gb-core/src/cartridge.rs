@@ -0,0 +1,1443 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::error;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io::Read;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[cfg(feature = "std")]
+use crate::battery;
+use crate::battery::SaveFormat;
+use crate::rtc;
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::utils::fnv1a_64;
+use log::{info, warn};
+
+/// Log target for ROM loading and the memory bank controllers.
+const LOG_TARGET: &str = "gb::mbc";
+
+/// Size of the header area every ROM has to cover.
+const HEADER_END: usize = 0x150;
+
+pub trait Cartridge {
+    fn read(&self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+    /// Writes the RAM to `save_data/` as a battery save. Without the `std`
+    /// feature there are no files, and hosts keep `ram` themselves.
+    fn write_save_data(&self, format: SaveFormat);
+    /// Replaces the blank RAM with the battery save from `save_data/`, if any.
+    /// Does nothing without the `std` feature.
+    fn load_save_data(&mut self) {}
+    /// Resets the mapper registers. RAM is cleared only when `clear_ram` is set.
+    fn reset(&mut self, clear_ram: bool);
+    fn rom(&self) -> &[u8];
+    /// External RAM, empty if the cartridge has none.
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+    /// Register writes that bring a freshly reset mapper to the current
+    /// banking state.
+    fn mbc_writes(&self) -> Vec<(u16, u8)> {
+        Vec::new()
+    }
+    /// ROM bank mapped at 0x4000-0x7fff.
+    fn rom_bank(&self) -> usize {
+        1
+    }
+    /// Whether a write to `addr` in 0x0000-0x7fff selects a ROM or RAM
+    /// bank, rather than e.g. enabling RAM. Mappers without banks have none.
+    fn is_bank_register(&self, _addr: u16) -> bool {
+        false
+    }
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+
+    /// Title from the cartridge header.
+    fn title(&self) -> String {
+        get_title(&self.rom()[0x134..=0x143])
+    }
+
+    /// Stable hash of the whole ROM image.
+    fn rom_hash(&self) -> u64 {
+        fnv1a_64(self.rom())
+    }
+
+    /// Header summary and problems found in the ROM image.
+    fn report(&self) -> RomReport {
+        analyze(self.rom()).expect("a loaded ROM holds a header")
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// The image is too short to hold a cartridge header.
+    TooSmall(usize),
+    /// The header ROM size code at 0x148 is not a known one.
+    UnknownRomSize(u8),
+    /// The header RAM size code at 0x149 is not a known one.
+    UnknownRamSize(u8),
+    /// The header checksum at 0x14d does not match.
+    BadChecksum,
+    /// The cartridge type at 0x147 is not emulated.
+    UnsupportedMbc(u8),
+    /// Reading the image failed, with the reason.
+    Read(String),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CartridgeError::TooSmall(len) => write!(f, "ROM is only {} bytes", len),
+            CartridgeError::UnknownRomSize(code) => {
+                write!(f, "unknown ROM size code 0x{:02x}", code)
+            }
+            CartridgeError::UnknownRamSize(code) => {
+                write!(f, "unknown RAM size code 0x{:02x}", code)
+            }
+            CartridgeError::BadChecksum => write!(f, "header checksum mismatch"),
+            CartridgeError::UnsupportedMbc(mbc) => {
+                write!(f, "unsupported cartridge type 0x{:02x}", mbc)
+            }
+            CartridgeError::Read(ref reason) => write!(f, "cannot read ROM: {}", reason),
+        }
+    }
+}
+
+impl error::Error for CartridgeError {}
+
+/// What the header says about a ROM image, and where the image disagrees.
+#[derive(Debug)]
+pub struct RomReport {
+    pub title: String,
+    /// Cartridge type code at 0x147.
+    pub mbc_type: u8,
+    pub mbc_type_name: &'static str,
+    /// Size from the header, `None` for an unknown size code.
+    pub declared_rom_size: Option<usize>,
+    /// Size of the image itself.
+    pub rom_size: usize,
+    /// External RAM size from the header, `None` for an unknown size code.
+    pub ram_size: Option<usize>,
+    /// Whether the header checksum at 0x14d matches. The boot ROM refuses
+    /// to start the game otherwise.
+    pub header_checksum_ok: bool,
+    /// Whether the global checksum at 0x14e matches. Nothing checks it, so
+    /// homebrew often leaves it blank.
+    pub global_checksum_ok: bool,
+    /// Problems that do not prevent loading the ROM.
+    pub warnings: Vec<String>,
+}
+
+/// Inspects the header of a ROM image without building a cartridge.
+pub fn analyze(rom: &[u8]) -> Result<RomReport, CartridgeError> {
+    if rom.len() < HEADER_END {
+        return Err(CartridgeError::TooSmall(rom.len()));
+    }
+
+    let declared_rom_size = match rom[0x148] {
+        n if (0x00..=0x08).contains(&n) => Some((32 * 1024) << n),
+        _ => None,
+    };
+
+    let mut warnings = Vec::new();
+    match declared_rom_size {
+        Some(size) if rom.len() < size => warnings.push(format!(
+            "ROM is truncated: {} bytes of {} declared, missing data reads as 0xff",
+            rom.len(),
+            size
+        )),
+        Some(size) if rom.len() > size => warnings.push(format!(
+            "ROM is larger than declared: {} bytes of {} declared",
+            rom.len(),
+            size
+        )),
+        _ => {}
+    }
+    if rom.len() & 0x3fff != 0 {
+        warnings.push(format!(
+            "ROM size {} is not a whole number of 16KB banks",
+            rom.len()
+        ));
+    }
+
+    Ok(RomReport {
+        title: get_title(&rom[0x134..=0x143]),
+        mbc_type: rom[0x147],
+        mbc_type_name: get_mbc_type_name(rom[0x147]),
+        declared_rom_size,
+        rom_size: rom.len(),
+        ram_size: ram_size_kb(rom[0x149]).map(|kb| kb * 1024),
+        header_checksum_ok: header_checksum(rom) == rom[0x14d],
+        global_checksum_ok: global_checksum(rom) == u16::from_be_bytes([rom[0x14e], rom[0x14f]]),
+        warnings,
+    })
+}
+
+/// Header checksum of a ROM image as stored at 0x14d, over 0x134-0x14c.
+/// The image has to hold a header.
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..=0x14c]
+        .iter()
+        .fold(0u8, |checksum, &b| checksum.wrapping_sub(b).wrapping_sub(1))
+}
+
+/// Global checksum of a ROM image as stored big-endian at 0x14e: the sum of
+/// every byte but the checksum itself. The image has to hold a header.
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 0x14e && i != 0x14f)
+        .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16))
+}
+
+/// Writes both checksums into the header, e.g. after editing a homebrew
+/// ROM. The header checksum goes first since the global one covers it.
+pub fn fix_checksums(rom: &mut [u8]) {
+    rom[0x14d] = header_checksum(rom);
+    let global = global_checksum(rom);
+    rom[0x14e..0x150].copy_from_slice(&global.to_be_bytes());
+}
+
+struct RomOnly {
+    rom: Vec<u8>,
+}
+
+/// 32KB of ROM and up to 8KB of RAM, both always mapped.
+struct RomRam {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    /// Only ROM+RAM+BATTERY keeps its RAM.
+    battery: bool,
+    title: String,
+}
+
+struct MBC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mode_flag: bool,
+    is_ram_enable: bool,
+    rom_bank_no: u8,
+    ram_bank_no: u8,
+    num_rom_banks: usize,
+    title: String,
+}
+pub struct MBC2 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank_no: usize,
+    ram_enable: bool,
+    title: String,
+}
+struct MBC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank_no: u8,
+    ram_bank_no: u8,
+    rtc: rtc::Rtc,
+    ram_enable: bool,
+    /// MBC30, with 8 ROM bank bits and 8 RAM banks instead of 7 and 4.
+    mbc30: bool,
+    title: String,
+}
+
+struct MBC5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank_no: usize,
+    ram_bank_no: usize,
+    ram_enable: bool,
+    title: String,
+}
+
+#[cfg(feature = "std")]
+pub fn new(cartridge_name: &str) -> Box<dyn Cartridge> {
+    info!(target: LOG_TARGET, "Reading {} file...", cartridge_name);
+    // let path = Path::new("cartridges").join(cartridge_name);
+    let path = Path::new(cartridge_name);
+    let rom = fs::read(path).expect("Error while reading ROM file");
+    info!(target: LOG_TARGET, "Finish reading {} file", cartridge_name);
+
+    let mut cartridge =
+        from_bytes(rom).unwrap_or_else(|e| panic!("Cannot load {}: {}", cartridge_name, e));
+    cartridge.load_save_data();
+    cartridge
+}
+
+/// Builds a cartridge from a ROM image read to its end from `reader`, e.g.
+/// stdin or an HTTP body, for hosts without a local path. RAM is blank as
+/// with `from_bytes`.
+#[cfg(feature = "std")]
+pub fn from_reader<R: Read>(mut reader: R) -> Result<Box<dyn Cartridge>, CartridgeError> {
+    let mut rom = Vec::new();
+    reader
+        .read_to_end(&mut rom)
+        .map_err(|e| CartridgeError::Read(e.to_string()))?;
+    from_bytes(rom)
+}
+
+/// Builds a cartridge from a ROM image with blank RAM.
+///
+/// Only the header is validated. Nothing is read from disk, see
+/// `Cartridge::load_save_data` for battery saves.
+pub fn from_bytes(rom: Vec<u8>) -> Result<Box<dyn Cartridge>, CartridgeError> {
+    let report = analyze(&rom)?;
+    for warning in &report.warnings {
+        warn!(target: LOG_TARGET, "{}", warning);
+    }
+
+    let title = report.title;
+    info!(target: LOG_TARGET, "ROM title: {}", title);
+
+    let mbc_type = rom[0x147];
+    let mbc_type_name = get_mbc_type_name(mbc_type);
+
+    let rom_size_kb = match rom[0x148] {
+        n if (0x00..=0x08).contains(&n) => 32 << n,
+        n => return Err(CartridgeError::UnknownRomSize(n)),
+    };
+    let ram_size_kb = ram_size_kb(rom[0x149]).ok_or(CartridgeError::UnknownRamSize(rom[0x149]))?;
+
+    if !report.header_checksum_ok {
+        return Err(CartridgeError::BadChecksum);
+    }
+    info!(target: LOG_TARGET, "ROM size: {}KB", rom_size_kb);
+    info!(target: LOG_TARGET, "RAM size: {}KB", ram_size_kb);
+    info!(target: LOG_TARGET, "MBC type: {}", mbc_type_name);
+
+    let ram_size = ram_size_kb * 1024;
+    Ok(match mbc_type {
+        0x00 => Box::new(RomOnly::new(rom)),
+        0x01..=0x03 => Box::new(MBC1::new(rom, &title, ram_size)),
+        0x05 | 0x06 => Box::new(MBC2::new(rom, &title)),
+        0x08 | 0x09 => Box::new(RomRam::new(rom, &title, ram_size, mbc_type == 0x09)),
+        0x0f..=0x13 => Box::new(MBC3::new(rom, &title, ram_size)),
+        0x19..=0x1e => Box::new(MBC5::new(rom, &title, ram_size)),
+        _ => return Err(CartridgeError::UnsupportedMbc(mbc_type)),
+    })
+}
+
+fn ram_size_kb(code: u8) -> Option<usize> {
+    match code {
+        0x00 => Some(0),
+        0x01 => Some(2), // Listed in various unofficial docs as 2KB
+        0x02 => Some(8),
+        0x03 => Some(32),
+        0x04 => Some(128),
+        0x05 => Some(64),
+        _ => None,
+    }
+}
+
+/// Turns the title bytes of a header into a string, dropping padding.
+pub fn get_title(rom: &[u8]) -> String {
+    rom.iter()
+        .filter(|&s| (*s != 0) & (*s != 128))
+        .map(|&s| s as char)
+        .collect::<String>()
+}
+
+fn get_mbc_type_name(mbc_type: u8) -> &'static str {
+    match mbc_type {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0B => "MMM01",
+        0x0C => "MMM01+RAM",
+        0x0D => "MMM01+RAM+BATTERY",
+        0x0F => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1A => "MBC5+RAM",
+        0x1B => "MBC5+RAM+BATTERY",
+        0x1C => "MBC5+RUMBLE",
+        0x1D => "MBC5+RUMBLE+RAM",
+        0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xFC => "POCKET CAMERA",
+        0xFD => "BANDAI TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1+RAM+BATTERY",
+        // _ => panic!("Invalid mbc type: {}", mbc_type),
+        _ => "Invalid mbc type",
+    }
+}
+
+impl Cartridge for RomOnly {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7fff => rom_byte(&self.rom, (addr >> 14) as usize, addr),
+            // No RAM on the cartridge
+            _ => 0xff,
+        }
+    }
+
+    fn write(&mut self, _addr: u16, _value: u8) {
+        {}
+    }
+    fn write_save_data(&self, _format: SaveFormat) {}
+    fn reset(&mut self, _clear_ram: bool) {}
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn save_state(&self, _w: &mut StateWriter) {}
+    fn load_state(&mut self, _r: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+impl RomOnly {
+    fn new(rom: Vec<u8>) -> Self {
+        RomOnly { rom }
+    }
+}
+
+impl Cartridge for RomRam {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7fff => rom_byte(&self.rom, (addr >> 14) as usize, addr),
+            0xa000..=0xbfff => ram_index(&self.ram, 0, addr).map_or(0xff, |i| self.ram[i]),
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        // Without a mapper, writes to ROM go nowhere
+        if let 0xa000..=0xbfff = addr {
+            if let Some(i) = ram_index(&self.ram, 0, addr) {
+                self.ram[i] = value;
+            }
+        }
+    }
+
+    fn write_save_data(&self, format: SaveFormat) {
+        if self.battery {
+            write_ram(&self.title, &self.ram, format);
+        }
+    }
+
+    fn load_save_data(&mut self) {
+        if self.battery {
+            load_ram(&self.title, &mut self.ram);
+        }
+    }
+
+    fn reset(&mut self, clear_ram: bool) {
+        if clear_ram {
+            self.ram.fill(0);
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_vec(&self.ram);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.ram = read_ram_state(r, self.ram.len())?;
+        Ok(())
+    }
+}
+
+impl RomRam {
+    fn new(rom: Vec<u8>, title: &str, ram_size: usize, battery: bool) -> Self {
+        info!(target: LOG_TARGET, "ROM+RAM created");
+        RomRam {
+            rom,
+            ram: vec![0; ram_size.min(0x2000)],
+            battery,
+            title: title.to_string(),
+        }
+    }
+}
+
+impl Cartridge for MBC1 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // ROM bank 00, or 20/40/60 in mode 1
+            0x0000..=0x3fff => rom_byte(&self.rom, self.rom_bank0_no(), addr),
+            // ROM bank 01-7f
+            0x4000..=0x7fff => rom_byte(&self.rom, self.rom_bank_no(), addr),
+            // RAM bank 00-03
+            0xa000..=0xbfff => {
+                if !self.is_ram_enable {
+                    return 0xff;
+                }
+                ram_index(&self.ram, self.ram_bank_no() as usize, addr)
+                    .map_or(0xff, |i| self.ram[i])
+            }
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.is_ram_enable = value & 0x0f == 0x0a,
+            0x2000..=0x3fff => self.rom_bank_no = value & 0x1f,
+            0x4000..=0x5fff => self.ram_bank_no = value & 0x03,
+            0x6000..=0x7fff => self.mode_flag = value & 0x01 == 0x01,
+            0xa000..=0xbfff => {
+                if !self.is_ram_enable {
+                    return;
+                }
+                if let Some(i) = ram_index(&self.ram, self.ram_bank_no() as usize, addr) {
+                    self.ram[i] = value;
+                }
+            }
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    fn is_bank_register(&self, addr: u16) -> bool {
+        (0x2000..=0x5fff).contains(&addr)
+    }
+
+    fn write_save_data(&self, format: SaveFormat) {
+        write_ram(&self.title, &self.ram, format);
+    }
+
+    fn load_save_data(&mut self) {
+        load_ram(&self.title, &mut self.ram);
+    }
+
+    fn reset(&mut self, clear_ram: bool) {
+        self.mode_flag = false;
+        self.is_ram_enable = false;
+        self.rom_bank_no = 0;
+        self.ram_bank_no = 0;
+        if clear_ram {
+            self.ram.fill(0);
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank_no()
+    }
+
+    fn mbc_writes(&self) -> Vec<(u16, u8)> {
+        vec![
+            (0x0000, if self.is_ram_enable { 0x0a } else { 0x00 }),
+            (0x2000, self.rom_bank_no),
+            (0x4000, self.ram_bank_no),
+            (0x6000, u8::from(self.mode_flag)),
+        ]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_vec(&self.ram);
+        w.write_bool(self.mode_flag);
+        w.write_bool(self.is_ram_enable);
+        w.write_u8(self.rom_bank_no);
+        w.write_u8(self.ram_bank_no);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.ram = read_ram_state(r, self.ram.len())?;
+        self.mode_flag = r.read_bool()?;
+        self.is_ram_enable = r.read_bool()?;
+        self.rom_bank_no = r.read_u8()?;
+        self.ram_bank_no = r.read_u8()?;
+        Ok(())
+    }
+}
+
+impl MBC1 {
+    fn new(rom: Vec<u8>, title: &str, ram_size: usize) -> Self {
+        let num_rom_banks = 2 << rom[0x148] as usize;
+
+        info!(target: LOG_TARGET, "MBC1 created");
+        MBC1 {
+            rom,
+            ram: vec![0; ram_size],
+            mode_flag: false,
+            is_ram_enable: false,
+            rom_bank_no: 0,
+            ram_bank_no: 0,
+            num_rom_banks,
+            title: title.to_string(),
+        }
+    }
+    /// Bank at 0x4000. The upper two bits apply in both modes, and only
+    /// the lower five are checked for 0, so 0x20, 0x40 and 0x60 map to the
+    /// bank above.
+    fn rom_bank_no(&self) -> usize {
+        let low = match self.rom_bank_no {
+            0 => 1,
+            n => n,
+        };
+        (self.ram_bank_no << 5 | low) as usize & (self.num_rom_banks - 1)
+    }
+
+    /// Bank at 0x0000, which mode 1 moves on 1MB and larger ROMs.
+    fn rom_bank0_no(&self) -> usize {
+        if self.mode_flag {
+            (self.ram_bank_no << 5) as usize & (self.num_rom_banks - 1)
+        } else {
+            0
+        }
+    }
+
+    fn ram_bank_no(&self) -> u8 {
+        if self.mode_flag {
+            self.ram_bank_no
+        } else {
+            0
+        }
+    }
+}
+
+impl Cartridge for MBC2 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3fff => rom_byte(&self.rom, 0, addr),
+            // Bank 0 maps to 1
+            0x4000..=0x7fff => rom_byte(&self.rom, self.rom_bank_no.max(1), addr),
+            // Only the low nibble is stored, the rest is open bus
+            0xa000..=0xa1ff if self.ram_enable => 0xf0 | self.ram[(addr - 0xa000) as usize],
+            _ => 0xff,
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        let value = value & 0x0f;
+        match addr {
+            0xa000..=0xa1ff if self.ram_enable => self.ram[(addr - 0xa000) as usize] = value,
+            // Address bit 8 picks the register anywhere in 0x0000-0x3fff
+            0x0000..=0x3fff if addr & 0x0100 == 0 => self.ram_enable = value == 0x0a,
+            0x0000..=0x3fff => self.rom_bank_no = value as usize,
+            _ => {}
+        }
+    }
+    fn is_bank_register(&self, addr: u16) -> bool {
+        addr <= 0x3fff && addr & 0x0100 != 0
+    }
+
+    fn write_save_data(&self, format: SaveFormat) {
+        write_ram(&self.title, &self.ram, format);
+    }
+
+    fn reset(&mut self, clear_ram: bool) {
+        self.rom_bank_no = 0;
+        self.ram_enable = false;
+        if clear_ram {
+            self.ram.fill(0);
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank_no.max(1)
+    }
+
+    fn mbc_writes(&self) -> Vec<(u16, u8)> {
+        vec![
+            (0x0000, if self.ram_enable { 0x0a } else { 0x00 }),
+            (0x2100, self.rom_bank_no as u8),
+        ]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_vec(&self.ram);
+        w.write_u32(self.rom_bank_no as u32);
+        w.write_bool(self.ram_enable);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.ram = read_ram_state(r, self.ram.len())?;
+        self.rom_bank_no = r.read_u32()? as usize;
+        self.ram_enable = r.read_bool()?;
+        Ok(())
+    }
+}
+
+impl MBC2 {
+    fn new(rom: Vec<u8>, title: &str) -> Self {
+        info!(target: LOG_TARGET, "MBC2 created");
+        MBC2 {
+            rom,
+            ram: vec![0; 512],
+            rom_bank_no: 0,
+            ram_enable: false,
+            title: title.to_string(),
+        }
+    }
+}
+
+impl Cartridge for MBC3 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3fff => rom_byte(&self.rom, 0, addr),
+            // The register reads 0 until the first write, which maps to 1
+            0x4000..=0x7fff => rom_byte(&self.rom, self.rom_bank_no.max(1) as usize, addr),
+            0xa000..=0xbfff => {
+                if self.ram_enable {
+                    match self.ram_bank_no {
+                        n if n <= self.max_ram_bank() => {
+                            ram_index(&self.ram, n as usize, addr).map_or(0xff, |i| self.ram[i])
+                        }
+                        n if (0x08..=0x0c).contains(&n) => self.rtc.read(n as u16),
+                        // Nothing is mapped to the other banks
+                        _ => 0xff,
+                    }
+                } else {
+                    0xff
+                }
+            }
+            _ => panic!("Invalid addr 0x{:04x}, MBC3 read", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable = (value & 0x0f) == 0x0a,
+            0x2000..=0x3fff => {
+                let rom_bank = if self.mbc30 { value } else { value & 0x7f };
+                let rom_bank = match rom_bank {
+                    0 => 1,
+                    _ => rom_bank,
+                };
+                self.rom_bank_no = rom_bank;
+            }
+            0x4000..=0x5fff => {
+                self.ram_bank_no = value & 0x0f;
+            }
+            0x6000..=0x7fff => {
+                if value & 0x01 != 0 {
+                    self.rtc.tic();
+                }
+            }
+            0xa000..=0xbfff => {
+                if self.ram_enable {
+                    match self.ram_bank_no {
+                        n if n <= self.max_ram_bank() => {
+                            if let Some(i) = ram_index(&self.ram, n as usize, addr) {
+                                self.ram[i] = value;
+                            }
+                        }
+                        0x08..=0x0c => self.rtc.write(self.ram_bank_no as u16, value),
+                        _ => {}
+                    }
+                }
+            }
+            _ => panic!("Invalid address: 0x{:04x}", addr),
+        }
+    }
+    /// 0x4000-0x5fff selects an RTC register as well as RAM banks.
+    fn is_bank_register(&self, addr: u16) -> bool {
+        (0x2000..=0x5fff).contains(&addr)
+    }
+
+    fn write_save_data(&self, format: SaveFormat) {
+        write_ram(&self.title, &self.ram, format);
+    }
+
+    fn load_save_data(&mut self) {
+        load_ram(&self.title, &mut self.ram);
+    }
+
+    fn reset(&mut self, clear_ram: bool) {
+        self.rom_bank_no = 0;
+        self.ram_bank_no = 0;
+        self.ram_enable = false;
+        if clear_ram {
+            self.ram.fill(0);
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank_no.max(1) as usize
+    }
+
+    fn mbc_writes(&self) -> Vec<(u16, u8)> {
+        vec![
+            (0x0000, if self.ram_enable { 0x0a } else { 0x00 }),
+            (0x2000, self.rom_bank_no),
+            (0x4000, self.ram_bank_no),
+        ]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_vec(&self.ram);
+        w.write_u8(self.rom_bank_no);
+        w.write_u8(self.ram_bank_no);
+        self.rtc.save_state(w);
+        w.write_bool(self.ram_enable);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.ram = read_ram_state(r, self.ram.len())?;
+        self.rom_bank_no = r.read_u8()?;
+        self.ram_bank_no = r.read_u8()?;
+        self.rtc.load_state(r)?;
+        self.ram_enable = r.read_bool()?;
+        Ok(())
+    }
+}
+
+impl MBC3 {
+    /// Carts with more than 2MB of ROM or 32KB of RAM, the most an MBC3 can
+    /// map, get an MBC30 instead.
+    fn new(rom: Vec<u8>, title: &str, ram_size: usize) -> Self {
+        let mbc30 = rom[0x148] > 0x06 || ram_size > 0x8000;
+        info!(
+            target: LOG_TARGET,
+            "{} created",
+            if mbc30 { "MBC30" } else { "MBC3" }
+        );
+        MBC3 {
+            rom,
+            ram: vec![0; ram_size],
+            rom_bank_no: 0,
+            ram_bank_no: 0,
+            rtc: rtc::Rtc::new(),
+            ram_enable: false,
+            mbc30,
+            title: title.to_string(),
+        }
+    }
+
+    fn max_ram_bank(&self) -> u8 {
+        if self.mbc30 {
+            0x07
+        } else {
+            0x03
+        }
+    }
+}
+
+impl Cartridge for MBC5 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3fff => rom_byte(&self.rom, 0, addr),
+            0x4000..=0x7fff => rom_byte(&self.rom, self.rom_bank_no, addr),
+            0xa000..=0xbfff if self.ram_enable => {
+                ram_index(&self.ram, self.ram_bank_no, addr).map_or(0xff, |i| self.ram[i])
+            }
+            // Disabled RAM leaves the bus floating
+            _ => 0xff,
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1fff => {
+                self.ram_enable = (value & 0x0f) == 0x0a;
+            }
+            0x2000..=0x2fff => self.rom_bank_no = (self.rom_bank_no & 0x100) | (value as usize),
+            0x3000..=0x3fff => {
+                self.rom_bank_no = (self.rom_bank_no & 0x0ff) | (((value & 0x01) as usize) << 8)
+            }
+            0x4000..=0x5fff => self.ram_bank_no = (value & 0x0f) as usize,
+            0xa000..=0xbfff if self.ram_enable => {
+                if let Some(i) = ram_index(&self.ram, self.ram_bank_no, addr) {
+                    self.ram[i] = value;
+                }
+            }
+            _ => {}
+        }
+    }
+    fn is_bank_register(&self, addr: u16) -> bool {
+        (0x2000..=0x5fff).contains(&addr)
+    }
+
+    fn write_save_data(&self, format: SaveFormat) {
+        write_ram(&self.title, &self.ram, format);
+    }
+
+    fn load_save_data(&mut self) {
+        load_ram(&self.title, &mut self.ram);
+    }
+
+    fn reset(&mut self, clear_ram: bool) {
+        self.rom_bank_no = 1;
+        self.ram_bank_no = 0;
+        self.ram_enable = false;
+        if clear_ram {
+            self.ram.fill(0);
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank_no
+    }
+
+    fn mbc_writes(&self) -> Vec<(u16, u8)> {
+        vec![
+            (0x0000, if self.ram_enable { 0x0a } else { 0x00 }),
+            (0x2000, self.rom_bank_no as u8),
+            (0x3000, (self.rom_bank_no >> 8) as u8),
+            (0x4000, self.ram_bank_no as u8),
+        ]
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_vec(&self.ram);
+        w.write_u32(self.rom_bank_no as u32);
+        w.write_u32(self.ram_bank_no as u32);
+        w.write_bool(self.ram_enable);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.ram = read_ram_state(r, self.ram.len())?;
+        self.rom_bank_no = r.read_u32()? as usize;
+        self.ram_bank_no = r.read_u32()? as usize;
+        self.ram_enable = r.read_bool()?;
+        Ok(())
+    }
+}
+
+impl MBC5 {
+    fn new(rom: Vec<u8>, title: &str, ram_size: usize) -> Self {
+        info!(target: LOG_TARGET, "MBC5 created");
+        MBC5 {
+            rom,
+            ram: vec![0; ram_size],
+            // Unlike the others, MBC5 can map bank 0 here, but starts at 1
+            rom_bank_no: 1,
+            ram_bank_no: 0,
+            ram_enable: false,
+            title: title.to_string(),
+        }
+    }
+}
+
+/// Byte at `addr` in ROM bank `bank`.
+///
+/// Bank numbers wrap at the image size rounded up to a power of two, like
+/// unconnected address lines, and bytes past the end of a truncated image
+/// read as 0xff.
+fn rom_byte(rom: &[u8], bank: usize, addr: u16) -> u8 {
+    let index = (bank * 0x4000 + (addr & 0x3fff) as usize) & (rom.len().next_power_of_two() - 1);
+    rom.get(index).copied().unwrap_or(0xff)
+}
+
+/// Index of `addr` in the selected RAM bank, wrapping around RAM smaller
+/// than the bank. `None` when the cartridge has no RAM at all.
+fn ram_index(ram: &[u8], bank: usize, addr: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    Some((bank * 0x2000 + (addr & 0x1fff) as usize) % ram.len())
+}
+
+/// Cartridge RAM from a savestate, which has to be as large as the RAM of
+/// the cartridge it is loaded into.
+fn read_ram_state(r: &mut StateReader, len: usize) -> Result<Vec<u8>, StateError> {
+    let ram = r.read_vec()?;
+    if ram.len() != len {
+        return Err(StateError::Corrupt);
+    }
+    Ok(ram)
+}
+
+#[cfg(feature = "std")]
+fn write_ram(title: &str, ram: &[u8], format: SaveFormat) {
+    let save_file_path = Path::new("save_data").join(title);
+    info!(target: LOG_TARGET, "Writing save file to: {:?}", &save_file_path);
+    fs::write(&save_file_path, battery::encode(ram, format)).unwrap();
+}
+
+/// Reads a battery save in either `SaveFormat`.
+#[cfg(feature = "std")]
+fn load_ram(title: &str, ram: &mut [u8]) {
+    let save_file_path = Path::new("save_data").join(title);
+    match fs::read(&save_file_path).map(|data| battery::decode(&data, ram.len())) {
+        Ok(Ok(data)) => {
+            ram.copy_from_slice(&data);
+            info!(target: LOG_TARGET, "Read save data, path: {:?}", &save_file_path);
+        }
+        Ok(Err(e)) => warn!(
+            target: LOG_TARGET,
+            "Ignoring save data, path: {:?}: {}", &save_file_path, e
+        ),
+        Err(_) => info!(target: LOG_TARGET, "No save data, checked path: {:?}", &save_file_path),
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn write_ram(_title: &str, _ram: &[u8], _format: SaveFormat) {}
+
+#[cfg(not(feature = "std"))]
+fn load_ram(_title: &str, _ram: &mut [u8]) {}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Recomputes the header checksum after editing the header.
+    pub(crate) fn fix_checksum(rom: &mut [u8]) {
+        rom[0x14d] = header_checksum(rom);
+    }
+
+    /// A blank 32KB ROM with a valid header.
+    pub(crate) fn rom_with_header(mbc_type: u8, ram_code: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = mbc_type;
+        rom[0x149] = ram_code;
+        fix_checksum(&mut rom);
+        rom
+    }
+
+    /// Writes `rom` to the temp directory, for code that loads from a path.
+    #[cfg(feature = "std")]
+    pub(crate) fn write_temp_rom(name: &str, rom: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("rust-gb-{}-{}.gb", name, std::process::id()));
+        fs::write(&path, rom).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_bad_headers() {
+        assert_eq!(
+            from_bytes(vec![0; 0x100]).err(),
+            Some(CartridgeError::TooSmall(0x100))
+        );
+        assert_eq!(
+            from_bytes(vec![0; 0x8000]).err(),
+            Some(CartridgeError::BadChecksum)
+        );
+        assert_eq!(
+            from_bytes(rom_with_header(0xfc, 0)).err(),
+            Some(CartridgeError::UnsupportedMbc(0xfc))
+        );
+        assert_eq!(
+            from_bytes(rom_with_header(0x01, 0x07)).err(),
+            Some(CartridgeError::UnknownRamSize(0x07))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_from_reader() {
+        let rom = rom_with_header(0x01, 0);
+        let cartridge = from_reader(&rom[..]).unwrap();
+        assert_eq!(cartridge.rom(), &rom[..]);
+
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("connection reset"))
+            }
+        }
+        assert_eq!(
+            from_reader(Failing).err(),
+            Some(CartridgeError::Read("connection reset".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_ram_access_without_ram() {
+        for mbc_type in [0x00, 0x01, 0x11, 0x19] {
+            let mut cartridge = from_bytes(rom_with_header(mbc_type, 0)).unwrap();
+            cartridge.write(0x0000, 0x0a);
+            cartridge.write(0xa000, 0x12);
+            assert_eq!(cartridge.read(0xa000), 0xff);
+        }
+    }
+
+    #[test]
+    fn test_disabled_ram_reads_open_bus() {
+        // MBC1, MBC2, MBC3 and MBC5 with battery RAM
+        for mbc_type in [0x03, 0x06, 0x13, 0x1b] {
+            let mut cartridge = from_bytes(rom_with_header(mbc_type, 0x02)).unwrap();
+            assert_eq!(cartridge.read(0xa000), 0xff, "type 0x{:02x}", mbc_type);
+
+            cartridge.write(0x0000, 0x0a);
+            cartridge.write(0xa000, 0x05);
+            assert_eq!(
+                cartridge.read(0xa000) & 0x0f,
+                0x05,
+                "type 0x{:02x}",
+                mbc_type
+            );
+
+            cartridge.write(0x0000, 0x00);
+            assert_eq!(cartridge.read(0xa000), 0xff, "type 0x{:02x}", mbc_type);
+            cartridge.write(0xa000, 0x0c);
+            cartridge.write(0x0000, 0x0a);
+            assert_eq!(
+                cartridge.read(0xa000) & 0x0f,
+                0x05,
+                "type 0x{:02x}",
+                mbc_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_mbc2_ram_upper_nibble() {
+        let mut cartridge = from_bytes(rom_with_header(0x06, 0)).unwrap();
+        cartridge.write(0x0000, 0x0a);
+        cartridge.write(0xa010, 0x3c);
+        assert_eq!(cartridge.read(0xa010), 0xfc);
+    }
+
+    #[test]
+    fn test_rom_ram() {
+        let mut cartridge = from_bytes(rom_with_header(0x09, 0x02)).unwrap();
+        assert_eq!(cartridge.ram().len(), 0x2000);
+        // No enable register to write first
+        cartridge.write(0xa000, 0x12);
+        cartridge.write(0xbfff, 0x34);
+        cartridge.write(0x2000, 0x01);
+        assert_eq!(cartridge.read(0xa000), 0x12);
+        assert_eq!(cartridge.read(0xbfff), 0x34);
+        assert_eq!(cartridge.read(0x4000), 0x00);
+
+        cartridge.reset(false);
+        assert_eq!(cartridge.read(0xa000), 0x12);
+        cartridge.reset(true);
+        assert_eq!(cartridge.read(0xa000), 0x00);
+
+        // 2KB of RAM repeats through the area
+        let mut cartridge = from_bytes(rom_with_header(0x08, 0x01)).unwrap();
+        cartridge.write(0xa000, 0x56);
+        assert_eq!(cartridge.read(0xa800), 0x56);
+    }
+
+    #[test]
+    fn test_truncated_rom() {
+        // 48KB of a 64KB MBC5 ROM, each bank filled with its number
+        let mut rom = rom_with_header(0x19, 0);
+        rom[0x148] = 0x01;
+        fix_checksum(&mut rom);
+        rom.resize(0xc000, 0);
+        rom[0x4000..0x8000].fill(1);
+        rom[0x8000..0xc000].fill(2);
+
+        let report = analyze(&rom).unwrap();
+        assert_eq!(report.declared_rom_size, Some(0x10000));
+        assert_eq!(report.warnings.len(), 1);
+
+        let mut cartridge = from_bytes(rom).unwrap();
+        for (bank, value) in [(1, 1), (2, 2), (3, 0xff), (6, 2)] {
+            cartridge.write(0x2000, bank);
+            assert_eq!(cartridge.read(0x4000), value);
+        }
+    }
+
+    #[test]
+    fn test_checksums() {
+        let mut rom = rom_with_header(0x13, 0x03);
+        rom[0x134..0x139].copy_from_slice(b"HELLO");
+        rom[0x7fff] = 0xff;
+        let report = analyze(&rom).unwrap();
+        assert!(!report.header_checksum_ok);
+        assert!(!report.global_checksum_ok);
+        assert_eq!(report.mbc_type, 0x13);
+        assert_eq!(report.ram_size, Some(32 * 1024));
+
+        fix_checksums(&mut rom);
+        let report = analyze(&rom).unwrap();
+        assert!(report.header_checksum_ok);
+        assert!(report.global_checksum_ok);
+        // Both checksums together with the bytes they cover
+        let sum: u32 =
+            rom.iter().map(|&b| b as u32).sum::<u32>() - rom[0x14e] as u32 - rom[0x14f] as u32;
+        assert_eq!(u16::from_be_bytes([rom[0x14e], rom[0x14f]]), sum as u16);
+    }
+
+    /// A ROM of header size `size_code` where every bank holds its number
+    /// at offset 0x2000 and its inverse in the last byte.
+    fn numbered_rom(mbc_type: u8, size_code: u8) -> Vec<u8> {
+        let mut rom = rom_with_header(mbc_type, 0x03);
+        rom.resize(0x8000 << size_code, 0);
+        for (bank, data) in rom.chunks_mut(0x4000).enumerate() {
+            data[0x2000..0x2002].copy_from_slice(&(bank as u16).to_le_bytes());
+            data[0x3fff] = !bank as u8;
+        }
+        rom[0x148] = size_code;
+        fix_checksum(&mut rom);
+        rom
+    }
+
+    /// Banks seen at 0x0000 and 0x4000 after `writes`, on a fresh cartridge.
+    fn mapped_banks(rom: &[u8], writes: &[(u16, u8)]) -> (usize, usize) {
+        let mut cartridge = from_bytes(rom.to_vec()).unwrap();
+        for &(addr, value) in writes {
+            cartridge.write(addr, value);
+        }
+        let bank_at = |base: u16| {
+            let bank =
+                u16::from_le_bytes([cartridge.read(base + 0x2000), cartridge.read(base + 0x2001)])
+                    as usize;
+            assert_eq!(cartridge.read(base + 0x3fff), !bank as u8);
+            bank
+        };
+        (bank_at(0x0000), bank_at(0x4000))
+    }
+
+    /// Register writes and the banks they map at 0x0000 and 0x4000.
+    type MappingCase<'a> = (&'a [(u16, u8)], (usize, usize));
+
+    fn check_mapping(name: &str, rom: &[u8], cases: &[MappingCase]) {
+        for (writes, banks) in cases {
+            assert_eq!(mapped_banks(rom, writes), *banks, "{} {:x?}", name, writes);
+        }
+    }
+
+    #[test]
+    fn test_mbc1_mapping() {
+        // 2MB, where the upper bits reach banks 0x20-0x7f
+        check_mapping(
+            "MBC1 2MB",
+            &numbered_rom(0x01, 0x06),
+            &[
+                (&[], (0x00, 0x01)),
+                (&[(0x2000, 0x00)], (0x00, 0x01)),
+                (&[(0x2000, 0x1f)], (0x00, 0x1f)),
+                // Only five bits, so this is 0 and maps to 1
+                (&[(0x2000, 0x20)], (0x00, 0x01)),
+                (&[(0x4000, 0x01), (0x2000, 0x00)], (0x00, 0x21)),
+                (&[(0x4000, 0x02), (0x2000, 0x00)], (0x00, 0x41)),
+                (&[(0x4000, 0x03), (0x2000, 0x1f)], (0x00, 0x7f)),
+                (&[(0x4000, 0x07), (0x2000, 0x01)], (0x00, 0x61)),
+                // Mode 1 moves bank 0 and keeps the upper bits at 0x4000
+                (
+                    &[(0x6000, 0x01), (0x4000, 0x02), (0x2000, 0x05)],
+                    (0x40, 0x45),
+                ),
+                (
+                    &[(0x6000, 0x01), (0x4000, 0x03), (0x2000, 0x00)],
+                    (0x60, 0x61),
+                ),
+                (
+                    &[(0x6000, 0x01), (0x6000, 0x00), (0x4000, 0x01)],
+                    (0x00, 0x21),
+                ),
+            ],
+        );
+        // 256KB, where the upper bits are not connected
+        check_mapping(
+            "MBC1 256KB",
+            &numbered_rom(0x01, 0x03),
+            &[
+                (&[(0x2000, 0x0f)], (0x00, 0x0f)),
+                (&[(0x2000, 0x11)], (0x00, 0x01)),
+                (&[(0x2000, 0x10)], (0x00, 0x00)),
+                (
+                    &[(0x6000, 0x01), (0x4000, 0x01), (0x2000, 0x02)],
+                    (0x00, 0x02),
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_mbc1_ram_banks() {
+        let mut cartridge = from_bytes(rom_with_header(0x03, 0x03)).unwrap();
+        cartridge.write(0x0000, 0x0a);
+        cartridge.write(0x6000, 0x01);
+        for bank in 0..4 {
+            cartridge.write(0x4000, bank);
+            cartridge.write(0xa000, 0x10 + bank);
+        }
+        assert_eq!(cartridge.ram()[0x6000], 0x13);
+        assert_eq!(cartridge.read(0xa000), 0x13);
+        // Mode 0 always uses RAM bank 0
+        cartridge.write(0x6000, 0x00);
+        assert_eq!(cartridge.read(0xa000), 0x10);
+    }
+
+    #[test]
+    fn test_mbc2_mapping() {
+        check_mapping(
+            "MBC2",
+            &numbered_rom(0x05, 0x03),
+            &[
+                (&[], (0x00, 0x01)),
+                (&[(0x2100, 0x00)], (0x00, 0x01)),
+                (&[(0x2100, 0x0f)], (0x00, 0x0f)),
+                (&[(0x3100, 0x13)], (0x00, 0x03)),
+                // Address bit 8 decides, not the 0x2000 boundary
+                (&[(0x0100, 0x05)], (0x00, 0x05)),
+                (&[(0x2000, 0x05)], (0x00, 0x01)),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_mbc3_mapping() {
+        check_mapping(
+            "MBC3 2MB",
+            &numbered_rom(0x11, 0x06),
+            &[
+                (&[], (0x00, 0x01)),
+                (&[(0x2000, 0x00)], (0x00, 0x01)),
+                (&[(0x2000, 0x20)], (0x00, 0x20)),
+                (&[(0x2000, 0x7f)], (0x00, 0x7f)),
+                (&[(0x2000, 0x80)], (0x00, 0x01)),
+                // Neither the RAM bank nor the latch moves ROM
+                (
+                    &[(0x2000, 0x12), (0x4000, 0x03), (0x6000, 0x01)],
+                    (0x00, 0x12),
+                ),
+            ],
+        );
+        // MBC30 uses the top bit
+        check_mapping(
+            "MBC30 4MB",
+            &numbered_rom(0x11, 0x07),
+            &[
+                (&[(0x2000, 0x7f)], (0x00, 0x7f)),
+                (&[(0x2000, 0x80)], (0x00, 0x80)),
+                (&[(0x2000, 0xff)], (0x00, 0xff)),
+                (&[(0x2000, 0x00)], (0x00, 0x01)),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_mbc30_ram_banks() {
+        let mut cartridge = from_bytes(rom_with_header(0x13, 0x05)).unwrap();
+        assert_eq!(cartridge.ram().len(), 0x10000);
+        cartridge.write(0x0000, 0x0a);
+        for bank in 0..8 {
+            cartridge.write(0x4000, bank);
+            cartridge.write(0xa000, 0x10 + bank);
+        }
+        for bank in 0..8 {
+            cartridge.write(0x4000, bank);
+            assert_eq!(cartridge.read(0xa000), 0x10 + bank);
+            assert_eq!(cartridge.ram()[bank as usize * 0x2000], 0x10 + bank);
+        }
+
+        // Plain MBC3 has only 4 banks, nothing is mapped to the others
+        let mut cartridge = from_bytes(rom_with_header(0x13, 0x03)).unwrap();
+        cartridge.write(0x0000, 0x0a);
+        cartridge.write(0x4000, 0x04);
+        cartridge.write(0xa000, 0x12);
+        assert_eq!(cartridge.read(0xa000), 0xff);
+        assert!(cartridge.ram().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_mbc5_mapping() {
+        check_mapping(
+            "MBC5 8MB",
+            &numbered_rom(0x19, 0x08),
+            &[
+                (&[], (0x000, 0x001)),
+                // Bank 0 can be mapped twice
+                (&[(0x2000, 0x00)], (0x000, 0x000)),
+                (&[(0x2000, 0xff)], (0x000, 0x0ff)),
+                (&[(0x3000, 0x01), (0x2000, 0x00)], (0x000, 0x100)),
+                (&[(0x3000, 0x01), (0x2000, 0xff)], (0x000, 0x1ff)),
+                (&[(0x3000, 0xff), (0x2000, 0x01)], (0x000, 0x101)),
+                (
+                    &[(0x2000, 0x12), (0x3000, 0x01), (0x3000, 0x00)],
+                    (0x000, 0x012),
+                ),
+                (&[(0x2fff, 0x34), (0x3fff, 0x01)], (0x000, 0x134)),
+            ],
+        );
+        // Bank numbers wrap at the ROM size
+        check_mapping(
+            "MBC5 1MB",
+            &numbered_rom(0x19, 0x05),
+            &[(&[(0x3000, 0x01), (0x2000, 0x45)], (0x00, 0x05))],
+        );
+    }
+
+    #[test]
+    fn test_bank_registers() {
+        let banked = |mbc_type: u8| {
+            let cartridge = from_bytes(rom_with_header(mbc_type, 0x00)).unwrap();
+            [0x0000, 0x0100, 0x2000, 0x2100, 0x4000, 0x6000]
+                .iter()
+                .map(|&addr| cartridge.is_bank_register(addr))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(banked(0x00), [false; 6]);
+        assert_eq!(banked(0x01), [false, false, true, true, true, false]);
+        assert_eq!(banked(0x05), [false, true, false, true, false, false]);
+        assert_eq!(banked(0x13), [false, false, true, true, true, false]);
+        assert_eq!(banked(0x19), [false, false, true, true, true, false]);
+    }
+
+    #[test]
+    fn test_mbc3_unmapped_ram_bank() {
+        let mut cartridge = from_bytes(rom_with_header(0x13, 0x03)).unwrap();
+        cartridge.write(0x0000, 0x0a);
+        cartridge.write(0x4000, 0x05);
+        cartridge.write(0xa000, 0x12);
+        assert_eq!(cartridge.read(0xa000), 0xff);
+    }
+}
@@ -1,12 +1,39 @@
-use std::fmt;
-
-use log::debug;
-
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::Read;
+
+use log::{debug, warn};
+
+use crate::accuracy::AccuracyOptions;
+use crate::bess;
+use crate::bus::{Bus, FlatBus};
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::cycles;
+use crate::input::{InputProvider, Players, PollPoint, PollRate};
+use crate::joypad::ButtonState;
 use crate::mmu::Mmu;
+use crate::ppu::DOTS_PER_LINE;
 use crate::register::Register;
-use crate::utils::get_addr_from_registers;
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::stats::Stats;
+use crate::utils::{fnv1a_64, get_addr_from_registers};
+
+/// Log target for instruction tracing.
+const LOG_TARGET: &str = "gb::cpu";
+
+/// Upper bound for a single HALT skip, so callers still see regular steps.
+const MAX_HALT_SKIP: u32 = DOTS_PER_LINE as u32;
+
+/// Scanlines in a frame, 144 drawn and 10 of VBlank.
+pub const LINES_PER_FRAME: u32 = 154;
+/// CPU cycles per frame, 154 lines of 456 dots.
+pub const CYCLES_PER_FRAME: u32 = DOTS_PER_LINE as u32 * LINES_PER_FRAME;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Interrupt {
     VBlank,
     LCDStat,
@@ -33,7 +60,62 @@ impl fmt::Display for CcFlag {
     }
 }
 
-pub struct Cpu {
+/// Register file as seen by the program, with the flags packed into F.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+/// Frames `Cpu::run_frames` returns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeepFrames {
+    All,
+    /// Only the last, e.g. for agents that look at one frame per step.
+    Last,
+}
+
+/// What the machine put out over a `Cpu::run_frames`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BatchOutput {
+    /// RGB24 frames as `Ppu::get_frame` gives them, back to back.
+    pub frames: Vec<u8>,
+    /// Interleaved stereo samples, once `Apu::set_sample_rate` set a rate.
+    pub audio: Vec<i16>,
+    /// Bytes the game sent on the link port.
+    pub serial: Vec<u8>,
+    /// Instructions executed.
+    pub steps: u64,
+}
+
+/// A way the game can never go on from, short of a reset, see `Cpu::hang`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hang {
+    /// HALT with no interrupt enabled in IE to wake it up.
+    HaltWithoutInterrupts,
+    /// `jr -2` jumping to itself with interrupts disabled, or none enabled
+    /// in IE.
+    SelfLoop,
+}
+
+impl fmt::Display for Hang {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hang::HaltWithoutInterrupts => write!(f, "HALT with no interrupts enabled"),
+            Hang::SelfLoop => write!(f, "jr -2 loop with no interrupts enabled"),
+        }
+    }
+}
+
+pub struct Cpu<B: Bus = Mmu> {
     a: u8,
     f: u8,
     b: u8,
@@ -49,15 +131,380 @@ pub struct Cpu {
     half_carry_flag: bool,
     carry_flag: bool,
 
-    pub mmu: Mmu,
+    pub mmu: B,
     clock: u32,
     ime: bool,
     halt: bool,
-    total_elapsed_clock: u32, // for debug
+    /// Set by an illegal opcode, which hangs the CPU until reset.
+    locked: bool,
+    halt_skip: bool,
+    /// Cycles run since power-on or reset, see `cycles`.
+    elapsed_cycles: u64,
+    /// Instructions executed, for `Stats`
+    instructions: u64,
+    /// Cycles run, for `Stats`
+    stats_cycles: u64,
+    /// Frames run by `run_frame_with` since power-on or reset.
+    frames: u64,
+    /// Registers `reset` leaves, see `set_start_registers`.
+    start_registers: Registers,
+    /// Cycles into the current frame, so a frame left mid-way, e.g. by a
+    /// savestate, carries on with the same scanline count.
+    frame_cycles: u32,
 }
 
 impl Cpu {
+    #[cfg(feature = "std")]
     pub fn new(cartridge_name: &str) -> Self {
+        Cpu::with_bus(Mmu::new(cartridge_name))
+    }
+
+    /// Creates a `Cpu` for a ROM image already in memory, see `Mmu::with_rom`.
+    pub fn with_rom(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        Ok(Cpu::with_bus(Mmu::with_rom(rom)?))
+    }
+
+    /// Creates a `Cpu` for a ROM image read from `reader`, see `Mmu::from_reader`.
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, CartridgeError> {
+        Ok(Cpu::with_bus(Mmu::from_reader(reader)?))
+    }
+
+    /// Restarts the game without reloading the cartridge.
+    ///
+    /// A soft reset keeps cartridge RAM, a hard reset clears it as well.
+    pub fn reset(&mut self, hard: bool) {
+        self.mmu.reset(hard);
+
+        self.set_registers(self.start_registers);
+
+        self.clock = 0;
+        self.ime = false;
+        self.halt = false;
+        self.locked = false;
+        self.elapsed_cycles = 0;
+        self.frames = 0;
+        self.frame_cycles = 0;
+    }
+
+    /// Switches to another game without building a new `Cpu`, e.g. in a
+    /// launcher: puts `cartridge` in and restarts, with the settings of the
+    /// machine kept. The new cartridge keeps the RAM it came with.
+    ///
+    /// Returns the old cartridge, e.g. to write its battery save. See
+    /// `Mmu::swap_cartridge` to swap without restarting.
+    pub fn change_cartridge(&mut self, cartridge: Box<dyn Cartridge>) -> Box<dyn Cartridge> {
+        let old = self.mmu.swap_cartridge(cartridge);
+        self.reset(false);
+        self.reset_stats();
+        old
+    }
+
+    /// Sets every option that trades accuracy for speed at once, e.g. to
+    /// `Accuracy::Fast.options()` right after creating the `Cpu`. The
+    /// options stay across resets and cartridge changes.
+    pub fn set_accuracy(&mut self, options: AccuracyOptions) {
+        self.set_halt_skip(options.halt_skip);
+        self.mmu.ppu.set_stat_write_bug(options.stat_write_bug);
+        self.mmu.ppu.set_oam_bug(options.oam_bug);
+    }
+
+    pub fn accuracy(&self) -> AccuracyOptions {
+        AccuracyOptions {
+            halt_skip: self.halt_skip,
+            stat_write_bug: self.mmu.ppu.stat_write_bug(),
+            oam_bug: self.mmu.ppu.oam_bug(),
+        }
+    }
+
+    /// Runs the rest of the frame: until the PPU enters VBlank, or up to
+    /// one frame worth of cycles while the LCD is off. Calls `on_line` with
+    /// the number of scanlines into the frame as it starts, 0, and each
+    /// time another one has passed, up to 153. Returns the number of
+    /// instructions executed.
+    pub fn run_frame_with(&mut self, mut on_line: impl FnMut(&mut Cpu, u32)) -> u64 {
+        let mut steps = 1;
+        while !self.frame_step(&mut on_line) {
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Runs the rest of the frame like `run_frame_with`, handing the joypad
+    /// the buttons `input` gives at the points `rate` sets.
+    pub fn run_frame(&mut self, input: &mut dyn InputProvider, rate: PollRate) -> u64 {
+        let mut steps = 1;
+        while !self.step_frame(input, rate) {
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Runs the rest of the frame like `run_frame_with`, drawing it into
+    /// `buffer`, 160x144 RGB565 pixels, as it goes. See `Ppu::render_into`.
+    #[cfg(feature = "rgb565")]
+    pub fn run_frame_into(&mut self, buffer: &mut [u16]) -> u64 {
+        let mut steps = 1;
+        while !self.frame_step(&mut |_, _| {}) {
+            self.mmu.ppu.render_into(buffer);
+            steps += 1;
+        }
+        self.mmu.ppu.render_into(buffer);
+        steps
+    }
+
+    /// Runs a single instruction of the frame `run_frame` would run,
+    /// polling `input` on the way as it would. Returns whether the frame
+    /// ended.
+    ///
+    /// TAS tools step this way to save states between any two
+    /// instructions; after loading one, `run_frame` finishes the frame with
+    /// the same polls as the run the state came from.
+    pub fn step_frame(&mut self, input: &mut dyn InputProvider, rate: PollRate) -> bool {
+        let frame = self.frames;
+        self.frame_step(&mut |cpu: &mut Cpu, line| {
+            if rate.polls_at(line) {
+                cpu.apply_input(input.poll(PollPoint { frame, line }));
+            }
+        })
+    }
+
+    /// Runs `n` frames in one call, like `run_frame_with` would one at a
+    /// time, for batch and server use (e.g. training agents to play) that
+    /// would pay the overhead of a call per frame otherwise. The first
+    /// player holds `inputs[i]` through frame `i`, or the last of `inputs`
+    /// once they run out; with none the joypad is left as it is.
+    ///
+    /// Audio samples and logged serial bytes not taken before the call
+    /// come along in the output.
+    pub fn run_frames(
+        &mut self,
+        n: usize,
+        inputs: &[ButtonState],
+        keep: KeepFrames,
+    ) -> BatchOutput {
+        let logging = self.mmu.is_serial_logging();
+        if !logging {
+            self.mmu.start_serial_log();
+        }
+        let mut output = BatchOutput::default();
+        for i in 0..n {
+            if let Some(&buttons) = inputs.get(i).or_else(|| inputs.last()) {
+                self.mmu.joypad.set_player_state(0, buttons);
+            }
+            output.steps += self.run_frame_with(|_, _| {});
+            if keep == KeepFrames::All || i + 1 == n {
+                output.frames.extend_from_slice(self.mmu.ppu.get_frame());
+            }
+        }
+        output.audio = self.mmu.take_audio_samples();
+        output.serial = self.mmu.take_serial_bytes();
+        if !logging {
+            self.mmu.stop_serial_log();
+        }
+        output
+    }
+
+    /// Frames run since power-on or reset.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Cycles run so far in the current frame.
+    pub fn frame_cycles(&self) -> u32 {
+        self.frame_cycles
+    }
+
+    /// Runs one instruction, calling `on_line` as the frame starts and each
+    /// time a scanline has passed. Returns whether the frame ended.
+    fn frame_step(&mut self, on_line: &mut impl FnMut(&mut Cpu, u32)) -> bool {
+        if self.frame_cycles == 0 {
+            on_line(self, 0);
+        }
+        let line = self.frame_cycles / DOTS_PER_LINE as u32;
+        self.frame_cycles += self.step() as u32;
+        // Frames start right after VBlank, so with the LCD on the next one
+        // always comes before the cap
+        if self.mmu.ppu.take_vblank_entered() || self.frame_cycles >= CYCLES_PER_FRAME {
+            self.frames += 1;
+            self.frame_cycles = 0;
+            return true;
+        }
+        let new_line = self.frame_cycles / DOTS_PER_LINE as u32;
+        if new_line != line {
+            on_line(self, new_line);
+        }
+        false
+    }
+
+    fn apply_input(&mut self, players: Players) {
+        for (player, buttons) in players.iter().enumerate() {
+            self.mmu.joypad.set_player_state(player, *buttons);
+        }
+    }
+
+    /// Counters of what the machine did since power-on or `reset_stats`.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            instructions: self.instructions,
+            cycles: self.stats_cycles,
+            ..self.mmu.stats
+        }
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.instructions = 0;
+        self.stats_cycles = 0;
+        self.mmu.stats = Stats::default();
+    }
+
+    /// Identifies the loaded game as `TITLE-hash`, e.g. for per-game save directories.
+    pub fn game_id(&self) -> String {
+        format!(
+            "{}-{:016x}",
+            self.mmu.cartridge.title(),
+            self.mmu.cartridge.rom_hash()
+        )
+    }
+
+    /// Hash of the current frame, see `Ppu::frame_hash`.
+    pub fn frame_hash(&self) -> u64 {
+        self.mmu.ppu.frame_hash()
+    }
+
+    /// Hash of each component's part of the state, in the order of the
+    /// state, e.g. for two linked machines to tell whether and where they
+    /// drifted apart. The frame is left out, since it depends on the
+    /// palette, and so is the wall-clock start of the RTC.
+    pub fn state_hashes(&self) -> Vec<(&'static str, u64)> {
+        let w = self.write_state();
+        let sections = w.sections();
+        let data = w.into_bytes();
+        let mut parts: Vec<(&'static str, Vec<u8>)> = Vec::new();
+        for (name, range) in sections {
+            if matches!(name, "header" | "frame" | "rtc-base") {
+                continue;
+            }
+            match parts.iter_mut().find(|(part, _)| *part == name) {
+                Some((_, bytes)) => bytes.extend_from_slice(&data[range]),
+                None => parts.push((name, data[range].to_vec())),
+            }
+        }
+        parts
+            .into_iter()
+            .map(|(name, bytes)| (name, fnv1a_64(&bytes)))
+            .collect()
+    }
+
+    /// Serializes the whole machine state.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.write_state().into_bytes()
+    }
+
+    /// The `StateWriter` behind `save_state`, with its sections marked.
+    pub(crate) fn write_state(&self) -> StateWriter {
+        let mut w = StateWriter::new();
+        w.begin_section("header");
+        w.write_header(self.mmu.cartridge.rom_hash());
+        w.begin_section("cpu");
+        w.write_u8(self.a);
+        w.write_u8(self.f);
+        w.write_u8(self.b);
+        w.write_u8(self.c);
+        w.write_u8(self.d);
+        w.write_u8(self.e);
+        w.write_u8(self.h);
+        w.write_u8(self.l);
+        w.write_u16(self.sp);
+        w.write_u16(self.pc);
+        w.write_bool(self.zero_flag);
+        w.write_bool(self.subtraction_flag);
+        w.write_bool(self.half_carry_flag);
+        w.write_bool(self.carry_flag);
+        w.write_u32(self.clock);
+        w.write_bool(self.ime);
+        w.write_bool(self.halt);
+        w.write_bool(self.locked);
+        w.write_u64(self.elapsed_cycles);
+        w.write_u64(self.frames);
+        w.write_u32(self.frame_cycles);
+        self.mmu.save_state(&mut w);
+        w
+    }
+
+    /// Serializes the machine like `save_state` and appends BESS blocks, so
+    /// other emulators can load the result as well.
+    pub fn save_state_bess(&self) -> Vec<u8> {
+        let mut data = self.save_state();
+        bess::append(self, &mut data);
+        data
+    }
+
+    /// Restores the BESS blocks of a state written by any BESS emulator.
+    ///
+    /// Whatever the blocks do not describe, such as the exact PPU and timer
+    /// position, restarts from power-on.
+    pub fn load_state_bess(&mut self, data: &[u8]) -> Result<(), StateError> {
+        bess::load(self, data)
+    }
+
+    /// Restores a state produced by `save_state` for the same ROM.
+    ///
+    /// The machine is left untouched if the state does not load.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(data);
+        r.read_header(self.mmu.cartridge.rom_hash())?;
+        // Components take their part as it is read, so a state found
+        // corrupt halfway is undone with the one from before
+        let backup = self.save_state();
+        if let Err(e) = self.read_state(&mut r) {
+            let mut r = StateReader::new(&backup);
+            r.read_header(self.mmu.cartridge.rom_hash())
+                .and_then(|_| self.read_state(&mut r))
+                .expect("a saved state loads back");
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The part of `load_state` after the header.
+    fn read_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.a = r.read_u8()?;
+        self.f = r.read_u8()?;
+        self.b = r.read_u8()?;
+        self.c = r.read_u8()?;
+        self.d = r.read_u8()?;
+        self.e = r.read_u8()?;
+        self.h = r.read_u8()?;
+        self.l = r.read_u8()?;
+        self.sp = r.read_u16()?;
+        self.pc = r.read_u16()?;
+        self.zero_flag = r.read_bool()?;
+        self.subtraction_flag = r.read_bool()?;
+        self.half_carry_flag = r.read_bool()?;
+        self.carry_flag = r.read_bool()?;
+        self.clock = r.read_u32()?;
+        self.ime = r.read_bool()?;
+        self.halt = r.read_bool()?;
+        self.locked = r.read_bool()?;
+        self.elapsed_cycles = r.read_u64()?;
+        self.frames = r.read_u64()?;
+        self.frame_cycles = r.read_u32()?;
+        self.mmu.load_state(r)
+    }
+}
+
+impl Cpu<FlatBus> {
+    /// Creates a `Cpu` on top of `FlatBus`, for running single instructions
+    /// against plain RAM.
+    pub fn with_flat_ram() -> Self {
+        Cpu::with_bus(FlatBus::new())
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    /// Creates a `Cpu` on top of any bus.
+    pub fn with_bus(bus: B) -> Self {
         Cpu {
             a: 0,
             f: 0,
@@ -74,14 +521,115 @@ impl Cpu {
             half_carry_flag: false,
             carry_flag: false,
 
-            mmu: Mmu::new(cartridge_name),
+            mmu: bus,
             clock: 0,
             ime: false,
             halt: false,
-            total_elapsed_clock: 0,
+            locked: false,
+            halt_skip: true,
+            elapsed_cycles: 0,
+            instructions: 0,
+            stats_cycles: 0,
+            frames: 0,
+            frame_cycles: 0,
+            start_registers: Registers {
+                pc: 0x100,
+                ..Registers::default()
+            },
+        }
+    }
+
+    /// Clock cycles (T-cycles) run since power-on or reset. Unlike the
+    /// internal clock it never wraps, so it works as a time base for
+    /// embedders, e.g. to timestamp events.
+    pub fn cycles(&self) -> u64 {
+        self.elapsed_cycles
+    }
+
+    /// Whether an illegal opcode hung the CPU. The PC is left just past
+    /// the opcode until a reset.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Whether the game is stuck in a classic hang, e.g. a crash that ended
+    /// in an endless loop. Checked from the current state alone, so it is
+    /// cheap enough to ask once a frame; an illegal opcode is `is_locked`.
+    pub fn hang(&self) -> Option<Hang> {
+        let no_interrupts = self.mmu.peek(0xffff) & 0x1f == 0;
+        if self.halt && no_interrupts {
+            Some(Hang::HaltWithoutInterrupts)
+        } else if !self.halt
+            && (!self.ime || no_interrupts)
+            && self.mmu.peek(self.pc) == 0x18
+            && self.mmu.peek(self.pc.wrapping_add(1)) == 0xfe
+        {
+            Some(Hang::SelfLoop)
+        } else {
+            None
         }
     }
 
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.a,
+            f: self.get_byte_from_flags(),
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp,
+            pc: self.pc,
+        }
+    }
+
+    pub fn set_registers(&mut self, regs: Registers) {
+        self.a = regs.a;
+        self.set_flags_from_byte(regs.f);
+        self.b = regs.b;
+        self.c = regs.c;
+        self.d = regs.d;
+        self.e = regs.e;
+        self.h = regs.h;
+        self.l = regs.l;
+        self.sp = regs.sp;
+        self.pc = regs.pc;
+    }
+
+    /// Sets the registers the program starts with now and after every
+    /// `reset`, e.g. from a `quirks::Quirk`. They are all zero but the PC,
+    /// 0x100, unless set.
+    pub fn set_start_registers(&mut self, regs: Registers) {
+        self.start_registers = regs;
+        self.set_registers(regs);
+    }
+
+    /// Interrupt master enable.
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn set_ime(&mut self, ime: bool) {
+        self.ime = ime;
+    }
+
+    pub(crate) fn halted(&self) -> bool {
+        self.halt
+    }
+
+    pub(crate) fn set_halted(&mut self, halted: bool) {
+        self.halt = halted;
+    }
+
+    /// Enables jumping straight to the next timer or PPU event while halted.
+    ///
+    /// On by default. When disabled the halted CPU advances 4 cycles per step.
+    pub fn set_halt_skip(&mut self, enabled: bool) {
+        self.halt_skip = enabled;
+    }
+
     fn get_f_num(&self) -> u8 {
         let mut res: u8 = 0;
         if self.zero_flag {
@@ -103,10 +651,12 @@ impl Cpu {
         let pc = self.pc;
         let opcode = self.mmu.read_byte(pc);
         debug!(
+            target: LOG_TARGET,
             "PC: 0x{:04x}, opcode: 0x{:04x}, sp: 0x{:04x}",
             pc, opcode, self.sp
         );
         debug!(
+            target: LOG_TARGET,
             "a: 0x{:02x}, f: {:02x}, b: 0x{:02x}, c: 0x{:02x}",
             self.a,
             self.get_f_num(),
@@ -114,43 +664,65 @@ impl Cpu {
             self.c
         );
         debug!(
+            target: LOG_TARGET,
             "d: 0x{:02x}, e: 0x{:02x}, h: 0x{:02x}, l: 0x{:02x}",
             self.d, self.e, self.h, self.l
         );
-        debug!("halted: {}", self.halt);
+        debug!(target: LOG_TARGET, "halted: {}", self.halt);
 
         let mut elapse_clock = 0;
-        if self.halt {
+        if self.locked {
             elapse_clock += 4;
             self.add_clock(4);
+        } else if self.halt {
+            let cycles = self.halt_cycles();
+            elapse_clock += cycles;
+            self.add_clock(cycles);
         } else {
             self.add_program_count(1);
             let before_clock = self.clock;
             self.exec(opcode);
+            self.instructions += 1;
             let after_clock = self.clock;
             elapse_clock = after_clock.wrapping_sub(before_clock);
         }
 
-        self.mmu.update(elapse_clock as u8);
+        self.mmu.update(elapse_clock as u16);
 
         debug!(
-            "ime: {}, interrupt_flag: 0b{:08b}, interrupt_enable: 0b{:08b}",
-            self.ime, self.mmu.interrupt_flag, self.mmu.interrupt_enable
+            target: LOG_TARGET,
+            "ime: {}, pending interrupts: 0b{:08b}",
+            self.ime,
+            self.mmu.pending_interrupts()
         );
 
-        if self.ime {
-            self.handle_interrupt();
-            // self.mmu.update(8);
-            // elapse_clock += 8;
+        if self.ime && !self.locked {
+            elapse_clock += self.handle_interrupt();
         }
 
-        self.total_elapsed_clock += elapse_clock as u32;
-        debug!("total_elapsed_clock: {}", self.clock);
+        self.elapsed_cycles += elapse_clock as u64;
+        self.stats_cycles += elapse_clock as u64;
+        debug!(target: LOG_TARGET, "elapsed cycles: {}", self.elapsed_cycles);
         elapse_clock as u16
     }
 
-    fn handle_interrupt(&mut self) {
-        let interrupt_source = self.mmu.interrupt_flag & self.mmu.interrupt_enable;
+    /// Cycles the halted CPU can skip before an interrupt may be raised.
+    fn halt_cycles(&self) -> u32 {
+        if !self.halt_skip || self.mmu.pending_interrupts() != 0 {
+            return 4;
+        }
+        let cycles = self
+            .mmu
+            .cycles_until_next_event()
+            .map_or(MAX_HALT_SKIP, |c| c.min(MAX_HALT_SKIP as u64) as u32);
+        // Stay on the 4-cycle grid of the normal halt loop
+        ((cycles + 3) & !3).max(4)
+    }
+
+    /// Dispatches the pending interrupt with the highest priority, if any.
+    /// Returns the cycles it took.
+    fn handle_interrupt(&mut self) -> u32 {
+        let interrupt_source = self.mmu.pending_interrupts();
         for bit in 0..=4 {
             let interrupt_type = match interrupt_source & (1 << bit) {
                 0x01 => Interrupt::VBlank,
@@ -162,7 +734,9 @@ impl Cpu {
             };
 
             self.exec_interrupt(interrupt_type);
+            return cycles::INTERRUPT;
         }
+        0
     }
 
     fn exec_interrupt(&mut self, interrupt_type: Interrupt) {
@@ -183,11 +757,11 @@ impl Cpu {
         let pc = self.pc;
 
         self.write_word(sp, pc);
-        self.add_clock(20); // todo
+        self.add_clock(cycles::INTERRUPT);
         self.pc = addr;
 
-        self.mmu.update(20);
-        debug!("Interrupt {:?}, addr: 0x{:04x}", interrupt_type, self.pc);
+        self.mmu.update(cycles::INTERRUPT as u16);
+        debug!(target: LOG_TARGET, "Interrupt {:?}, addr: 0x{:04x}", interrupt_type, self.pc);
     }
 
     /// Put value n into nn.
@@ -198,7 +772,7 @@ impl Cpu {
     fn load_nn_n(&mut self, reg: Register) {
         let pc = self.pc;
         let value = self.mmu.read_byte(pc);
-        debug!("Instruction load_nn_n reg: {}, value: {}", reg, value);
+        debug!(target: LOG_TARGET, "Instruction load_nn_n reg: {}, value: {}", reg, value);
 
         match reg {
             Register::B => self.b = value,
@@ -211,7 +785,6 @@ impl Cpu {
         }
 
         self.add_program_count(1);
-        self.add_clock(8);
     }
 
     /// Put value r2 into r1.
@@ -228,7 +801,7 @@ impl Cpu {
     /// 60, 61, 62, 63, 64, 65, 67       H
     /// 68, 69, 6A, 6B, 6C, 6D, 6F       L
     fn load_r1_r2(&mut self, reg1: Register, reg2: Register) {
-        debug!("Instruction load_r1_r2 r1: {}, r2: {}", reg1, reg2);
+        debug!(target: LOG_TARGET, "Instruction load_r1_r2 r1: {}, r2: {}", reg1, reg2);
 
         let value = match reg2 {
             Register::A => self.a,
@@ -251,8 +824,6 @@ impl Cpu {
             Register::L => self.l = value,
             _ => panic!("Invalid register1 {}", reg1),
         }
-
-        self.add_clock(4);
     }
 
     /// Put value memory8 into r1.
@@ -267,6 +838,7 @@ impl Cpu {
         let value = self.mmu.read_byte(addr);
 
         debug!(
+            target: LOG_TARGET,
             "Instruction load_r1_hl r1: {}, memory8: {}, addr: {}",
             reg1, value, addr
         );
@@ -281,8 +853,6 @@ impl Cpu {
             Register::L => self.l = value,
             _ => panic!("Invalid register1 {}", reg1),
         }
-
-        self.add_clock(8);
     }
 
     /// Put value r1 into memory8.
@@ -309,9 +879,7 @@ impl Cpu {
         };
         self.mmu.write_byte(addr, value);
 
-        debug!("Instruction load_hl_r1 addr: {}, r1: {}", addr, reg1);
-
-        self.add_clock(8);
+        debug!(target: LOG_TARGET, "Instruction load_hl_r1 addr: {}, r1: {}", addr, reg1);
     }
 
     /// Put immediate 8bit into memory8.
@@ -328,10 +896,9 @@ impl Cpu {
         let addr = get_addr_from_registers(high_register, low_register);
         let value = self.mmu.read_byte(pc);
         self.mmu.write_byte(addr, value);
-        debug!("Instruction load_hl_imm hl: {}, value: {}", addr, value);
+        debug!(target: LOG_TARGET, "Instruction load_hl_imm hl: {}, value: {}", addr, value);
 
         self.add_program_count(1);
-        self.add_clock(12);
     }
 
     /// Put value a into nn
@@ -346,9 +913,7 @@ impl Cpu {
         };
         let value = self.a;
         self.mmu.write_byte(addr, value);
-        debug!("Instruction load_nn_a addr: {}, value: {}", addr, value);
-
-        self.add_clock(8);
+        debug!(target: LOG_TARGET, "Instruction load_nn_a addr: {}, value: {}", addr, value);
     }
 
     /// Put value nn into a
@@ -364,9 +929,7 @@ impl Cpu {
         let value = self.mmu.read_byte(addr);
         self.a = value;
 
-        debug!("Instruction load_nn_a addr: {}, value: {}", addr, value);
-
-        self.add_clock(8);
+        debug!(target: LOG_TARGET, "Instruction load_nn_a addr: {}, value: {}", addr, value);
     }
 
     /// Put value a into n
@@ -379,10 +942,9 @@ impl Cpu {
         let value = self.a;
         self.mmu.write_byte(addr, value);
 
-        debug!("Instruction load_imm_a addr: {}, value: {}", addr, value);
+        debug!(target: LOG_TARGET, "Instruction load_imm_a addr: {}, value: {}", addr, value);
 
         self.add_program_count(2);
-        self.add_clock(16);
     }
 
     /// put value d8 into a
@@ -393,10 +955,9 @@ impl Cpu {
         let value = self.mmu.read_byte(addr);
         self.a = value;
 
-        debug!("Instruction load_a_d8 addr: {}, value: {}", addr, value);
+        debug!(target: LOG_TARGET, "Instruction load_a_d8 addr: {}, value: {}", addr, value);
 
         self.add_program_count(1);
-        self.add_clock(8);
     }
 
     /// Put value n into a
@@ -409,10 +970,9 @@ impl Cpu {
         let value = self.mmu.read_byte(addr);
         self.a = value;
 
-        debug!("Instruction load_a_imm addr: {}, value: {}", addr, value);
+        debug!(target: LOG_TARGET, "Instruction load_a_imm addr: {}, value: {}", addr, value);
 
         self.add_program_count(2);
-        self.add_clock(16);
     }
 
     /// Put value at address 0xFF00 + register C into A
@@ -422,10 +982,9 @@ impl Cpu {
         let value = self.mmu.read_byte(addr);
         self.a = value;
 
-        debug!("Instruction load_a_c addr: {}, value: {}", addr, value);
+        debug!(target: LOG_TARGET, "Instruction load_a_c addr: {}, value: {}", addr, value);
 
         // self.add_program_count(1);
-        self.add_clock(8);
     }
 
     /// Put A into address 0xFF00 + register C
@@ -435,10 +994,9 @@ impl Cpu {
         let value = self.a;
         self.mmu.write_byte(addr, value);
 
-        debug!("Instruction load_c_a addr: {}, value: {}", addr, value);
+        debug!(target: LOG_TARGET, "Instruction load_c_a addr: {}, value: {}", addr, value);
 
         // self.add_program_count(1);
-        self.add_clock(8);
     }
 
     /// Put value a into address HL.
@@ -450,6 +1008,7 @@ impl Cpu {
         let addr = get_addr_from_registers(high_register, low_register);
         let value = self.a;
         debug!(
+            target: LOG_TARGET,
             "Instruction load_hli_a addr: 0x{:04x}, value: 0x{:04x}",
             addr, value
         );
@@ -459,8 +1018,6 @@ impl Cpu {
         if self.l == 0 {
             self.h = self.h.wrapping_add(1);
         }
-
-        self.add_clock(8);
     }
 
     /// Put value a into address HL
@@ -476,9 +1033,7 @@ impl Cpu {
             self.h = self.h.wrapping_sub(1);
         }
 
-        debug!("Instruction load_hld_a addr: {}, value: {}", addr, value);
-
-        self.add_clock(8);
+        debug!(target: LOG_TARGET, "Instruction load_hld_a addr: {}, value: {}", addr, value);
     }
 
     /// Put value at address HL into a
@@ -495,9 +1050,7 @@ impl Cpu {
             self.h = self.h.wrapping_add(1);
         }
 
-        debug!("Instruction load_a_hli addr: {}", addr);
-
-        self.add_clock(8);
+        debug!(target: LOG_TARGET, "Instruction load_a_hli addr: {}", addr);
     }
 
     /// Put value at address HL into a
@@ -514,9 +1067,7 @@ impl Cpu {
             self.h = self.h.wrapping_sub(1);
         }
 
-        debug!("Instruction load_a_hli addr: {}", addr);
-
-        self.add_clock(8);
+        debug!(target: LOG_TARGET, "Instruction load_a_hli addr: {}", addr);
     }
 
     /// Put A into memory address $FF00 + n
@@ -527,11 +1078,10 @@ impl Cpu {
         let n = self.mmu.read_byte(pc);
         let addr = 0xFF00 + n as u16;
         let value = self.a;
-        debug!("Instruction load_n_a addr: {:0x}, value: {}", addr, value);
+        debug!(target: LOG_TARGET, "Instruction load_n_a addr: {:0x}, value: {}", addr, value);
         self.mmu.write_byte(addr, value);
 
         self.add_program_count(1);
-        self.add_clock(12);
     }
 
     /// Put memory address $FF00 + n into A
@@ -541,12 +1091,11 @@ impl Cpu {
         let pc = self.pc;
         let n = self.mmu.read_byte(pc);
         let addr = 0xFF00 + n as u16;
-        debug!("Instruction load_a_n addr: 0x{:0x}", addr);
+        debug!(target: LOG_TARGET, "Instruction load_a_n addr: 0x{:0x}", addr);
         let value = self.mmu.read_byte(addr);
         self.a = value;
 
         self.add_program_count(1);
-        self.add_clock(12);
     }
 
     /// Put value nn into n.
@@ -556,7 +1105,7 @@ impl Cpu {
     fn load_n_nn(&mut self, reg: Register) {
         let pc = self.pc;
         let low_value = self.mmu.read_byte(pc);
-        let high_value = self.mmu.read_byte(pc + 1);
+        let high_value = self.mmu.read_byte(pc.wrapping_add(1));
 
         match reg {
             Register::BC => {
@@ -578,21 +1127,19 @@ impl Cpu {
         }
 
         debug!(
+            target: LOG_TARGET,
             "Instruction load_n_nn high_value: 0x{:0x}, low_value: 0x{:0x}, register: {}",
             high_value, low_value, reg
         );
 
         self.add_program_count(2);
-        self.add_clock(12);
     }
 
     /// Put HL into SP
     /// Opcode for F9
     fn load_sp_hl(&mut self) {
         self.sp = ((self.h as u16) << 8) + self.l as u16;
-        debug!("Instruction load_sp_hl");
-
-        self.add_clock(8);
+        debug!(target: LOG_TARGET, "Instruction load_sp_hl");
     }
 
     /// Put SP + n effective address into HL.
@@ -616,7 +1163,7 @@ impl Cpu {
         self.h = (value >> 8) as u8;
         self.l = value as u8;
 
-        debug!("Instruction load_sp_n sp: {}, n: {}", sp, n as i8);
+        debug!(target: LOG_TARGET, "Instruction load_sp_n sp: {}, n: {}", sp, n as i8);
 
         let half_carry_flag = (sp & 0x0f) + (n & 0x0f) > 0x0f;
         let carry_flag = (sp & 0xff) + (n & 0xff) > 0xff;
@@ -627,7 +1174,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        self.add_clock(12);
     }
 
     /// Put SP at address nn
@@ -640,10 +1186,9 @@ impl Cpu {
 
         self.write_word(addr, sp);
 
-        debug!("Instruction load_nn_sp addr: {}, sp: {}", addr, sp);
+        debug!(target: LOG_TARGET, "Instruction load_nn_sp addr: {}, sp: {}", addr, sp);
 
         self.add_program_count(2);
-        self.add_clock(20);
     }
 
     /// Push register pair nn onto stack.
@@ -651,7 +1196,7 @@ impl Cpu {
     /// nn = AF, BC, DE, HL
     /// Opcode for F5, C5, D5, E5
     fn push_nn(&mut self, reg1: Register, reg2: Register) {
-        debug!("Instruction Push {}{}", reg1, reg2);
+        debug!(target: LOG_TARGET, "Instruction Push {}{}", reg1, reg2);
 
         self.sp = self.sp.wrapping_sub(2);
 
@@ -667,8 +1212,6 @@ impl Cpu {
         let value = ((high_value as u16) << 8) | low_value as u16;
 
         self.write_word(addr, value);
-
-        self.add_clock(16);
     }
 
     /// Pop tow bytes off stack into register pair nn.
@@ -677,11 +1220,12 @@ impl Cpu {
     /// Opcode for F1, C1, D1, E1
     fn pop_nn(&mut self, reg1: Register, reg2: Register) {
         let low_value = self.mmu.read_byte(self.sp);
-        self.sp += 1;
+        self.sp = self.sp.wrapping_add(1);
         let high_value = self.mmu.read_byte(self.sp);
-        self.sp += 1;
+        self.sp = self.sp.wrapping_add(1);
 
         debug!(
+            target: LOG_TARGET,
             "Instruction Pop {}{}, high_value: 0x{:04x}, low_value: 0x{:04x}",
             reg1, reg2, high_value, low_value
         );
@@ -705,8 +1249,6 @@ impl Cpu {
             }
             _ => panic!("Invalid register {} {}", reg1, reg2),
         }
-
-        self.add_clock(12);
     }
 
     /// Add register n value to A.
@@ -720,7 +1262,7 @@ impl Cpu {
     ///
     /// Opcode for 87, 80, 81, 82, 83, 84, 85
     fn add_a_r(&mut self, reg: Register) {
-        debug!("Instruction add_a_r reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction add_a_r reg: {}", reg);
 
         let value = match reg {
             Register::A => self.a,
@@ -742,8 +1284,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(half_carry_flag);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(4);
     }
 
     /// Add HL value to A
@@ -756,7 +1296,7 @@ impl Cpu {
     ///
     /// Opcode for 86
     fn add_a_hl(&mut self) {
-        debug!("Instruction add_a_hl");
+        debug!(target: LOG_TARGET, "Instruction add_a_hl");
 
         let addr = get_addr_from_registers(self.h, self.l);
         let value = self.mmu.read_byte(addr);
@@ -770,8 +1310,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(half_carry_flag);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(8);
     }
 
     /// Add d8 to A
@@ -784,7 +1322,7 @@ impl Cpu {
     ///
     /// Opcode for C6
     fn add_a_d8(&mut self) {
-        debug!("Instruction add_a_d8");
+        debug!(target: LOG_TARGET, "Instruction add_a_d8");
 
         let addr = self.pc;
         let value = self.mmu.read_byte(addr);
@@ -800,7 +1338,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        self.add_clock(8);
     }
 
     /// Add n + Carry flag to A
@@ -814,7 +1351,7 @@ impl Cpu {
     ///
     /// Opcode for 8F, 88, 89, 8A, 8B, 8C, 8D
     fn adc_a_n(&mut self, reg: Register) {
-        debug!("Instruction adc_a_n reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction adc_a_n reg: {}", reg);
 
         let c = if self.carry_flag { 1 } else { 0 };
 
@@ -839,8 +1376,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(half_carry_flag);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(4);
     }
 
     /// Add HL value + Carry flag to A
@@ -853,7 +1388,7 @@ impl Cpu {
     ///
     /// Opcode for 8E
     fn adc_a_hl(&mut self) {
-        debug!("Instruction adc_a_hl");
+        debug!(target: LOG_TARGET, "Instruction adc_a_hl");
 
         let c = if self.carry_flag { 1 } else { 0 };
 
@@ -870,8 +1405,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(half_carry_flag);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(8);
     }
 
     /// Add d8 + Carry flag to A
@@ -884,7 +1417,7 @@ impl Cpu {
     ///
     /// Opcode for CE
     fn adc_a_d8(&mut self) {
-        debug!("Instruction adc_a_d8");
+        debug!(target: LOG_TARGET, "Instruction adc_a_d8");
 
         let c = if self.carry_flag { 1 } else { 0 };
 
@@ -903,7 +1436,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        self.add_clock(8);
     }
 
     /// Subtract n from A
@@ -917,7 +1449,7 @@ impl Cpu {
     ///
     /// Opcode for 97, 90, 91, 92, 93, 94, 95
     fn sub_a_n(&mut self, reg: Register) {
-        debug!("Instruction sub_a_n reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction sub_a_n reg: {}", reg);
 
         let value = match reg {
             Register::A => self.a,
@@ -939,8 +1471,6 @@ impl Cpu {
         self.set_subtraction_flag(true);
         self.set_half_carry_flag(half_carry_flag);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(4);
     }
 
     /// Subtract (HL) from A
@@ -953,7 +1483,7 @@ impl Cpu {
     ///
     /// Opcode for 96
     fn sub_a_hl(&mut self) {
-        debug!("Instruction sub_a_hl");
+        debug!(target: LOG_TARGET, "Instruction sub_a_hl");
 
         let addr = get_addr_from_registers(self.h, self.l);
         let value = self.mmu.read_byte(addr);
@@ -967,8 +1497,6 @@ impl Cpu {
         self.set_subtraction_flag(true);
         self.set_half_carry_flag(half_carry_flag);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(8);
     }
 
     /// Subtract d8 from A
@@ -981,7 +1509,7 @@ impl Cpu {
     ///
     /// Opcode for D6
     fn sub_a_d8(&mut self) {
-        debug!("Instruction sub_a_d8");
+        debug!(target: LOG_TARGET, "Instruction sub_a_d8");
 
         let addr = self.pc;
         let value = self.mmu.read_byte(addr);
@@ -997,7 +1525,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        self.add_clock(8);
     }
 
     /// Subtract n + Carry flag from A
@@ -1011,7 +1538,7 @@ impl Cpu {
     ///
     /// Opcode for 9F, 98, 99, 9A, 9B, 9C, 9D
     fn sbc_a_n(&mut self, reg: Register) {
-        debug!("Instruction sbc_a_n reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction sbc_a_n reg: {}", reg);
 
         let c = if self.carry_flag { 1 } else { 0 };
 
@@ -1036,8 +1563,6 @@ impl Cpu {
         self.set_subtraction_flag(true);
         self.set_half_carry_flag(half_carry_flag);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(4);
     }
 
     /// Subtract (HL) + carry flag from A
@@ -1050,7 +1575,7 @@ impl Cpu {
     ///
     /// Opcode for 9E
     fn sbc_a_hl(&mut self) {
-        debug!("Instruction sbc_a_hl");
+        debug!(target: LOG_TARGET, "Instruction sbc_a_hl");
 
         let addr = get_addr_from_registers(self.h, self.l);
         let value = self.mmu.read_byte(addr);
@@ -1067,8 +1592,6 @@ impl Cpu {
         self.set_subtraction_flag(true);
         self.set_half_carry_flag(half_carry_flag);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(8);
     }
 
     /// Subtract d8 + Carry flag from A
@@ -1081,7 +1604,7 @@ impl Cpu {
     ///
     /// Opcode for DE
     fn sbc_a_d8(&mut self) {
-        debug!("Instruction sbc_a_d8");
+        debug!(target: LOG_TARGET, "Instruction sbc_a_d8");
 
         let addr = self.pc;
         let value = self.mmu.read_byte(addr);
@@ -1100,7 +1623,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        self.add_clock(8);
     }
 
     /// And n with A, result in A
@@ -1114,7 +1636,7 @@ impl Cpu {
     ///
     /// Opcode for A7, A0, A1, A2, A3, A4, A5
     fn and_r8(&mut self, reg: Register) {
-        debug!("Instruction and_r8 reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction and_r8 reg: {}", reg);
 
         let value = match reg {
             Register::A => self.a,
@@ -1133,8 +1655,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(true);
         self.set_carry_flag(false);
-
-        self.add_clock(4);
     }
 
     /// And (HL) with A, result in A
@@ -1156,8 +1676,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(true);
         self.set_carry_flag(false);
-
-        self.add_clock(8);
     }
 
     /// And d8 with A, result in A
@@ -1181,7 +1699,6 @@ impl Cpu {
         self.set_carry_flag(false);
 
         self.add_program_count(1);
-        self.add_clock(8);
     }
 
     /// Or r8 with register A, result in Affected
@@ -1195,7 +1712,7 @@ impl Cpu {
     ///
     /// Opcode for B7, B0, B1, B2, B3, B4, B5
     fn or_r8(&mut self, reg: Register) {
-        debug!("Instruction or_r8 reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction or_r8 reg: {}", reg);
         let value = match reg {
             Register::A => self.a,
             Register::B => self.b,
@@ -1213,8 +1730,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(false);
         self.set_carry_flag(false);
-
-        self.add_clock(4);
     }
 
     /// Or (HL) with register A, result in Affected
@@ -1227,7 +1742,7 @@ impl Cpu {
     ///
     /// Opcode for B6
     fn or_hl(&mut self) {
-        debug!("Instruction or_hl");
+        debug!(target: LOG_TARGET, "Instruction or_hl");
         let addr = get_addr_from_registers(self.h, self.l);
         let value = self.mmu.read_byte(addr);
 
@@ -1237,8 +1752,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(false);
         self.set_carry_flag(false);
-
-        self.add_clock(8);
     }
 
     /// Or d8 with register A, result in Affected
@@ -1251,7 +1764,7 @@ impl Cpu {
     ///
     /// Opcode for F6
     fn or_d8(&mut self) {
-        debug!("Instruction or_d8");
+        debug!(target: LOG_TARGET, "Instruction or_d8");
         let addr = self.pc;
         let value = self.mmu.read_byte(addr);
 
@@ -1263,7 +1776,6 @@ impl Cpu {
         self.set_carry_flag(false);
 
         self.add_program_count(1);
-        self.add_clock(8);
     }
 
     /// Xor r8 with register A, result in A
@@ -1277,7 +1789,7 @@ impl Cpu {
     ///
     /// Opcode for AF, A8, A9, AA, AB, AC, AD
     fn xor_r8(&mut self, reg: Register) {
-        debug!("Instruction xor_r8 reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction xor_r8 reg: {}", reg);
         let value = match reg {
             Register::A => self.a,
             Register::B => self.b,
@@ -1290,14 +1802,12 @@ impl Cpu {
         };
 
         self.a ^= value;
-        debug!("xor A self.a: 0x{:02x}, value: {:0b}", self.a, value);
+        debug!(target: LOG_TARGET, "xor A self.a: 0x{:02x}, value: {:0b}", self.a, value);
 
         self.set_zero_flag(self.a == 0);
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(false);
         self.set_carry_flag(false);
-
-        self.add_clock(4);
     }
 
     /// Xor (HL) with register A, result in A
@@ -1310,7 +1820,7 @@ impl Cpu {
     ///
     /// Opcode for AE
     fn xor_hl(&mut self) {
-        debug!("Instruction xor_hl");
+        debug!(target: LOG_TARGET, "Instruction xor_hl");
         let addr = get_addr_from_registers(self.h, self.l);
         let value = self.mmu.read_byte(addr);
 
@@ -1320,8 +1830,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(false);
         self.set_carry_flag(false);
-
-        self.add_clock(8);
     }
 
     /// Xor d8 with register A, result in A
@@ -1334,7 +1842,7 @@ impl Cpu {
     ///
     /// Opcode for EE
     fn xor_d8(&mut self) {
-        debug!("Instruction xor_d8");
+        debug!(target: LOG_TARGET, "Instruction xor_d8");
         let addr = self.pc;
         let value = self.mmu.read_byte(addr);
 
@@ -1346,7 +1854,6 @@ impl Cpu {
         self.set_carry_flag(false);
 
         self.add_program_count(1);
-        self.add_clock(8);
     }
 
     /// compare A with r8. Result are thrown away
@@ -1360,7 +1867,7 @@ impl Cpu {
     ///
     /// Opcode for BF, B8, B9, BA, BB, BC, BD
     fn cp_r8(&mut self, reg: Register) {
-        debug!("Instruction cp_r8 reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction cp_r8 reg: {}", reg);
         let value = match reg {
             Register::A => self.a,
             Register::B => self.b,
@@ -1379,8 +1886,6 @@ impl Cpu {
         self.set_subtraction_flag(true);
         self.set_half_carry_flag(half_carry_flag);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(4);
     }
 
     /// compare A with (HL). Result are thrown away
@@ -1393,7 +1898,7 @@ impl Cpu {
     ///
     /// Opcode for BE
     fn cp_hl(&mut self) {
-        debug!("Instruction cp_hl");
+        debug!(target: LOG_TARGET, "Instruction cp_hl");
         let addr = get_addr_from_registers(self.h, self.l);
         let value = self.mmu.read_byte(addr);
 
@@ -1404,8 +1909,6 @@ impl Cpu {
         self.set_subtraction_flag(true);
         self.set_half_carry_flag(half_carry_flag);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(8);
     }
 
     /// compare A with d8. Result are thrown away
@@ -1421,6 +1924,7 @@ impl Cpu {
         let addr = self.pc;
         let value = self.mmu.read_byte(addr);
         debug!(
+            target: LOG_TARGET,
             "Instruction cp_d8 addr: 0x{:04x}, value: 0x{:04x}",
             addr, value
         );
@@ -1434,7 +1938,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        self.add_clock(8);
     }
 
     /// INcrement register n
@@ -1448,7 +1951,7 @@ impl Cpu {
     ///
     /// Opcode for 3C, 04, 0C, 14, 1C, 24, 2C
     fn inc_r8(&mut self, reg: Register) {
-        debug!("Instruction inc_r8 reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction inc_r8 reg: {}", reg);
         let value = match reg {
             Register::A => self.a.wrapping_add(1),
             Register::B => self.b.wrapping_add(1),
@@ -1476,8 +1979,6 @@ impl Cpu {
         self.set_zero_flag(value == 0);
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(half_carry_flag);
-
-        self.add_clock(4);
     }
 
     /// INcrement (HL)
@@ -1490,7 +1991,7 @@ impl Cpu {
     ///
     /// Opcode for 34
     fn inc_hl(&mut self) {
-        debug!("Instruction inc_hl");
+        debug!(target: LOG_TARGET, "Instruction inc_hl");
         let addr = get_addr_from_registers(self.h, self.l);
         let mut value = self.mmu.read_byte(addr);
 
@@ -1502,8 +2003,6 @@ impl Cpu {
         self.set_zero_flag(value == 0);
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(half_carry_flag);
-
-        self.add_clock(12);
     }
 
     /// Decrement register n
@@ -1517,7 +2016,7 @@ impl Cpu {
     ///
     /// Opcode for 3D, 05, 0D, 15, 1D, 25, 2D
     fn dec_r8(&mut self, reg: Register) {
-        debug!("dec_r8 reg {}", reg);
+        debug!(target: LOG_TARGET, "dec_r8 reg {}", reg);
         let value = match reg {
             Register::A => {
                 self.a = self.a.wrapping_sub(1);
@@ -1555,8 +2054,6 @@ impl Cpu {
         self.set_zero_flag(value == 0);
         self.set_subtraction_flag(true);
         self.set_half_carry_flag(half_carry_flag);
-
-        self.add_clock(4);
     }
 
     /// Decrement (HL)
@@ -1569,7 +2066,7 @@ impl Cpu {
     ///
     /// Opcode for 35
     fn dec_hl(&mut self) {
-        debug!("Instruction dec_hl");
+        debug!(target: LOG_TARGET, "Instruction dec_hl");
         let addr = get_addr_from_registers(self.h, self.l);
         let mut value = self.mmu.read_byte(addr);
 
@@ -1581,8 +2078,6 @@ impl Cpu {
         self.set_zero_flag(value == 0);
         self.set_subtraction_flag(true);
         self.set_half_carry_flag(half_carry_flag);
-
-        self.add_clock(12);
     }
 
     /// add n to HL
@@ -1596,7 +2091,7 @@ impl Cpu {
     ///
     /// Opcode for 09, 19, 29, 39
     fn add_hl_n(&mut self, reg: Register) {
-        debug!("Instruction add_hl_n reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction add_hl_n reg: {}", reg);
         let value = match reg {
             Register::BC => ((self.b as u16) << 8) + (self.c as u16),
             Register::DE => ((self.d as u16) << 8) + (self.e as u16),
@@ -1615,8 +2110,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(half_carry_flag);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(8);
     }
 
     /// Add n to SP
@@ -1644,7 +2137,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        self.add_clock(16);
     }
 
     /// Increment register nn
@@ -1655,7 +2147,7 @@ impl Cpu {
     ///
     /// Opcode for 03, 13, 23, 33
     fn inc_r16(&mut self, reg: Register) {
-        debug!("Instruction inc_r16 reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction inc_r16 reg: {}", reg);
         let (mut high_value, mut low_value) = match reg {
             Register::BC => (self.b, self.c),
             Register::DE => (self.d, self.e),
@@ -1663,6 +2155,8 @@ impl Cpu {
             Register::SP => ((self.sp >> 8) as u8, self.sp as u8),
             _ => panic!("Invalid register {}", reg),
         };
+        self.mmu
+            .idu_access(get_addr_from_registers(high_value, low_value));
 
         low_value = low_value.wrapping_add(1);
         if low_value == 0 {
@@ -1685,8 +2179,6 @@ impl Cpu {
             Register::SP => self.sp = ((high_value as u16) << 8) + low_value as u16,
             _ => panic!("Invalid register {}", reg),
         }
-
-        self.add_clock(8);
     }
 
     /// Decrement register nn
@@ -1697,7 +2189,7 @@ impl Cpu {
     ///
     /// Opcode for 0B, 1B, 2B, 3B
     fn dec_r16(&mut self, reg: Register) {
-        debug!("Instruction dec_r16 reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction dec_r16 reg: {}", reg);
         let (mut high_value, mut low_value) = match reg {
             Register::BC => (self.b, self.c),
             Register::DE => (self.d, self.e),
@@ -1705,6 +2197,8 @@ impl Cpu {
             Register::SP => ((self.sp >> 8) as u8, self.sp as u8),
             _ => panic!("Invalid register {}", reg),
         };
+        self.mmu
+            .idu_access(get_addr_from_registers(high_value, low_value));
 
         low_value = low_value.wrapping_sub(1);
         if low_value == 0xff {
@@ -1727,8 +2221,6 @@ impl Cpu {
             Register::SP => self.sp = ((high_value as u16) << 8) + low_value as u16,
             _ => panic!("Invalid register {}", reg),
         }
-
-        self.add_clock(8);
     }
 
     /// Decimal adjust register A
@@ -1741,7 +2233,7 @@ impl Cpu {
     ///
     /// Opcode for 27
     fn daa(&mut self) {
-        debug!("Instruction daa");
+        debug!(target: LOG_TARGET, "Instruction daa");
 
         let mut a = self.a;
 
@@ -1766,8 +2258,6 @@ impl Cpu {
 
         self.set_zero_flag(a == 0);
         self.set_half_carry_flag(false);
-
-        self.add_clock(4);
     }
 
     /// Complement A register. (Flip all bits)
@@ -1780,14 +2270,12 @@ impl Cpu {
     ///
     /// Opcode for 2F
     fn cpl(&mut self) {
-        debug!("Instruction cpl");
+        debug!(target: LOG_TARGET, "Instruction cpl");
 
         self.a = !self.a;
 
         self.set_subtraction_flag(true);
         self.set_half_carry_flag(true);
-
-        self.add_clock(4);
     }
 
     /// Complement carry falg
@@ -1800,13 +2288,11 @@ impl Cpu {
     ///
     /// Opcode for 3F
     fn ccf(&mut self) {
-        debug!("Instruction ccf");
+        debug!(target: LOG_TARGET, "Instruction ccf");
 
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(false);
         self.set_carry_flag(!self.carry_flag);
-
-        self.add_clock(4);
     }
 
     /// Set carry flag
@@ -1819,42 +2305,35 @@ impl Cpu {
     ///
     /// Opcode for 37
     fn scf(&mut self) {
-        debug!("Instruction scf");
+        debug!(target: LOG_TARGET, "Instruction scf");
 
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(false);
         self.set_carry_flag(true);
-
-        self.add_clock(4);
     }
 
     /// No operation
     ///
     /// Opcode for 00
     fn nop(&mut self) {
-        debug!("Instruction nop");
-
-        self.add_clock(4);
+        debug!(target: LOG_TARGET, "Instruction nop");
     }
 
     /// Halt instruction
     /// Opcode for 76
     fn halt(&mut self) {
-        debug!("Instruction halt");
+        debug!(target: LOG_TARGET, "Instruction halt");
 
         if self.ime {
             self.halt = true;
         }
-
-        self.add_clock(4);
     }
 
     /// Stop instruction
     /// Opcode for 10
     fn stop(&mut self) {
-        debug!("Instruction stop");
-
-        self.add_clock(4);
+        debug!(target: LOG_TARGET, "Instruction stop");
+        self.mmu.stop();
     }
 
     /// DI
@@ -1864,11 +2343,9 @@ impl Cpu {
     ///
     /// Opcode for F3
     fn di(&mut self) {
-        debug!("Instruction DI");
+        debug!(target: LOG_TARGET, "Instruction DI");
 
         self.ime = false;
-
-        self.add_clock(4);
     }
 
     // EI
@@ -1878,11 +2355,9 @@ impl Cpu {
     ///
     /// Opcode for FB
     fn ei(&mut self) {
-        debug!("Instruction ei");
+        debug!(target: LOG_TARGET, "Instruction ei");
 
         self.ime = true;
-
-        self.add_clock(4);
     }
 
     /// Rotate A left. Old bit 7 to carry flag
@@ -1895,7 +2370,7 @@ impl Cpu {
     ///
     /// Opcode for 07
     fn rlca(&mut self) {
-        debug!("Instruction rlca");
+        debug!(target: LOG_TARGET, "Instruction rlca");
 
         let a = self.a;
         let carry_flag = ((a >> 7) & 0x01) == 0x01;
@@ -1906,8 +2381,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(false);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(4);
     }
 
     /// Rotate A left through carry flag.
@@ -1920,7 +2393,7 @@ impl Cpu {
     ///
     /// Opcode for 17
     fn rla(&mut self) {
-        debug!("Instruction rla");
+        debug!(target: LOG_TARGET, "Instruction rla");
         let c = if self.carry_flag { 1 } else { 0 };
         let carry_flag = (self.a >> 7) & 1 == 1;
         self.a = (self.a << 1) | c;
@@ -1929,8 +2402,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(false);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(4);
     }
 
     /// Rotate A right. Old bit 0 to Carry flag.
@@ -1943,7 +2414,7 @@ impl Cpu {
     ///
     /// Opcode for 0F
     fn rrca(&mut self) {
-        debug!("Instruction rrca");
+        debug!(target: LOG_TARGET, "Instruction rrca");
         let carry_flag = self.a & 1 == 1;
         self.a = self.a.rotate_right(1);
 
@@ -1951,8 +2422,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(false);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(4);
     }
 
     /// Rotate A ritght through Carry flag
@@ -1965,7 +2434,7 @@ impl Cpu {
     ///
     /// Opcode for 1F
     fn rra(&mut self) {
-        debug!("Instruction rra");
+        debug!(target: LOG_TARGET, "Instruction rra");
         let carry_flag = self.a & 1 == 1;
         let c = if self.carry_flag { 1 } else { 0 };
         self.a = (self.a >> 1) | (c << 7);
@@ -1974,8 +2443,6 @@ impl Cpu {
         self.set_subtraction_flag(false);
         self.set_half_carry_flag(false);
         self.set_carry_flag(carry_flag);
-
-        self.add_clock(4);
     }
 
     /// Rotate n left. Old 7 to Carry flag
@@ -1989,7 +2456,7 @@ impl Cpu {
     ///
     /// Opcode for CB (07, 00, 01, 02, 03, 04, 05, 06)
     fn rlc_n(&mut self, reg: Register) {
-        debug!("Instruction rlc_n reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction rlc_n reg: {}", reg);
         let value = self.read_r8(reg);
 
         let carry_flag = (value >> 7) & 1 == 1;
@@ -2003,11 +2470,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        if reg == Register::HL {
-            self.add_clock(16);
-        } else {
-            self.add_clock(8);
-        }
     }
 
     /// Rotate n right. Old bit 0 to Carry flag
@@ -2021,7 +2483,7 @@ impl Cpu {
     ///
     /// Opcode for CB (0F, 08, 09, 0A, 0B, 0C, 0D, 0E)
     fn rrc_n(&mut self, reg: Register) {
-        debug!("Instruction rrc_n reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction rrc_n reg: {}", reg);
         let value = self.read_r8(reg);
 
         let carry_flag = (value & 1) == 1;
@@ -2035,11 +2497,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        if reg == Register::HL {
-            self.add_clock(16);
-        } else {
-            self.add_clock(8);
-        }
     }
 
     /// Rotate n left through Carry flag
@@ -2053,7 +2510,7 @@ impl Cpu {
     ///
     /// Opcode for CB (17, 10, 11, 12, 13, 14, 15, 16)
     fn rl_n(&mut self, reg: Register) {
-        debug!("Instruction rl_n reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction rl_n reg: {}", reg);
         let value = self.read_r8(reg);
         let c = if self.carry_flag { 1 } else { 0 };
 
@@ -2069,11 +2526,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        if reg == Register::HL {
-            self.add_clock(16);
-        } else {
-            self.add_clock(8);
-        }
     }
 
     /// Rotate n right through Carry flag
@@ -2087,7 +2539,7 @@ impl Cpu {
     ///
     /// Opcode for CB (1F, 18, 19, 1A, 1B, 1C, 1D, 1E)
     fn rr_n(&mut self, reg: Register) {
-        debug!("Instruction rr_n reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction rr_n reg: {}", reg);
         let value = self.read_r8(reg);
         let c = if self.carry_flag { 1 } else { 0 };
 
@@ -2103,11 +2555,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        if reg == Register::HL {
-            self.add_clock(16);
-        } else {
-            self.add_clock(8);
-        }
     }
 
     /// Shift n left into Carry. LSB of n set ot 0
@@ -2121,7 +2568,7 @@ impl Cpu {
     ///
     /// Opcode for CB (27, 20, 21, 22, 23, 24, 25, 26)
     fn sla_n(&mut self, reg: Register) {
-        debug!("Instruction sla_n reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction sla_n reg: {}", reg);
         let value = self.read_r8(reg);
 
         let carry_flag = (value & 0x80) > 0;
@@ -2135,11 +2582,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        if reg == Register::HL {
-            self.add_clock(16);
-        } else {
-            self.add_clock(8);
-        }
     }
 
     /// Shift n right into Carry. MSB doesn't change
@@ -2153,7 +2595,7 @@ impl Cpu {
     ///
     /// Opcode for CB (2F, 28, 29, 2A, 2B, 2C, 2D, 2E)
     fn sra_n(&mut self, reg: Register) {
-        debug!("Instruction sra_n reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction sra_n reg: {}", reg);
         let value = self.read_r8(reg);
 
         let carry_flag = (value & 1) == 1;
@@ -2167,11 +2609,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        if reg == Register::HL {
-            self.add_clock(16);
-        } else {
-            self.add_clock(8);
-        }
     }
 
     /// Swap upper & lower nibles of n.
@@ -2185,7 +2622,7 @@ impl Cpu {
     ///
     /// Opcode for CB (37, 30, 31, 32, 33, 34, 35, 36)
     fn swap(&mut self, reg: Register) {
-        debug!("Instruction Swap reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction Swap reg: {}", reg);
         let value = self.read_r8(reg);
         let value = ((value & 0xf0) >> 4) | ((value & 0x0f) << 4);
 
@@ -2197,11 +2634,6 @@ impl Cpu {
         self.set_carry_flag(false);
 
         self.add_program_count(1);
-        if reg == Register::HL {
-            self.add_clock(16);
-        } else {
-            self.add_clock(8);
-        }
     }
 
     /// Shift n right into carry. MSB set to 0.
@@ -2215,7 +2647,7 @@ impl Cpu {
     ///
     /// Opcode for CB (3F, 38, 39, 3A, 3B, 3C, 3D, 3E)
     fn srl_n(&mut self, reg: Register) {
-        debug!("Instruction srl_n reg: {}", reg);
+        debug!(target: LOG_TARGET, "Instruction srl_n reg: {}", reg);
         let value = self.read_r8(reg);
 
         let carry_flag = (value & 1) == 1;
@@ -2230,11 +2662,6 @@ impl Cpu {
         self.set_carry_flag(carry_flag);
 
         self.add_program_count(1);
-        if reg == Register::HL {
-            self.add_clock(16);
-        } else {
-            self.add_clock(8);
-        }
     }
 
     /// Test bit b in register r.
@@ -2249,7 +2676,7 @@ impl Cpu {
     ///
     /// Opcode for CB (40 - 7F)
     fn bit(&mut self, reg: Register, b: u8) {
-        debug!("Instruction bit reg: {}, bit: {}", reg, b);
+        debug!(target: LOG_TARGET, "Instruction bit reg: {}, bit: {}", reg, b);
 
         let value = self.read_r8(reg);
 
@@ -2260,11 +2687,6 @@ impl Cpu {
         self.set_half_carry_flag(true);
 
         self.add_program_count(1);
-        if reg == Register::HL {
-            self.add_clock(12);
-        } else {
-            self.add_clock(8);
-        }
     }
 
     /// Reset bit b in register r
@@ -2276,18 +2698,13 @@ impl Cpu {
     ///
     /// Opcode for CB (80 - BF)
     fn res(&mut self, reg: Register, b: u8) {
-        debug!("Instruction res reg: {}, bit b: {}", reg, b);
+        debug!(target: LOG_TARGET, "Instruction res reg: {}, bit b: {}", reg, b);
 
         let value = self.read_r8(reg);
         let value = value & !(1 << b);
         self.write_r8(reg, value);
 
         self.add_program_count(1);
-        if reg == Register::HL {
-            self.add_clock(16);
-        } else {
-            self.add_clock(8);
-        }
     }
 
     /// Set bit b in register r
@@ -2299,7 +2716,7 @@ impl Cpu {
     ///
     /// Opcode for CB (C0 - FF)
     fn set(&mut self, reg: Register, b: u8) {
-        debug!("Instruction set reg: {}, bit: {}", reg, b);
+        debug!(target: LOG_TARGET, "Instruction set reg: {}, bit: {}", reg, b);
 
         let value = self.read_r8(reg);
         let value = value | (1 << b);
@@ -2307,16 +2724,11 @@ impl Cpu {
         self.write_r8(reg, value);
 
         self.add_program_count(1);
-        if reg == Register::HL {
-            self.add_clock(16);
-        } else {
-            self.add_clock(8);
-        }
     }
 
-    /// Prefix CB
-    fn prefix_cb(&mut self) {
-        debug!("Instruction prefix_cb");
+    /// Prefix CB. Returns the CB opcode run.
+    fn prefix_cb(&mut self) -> u8 {
+        debug!(target: LOG_TARGET, "Instruction prefix_cb");
         let pc = self.pc;
         let opcode = self.mmu.read_byte(pc);
         let b = (opcode >> 3) & 0x07;
@@ -2346,6 +2758,7 @@ impl Cpu {
             0x80..=0xbf => self.res(reg, b),
             0xc0..=0xff => self.set(reg, b),
         }
+        opcode
     }
 
     /// Junm to adress nn
@@ -2353,13 +2766,12 @@ impl Cpu {
     ///
     /// Opcode for C3
     fn jp_nn(&mut self) {
-        debug!("Instruction jp_nn");
+        debug!(target: LOG_TARGET, "Instruction jp_nn");
         let addr = self.pc;
         let value = self.read_word(addr);
         self.pc = value;
 
         // self.add_program_count(2);
-        self.add_clock(16);
     }
 
     /// Jump to address nn if following condition is true:
@@ -2368,9 +2780,11 @@ impl Cpu {
     /// cc = NC, Jump if C flag is reset.
     /// cc =  C, Jump if C flag is set.
     ///
+    /// Returns whether the condition held.
+    ///
     /// Opcode for C2, CA, D2, DA
-    fn jump_cc_nn(&mut self, cc: CcFlag) {
-        debug!("Instruction jump_cc_nn {}", cc);
+    fn jump_cc_nn(&mut self, cc: CcFlag) -> bool {
+        debug!(target: LOG_TARGET, "Instruction jump_cc_nn {}", cc);
         let flag = match cc {
             CcFlag::NZ => !self.zero_flag,
             CcFlag::Z => self.zero_flag,
@@ -2382,23 +2796,19 @@ impl Cpu {
             let addr = self.pc;
             let value = self.read_word(addr);
             self.pc = value;
-
-            self.add_clock(16);
         } else {
             self.add_program_count(2);
-            self.add_clock(12)
         }
+        flag
     }
 
     /// Jump to address contained in HL.
     ///
     /// Opcode for E9
     fn jump_hl(&mut self) {
-        debug!("Instruction jump_hl");
+        debug!(target: LOG_TARGET, "Instruction jump_hl");
         let addr = get_addr_from_registers(self.h, self.l);
         self.pc = addr;
-
-        self.add_clock(4);
     }
 
     /// Add n to current address and jump to it.
@@ -2406,13 +2816,12 @@ impl Cpu {
     ///
     /// Opcode for 18
     fn jr_n(&mut self) {
-        debug!("Instruction jr_n");
+        debug!(target: LOG_TARGET, "Instruction jr_n");
         let addr = self.pc;
         let value = self.mmu.read_byte(addr) as i8;
         self.pc = self.pc.wrapping_add(value as u16);
 
         self.add_program_count(1);
-        self.add_clock(12);
     }
 
     /// If following condition is true then add n to current
@@ -2423,9 +2832,11 @@ impl Cpu {
     /// cc = NC, Jump if C flag is reset.
     /// cc =  C, Jump if C flag is set.
     ///
+    /// Returns whether the condition held.
+    ///
     /// Opcode for 20, 28, 30, 38
-    fn jr_cc_n(&mut self, cc: CcFlag) {
-        debug!("Instruction jr_cc_n {}", cc);
+    fn jr_cc_n(&mut self, cc: CcFlag) -> bool {
+        debug!(target: LOG_TARGET, "Instruction jr_cc_n {}", cc);
         let flag = match cc {
             CcFlag::NZ => !self.zero_flag,
             CcFlag::Z => self.zero_flag,
@@ -2437,11 +2848,10 @@ impl Cpu {
             let addr = self.pc;
             let value = self.mmu.read_byte(addr) as i8;
             self.pc = self.pc.wrapping_add(value as u16).wrapping_add(1);
-            self.add_clock(12);
         } else {
             self.add_program_count(1);
-            self.add_clock(8);
         }
+        flag
     }
 
     /// Push address of next instruction onto stack and then
@@ -2451,19 +2861,18 @@ impl Cpu {
     /// Opcode for CD
     fn call_nn(&mut self) {
         let addr = self.read_word(self.pc);
-        debug!("Instruction call_nn 0x{:04x}", addr);
+        debug!(target: LOG_TARGET, "Instruction call_nn 0x{:04x}", addr);
 
         self.add_program_count(2);
         self.sp = self.sp.wrapping_sub(2);
 
         let sp = self.sp;
         let pc = self.pc;
-        debug!("call_nn sp: 0x{:04x}, pc: 0x{:04x}", sp, pc);
+        debug!(target: LOG_TARGET, "call_nn sp: 0x{:04x}, pc: 0x{:04x}", sp, pc);
         self.write_word(sp, pc);
 
         // self.add_program_count(value);
         self.pc = addr;
-        self.add_clock(24);
     }
 
     /// Call address nn if following condition is true.
@@ -2473,9 +2882,11 @@ impl Cpu {
     /// cc = NC, Jump if C flag is reset.
     /// cc =  C, Jump if C flag is set.
     ///
+    /// Returns whether the condition held.
+    ///
     /// Opcode for C4, CC, D4, DC
-    fn call_cc_nn(&mut self, cc: CcFlag) {
-        debug!("Instruction call_cc_nn {}", cc);
+    fn call_cc_nn(&mut self, cc: CcFlag) -> bool {
+        debug!(target: LOG_TARGET, "Instruction call_cc_nn {}", cc);
         let flag = match cc {
             CcFlag::NZ => !self.zero_flag,
             CcFlag::Z => self.zero_flag,
@@ -2493,11 +2904,10 @@ impl Cpu {
             self.write_word(sp, pc);
 
             self.pc = addr;
-            self.add_clock(24);
         } else {
             self.add_program_count(2);
-            self.add_clock(12);
         }
+        flag
     }
 
     /// Push present address onto stack.
@@ -2506,26 +2916,23 @@ impl Cpu {
     ///
     /// Opcode for C7, CF, D7, DF, E7, EF, F7, FF
     fn rst_n(&mut self, n: u16) {
-        debug!("Instruction rst_n {}", n);
+        debug!(target: LOG_TARGET, "Instruction rst_n {}", n);
         self.sp = self.sp.wrapping_sub(2);
         let sp = self.sp;
         let pc = self.pc;
         self.write_word(sp, pc);
 
         self.pc = n;
-        self.add_clock(16);
     }
 
     /// Pop two bytes from stack & jump to that address
     /// Opcode for C9
     fn ret(&mut self) {
-        debug!("Instruction ret ");
+        debug!(target: LOG_TARGET, "Instruction ret ");
         let sp = self.sp;
         let addr = self.read_word(sp);
         self.pc = addr;
         self.sp = self.sp.wrapping_add(2);
-
-        self.add_clock(16);
     }
 
     /// Pop two bytes from stack & jump to that address
@@ -2535,9 +2942,11 @@ impl Cpu {
     /// cc = NC, Jump if C flag is reset.
     /// cc =  C, Jump if C flag is set.
     ///
+    /// Returns whether the condition held.
+    ///
     /// Opcode for C0, C8, D0, D8
-    fn ret_cc(&mut self, cc: CcFlag) {
-        debug!("Instruction ret_cc {}", cc);
+    fn ret_cc(&mut self, cc: CcFlag) -> bool {
+        debug!(target: LOG_TARGET, "Instruction ret_cc {}", cc);
         let flag = match cc {
             CcFlag::NZ => !self.zero_flag,
             CcFlag::Z => self.zero_flag,
@@ -2549,29 +2958,28 @@ impl Cpu {
             let addr = self.read_word(sp);
             self.pc = addr;
             self.sp = self.sp.wrapping_add(2);
-
-            self.add_clock(20);
-        } else {
-            self.add_clock(8);
         }
+        flag
     }
 
     /// Pop two bytes from stack & jump to that address then
     /// enable interrupts.
     /// Opcode for D9
     fn reti(&mut self) {
-        debug!("Instruction reti");
+        debug!(target: LOG_TARGET, "Instruction reti");
         let sp = self.sp;
         let addr = self.read_word(sp);
         self.pc = addr;
         self.sp = self.sp.wrapping_add(2);
 
         self.ime = true;
-
-        self.add_clock(16);
     }
 
+    /// Runs the instruction `opcode` with PC past it, charging its cycles
+    /// from `cycles`.
     pub fn exec(&mut self, opcode: u8) {
+        let mut taken = false;
+        let mut cb = None;
         match opcode {
             // 00
             0x00 => self.nop(),
@@ -2608,7 +3016,7 @@ impl Cpu {
             0x1E => self.load_nn_n(Register::E),
             0x1F => self.rra(),
             // 20
-            0x20 => self.jr_cc_n(CcFlag::NZ),
+            0x20 => taken = self.jr_cc_n(CcFlag::NZ),
             0x21 => self.load_n_nn(Register::HL),
             0x22 => self.load_hli_a(),
             0x23 => self.inc_r16(Register::HL),
@@ -2616,7 +3024,7 @@ impl Cpu {
             0x25 => self.dec_r8(Register::H),
             0x26 => self.load_nn_n(Register::H),
             0x27 => self.daa(),
-            0x28 => self.jr_cc_n(CcFlag::Z),
+            0x28 => taken = self.jr_cc_n(CcFlag::Z),
             0x29 => self.add_hl_n(Register::HL),
             0x2A => self.load_a_hli(),
             0x2B => self.dec_r16(Register::HL),
@@ -2625,7 +3033,7 @@ impl Cpu {
             0x2E => self.load_nn_n(Register::L),
             0x2F => self.cpl(),
             // 30
-            0x30 => self.jr_cc_n(CcFlag::NC),
+            0x30 => taken = self.jr_cc_n(CcFlag::NC),
             0x31 => self.load_n_nn(Register::SP),
             0x32 => self.load_hld_a(),
             0x33 => self.inc_r16(Register::SP),
@@ -2633,7 +3041,7 @@ impl Cpu {
             0x35 => self.dec_hl(),
             0x36 => self.load_hl_imm(),
             0x37 => self.scf(),
-            0x38 => self.jr_cc_n(CcFlag::C),
+            0x38 => taken = self.jr_cc_n(CcFlag::C),
             0x39 => self.add_hl_n(Register::SP),
             0x3A => self.load_a_hld(),
             0x3B => self.dec_r16(Register::SP),
@@ -2778,54 +3186,54 @@ impl Cpu {
             0xBE => self.cp_hl(),
             0xBF => self.cp_r8(Register::A),
             // C0
-            0xC0 => self.ret_cc(CcFlag::NZ),
+            0xC0 => taken = self.ret_cc(CcFlag::NZ),
             0xC1 => self.pop_nn(Register::B, Register::C),
-            0xC2 => self.jump_cc_nn(CcFlag::NZ),
+            0xC2 => taken = self.jump_cc_nn(CcFlag::NZ),
             0xC3 => self.jp_nn(),
-            0xC4 => self.call_cc_nn(CcFlag::NZ),
+            0xC4 => taken = self.call_cc_nn(CcFlag::NZ),
             0xC5 => self.push_nn(Register::B, Register::C),
             0xC6 => self.add_a_d8(),
             0xC7 => self.rst_n(0x00),
-            0xC8 => self.ret_cc(CcFlag::Z),
+            0xC8 => taken = self.ret_cc(CcFlag::Z),
             0xC9 => self.ret(),
-            0xCA => self.jump_cc_nn(CcFlag::Z),
-            0xCB => self.prefix_cb(),
-            0xCC => self.call_cc_nn(CcFlag::Z),
+            0xCA => taken = self.jump_cc_nn(CcFlag::Z),
+            0xCB => cb = Some(self.prefix_cb()),
+            0xCC => taken = self.call_cc_nn(CcFlag::Z),
             0xCD => self.call_nn(),
             0xCE => self.adc_a_d8(),
             0xCF => self.rst_n(0x08),
             // D0
-            0xD0 => self.ret_cc(CcFlag::NC),
+            0xD0 => taken = self.ret_cc(CcFlag::NC),
             0xD1 => self.pop_nn(Register::D, Register::E),
-            0xD2 => self.jump_cc_nn(CcFlag::NC),
-            0xD3 => panic!("Invalid opcode {}", opcode),
-            0xD4 => self.call_cc_nn(CcFlag::NC),
+            0xD2 => taken = self.jump_cc_nn(CcFlag::NC),
+            0xD3 => self.lock_up(opcode),
+            0xD4 => taken = self.call_cc_nn(CcFlag::NC),
             0xD5 => self.push_nn(Register::D, Register::E),
             0xD6 => self.sub_a_d8(),
             0xD7 => self.rst_n(0x10),
-            0xD8 => self.ret_cc(CcFlag::C),
+            0xD8 => taken = self.ret_cc(CcFlag::C),
             0xD9 => self.reti(),
-            0xDA => self.jump_cc_nn(CcFlag::C),
-            0xDB => panic!("Invalid opcode {}", opcode),
-            0xDC => self.call_cc_nn(CcFlag::C),
-            0xDD => panic!("Invalid opcode {}", opcode),
+            0xDA => taken = self.jump_cc_nn(CcFlag::C),
+            0xDB => self.lock_up(opcode),
+            0xDC => taken = self.call_cc_nn(CcFlag::C),
+            0xDD => self.lock_up(opcode),
             0xDE => self.sbc_a_d8(),
             0xDF => self.rst_n(0x18),
             // E0
             0xE0 => self.load_n_a(),
             0xE1 => self.pop_nn(Register::H, Register::L),
             0xE2 => self.load_c_a(),
-            0xE3 => panic!("Invalid opcode {}", opcode),
-            0xE4 => panic!("Invalid opcode {}", opcode),
+            0xE3 => self.lock_up(opcode),
+            0xE4 => self.lock_up(opcode),
             0xE5 => self.push_nn(Register::H, Register::L),
             0xE6 => self.and_d8(),
             0xE7 => self.rst_n(0x20),
             0xE8 => self.add_sp_d8(),
             0xE9 => self.jump_hl(),
             0xEA => self.load_imm_a(),
-            0xEB => panic!("Invalid opcode {}", opcode),
-            0xEC => panic!("Invalid opcode {}", opcode),
-            0xED => panic!("Invalid opcode {}", opcode),
+            0xEB => self.lock_up(opcode),
+            0xEC => self.lock_up(opcode),
+            0xED => self.lock_up(opcode),
             0xEE => self.xor_d8(),
             0xEF => self.rst_n(0x28),
             // F0
@@ -2833,7 +3241,7 @@ impl Cpu {
             0xF1 => self.pop_nn(Register::A, Register::F),
             0xF2 => self.load_a_c(),
             0xF3 => self.di(),
-            0xF4 => panic!("Invalid opcode {}", opcode),
+            0xF4 => self.lock_up(opcode),
             0xF5 => self.push_nn(Register::A, Register::F),
             0xF6 => self.or_d8(),
             0xF7 => self.rst_n(0x30),
@@ -2841,11 +3249,29 @@ impl Cpu {
             0xF9 => self.load_sp_hl(),
             0xFA => self.load_a_imm(),
             0xFB => self.ei(),
-            0xFC => panic!("Invalid opcode {}", opcode),
-            0xFD => panic!("Invalid opcode {}", opcode),
+            0xFC => self.lock_up(opcode),
+            0xFD => self.lock_up(opcode),
             0xFE => self.cp_d8(),
             0xFF => self.rst_n(0x38),
         }
+
+        let cycles = match cb {
+            Some(cb) => cycles::CB[cb as usize],
+            None if taken => cycles::TAKEN[opcode as usize],
+            None => cycles::OPCODE[opcode as usize],
+        };
+        self.add_clock(cycles as u32);
+    }
+
+    /// Illegal opcodes hang the CPU, interrupts included.
+    fn lock_up(&mut self, opcode: u8) {
+        warn!(
+            target: LOG_TARGET,
+            "Illegal opcode 0x{:02x} at 0x{:04x}, CPU locked up",
+            opcode,
+            self.pc.wrapping_sub(1)
+        );
+        self.locked = true;
     }
 
     fn add_program_count(&mut self, count: u16) {
@@ -2923,7 +3349,7 @@ impl Cpu {
     /// Read 8 byte value from register
     /// Regisger for A, B, C, D, E, H, L, (HL)
     fn read_r8(&mut self, reg: Register) -> u8 {
-        debug!("read_r8");
+        debug!(target: LOG_TARGET, "read_r8");
         match reg {
             Register::A => self.a,
             Register::B => self.b,
@@ -2943,7 +3369,7 @@ impl Cpu {
     /// Write 8 byte value to register
     /// Regisger for A, B, C, D, E, H, L, (HL)
     fn write_r8(&mut self, reg: Register, value: u8) {
-        debug!("write_r8");
+        debug!(target: LOG_TARGET, "write_r8");
         match reg {
             Register::A => self.a = value,
             Register::B => self.b = value,
@@ -2972,6 +3398,7 @@ impl Cpu {
         let high_value = (value >> 8) as u8;
 
         debug!(
+            target: LOG_TARGET,
             "write_word low_value: 0x{:0x}, high_value: {:0x}",
             low_value, high_value
         );
@@ -2983,42 +3410,415 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::accuracy::Accuracy;
+    use crate::cartridge;
+    use crate::cartridge::tests::{fix_checksum, rom_with_header};
+    use crate::heatmap::AccessHeatmap;
+    use crate::palette::BUILTIN_PALETTES;
+
+    /// Test ROM kept in `cartridges/` at the root of the workspace.
+    #[cfg(feature = "std")]
+    const HELLO_ROM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../cartridges/hello.gb");
+
+    #[test]
+    fn test_accuracy_presets() {
+        let mut cpu = Cpu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        assert_eq!(cpu.accuracy(), AccuracyOptions::default());
+        for accuracy in Accuracy::ALL {
+            cpu.set_accuracy(accuracy.options());
+            assert_eq!(cpu.accuracy(), accuracy.options());
+        }
+
+        cpu.set_accuracy(Accuracy::Fast.options());
+        cpu.reset(true);
+        assert_eq!(cpu.accuracy(), Accuracy::Fast.options());
+    }
+
+    #[test]
+    fn test_change_cartridge() {
+        let mut cpu = Cpu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        cpu.mmu.set_strict(true);
+        for _ in 0..10 {
+            cpu.step();
+        }
+
+        let mut rom = rom_with_header(0x09, 0x02);
+        rom[0x134] = b'B';
+        fix_checksum(&mut rom);
+        let mut cartridge = cartridge::from_bytes(rom).unwrap();
+        cartridge.ram_mut()[0] = 0x12;
+        let old = cpu.change_cartridge(cartridge);
+
+        assert_eq!(old.title(), "");
+        assert_eq!(cpu.mmu.cartridge.title(), "B");
+        assert_eq!(cpu.registers().pc, 0x100);
+        assert_eq!(cpu.stats().instructions, 0);
+        assert_eq!(cpu.mmu.read_byte(0xa000), 0x12);
+        assert!(cpu.mmu.is_strict());
+    }
+
+    /// Remembers where it was polled.
+    struct Polls(Vec<PollPoint>);
+
+    impl InputProvider for Polls {
+        fn poll(&mut self, at: PollPoint) -> Players {
+            self.0.push(at);
+            Players::default()
+        }
+    }
+
+    #[test]
+    fn test_run_frame_polls_input() {
+        use crate::input::ScriptedInput;
+        use crate::joypad::{ButtonState, Key};
+
+        let mut cpu = Cpu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        let mut polls = Polls(Vec::new());
+        cpu.run_frame(&mut polls, PollRate::Frame);
+        cpu.run_frame(&mut polls, PollRate::Lines(50));
+        let lines: Vec<_> = polls.0.iter().map(|at| (at.frame, at.line)).collect();
+        assert_eq!(lines, vec![(0, 0), (1, 0), (1, 50), (1, 100), (1, 150)]);
+
+        let mut script = ScriptedInput::new();
+        script.hold(3, 0, ButtonState::default().with(Key::Start));
+        cpu.run_frame(&mut script, PollRate::Frame);
+        assert_eq!(cpu.mmu.joypad.state(), ButtonState::default());
+        cpu.run_frame(&mut script, PollRate::Frame);
+        assert!(cpu.mmu.joypad.state().is_pressed(Key::Start));
+
+        cpu.reset(false);
+        cpu.run_frame(&mut polls, PollRate::Frame);
+        assert_eq!(polls.0.last().unwrap().frame, 0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "rgb565"))]
+    fn test_run_frames() {
+        use crate::joypad::Key;
+        use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+        let mut rom = rom_with_header(0x00, 0x00);
+        rom[0x100..0x10a].copy_from_slice(&[
+            0x3e, 0x42, // ld a, 0x42
+            0xe0, 0x01, // ldh (SB), a
+            0x3e, 0x81, // ld a, 0x81
+            0xe0, 0x02, // ldh (SC), a
+            0x18, 0xfe, // jr -2
+        ]);
+        fix_checksum(&mut rom);
+        let mut cpu = Cpu::with_rom(rom).unwrap();
+        cpu.mmu.apu.set_sample_rate(48_000);
+        let frame_len = SCREEN_WIDTH * SCREEN_HEIGHT * 3;
+
+        let start = ButtonState::default().with(Key::Start);
+        let output = cpu.run_frames(3, &[ButtonState::default(), start], KeepFrames::All);
+        assert_eq!(cpu.frames(), 3);
+        assert_eq!(output.frames.len(), 3 * frame_len);
+        assert_eq!(&output.frames[2 * frame_len..], cpu.mmu.ppu.get_frame());
+        assert!(!output.audio.is_empty());
+        assert_eq!(output.serial, [0x42]);
+        assert!(cpu.mmu.joypad.state().is_pressed(Key::Start));
+        assert!(!cpu.mmu.is_serial_logging());
+
+        let output = cpu.run_frames(2, &[], KeepFrames::Last);
+        assert_eq!(output.frames.len(), frame_len);
+        assert!(output.serial.is_empty());
+        assert!(cpu.mmu.joypad.state().is_pressed(Key::Start));
+    }
+
+    #[test]
+    fn test_state_hashes() {
+        let mut a = Cpu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        let mut b = Cpu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        a.run_frame_with(|_, _| {});
+        b.run_frame_with(|_, _| {});
+        b.mmu.ppu.set_dmg_palette(BUILTIN_PALETTES[1].shades);
+        assert_eq!(a.state_hashes(), b.state_hashes());
+
+        b.mmu.write_byte(0xc123, 0x45);
+        let differing: Vec<_> = a
+            .state_hashes()
+            .iter()
+            .zip(b.state_hashes())
+            .filter(|(a, b)| a.1 != b.1)
+            .map(|(a, _)| a.0)
+            .collect();
+        assert_eq!(differing, vec!["wram"]);
+        // Both PPU sections are in one hash
+        let names: Vec<_> = a.state_hashes().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names.iter().filter(|&&name| name == "ppu").count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_state_hashes_rtc() {
+        use core::convert::TryInto;
+
+        // MBC3+TIMER+RAM+BATTERY
+        let rom = rom_with_header(0x10, 0x02);
+        let a = Cpu::with_rom(rom.clone()).unwrap();
+        let mut b = Cpu::with_rom(rom).unwrap();
+        // As if b were started a day later
+        let w = a.write_state();
+        let (_, base) = w
+            .sections()
+            .into_iter()
+            .find(|(name, _)| *name == "rtc-base")
+            .unwrap();
+        let mut state = w.into_bytes();
+        let timestamp = i64::from_le_bytes(state[base.clone()].try_into().unwrap());
+        state[base].copy_from_slice(&(timestamp + 86400).to_le_bytes());
+        b.load_state(&state).unwrap();
+        assert_ne!(a.save_state(), b.save_state());
+        assert_eq!(a.state_hashes(), b.state_hashes());
+    }
+
+    #[test]
+    fn test_sub_frame_state() {
+        let mut cpu = Cpu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        let rate = PollRate::Lines(8);
+        let mut polls = Polls(Vec::new());
+        cpu.run_frame(&mut polls, rate);
+        while cpu.frame_cycles() < 70 * DOTS_PER_LINE as u32 + 200 {
+            assert!(!cpu.step_frame(&mut polls, rate));
+        }
+        let state = cpu.save_state();
+
+        // The rest of the frame and the next, from the state and without it
+        let finish = |cpu: &mut Cpu| {
+            let mut polls = Polls(Vec::new());
+            cpu.run_frame(&mut polls, rate);
+            cpu.run_frame(&mut polls, rate);
+            (
+                polls.0,
+                cpu.registers(),
+                cpu.mmu.read_byte(0xff41),
+                cpu.frames(),
+            )
+        };
+        let expected = finish(&mut cpu);
+        assert_eq!(expected.0[0], PollPoint { frame: 1, line: 72 });
+
+        let mut loaded = Cpu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.frames(), 1);
+        assert_eq!(finish(&mut loaded), expected);
+    }
+
+    #[test]
+    fn test_step_on_flat_bus() {
+        let mut cpu = Cpu::with_flat_ram();
+        cpu.set_registers(Registers {
+            pc: 0xc000,
+            ..Registers::default()
+        });
+        // LD B, 0x42
+        cpu.mmu.write_byte(0xc000, 0x06);
+        cpu.mmu.write_byte(0xc001, 0x42);
+
+        assert_eq!(cpu.step(), 8);
+        assert_eq!(cpu.registers().b, 0x42);
+        assert_eq!(cpu.registers().pc, 0xc002);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut rom = crate::cartridge::tests::rom_with_header(0x01, 0x00);
+        rom[0x100..0x10a].copy_from_slice(&[
+            0x3e, 0xc0, // LD A, 0xc0
+            0xe0, 0x46, // LDH (0x46), A
+            0xea, 0x00, 0x20, // LD (0x2000), A
+            0xea, 0x00, 0x00, // LD (0x0000), A
+        ]);
+        crate::cartridge::tests::fix_checksum(&mut rom);
+        let mut cpu = Cpu::with_rom(rom).unwrap();
+        for _ in 0..4 {
+            cpu.step();
+        }
+        cpu.mmu.interrupt_enable = 0x01;
+        cpu.mmu.interrupt_flag = 0x01;
+        cpu.ime = true;
+        cpu.step();
+
+        let stats = cpu.stats();
+        assert_eq!(stats.instructions, 5);
+        assert_eq!(stats.dma_transfers, 1);
+        // Enabling RAM selects no bank
+        assert_eq!(stats.bank_switches, 1);
+        assert_eq!(stats.interrupts(Interrupt::VBlank), 1);
+        assert_eq!(stats.interrupts(Interrupt::Timer), 0);
+        assert!(stats.cycles >= 8 + 12 + 16 + 16);
+
+        cpu.reset_stats();
+        assert_eq!(cpu.stats(), Stats::default());
+    }
+
+    #[test]
+    fn test_cycles_past_u32() {
+        let rom = rom_with_header(0x00, 0x00);
+        let mut cpu = Cpu::with_rom(rom.clone()).unwrap();
+        // About 17 minutes in
+        cpu.elapsed_cycles = u32::MAX as u64 - 2;
+        let cycles = cpu.step() as u64;
+        assert_eq!(cpu.cycles(), u32::MAX as u64 - 2 + cycles);
+
+        let mut loaded = Cpu::with_rom(rom).unwrap();
+        loaded.load_state(&cpu.save_state()).unwrap();
+        assert_eq!(loaded.cycles(), cpu.cycles());
+        cpu.reset(false);
+        assert_eq!(cpu.cycles(), 0);
+    }
+
+    #[test]
+    fn test_illegal_opcode_locks_up() {
+        let mut cpu = Cpu::with_flat_ram();
+        cpu.set_registers(Registers {
+            pc: 0xc000,
+            ..Registers::default()
+        });
+        cpu.mmu.write_byte(0xc000, 0xd3);
+
+        assert!(!cpu.is_locked());
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.registers().pc, 0xc001);
+        assert!(cpu.is_locked());
+    }
+
+    #[test]
+    fn test_hang() {
+        let mut cpu = Cpu::with_flat_ram();
+        cpu.set_registers(Registers {
+            pc: 0xc000,
+            ..Registers::default()
+        });
+        cpu.mmu.write_byte(0xc000, 0x18);
+        cpu.mmu.write_byte(0xc001, 0xfe);
+        cpu.step();
+        assert_eq!(cpu.hang(), Some(Hang::SelfLoop));
+        // An enabled interrupt can still leave the loop
+        cpu.set_ime(true);
+        cpu.mmu.write_byte(0xffff, 0x01);
+        assert_eq!(cpu.hang(), None);
+
+        cpu.mmu.write_byte(0xc000, 0x76);
+        cpu.step();
+        assert_eq!(cpu.hang(), None);
+        cpu.mmu.write_byte(0xffff, 0x00);
+        assert_eq!(cpu.hang(), Some(Hang::HaltWithoutInterrupts));
+    }
+
+    #[test]
+    fn test_hang_does_not_touch_the_bus() {
+        let mut cpu = Cpu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        cpu.mmu.set_heatmap(Some(AccessHeatmap::new()));
+        cpu.mmu.set_strict(true);
+        // A PC in OAM, which strict mode reports CPU reads of in mode 2
+        cpu.set_registers(Registers {
+            pc: 0xfe00,
+            ..Registers::default()
+        });
+        cpu.mmu.update(4);
+        cpu.hang();
+        let heatmap = cpu.mmu.heatmap().unwrap();
+        assert_eq!(heatmap.page(0xfe).reads, 0);
+        assert_eq!(heatmap.page(0xff).reads, 0);
+        assert!(cpu.mmu.take_violations().is_empty());
+    }
+
+    #[test]
+    fn test_cycles_from_tables() {
+        let run = |opcodes: &[u8], f: u8| {
+            let mut cpu = Cpu::with_flat_ram();
+            cpu.set_registers(Registers {
+                f,
+                h: 0xc8,
+                sp: 0xd000,
+                pc: 0xc000,
+                ..Registers::default()
+            });
+            for (i, &opcode) in opcodes.iter().enumerate() {
+                cpu.mmu.write_byte(0xc000 + i as u16, opcode);
+            }
+            cpu.step() as u8
+        };
+        for opcode in 0..=0xff {
+            if opcode == 0xcb {
+                continue;
+            }
+            for &f in &[0x00, 0xf0] {
+                // NZ, Z, NC and C in bits 3 and 4
+                let conditional = matches!(opcode, 0x20 | 0x28 | 0x30 | 0x38)
+                    || ((0xc0..=0xdf).contains(&opcode) && matches!(opcode & 7, 0 | 2 | 4));
+                let taken = conditional && (opcode & 0x08 != 0) == (f != 0);
+                let expected = if taken {
+                    cycles::TAKEN[opcode as usize]
+                } else {
+                    cycles::OPCODE[opcode as usize]
+                };
+                assert_eq!(run(&[opcode], f), expected, "0x{:02x} f {:02x}", opcode, f);
+            }
+        }
+        for opcode in 0..=0xff {
+            assert_eq!(run(&[0xcb, opcode], 0), cycles::CB[opcode as usize]);
+        }
+    }
+
+    #[test]
+    fn test_interrupt_cycles() {
+        let mut cpu = Cpu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        cpu.mmu.interrupt_enable = 0x05;
+        cpu.mmu.interrupt_flag = 0x05;
+        cpu.ime = true;
+        let before = cpu.stats().cycles;
+
+        // A NOP, then only VBlank of the two pending
+        assert_eq!(cpu.step() as u32, 4 + cycles::INTERRUPT);
+        assert_eq!(cpu.stats().cycles - before, 4 + cycles::INTERRUPT as u64);
+        assert_eq!(cpu.registers().pc, 0x40);
+        assert_eq!(cpu.mmu.interrupt_flag & 0x05, 0x04);
+    }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_get_byte_from_flags_zero() {
-        let mut cpu = Cpu::new("cartridges/hello.gb");
+        let mut cpu = Cpu::new(HELLO_ROM);
         cpu.set_zero_flag(true);
         let res = cpu.get_byte_from_flags();
         assert_eq!(0b1000_0000, res);
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_get_byte_from_flags_sub() {
-        let mut cpu = Cpu::new("cartridges/hello.gb");
+        let mut cpu = Cpu::new(HELLO_ROM);
         cpu.set_subtraction_flag(true);
         let res = cpu.get_byte_from_flags();
         assert_eq!(0b0100_0000, res);
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_get_byte_from_flags_half() {
-        let mut cpu = Cpu::new("cartridges/hello.gb");
+        let mut cpu = Cpu::new(HELLO_ROM);
         cpu.set_half_carry_flag(true);
         let res = cpu.get_byte_from_flags();
         assert_eq!(0b0010_0000, res);
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_get_byte_from_flags_carry() {
-        let mut cpu = Cpu::new("cartridges/hello.gb");
+        let mut cpu = Cpu::new(HELLO_ROM);
         cpu.set_carry_flag(true);
         let res = cpu.get_byte_from_flags();
         assert_eq!(0b0001_0000, res);
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_get_byte_from_flags_all() {
-        let mut cpu = Cpu::new("cartridges/hello.gb");
+        let mut cpu = Cpu::new(HELLO_ROM);
         cpu.set_zero_flag(true);
         cpu.set_subtraction_flag(true);
         cpu.set_half_carry_flag(true);
@@ -3027,8 +3827,9 @@ mod tests {
         assert_eq!(0b1111_0000, res);
     }
     #[test]
+    #[cfg(feature = "std")]
     fn test_set_flags_from_bytes_zero() {
-        let mut cpu = Cpu::new("cartridges/hello.gb");
+        let mut cpu = Cpu::new(HELLO_ROM);
         cpu.set_flags_from_byte(128);
         assert!(cpu.zero_flag);
         assert!(!cpu.subtraction_flag);
@@ -3037,8 +3838,9 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_set_flags_from_bytes_sub() {
-        let mut cpu = Cpu::new("cartridges/hello.gb");
+        let mut cpu = Cpu::new(HELLO_ROM);
         cpu.set_flags_from_byte(64);
         assert!(!cpu.zero_flag);
         assert!(cpu.subtraction_flag);
@@ -3047,8 +3849,9 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_set_flags_from_bytes_half() {
-        let mut cpu = Cpu::new("cartridges/hello.gb");
+        let mut cpu = Cpu::new(HELLO_ROM);
         cpu.set_flags_from_byte(32);
         assert!(!cpu.zero_flag);
         assert!(!cpu.subtraction_flag);
@@ -3057,8 +3860,9 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_set_flags_from_bytes_carry() {
-        let mut cpu = Cpu::new("cartridges/hello.gb");
+        let mut cpu = Cpu::new(HELLO_ROM);
         cpu.set_flags_from_byte(16);
         assert!(!cpu.zero_flag);
         assert!(!cpu.subtraction_flag);
@@ -3067,8 +3871,9 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_set_flags_from_bytes_all() {
-        let mut cpu = Cpu::new("cartridges/hello.gb");
+        let mut cpu = Cpu::new(HELLO_ROM);
         cpu.set_flags_from_byte(248);
         assert!(cpu.zero_flag);
         assert!(cpu.subtraction_flag);
@@ -3077,8 +3882,9 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_read_r8_all() {
-        let mut cpu = Cpu::new("cartridges/hello.gb");
+        let mut cpu = Cpu::new(HELLO_ROM);
         cpu.write_r8(Register::A, 1);
         cpu.write_r8(Register::B, 2);
         cpu.write_r8(Register::C, 3);
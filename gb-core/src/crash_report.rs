@@ -0,0 +1,227 @@
+//! Bundles what a bug report about a crash needs into one zip file: why it
+//! stopped, the registers, the ROM header, the IO registers, the latest
+//! events and a savestate to reproduce it from.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+
+use crate::cartridge;
+use crate::cpu::Cpu;
+
+const VERSION: &str = concat!("rust-gb ", env!("CARGO_PKG_VERSION"));
+
+/// Everything known about the emulator when it crashed, see `capture`.
+pub struct CrashReport {
+    /// Summary with the reason, version, registers and ROM header.
+    pub report: String,
+    /// The event log, empty unless it was enabled.
+    pub trace: String,
+    pub io: String,
+    /// `Cpu::save_state` at the time of the crash.
+    pub state: Vec<u8>,
+}
+
+impl CrashReport {
+    /// Captures the state of `cpu` after it crashed because of `reason`,
+    /// e.g. a panic message. The CPU may be stopped in the middle of an
+    /// instruction, which the savestate then shows.
+    pub fn capture(cpu: &Cpu, reason: &str) -> Self {
+        let mut report = String::new();
+        writeln!(report, "Reason: {}", reason).unwrap();
+        writeln!(report, "Version: {}", VERSION).unwrap();
+        writeln!(report, "Frame: {}", cpu.frames()).unwrap();
+        writeln!(report, "Cycles: {}", cpu.cycles()).unwrap();
+        let r = cpu.registers();
+        writeln!(
+            report,
+            "AF 0x{:02x}{:02x} BC 0x{:02x}{:02x} DE 0x{:02x}{:02x} HL 0x{:02x}{:02x} SP 0x{:04x} PC 0x{:04x}",
+            r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc
+        )
+        .unwrap();
+        writeln!(report, "Locked up: {}", cpu.is_locked()).unwrap();
+
+        let rom = cpu.mmu.cartridge.rom();
+        match cartridge::analyze(rom) {
+            Ok(header) => {
+                writeln!(report, "Title: {}", header.title).unwrap();
+                writeln!(
+                    report,
+                    "Cartridge type: 0x{:02x} {}",
+                    header.mbc_type, header.mbc_type_name
+                )
+                .unwrap();
+                writeln!(report, "ROM size: {} bytes", header.rom_size).unwrap();
+                writeln!(
+                    report,
+                    "Checksums: header {}, global {}",
+                    if header.header_checksum_ok {
+                        "ok"
+                    } else {
+                        "mismatch"
+                    },
+                    if header.global_checksum_ok {
+                        "ok"
+                    } else {
+                        "mismatch"
+                    }
+                )
+                .unwrap();
+                for warning in &header.warnings {
+                    writeln!(report, "Warning: {}", warning).unwrap();
+                }
+            }
+            Err(e) => writeln!(report, "Header: {}", e).unwrap(),
+        }
+        writeln!(report, "Game id: {}", cpu.game_id()).unwrap();
+
+        CrashReport {
+            report,
+            trace: cpu
+                .mmu
+                .event_log()
+                .map_or(String::new(), |log| log.to_string()),
+            io: cpu.mmu.io_snapshot().to_string(),
+            state: cpu.save_state(),
+        }
+    }
+
+    /// Writes the bundle as a zip with `report.txt`, `trace.log`, `io.txt`
+    /// and `state.bin`.
+    pub fn write_zip<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let entries: [(&str, &[u8]); 4] = [
+            ("report.txt", self.report.as_bytes()),
+            ("trace.log", self.trace.as_bytes()),
+            ("io.txt", self.io.as_bytes()),
+            ("state.bin", &self.state),
+        ];
+        w.write_all(&zip(&entries)?)
+    }
+}
+
+/// Builds a zip archive of deflated files. Every file is dated 1980-01-01,
+/// the earliest date zip can store.
+fn zip(entries: &[(&str, &[u8])]) -> io::Result<Vec<u8>> {
+    const DOS_DATE: u16 = (1 << 5) | 1;
+
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+    for (name, data) in entries {
+        let mut crc = Crc::new();
+        crc.update(data);
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+        let offset = archive.len() as u32;
+
+        // Fields shared by the local header and the directory entry, from
+        // the version needed to the name length
+        let mut common = Vec::new();
+        common.extend_from_slice(&20u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        // Deflate
+        common.extend_from_slice(&8u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&DOS_DATE.to_le_bytes());
+        common.extend_from_slice(&crc.sum().to_le_bytes());
+        common.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+
+        archive.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        archive.extend_from_slice(&common);
+        // No extra field
+        archive.extend_from_slice(&0u16.to_le_bytes());
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(&compressed);
+
+        directory.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        // Made by version 2.0
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        directory.extend_from_slice(&common);
+        // No extra field or comment, disk 0, no attributes
+        directory.extend_from_slice(&[0; 12]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let directory_offset = archive.len() as u32;
+    archive.extend_from_slice(&directory);
+    archive.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&directory_offset.to_le_bytes());
+    // No comment
+    archive.extend_from_slice(&0u16.to_le_bytes());
+    Ok(archive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::rom_with_header;
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    fn u16_at(bytes: &[u8], i: usize) -> u16 {
+        u16::from_le_bytes([bytes[i], bytes[i + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], i: usize) -> u32 {
+        u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+    }
+
+    /// Reads the files back through the central directory.
+    fn unzip(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let end = archive.len() - 22;
+        assert_eq!(u32_at(archive, end), 0x0605_4b50);
+        let mut entry = u32_at(archive, end + 16) as usize;
+        let mut files = Vec::new();
+        for _ in 0..u16_at(archive, end + 10) {
+            assert_eq!(u32_at(archive, entry), 0x0201_4b50);
+            let crc = u32_at(archive, entry + 16);
+            let compressed_size = u32_at(archive, entry + 20) as usize;
+            let name_len = u16_at(archive, entry + 28) as usize;
+            let name = &archive[entry + 46..entry + 46 + name_len];
+            let local = u32_at(archive, entry + 42) as usize;
+            assert_eq!(u32_at(archive, local), 0x0403_4b50);
+            let start = local + 30 + u16_at(archive, local + 26) as usize;
+
+            let mut data = Vec::new();
+            DeflateDecoder::new(&archive[start..start + compressed_size])
+                .read_to_end(&mut data)
+                .unwrap();
+            let mut check = Crc::new();
+            check.update(&data);
+            assert_eq!(check.sum(), crc);
+            files.push((String::from_utf8(name.to_vec()).unwrap(), data));
+            entry += 46 + name_len;
+        }
+        files
+    }
+
+    #[test]
+    fn test_write_zip() {
+        let mut cpu = Cpu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        cpu.mmu.enable_event_log(16);
+        cpu.run_frame_with(|_, _| {});
+        let crash = CrashReport::capture(&cpu, "index out of bounds");
+        assert!(crash.report.starts_with("Reason: index out of bounds\n"));
+        assert!(crash.report.contains(VERSION));
+
+        let mut archive = Vec::new();
+        crash.write_zip(&mut archive).unwrap();
+        let files = unzip(&archive);
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["report.txt", "trace.log", "io.txt", "state.bin"]);
+        assert_eq!(files[0].1, crash.report.as_bytes());
+        assert_eq!(files[3].1, crash.state);
+        let mut restored = Cpu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        restored.load_state(&files[3].1).unwrap();
+        assert_eq!(restored.frames(), cpu.frames());
+    }
+}
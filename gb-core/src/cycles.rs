@@ -0,0 +1,163 @@
+//! Clock cycles each instruction takes, as listed in Pan Docs. `Cpu::exec`
+//! charges every instruction from these tables, so they are the one place
+//! to fix a timing.
+//!
+//! All counts are in clock cycles (T-cycles), 4 per M-cycle.
+
+/// Cycles of each opcode, with conditional jumps, calls and returns not
+/// taken. Illegal opcodes take 4, the fetch before the CPU locks up. 0xCB
+/// is only the prefix; see `CB` for whole CB instructions.
+#[rustfmt::skip]
+pub const OPCODE: [u8; 256] = [
+//  x0  x1  x2  x3  x4  x5  x6  x7  x8  x9  xA  xB  xC  xD  xE  xF
+     4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4, // 0x
+     4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4, // 1x
+     8, 12,  8,  8,  4,  4,  8,  4,  8,  8,  8,  8,  4,  4,  8,  4, // 2x
+     8, 12,  8,  8, 12, 12, 12,  4,  8,  8,  8,  8,  4,  4,  8,  4, // 3x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 4x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 5x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 6x
+     8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4, // 7x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 8x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 9x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // Ax
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // Bx
+     8, 12, 12, 16, 12, 16,  8, 16,  8, 16, 12,  4, 12, 24,  8, 16, // Cx
+     8, 12, 12,  4, 12, 16,  8, 16,  8, 16, 12,  4, 12,  4,  8, 16, // Dx
+    12, 12,  8,  4,  4, 16,  8, 16, 16,  4, 16,  4,  4,  4,  8, 16, // Ex
+    12, 12,  8,  4,  4, 16,  8, 16, 12,  8, 16,  4,  4,  4,  8, 16, // Fx
+];
+
+/// Cycles of each opcode with its condition met. Only JR cc, JP cc, CALL
+/// cc and RET cc differ from `OPCODE`.
+#[rustfmt::skip]
+pub const TAKEN: [u8; 256] = [
+//  x0  x1  x2  x3  x4  x5  x6  x7  x8  x9  xA  xB  xC  xD  xE  xF
+     4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4, // 0x
+     4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4, // 1x
+    12, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4, // 2x
+    12, 12,  8,  8, 12, 12, 12,  4, 12,  8,  8,  8,  4,  4,  8,  4, // 3x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 4x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 5x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 6x
+     8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4, // 7x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 8x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // 9x
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // Ax
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4, // Bx
+    20, 12, 16, 16, 24, 16,  8, 16, 20, 16, 16,  4, 24, 24,  8, 16, // Cx
+    20, 12, 16,  4, 24, 16,  8, 16, 20, 16, 16,  4, 24,  4,  8, 16, // Dx
+    12, 12,  8,  4,  4, 16,  8, 16, 16,  4, 16,  4,  4,  4,  8, 16, // Ex
+    12, 12,  8,  4,  4, 16,  8, 16, 12,  8, 16,  4,  4,  4,  8, 16, // Fx
+];
+
+/// Cycles of each CB-prefixed instruction, the prefix included. Those on
+/// (HL) take longer, BIT less so since it does not write back.
+#[rustfmt::skip]
+pub const CB: [u8; 256] = [
+//  x0  x1  x2  x3  x4  x5  x6  x7  x8  x9  xA  xB  xC  xD  xE  xF
+     8,  8,  8,  8,  8,  8, 16,  8,  8,  8,  8,  8,  8,  8, 16,  8, // 0x
+     8,  8,  8,  8,  8,  8, 16,  8,  8,  8,  8,  8,  8,  8, 16,  8, // 1x
+     8,  8,  8,  8,  8,  8, 16,  8,  8,  8,  8,  8,  8,  8, 16,  8, // 2x
+     8,  8,  8,  8,  8,  8, 16,  8,  8,  8,  8,  8,  8,  8, 16,  8, // 3x
+     8,  8,  8,  8,  8,  8, 12,  8,  8,  8,  8,  8,  8,  8, 12,  8, // 4x
+     8,  8,  8,  8,  8,  8, 12,  8,  8,  8,  8,  8,  8,  8, 12,  8, // 5x
+     8,  8,  8,  8,  8,  8, 12,  8,  8,  8,  8,  8,  8,  8, 12,  8, // 6x
+     8,  8,  8,  8,  8,  8, 12,  8,  8,  8,  8,  8,  8,  8, 12,  8, // 7x
+     8,  8,  8,  8,  8,  8, 16,  8,  8,  8,  8,  8,  8,  8, 16,  8, // 8x
+     8,  8,  8,  8,  8,  8, 16,  8,  8,  8,  8,  8,  8,  8, 16,  8, // 9x
+     8,  8,  8,  8,  8,  8, 16,  8,  8,  8,  8,  8,  8,  8, 16,  8, // Ax
+     8,  8,  8,  8,  8,  8, 16,  8,  8,  8,  8,  8,  8,  8, 16,  8, // Bx
+     8,  8,  8,  8,  8,  8, 16,  8,  8,  8,  8,  8,  8,  8, 16,  8, // Cx
+     8,  8,  8,  8,  8,  8, 16,  8,  8,  8,  8,  8,  8,  8, 16,  8, // Dx
+     8,  8,  8,  8,  8,  8, 16,  8,  8,  8,  8,  8,  8,  8, 16,  8, // Ex
+     8,  8,  8,  8,  8,  8, 16,  8,  8,  8,  8,  8,  8,  8, 16,  8, // Fx
+];
+
+/// Cycles of dispatching an interrupt: two wait states, pushing PC and
+/// jumping to the handler.
+pub const INTERRUPT: u32 = 20;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The opcode table of Pan Docs in M-cycles, "taken/not taken" where
+    /// they differ and "-" for illegal opcodes.
+    const REFERENCE: [&str; 16] = [
+        "1 3 2 2 1 1 2 1 5 2 2 2 1 1 2 1",
+        "1 3 2 2 1 1 2 1 3 2 2 2 1 1 2 1",
+        "3/2 3 2 2 1 1 2 1 3/2 2 2 2 1 1 2 1",
+        "3/2 3 2 2 3 3 3 1 3/2 2 2 2 1 1 2 1",
+        "1 1 1 1 1 1 2 1 1 1 1 1 1 1 2 1",
+        "1 1 1 1 1 1 2 1 1 1 1 1 1 1 2 1",
+        "1 1 1 1 1 1 2 1 1 1 1 1 1 1 2 1",
+        "2 2 2 2 2 2 1 2 1 1 1 1 1 1 2 1",
+        "1 1 1 1 1 1 2 1 1 1 1 1 1 1 2 1",
+        "1 1 1 1 1 1 2 1 1 1 1 1 1 1 2 1",
+        "1 1 1 1 1 1 2 1 1 1 1 1 1 1 2 1",
+        "1 1 1 1 1 1 2 1 1 1 1 1 1 1 2 1",
+        "5/2 3 4/3 4 6/3 4 2 4 5/2 4 4/3 1 6/3 6 2 4",
+        "5/2 3 4/3 - 6/3 4 2 4 5/2 4 4/3 - 6/3 - 2 4",
+        "3 3 2 - - 4 2 4 4 1 4 - - - 2 4",
+        "3 3 2 1 - 4 2 4 3 2 4 1 - - 2 4",
+    ];
+
+    /// The CB table of Pan Docs in M-cycles, by operation (rows) and
+    /// operand B, C, D, E, H, L, (HL), A.
+    const CB_REFERENCE: [(&str, &str); 4] = [
+        ("RLC RRC RL RR SLA SRA SWAP SRL", "2 2 2 2 2 2 4 2"),
+        ("BIT", "2 2 2 2 2 2 3 2"),
+        ("RES", "2 2 2 2 2 2 4 2"),
+        ("SET", "2 2 2 2 2 2 4 2"),
+    ];
+
+    fn m_cycles(text: &str) -> u8 {
+        text.parse::<u8>().unwrap() * 4
+    }
+
+    #[test]
+    fn test_opcode_tables() {
+        for (row, line) in REFERENCE.iter().enumerate() {
+            let entries: Vec<_> = line.split(' ').collect();
+            assert_eq!(entries.len(), 16, "row {:x}", row);
+            for (col, entry) in entries.iter().enumerate() {
+                let opcode = row * 16 + col;
+                let (taken, not_taken) = match *entry {
+                    "-" => (4, 4),
+                    _ => match entry.split_once('/') {
+                        Some((taken, not_taken)) => (m_cycles(taken), m_cycles(not_taken)),
+                        None => (m_cycles(entry), m_cycles(entry)),
+                    },
+                };
+                assert_eq!(OPCODE[opcode], not_taken, "opcode 0x{:02x}", opcode);
+                assert_eq!(TAKEN[opcode], taken, "opcode 0x{:02x} taken", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cb_table() {
+        let mut rows = Vec::new();
+        for (ops, cycles) in CB_REFERENCE.iter() {
+            let operands: Vec<u8> = cycles.split(' ').map(m_cycles).collect();
+            // RLC to SRL take a row of 8 each, BIT, RES and SET 8 rows
+            let count = match ops.split(' ').count() {
+                1 => 8,
+                n => n,
+            };
+            for _ in 0..count {
+                rows.push(operands.clone());
+            }
+        }
+        assert_eq!(rows.len(), 32);
+        for (opcode, &cycles) in CB.iter().enumerate() {
+            assert_eq!(
+                cycles,
+                rows[opcode / 8][opcode % 8],
+                "opcode 0xcb 0x{:02x}",
+                opcode
+            );
+        }
+    }
+}
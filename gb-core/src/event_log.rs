@@ -0,0 +1,131 @@
+use alloc::collections::VecDeque;
+use core::fmt;
+
+use crate::cpu::Interrupt;
+use crate::ppu::PpuReg;
+
+/// Something that helps to explain why a game hangs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogEvent {
+    /// The CPU jumped to an interrupt handler.
+    Interrupt(Interrupt),
+    /// Write to LCDC, STAT, IF or IE.
+    IoWrite { addr: u16, value: u8 },
+    /// Write to the mapper registers in ROM space.
+    BankSwitch { addr: u16, value: u8 },
+    /// OAM DMA from `source << 8`.
+    Dma { source: u8 },
+}
+
+/// A `LogEvent` and the cycle it happened at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub cycle: u64,
+    pub event: LogEvent,
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>12} ", self.cycle)?;
+        match self.event {
+            LogEvent::Interrupt(interrupt) => write!(f, "IRQ  {:?}", interrupt),
+            LogEvent::IoWrite { addr, value } => {
+                let name = match addr {
+                    0xff0f => "IF",
+                    0xffff => "IE",
+                    _ => PpuReg::from_addr(addr).map_or("?", PpuReg::name),
+                };
+                write!(f, "IO   {} (0x{:04x}) = 0x{:02x}", name, addr, value)
+            }
+            LogEvent::BankSwitch { addr, value } => {
+                write!(f, "MBC  0x{:04x} = 0x{:02x}", addr, value)
+            }
+            LogEvent::Dma { source } => write!(f, "DMA  0x{:02x}00", source),
+        }
+    }
+}
+
+/// Keeps the latest events up to a fixed number, dropping the oldest.
+pub struct EventLog {
+    entries: VecDeque<LogEntry>,
+    capacity: usize,
+}
+
+impl EventLog {
+    /// Creates a new `EventLog` holding at most `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        EventLog {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub(crate) fn push(&mut self, cycle: u64, event: LogEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(LogEntry { cycle, event });
+    }
+
+    /// Entries from oldest to newest.
+    pub fn entries(&self) -> impl Iterator<Item = &LogEntry> {
+        self.entries.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// One entry per line, oldest first.
+impl fmt::Display for EventLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drops_oldest() {
+        let mut log = EventLog::new(2);
+        log.push(1, LogEvent::Dma { source: 0xc1 });
+        log.push(2, LogEvent::Interrupt(Interrupt::VBlank));
+        log.push(
+            3,
+            LogEvent::BankSwitch {
+                addr: 0x2000,
+                value: 0x05,
+            },
+        );
+
+        let cycles: Vec<u64> = log.entries().map(|e| e.cycle).collect();
+        assert_eq!(cycles, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut log = EventLog::new(4);
+        log.push(
+            70224,
+            LogEvent::IoWrite {
+                addr: 0xff40,
+                value: 0x91,
+            },
+        );
+        log.push(70300, LogEvent::Interrupt(Interrupt::VBlank));
+
+        assert_eq!(
+            log.to_string(),
+            "       70224 IO   LCDC (0xff40) = 0x91\n       70300 IRQ  VBlank\n"
+        );
+    }
+}
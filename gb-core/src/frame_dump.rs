@@ -0,0 +1,151 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::romdb::crc32;
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// How `FrameDumper` writes each frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameFormat {
+    /// The RGB24 bytes of `Ppu::get_frame` as they are, 160x144, without a
+    /// header, e.g. for `cmp` or ffmpeg's rawvideo input.
+    Rgb,
+    /// A PNG image, for viewers and image diff tools.
+    Png,
+}
+
+impl FrameFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            FrameFormat::Rgb => "rgb",
+            FrameFormat::Png => "png",
+        }
+    }
+}
+
+/// Writes frames to numbered files in a directory, 000000.png, 000001.png
+/// and so on, to compare runs frame by frame with other emulators.
+pub struct FrameDumper {
+    dir: PathBuf,
+    format: FrameFormat,
+    /// Frames written so far.
+    frames: u64,
+}
+
+impl FrameDumper {
+    /// Creates `dir` if needed and a new `FrameDumper` writing to it.
+    pub fn create<P: AsRef<Path>>(dir: P, format: FrameFormat) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(FrameDumper {
+            dir: dir.as_ref().to_path_buf(),
+            format,
+            frames: 0,
+        })
+    }
+
+    /// Writes the next frame, RGB24 as `Ppu::get_frame` returns it.
+    /// Returns the path written.
+    pub fn write(&mut self, frame: &[u8]) -> io::Result<PathBuf> {
+        let path = self
+            .dir
+            .join(format!("{:06}.{}", self.frames, self.format.extension()));
+        match self.format {
+            FrameFormat::Rgb => fs::write(&path, frame)?,
+            FrameFormat::Png => fs::write(&path, encode_png(frame, SCREEN_WIDTH, SCREEN_HEIGHT)?)?,
+        }
+        self.frames += 1;
+        Ok(path)
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+/// Encodes `width` x `height` RGB24 pixels as a PNG image.
+pub fn encode_png(rgb: &[u8], width: usize, height: usize) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // Each row starts with its filter type, 0 for none
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in rgb.chunks(width * 3).take(height) {
+        zlib.write_all(&[0])?;
+        zlib.write_all(row)?;
+    }
+    let data = zlib.finish()?;
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    for (kind, content) in [(b"IHDR", &header), (b"IDAT", &data), (b"IEND", &Vec::new())] {
+        png.extend_from_slice(&(content.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(content);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::convert::TryInto;
+    use std::env;
+    use std::io::Read;
+
+    #[test]
+    fn test_encode_png() {
+        let rgb = [
+            0xff, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0xff, 0x12, 0x34, 0x56,
+        ];
+        let png = encode_png(&rgb, 2, 2).unwrap();
+        assert_eq!(&png[..8], PNG_SIGNATURE);
+
+        // Walk the chunks, checking each CRC
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let body = &png[pos + 4..pos + 8 + len];
+            let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc32(body), crc);
+            chunks.push((body[..4].to_vec(), body[4..].to_vec()));
+            pos += 12 + len;
+        }
+        let kinds: Vec<_> = chunks.iter().map(|(kind, _)| kind.as_slice()).collect();
+        assert_eq!(kinds, vec![&b"IHDR"[..], b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+
+        let mut pixels = Vec::new();
+        ZlibDecoder::new(&chunks[1].1[..])
+            .read_to_end(&mut pixels)
+            .unwrap();
+        assert_eq!(pixels[0], 0);
+        assert_eq!(&pixels[1..7], &rgb[..6]);
+        assert_eq!(pixels[7], 0);
+        assert_eq!(&pixels[8..], &rgb[6..]);
+    }
+
+    #[test]
+    fn test_numbered_files() {
+        let dir = env::temp_dir().join(format!("rust-gb-frames-{}", std::process::id()));
+        let mut dumper = FrameDumper::create(&dir, FrameFormat::Rgb).unwrap();
+        let frame = vec![0x12; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        assert_eq!(dumper.write(&frame).unwrap(), dir.join("000000.rgb"));
+        assert_eq!(dumper.write(&frame).unwrap(), dir.join("000001.rgb"));
+        assert_eq!(dumper.frames(), 2);
+        assert_eq!(fs::read(dir.join("000001.rgb")).unwrap(), frame);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
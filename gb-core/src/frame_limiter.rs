@@ -0,0 +1,319 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::apu::CPU_CLOCK_HZ;
+use crate::cpu::{CYCLES_PER_FRAME, LINES_PER_FRAME};
+use crate::speed::Speed;
+
+/// Frames per second of real hardware, about 59.7275.
+pub const FRAME_RATE: f64 = CPU_CLOCK_HZ as f64 / CYCLES_PER_FRAME as f64;
+/// Frames `Pacing::Vsync` runs at most for one refresh before it gives up
+/// catching up.
+const MAX_VSYNC_FRAMES: u64 = 2;
+/// How far from a whole number of refreshes per frame `vsync_is_even`
+/// allows, about the 60 Hz of most displays.
+const EVEN_REFRESH_TOLERANCE: f64 = 0.02;
+
+/// Where `FrameLimiter` gets the time from.
+pub trait Clock {
+    /// Time since some fixed point, e.g. when the clock was created.
+    fn now(&self) -> Duration;
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// The host's monotonic clock.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    /// Creates a new `SystemClock`.
+    pub fn new() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// What keeps the frames in step with real time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pacing {
+    /// `FrameLimiter` sleeps until each frame is due.
+    Sleep,
+    /// Presenting blocks until the display refreshes. `FrameLimiter` does
+    /// not sleep and instead runs as many frames per refresh as have come
+    /// due, e.g. one most of the time on a 60 Hz display and none every
+    /// few seconds.
+    Vsync,
+}
+
+/// Whether `Pacing::Vsync` shows every frame for the same number of
+/// refreshes of a display running at `refresh_hz`, as on 60 or 120 Hz
+/// displays, but for a repeated or dropped frame every few seconds. On
+/// others, like 75 or 144 Hz, the frames alternate between lengths and
+/// motion judders.
+pub fn vsync_is_even(refresh_hz: f64) -> bool {
+    let refreshes = refresh_hz / FRAME_RATE;
+    refreshes.round() >= 1.0 && (refreshes - refreshes.round()).abs() < EVEN_REFRESH_TOLERANCE
+}
+
+/// Runs frames at the rate of real hardware, or `Speed` times it, or a
+/// turbo multiple of it.
+///
+/// Frame `n` is due `n` frame lengths after the limiter last synced with
+/// the clock, worked out in nanoseconds every time, so the rate stays
+/// 59.7275 Hz however long it runs.
+pub struct FrameLimiter<C: Clock = SystemClock> {
+    clock: C,
+    pacing: Pacing,
+    /// Frames to run in the time of one.
+    turbo: u32,
+    /// Length of a frame at `speed`, in nanoseconds.
+    frame_nanos: f64,
+    /// When the first frame since the last sync started.
+    epoch: Duration,
+    /// Frames since `epoch`.
+    frame: u64,
+}
+
+impl<C: Clock> FrameLimiter<C> {
+    /// Creates a new `FrameLimiter` at full speed, with the first frame due
+    /// now.
+    pub fn new(clock: C, pacing: Pacing) -> Self {
+        let epoch = clock.now();
+        let mut limiter = FrameLimiter {
+            clock,
+            pacing,
+            turbo: 1,
+            frame_nanos: 0.0,
+            epoch,
+            frame: 0,
+        };
+        limiter.set_speed(Speed::default());
+        limiter
+    }
+
+    pub fn pacing(&self) -> Pacing {
+        self.pacing
+    }
+
+    /// Slows the frames down, starting over from now.
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.frame_nanos = 1e9 / FRAME_RATE / speed.factor() as f64;
+        self.resync();
+    }
+
+    /// Runs `multiplier` frames in the time of one, e.g. while the
+    /// fast-forward key is held. 1 turns turbo off.
+    pub fn set_turbo(&mut self, multiplier: u32) {
+        self.turbo = multiplier.max(1);
+    }
+
+    pub fn turbo(&self) -> u32 {
+        self.turbo
+    }
+
+    /// Real time each frame takes, or each `turbo` frames.
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_nanos(self.frame_nanos.round() as u64)
+    }
+
+    /// Starts over from now, without trying to catch up on lost time, e.g.
+    /// after a pause.
+    pub fn resync(&mut self) {
+        self.epoch = self.clock.now();
+        self.frame = 0;
+    }
+
+    /// Frames to run before the next `end_frame`.
+    pub fn frames_due(&mut self) -> u32 {
+        let frames = match self.pacing {
+            Pacing::Sleep => 1,
+            Pacing::Vsync => {
+                let elapsed = self.clock.now().saturating_sub(self.epoch);
+                let now = (elapsed.as_nanos() as f64 / self.frame_nanos) as u64;
+                let due = now.saturating_sub(self.frame);
+                if due > MAX_VSYNC_FRAMES {
+                    self.resync();
+                    1
+                } else {
+                    self.frame = now;
+                    due as u32
+                }
+            }
+        };
+        frames * self.turbo
+    }
+
+    /// Sleeps until `line` scanlines into the current frame are due, so a
+    /// frame spreads over its time and input read on the way is fresh.
+    /// Does nothing with vsync or turbo. Returns the time slept.
+    pub fn wait_for_line(&mut self, line: u32) -> Duration {
+        if self.pacing != Pacing::Sleep || self.turbo > 1 {
+            return Duration::ZERO;
+        }
+        let due = self.due(self.frame as f64 + line as f64 / LINES_PER_FRAME as f64);
+        let start = self.clock.now();
+        match due.checked_sub(start) {
+            Some(early) if !early.is_zero() => {
+                self.clock.sleep(early);
+                self.clock.now() - start
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Sleeps until the next frame is due. A frame that is late already is
+    /// caught up on by not sleeping, unless it is more than a frame late or
+    /// `catch_up` is false, e.g. while the window is in the background;
+    /// then the limiter starts over from now.
+    ///
+    /// Returns how much later than due the sleep ended.
+    pub fn end_frame(&mut self, catch_up: bool) -> Duration {
+        if self.pacing == Pacing::Vsync {
+            if !catch_up {
+                self.resync();
+            }
+            return Duration::ZERO;
+        }
+        let next = self.due((self.frame + 1) as f64);
+        let now = self.clock.now();
+        if next > now {
+            self.clock.sleep(next - now);
+            self.frame += 1;
+            self.clock.now().saturating_sub(next)
+        } else if !catch_up || now - next > self.frame_duration() {
+            self.resync();
+            Duration::ZERO
+        } else {
+            self.frame += 1;
+            Duration::ZERO
+        }
+    }
+
+    /// When `frame` frames after `epoch` are due.
+    fn due(&self, frame: f64) -> Duration {
+        self.epoch + Duration::from_nanos((frame * self.frame_nanos).round() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Time that only moves when slept through or told to.
+    #[derive(Default)]
+    struct ManualClock {
+        now: Duration,
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Duration {
+            self.now
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.now += duration;
+        }
+    }
+
+    #[test]
+    fn test_rate_does_not_drift() {
+        let mut limiter = FrameLimiter::new(ManualClock::default(), Pacing::Sleep);
+        for _ in 0..59_727 {
+            assert_eq!(limiter.frames_due(), 1);
+            limiter.end_frame(true);
+        }
+        // Not 59727 times 16742 or 16743 us
+        let elapsed = limiter.clock.now.as_secs_f64();
+        assert!(
+            (elapsed - 59_727.0 / FRAME_RATE).abs() < 1e-6,
+            "{}",
+            elapsed
+        );
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let mut limiter = FrameLimiter::new(ManualClock::default(), Pacing::Sleep);
+        let frame = limiter.frame_duration();
+        // Frames are due at a fraction of a nanosecond more than `frame`
+        let assert_near = |a: Duration, b: Duration| {
+            assert!(a.as_nanos().abs_diff(b.as_nanos()) <= 1, "{:?} {:?}", a, b);
+        };
+        // Half a frame late is caught up on without sleeping
+        limiter.clock.now = frame + frame / 2;
+        assert_eq!(limiter.end_frame(true), Duration::ZERO);
+        limiter.end_frame(true);
+        assert_near(limiter.clock.now, frame * 2);
+
+        // Two frames late starts over
+        limiter.clock.now = frame * 5;
+        limiter.end_frame(true);
+        limiter.end_frame(true);
+        assert_near(limiter.clock.now, frame * 6);
+
+        limiter.set_speed(Speed::new(0.5));
+        limiter.end_frame(true);
+        assert_near(limiter.clock.now, frame * 8);
+    }
+
+    #[test]
+    fn test_wait_for_line() {
+        let mut limiter = FrameLimiter::new(ManualClock::default(), Pacing::Sleep);
+        let frame = limiter.frame_duration();
+        assert_eq!(limiter.wait_for_line(77), frame / 2);
+        assert_eq!(limiter.wait_for_line(10), Duration::ZERO);
+
+        limiter.set_turbo(4);
+        assert_eq!(limiter.frames_due(), 4);
+        assert_eq!(limiter.wait_for_line(150), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_vsync() {
+        let mut limiter = FrameLimiter::new(ManualClock::default(), Pacing::Vsync);
+        let refresh = Duration::from_nanos(1_000_000_000 / 60);
+        let mut frames = 0;
+        for _ in 0..600 {
+            limiter.clock.now += refresh;
+            let due = limiter.frames_due();
+            assert!(due <= 1);
+            frames += due;
+            assert_eq!(limiter.end_frame(true), Duration::ZERO);
+        }
+        // 10 s of a 60 Hz display
+        assert_eq!(frames, 597);
+        assert_eq!(limiter.clock.now, refresh * 600);
+
+        // A long stall is not caught up on
+        limiter.clock.now += refresh * 30;
+        assert_eq!(limiter.frames_due(), 1);
+    }
+
+    #[test]
+    fn test_vsync_is_even() {
+        for hz in [59.73, 60.0, 59.94, 120.0, 240.0] {
+            assert!(vsync_is_even(hz), "{}", hz);
+        }
+        for hz in [0.0, 30.0, 50.0, 75.0, 90.0, 144.0, 165.0] {
+            assert!(!vsync_is_even(hz), "{}", hz);
+        }
+    }
+}
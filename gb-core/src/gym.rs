@@ -0,0 +1,152 @@
+//! A reinforcement learning environment in the style of OpenAI Gym: an
+//! agent picks buttons, the game runs a few frames, and the agent gets the
+//! screen and a reward back. Bindings for Python or other languages only
+//! need to wrap `GymEnv::reset` and `GymEnv::step`.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::cpu::{BatchOutput, Cpu, KeepFrames};
+use crate::joypad::ButtonState;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Shape of an observation: rows, columns and RGB channels.
+pub const OBSERVATION_SHAPE: [usize; 3] = [SCREEN_HEIGHT, SCREEN_WIDTH, 3];
+/// Actions are `ButtonState` bits, so every combination of buttons.
+pub const ACTION_COUNT: usize = 256;
+
+/// Scores the game after a step, e.g. from a score or position in RAM.
+pub type RewardFn = Box<dyn FnMut(&Cpu) -> f32>;
+/// Whether the episode is over, e.g. on a game over screen.
+pub type DoneFn = Box<dyn FnMut(&Cpu) -> bool>;
+
+/// What `GymEnv::step` returns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    /// The last frame of the step, RGB24 in `OBSERVATION_SHAPE`.
+    pub observation: Vec<u8>,
+    pub reward: f32,
+    /// The episode ended: the done function said so, or the game locked up.
+    pub done: bool,
+    /// The episode ran out of steps, see `GymEnv::set_max_steps`.
+    pub truncated: bool,
+    /// Audio and serial bytes of the step, for agents that want them.
+    pub output: BatchOutput,
+}
+
+/// A game as an environment. Every episode starts from the state the
+/// `Cpu` was in when the environment was created, so episodes can start
+/// past the title screen from a loaded savestate.
+pub struct GymEnv {
+    cpu: Cpu,
+    /// Savestate `reset` goes back to.
+    start: Vec<u8>,
+    /// Frames each action is held for.
+    frame_skip: usize,
+    max_steps: Option<u64>,
+    steps: u64,
+    reward: RewardFn,
+    done: DoneFn,
+}
+
+impl GymEnv {
+    /// Creates a new `GymEnv` holding each action for `frame_skip` frames,
+    /// at least 1. There is no reward and episodes never end until set.
+    pub fn new(cpu: Cpu, frame_skip: usize) -> Self {
+        GymEnv {
+            start: cpu.save_state(),
+            cpu,
+            frame_skip: frame_skip.max(1),
+            max_steps: None,
+            steps: 0,
+            reward: Box::new(|_| 0.0),
+            done: Box::new(|_| false),
+        }
+    }
+
+    pub fn set_reward(&mut self, reward: RewardFn) {
+        self.reward = reward;
+    }
+
+    pub fn set_done(&mut self, done: DoneFn) {
+        self.done = done;
+    }
+
+    /// Cuts episodes off after `max_steps` steps, or never with `None`.
+    pub fn set_max_steps(&mut self, max_steps: Option<u64>) {
+        self.max_steps = max_steps;
+    }
+
+    /// The machine, e.g. for reward functions to test against or to read
+    /// RAM as a smaller observation than the screen.
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// Starts a new episode and returns its first observation.
+    pub fn reset(&mut self) -> Vec<u8> {
+        self.cpu
+            .load_state(&self.start)
+            .expect("state saved by the same machine");
+        self.steps = 0;
+        // Leftovers of the last episode
+        self.cpu.mmu.take_audio_samples();
+        self.cpu.mmu.ppu.get_frame().to_vec()
+    }
+
+    /// Holds the buttons of `action`, an index below `ACTION_COUNT`, for
+    /// the frame skip and returns what came of it.
+    pub fn step(&mut self, action: u8) -> Step {
+        let buttons = ButtonState::from_bits(action);
+        let mut output = self
+            .cpu
+            .run_frames(self.frame_skip, &[buttons], KeepFrames::Last);
+        self.steps += 1;
+        Step {
+            observation: core::mem::take(&mut output.frames),
+            reward: (self.reward)(&self.cpu),
+            done: (self.done)(&self.cpu) || self.cpu.is_locked(),
+            truncated: self.max_steps.is_some_and(|max| self.steps >= max),
+            output,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::{fix_checksum, rom_with_header};
+    use crate::joypad::Key;
+
+    #[test]
+    fn test_episode() {
+        let mut rom = rom_with_header(0x00, 0x00);
+        rom[0x100..0x106].copy_from_slice(&[
+            0x21, 0x00, 0xc0, // ld hl, 0xc000
+            0x34, // loop: inc (hl)
+            0x18, 0xfd, // jr loop
+        ]);
+        fix_checksum(&mut rom);
+        let mut env = GymEnv::new(Cpu::with_rom(rom).unwrap(), 4);
+        env.set_reward(Box::new(|cpu| cpu.mmu.peek(0xc000) as f32));
+        env.set_done(Box::new(|cpu| cpu.frames() >= 8));
+        env.set_max_steps(Some(3));
+
+        let observation = env.reset();
+        assert_eq!(observation.len(), OBSERVATION_SHAPE.iter().product());
+        let start = ButtonState::default().with(Key::Start);
+        let step = env.step(start.bits());
+        assert_eq!(step.observation.len(), observation.len());
+        assert_ne!(step.reward, 0.0);
+        assert!(!step.done && !step.truncated);
+        assert_eq!(env.cpu().frames(), 4);
+        assert!(env.cpu().mmu.joypad.state().is_pressed(Key::Start));
+        assert!(env.step(0).done);
+        assert!(env.step(0).truncated);
+
+        env.reset();
+        assert_eq!(env.cpu().frames(), 0);
+        assert_eq!(env.cpu().mmu.peek(0xc000), 0);
+        assert!(!env.step(0).truncated);
+    }
+}
@@ -0,0 +1,230 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+/// Bytes per page, the granularity of the whole-address-space counters.
+pub const PAGE_SIZE: usize = 0x100;
+const NUM_PAGES: usize = 0x10000 / PAGE_SIZE;
+/// Pixels per page in `write_ppm`, which lays the pages out 16 by 16.
+#[cfg(feature = "std")]
+const CELL_PIXELS: usize = 8;
+
+/// Read and write counts of one page or byte.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccessCount {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+/// Counters that can be bumped through `&self`, as `Mmu::read_byte` is.
+struct Counters {
+    reads: Vec<Cell<u64>>,
+    writes: Vec<Cell<u64>>,
+}
+
+impl Counters {
+    fn new(len: usize) -> Self {
+        Counters {
+            reads: vec![Cell::new(0); len],
+            writes: vec![Cell::new(0); len],
+        }
+    }
+
+    fn bump(counter: &Cell<u64>) {
+        counter.set(counter.get().saturating_add(1));
+    }
+
+    fn get(&self, index: usize) -> AccessCount {
+        AccessCount {
+            reads: self.reads[index].get(),
+            writes: self.writes[index].get(),
+        }
+    }
+}
+
+/// Counts memory accesses per page, and per byte inside an optional range,
+/// to find a game's hot variables or a busy loop.
+///
+/// Everything that goes through the bus counts, including tools that read
+/// memory while the heatmap is on. OAM DMA takes its own path but counts
+/// too, a read of the source and a write to OAM per byte. `Mmu::peek` does
+/// not count.
+pub struct AccessHeatmap {
+    pages: Counters,
+    detail: Option<(Range<u16>, Counters)>,
+}
+
+impl Default for AccessHeatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccessHeatmap {
+    /// Creates a new `AccessHeatmap` counting per page only.
+    pub fn new() -> Self {
+        AccessHeatmap {
+            pages: Counters::new(NUM_PAGES),
+            detail: None,
+        }
+    }
+
+    /// Creates a new `AccessHeatmap` that also counts every byte in `range`.
+    pub fn with_detail(range: Range<u16>) -> Self {
+        let len = range.len();
+        AccessHeatmap {
+            pages: Counters::new(NUM_PAGES),
+            detail: Some((range, Counters::new(len))),
+        }
+    }
+
+    pub(crate) fn record_read(&self, addr: u16) {
+        Counters::bump(&self.pages.reads[addr as usize / PAGE_SIZE]);
+        if let Some((range, bytes)) = &self.detail {
+            if range.contains(&addr) {
+                Counters::bump(&bytes.reads[(addr - range.start) as usize]);
+            }
+        }
+    }
+
+    pub(crate) fn record_write(&self, addr: u16) {
+        Counters::bump(&self.pages.writes[addr as usize / PAGE_SIZE]);
+        if let Some((range, bytes)) = &self.detail {
+            if range.contains(&addr) {
+                Counters::bump(&bytes.writes[(addr - range.start) as usize]);
+            }
+        }
+    }
+
+    /// Counts of page `page`, which covers `page * PAGE_SIZE` onwards.
+    pub fn page(&self, page: u8) -> AccessCount {
+        self.pages.get(page as usize)
+    }
+
+    /// Counts of `addr` if it is inside the detail range.
+    pub fn byte(&self, addr: u16) -> Option<AccessCount> {
+        let (range, bytes) = self.detail.as_ref()?;
+        if range.contains(&addr) {
+            Some(bytes.get((addr - range.start) as usize))
+        } else {
+            None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        let detail = self.detail.as_ref().map(|(_, bytes)| bytes);
+        for counters in Some(&self.pages).into_iter().chain(detail) {
+            counters
+                .reads
+                .iter()
+                .chain(&counters.writes)
+                .for_each(|c| c.set(0));
+        }
+    }
+
+    /// Writes `addr,size,reads,writes` rows: every page with a size of
+    /// `PAGE_SIZE`, then every byte of the detail range with a size of 1.
+    #[cfg(feature = "std")]
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "addr,size,reads,writes")?;
+        for page in 0..NUM_PAGES {
+            let count = self.pages.get(page);
+            writeln!(
+                w,
+                "0x{:04x},{},{},{}",
+                page * PAGE_SIZE,
+                PAGE_SIZE,
+                count.reads,
+                count.writes
+            )?;
+        }
+        if let Some((range, bytes)) = &self.detail {
+            for (i, addr) in range.clone().enumerate() {
+                let count = bytes.get(i);
+                writeln!(w, "0x{:04x},1,{},{}", addr, count.reads, count.writes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the pages as a binary PPM image, 16 pages per row starting at
+    /// 0x0000. Reads are green and writes are red, on a log scale.
+    #[cfg(feature = "std")]
+    pub fn write_ppm<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let side = 16 * CELL_PIXELS;
+        let max = (0..NUM_PAGES)
+            .map(|page| self.pages.get(page))
+            .flat_map(|count| [count.reads, count.writes])
+            .max()
+            .unwrap_or(0);
+        let shade = |value: u64| -> u8 {
+            if max == 0 {
+                return 0;
+            }
+            let scaled = ((value as f64).ln_1p() / (max as f64).ln_1p()) * 255.0;
+            scaled.round() as u8
+        };
+
+        write!(w, "P6\n{} {}\n255\n", side, side)?;
+        for y in 0..side {
+            for x in 0..side {
+                let count = self.pages.get(y / CELL_PIXELS * 16 + x / CELL_PIXELS);
+                w.write_all(&[shade(count.writes), shade(count.reads), 0])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts() {
+        let mut heatmap = AccessHeatmap::with_detail(0xc000..0xc010);
+        heatmap.record_read(0xc001);
+        heatmap.record_read(0xc0ff);
+        heatmap.record_write(0xc001);
+        heatmap.record_write(0xff80);
+
+        assert_eq!(
+            heatmap.page(0xc0),
+            AccessCount {
+                reads: 2,
+                writes: 1
+            }
+        );
+        assert_eq!(heatmap.page(0xff).writes, 1);
+        assert_eq!(
+            heatmap.byte(0xc001),
+            Some(AccessCount {
+                reads: 1,
+                writes: 1
+            })
+        );
+        assert_eq!(heatmap.byte(0xc0ff), None);
+
+        heatmap.clear();
+        assert_eq!(heatmap.page(0xc0), AccessCount::default());
+        assert_eq!(heatmap.byte(0xc001), Some(AccessCount::default()));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_csv() {
+        let heatmap = AccessHeatmap::with_detail(0xc000..0xc002);
+        heatmap.record_write(0xc001);
+        let mut csv = Vec::new();
+        heatmap.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 1 + NUM_PAGES + 2);
+        assert_eq!(lines[1 + 0xc0], "0xc000,256,0,1");
+        assert_eq!(lines[NUM_PAGES + 2], "0xc001,1,0,1");
+    }
+}
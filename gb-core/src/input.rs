@@ -0,0 +1,590 @@
+//! Where the buttons come from. `Cpu::run_frame` asks an `InputProvider`
+//! at the points a `PollRate` sets, so a keyboard, a script, a recorded
+//! movie or a netplay peer all drive the joypad the same way.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::error;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+#[cfg(feature = "std")]
+use log::warn;
+
+use crate::joypad::ButtonState;
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[cfg(feature = "std")]
+const LOG_TARGET: &str = "gb::input";
+
+/// Players of SGB multiplayer; other games only read the first.
+pub const MAX_PLAYERS: usize = 4;
+
+/// Buttons of every player at once.
+pub type Players = [ButtonState; MAX_PLAYERS];
+
+const MOVIE_MAGIC: &[u8; 4] = b"GBMV";
+const MOVIE_VERSION: u32 = 1;
+
+/// When `Cpu::run_frame` polls its `InputProvider`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PollRate {
+    /// Once, as the frame starts.
+    Frame,
+    /// As the frame starts and every N scanlines after, so presses land
+    /// within the frame as they would on hardware.
+    Lines(u32),
+}
+
+impl PollRate {
+    /// Whether to poll `line` scanlines into the frame.
+    pub(crate) fn polls_at(self, line: u32) -> bool {
+        match self {
+            PollRate::Frame => line == 0,
+            PollRate::Lines(lines) => line.is_multiple_of(lines.max(1)),
+        }
+    }
+
+    /// 0 for `Frame`, the number of lines otherwise.
+    fn to_code(self) -> u32 {
+        match self {
+            PollRate::Frame => 0,
+            PollRate::Lines(lines) => lines.max(1),
+        }
+    }
+
+    fn from_code(code: u32) -> Self {
+        match code {
+            0 => PollRate::Frame,
+            lines => PollRate::Lines(lines),
+        }
+    }
+}
+
+/// Where in the emulation a poll happens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PollPoint {
+    /// Frames run since power-on or reset.
+    pub frame: u64,
+    /// Scanlines run so far in the frame, 0 as it starts.
+    pub line: u32,
+}
+
+/// A source of buttons for the joypad.
+pub trait InputProvider {
+    /// Buttons held by each player at `at`.
+    fn poll(&mut self, at: PollPoint) -> Players;
+}
+
+impl<P: InputProvider + ?Sized> InputProvider for &mut P {
+    fn poll(&mut self, at: PollPoint) -> Players {
+        (**self).poll(at)
+    }
+}
+
+/// Buttons set ahead of time frame by frame, e.g. for tests or to get
+/// through a menu before measuring something.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptedInput {
+    /// For each player, the buttons held from each frame on.
+    changes: [BTreeMap<u64, ButtonState>; MAX_PLAYERS],
+}
+
+impl ScriptedInput {
+    /// Creates a new `ScriptedInput` that holds nothing.
+    pub fn new() -> Self {
+        ScriptedInput::default()
+    }
+
+    /// Holds `buttons` for `player` from `frame` until the next change for
+    /// the same player.
+    pub fn hold(&mut self, frame: u64, player: usize, buttons: ButtonState) {
+        self.changes[player].insert(frame, buttons);
+    }
+}
+
+impl InputProvider for ScriptedInput {
+    fn poll(&mut self, at: PollPoint) -> Players {
+        let mut players = Players::default();
+        for (buttons, changes) in players.iter_mut().zip(&self.changes) {
+            if let Some((_, held)) = changes.range(..=at.frame).next_back() {
+                *buttons = *held;
+            }
+        }
+        players
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MovieError {
+    /// The data is not a movie.
+    BadMagic,
+    /// The movie was written by an incompatible version.
+    UnsupportedVersion(u32),
+    /// The data ended in the middle of the movie.
+    Truncated,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::BadMagic => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(v) => write!(f, "unsupported movie version {}", v),
+            MovieError::Truncated => write!(f, "movie is truncated"),
+        }
+    }
+}
+
+impl error::Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(_: StateError) -> Self {
+        MovieError::Truncated
+    }
+}
+
+/// The buttons of every poll of a run, to play it back exactly.
+///
+/// Playing back only gives the same run from the same starting point:
+/// the same game, from power-on or reset, with the same `PollRate`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    /// `Cartridge::rom_hash` of the game it was recorded on.
+    pub rom_hash: u64,
+    pub rate: PollRate,
+    pub polls: Vec<Players>,
+}
+
+impl Movie {
+    /// Creates a new empty `Movie`.
+    pub fn new(rom_hash: u64, rate: PollRate) -> Self {
+        Movie {
+            rom_hash,
+            rate,
+            polls: Vec::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.write_bytes(MOVIE_MAGIC);
+        w.write_u32(MOVIE_VERSION);
+        w.write_u64(self.rom_hash);
+        w.write_u32(self.rate.to_code());
+        let polls: Vec<u8> = self.polls.iter().flatten().map(|b| b.bits()).collect();
+        w.write_vec(&polls);
+        w.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        let mut r = StateReader::new(data);
+        let mut magic = [0; 4];
+        r.read_bytes(&mut magic)?;
+        if &magic != MOVIE_MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        let version = r.read_u32()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_hash = r.read_u64()?;
+        let rate = PollRate::from_code(r.read_u32()?);
+        let polls = r.read_vec()?;
+        if polls.len() % MAX_PLAYERS != 0 {
+            return Err(MovieError::Truncated);
+        }
+        let polls = polls
+            .chunks(MAX_PLAYERS)
+            .map(|poll| {
+                let mut players = Players::default();
+                for (buttons, &bits) in players.iter_mut().zip(poll) {
+                    *buttons = ButtonState::from_bits(bits);
+                }
+                players
+            })
+            .collect();
+        Ok(Movie {
+            rom_hash,
+            rate,
+            polls,
+        })
+    }
+}
+
+/// Passes on the buttons of another `InputProvider` and records them.
+pub struct MovieRecorder<P: InputProvider> {
+    input: P,
+    movie: Movie,
+}
+
+impl<P: InputProvider> MovieRecorder<P> {
+    /// Creates a new `MovieRecorder` recording `input`, see `Movie::new`.
+    pub fn new(input: P, rom_hash: u64, rate: PollRate) -> Self {
+        MovieRecorder {
+            input,
+            movie: Movie::new(rom_hash, rate),
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn into_movie(self) -> Movie {
+        self.movie
+    }
+}
+
+impl<P: InputProvider> InputProvider for MovieRecorder<P> {
+    fn poll(&mut self, at: PollPoint) -> Players {
+        let players = self.input.poll(at);
+        self.movie.polls.push(players);
+        players
+    }
+}
+
+/// Plays back a `Movie`, then lets go of every button.
+pub struct MoviePlayer {
+    movie: Movie,
+    next: usize,
+}
+
+impl MoviePlayer {
+    /// Creates a new `MoviePlayer` at the start of `movie`.
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer { movie, next: 0 }
+    }
+
+    /// The rate to run with, the one the movie was recorded at.
+    pub fn rate(&self) -> PollRate {
+        self.movie.rate
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.movie.polls.len()
+    }
+}
+
+impl InputProvider for MoviePlayer {
+    fn poll(&mut self, _at: PollPoint) -> Players {
+        let players = self.movie.polls.get(self.next).copied();
+        self.next += 1;
+        players.unwrap_or_default()
+    }
+}
+
+/// The two ends of a netplay session no longer run the same machine, as
+/// found by `NetplayInput::check_sync`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Desync {
+    /// Frame the local end compared at.
+    pub frame: u64,
+    /// Frame the peer compared at, normally the same.
+    pub peer_frame: u64,
+    /// Components whose state differs, as `Cpu::state_hashes` names them.
+    /// Empty if the peer hashed other components, e.g. on another version.
+    pub components: Vec<&'static str>,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.peer_frame != self.frame {
+            write!(
+                f,
+                "desync at frame {}: the peer is at frame {}",
+                self.frame, self.peer_frame
+            )
+        } else if self.components.is_empty() {
+            write!(
+                f,
+                "desync at frame {}: the peer's state is laid out differently",
+                self.frame
+            )
+        } else {
+            write!(
+                f,
+                "desync at frame {}: {} differ",
+                self.frame,
+                self.components.join(", ")
+            )
+        }
+    }
+}
+
+impl error::Error for Desync {}
+
+/// Lockstep netplay over a stream such as a `TcpStream`, for SGB
+/// multiplayer between two machines. Every poll sends the first player of
+/// `local` to the peer and waits for the peer's, so both ends see the same
+/// buttons at the same points.
+///
+/// Both ends must run the same game from the same state with the same
+/// `PollRate`, one as player 0 and the other as player 1. `check_sync`
+/// tells when they drifted apart anyway. Needs the `std` feature.
+#[cfg(feature = "std")]
+pub struct NetplayInput<S: Read + Write, P: InputProvider> {
+    stream: S,
+    local: P,
+    local_player: usize,
+    /// The first error talking to the peer, after which only the local
+    /// buttons are passed on.
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<S: Read + Write, P: InputProvider> NetplayInput<S, P> {
+    /// Creates a new `NetplayInput` with `local` as `local_player`, 0 or 1.
+    pub fn new(stream: S, local: P, local_player: usize) -> Self {
+        NetplayInput {
+            stream,
+            local,
+            local_player: local_player.min(1),
+            error: None,
+        }
+    }
+
+    /// The error that cut the peer off, if any.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Sends `Cpu::state_hashes` of the local machine to the peer and
+    /// compares them with the peer's, so a desync shows up as an error
+    /// naming the frame and the components that differ, rather than as
+    /// glitches later on. Both ends must call it at the same points, e.g.
+    /// after every 60th frame.
+    ///
+    /// Does nothing once the peer is gone.
+    pub fn check_sync(&mut self, frame: u64, hashes: &[(&'static str, u64)]) -> Result<(), Desync> {
+        if self.error.is_some() {
+            return Ok(());
+        }
+        let (peer_frame, peer_hashes) = match self.exchange_hashes(frame, hashes) {
+            Ok(peer) => peer,
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Lost the netplay peer: {}", e);
+                self.error = Some(e);
+                return Ok(());
+            }
+        };
+        let components = if peer_hashes.len() == hashes.len() {
+            hashes
+                .iter()
+                .zip(&peer_hashes)
+                .filter(|((_, local), peer)| local != *peer)
+                .map(|((name, _), _)| *name)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if peer_frame == frame && peer_hashes.len() == hashes.len() && components.is_empty() {
+            return Ok(());
+        }
+        Err(Desync {
+            frame,
+            peer_frame,
+            components,
+        })
+    }
+
+    /// Sends the frame, the number of hashes and the hashes, and reads the
+    /// peer's the same way.
+    fn exchange_hashes(
+        &mut self,
+        frame: u64,
+        hashes: &[(&'static str, u64)],
+    ) -> io::Result<(u64, Vec<u64>)> {
+        let mut message = frame.to_le_bytes().to_vec();
+        message.push(hashes.len() as u8);
+        for (_, hash) in hashes {
+            message.extend_from_slice(&hash.to_le_bytes());
+        }
+        self.stream.write_all(&message)?;
+        self.stream.flush()?;
+
+        let mut word = [0; 8];
+        self.stream.read_exact(&mut word)?;
+        let peer_frame = u64::from_le_bytes(word);
+        let mut count = [0];
+        self.stream.read_exact(&mut count)?;
+        let mut peer_hashes = Vec::with_capacity(count[0] as usize);
+        for _ in 0..count[0] {
+            self.stream.read_exact(&mut word)?;
+            peer_hashes.push(u64::from_le_bytes(word));
+        }
+        Ok((peer_frame, peer_hashes))
+    }
+
+    fn exchange(&mut self, buttons: ButtonState) -> io::Result<ButtonState> {
+        self.stream.write_all(&[buttons.bits()])?;
+        self.stream.flush()?;
+        let mut remote = [0];
+        self.stream.read_exact(&mut remote)?;
+        Ok(ButtonState::from_bits(remote[0]))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: Read + Write, P: InputProvider> InputProvider for NetplayInput<S, P> {
+    fn poll(&mut self, at: PollPoint) -> Players {
+        let mut players = Players::default();
+        let local = self.local.poll(at)[0];
+        players[self.local_player] = local;
+        if self.error.is_none() {
+            match self.exchange(local) {
+                Ok(remote) => players[self.local_player ^ 1] = remote,
+                Err(e) => {
+                    warn!(target: LOG_TARGET, "Lost the netplay peer: {}", e);
+                    self.error = Some(e);
+                }
+            }
+        }
+        players
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joypad::Key;
+    #[cfg(feature = "std")]
+    use alloc::collections::VecDeque;
+
+    fn at(frame: u64) -> PollPoint {
+        PollPoint { frame, line: 0 }
+    }
+
+    #[test]
+    fn test_scripted_input() {
+        let mut script = ScriptedInput::new();
+        script.hold(10, 0, ButtonState::default().with(Key::Start));
+        script.hold(12, 0, ButtonState::default());
+        script.hold(11, 1, ButtonState::default().with(Key::A));
+
+        assert_eq!(script.poll(at(9)), Players::default());
+        assert!(script.poll(at(10))[0].is_pressed(Key::Start));
+        let players = script.poll(at(11));
+        assert!(players[0].is_pressed(Key::Start) && players[1].is_pressed(Key::A));
+        let players = script.poll(at(100));
+        assert_eq!(players[0], ButtonState::default());
+        assert!(players[1].is_pressed(Key::A));
+    }
+
+    #[test]
+    fn test_movie_round_trip() {
+        let mut script = ScriptedInput::new();
+        script.hold(1, 0, ButtonState::default().with(Key::Right));
+        script.hold(2, 3, ButtonState::default().with(Key::B));
+        let mut recorder = MovieRecorder::new(&mut script, 0x1234, PollRate::Lines(8));
+        let recorded: Vec<_> = (0..3).map(|frame| recorder.poll(at(frame))).collect();
+        let movie = recorder.into_movie();
+        assert_eq!(movie.polls, recorded);
+
+        let loaded = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(loaded, movie);
+        let mut player = MoviePlayer::new(loaded);
+        assert_eq!(player.rate(), PollRate::Lines(8));
+        let played: Vec<_> = (0..3).map(|frame| player.poll(at(frame))).collect();
+        assert_eq!(played, recorded);
+        assert!(player.is_finished());
+        assert_eq!(player.poll(at(3)), Players::default());
+
+        let bytes = movie.to_bytes();
+        assert_eq!(Movie::from_bytes(b"GBMX"), Err(MovieError::BadMagic));
+        assert_eq!(
+            Movie::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MovieError::Truncated)
+        );
+    }
+
+    /// One end of a connection: reads what the peer sent, keeps what is
+    /// written.
+    #[cfg(feature = "std")]
+    #[derive(Default)]
+    struct Peer {
+        incoming: VecDeque<u8>,
+        sent: Vec<u8>,
+    }
+
+    #[cfg(feature = "std")]
+    impl Read for Peer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.incoming.read(buf)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_netplay_exchange() {
+        let mut script = ScriptedInput::new();
+        script.hold(0, 0, ButtonState::default().with(Key::Up));
+        let peer = Peer {
+            incoming: VecDeque::from(vec![ButtonState::default().with(Key::A).bits()]),
+            ..Peer::default()
+        };
+        let mut netplay = NetplayInput::new(peer, script, 1);
+
+        let players = netplay.poll(at(0));
+        assert!(players[0].is_pressed(Key::A));
+        assert!(players[1].is_pressed(Key::Up));
+        assert_eq!(
+            netplay.stream.sent,
+            vec![ButtonState::default().with(Key::Up).bits()]
+        );
+
+        // The peer is gone, the local buttons still come through
+        let players = netplay.poll(at(1));
+        assert_eq!(players[0], ButtonState::default());
+        assert!(players[1].is_pressed(Key::Up));
+        assert!(netplay.take_error().is_some());
+    }
+
+    /// What `check_sync` sends for `hashes` at `frame`.
+    #[cfg(feature = "std")]
+    fn sync_message(frame: u64, hashes: &[u64]) -> Vec<u8> {
+        let mut message = frame.to_le_bytes().to_vec();
+        message.push(hashes.len() as u8);
+        for hash in hashes {
+            message.extend_from_slice(&hash.to_le_bytes());
+        }
+        message
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_netplay_check_sync() {
+        let hashes = [("cpu", 1), ("ppu", 2), ("wram", 3)];
+        let mut incoming = sync_message(60, &[1, 2, 3]);
+        incoming.extend(sync_message(120, &[1, 5, 6]));
+        incoming.extend(sync_message(179, &[1, 2, 3]));
+        let peer = Peer {
+            incoming: incoming.into(),
+            ..Peer::default()
+        };
+        let mut netplay = NetplayInput::new(peer, ScriptedInput::new(), 0);
+
+        assert_eq!(netplay.check_sync(60, &hashes), Ok(()));
+        assert_eq!(netplay.stream.sent, sync_message(60, &[1, 2, 3]));
+        let desync = netplay.check_sync(120, &hashes).unwrap_err();
+        assert_eq!(desync.components, vec!["ppu", "wram"]);
+        assert_eq!(desync.to_string(), "desync at frame 120: ppu, wram differ");
+        let desync = netplay.check_sync(180, &hashes).unwrap_err();
+        assert_eq!(desync.peer_frame, 179);
+
+        // The peer is gone
+        assert_eq!(netplay.check_sync(240, &hashes), Ok(()));
+        assert!(netplay.take_error().is_some());
+    }
+}
@@ -0,0 +1,294 @@
+use core::fmt;
+
+use crate::bus::Bus;
+
+/// LCDC (0xff40) split into its bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lcdc {
+    pub raw: u8,
+    pub lcd_enable: bool,
+    /// Window tile map at 0x9c00 instead of 0x9800.
+    pub window_map_9c00: bool,
+    pub window_enable: bool,
+    /// BG/window tile data at 0x8000 instead of 0x8800.
+    pub tile_data_8000: bool,
+    /// BG tile map at 0x9c00 instead of 0x9800.
+    pub bg_map_9c00: bool,
+    /// 8x16 objects instead of 8x8.
+    pub obj_size_16: bool,
+    pub obj_enable: bool,
+    pub bg_enable: bool,
+}
+
+impl From<u8> for Lcdc {
+    fn from(raw: u8) -> Self {
+        Lcdc {
+            raw,
+            lcd_enable: raw & 0x80 != 0,
+            window_map_9c00: raw & 0x40 != 0,
+            window_enable: raw & 0x20 != 0,
+            tile_data_8000: raw & 0x10 != 0,
+            bg_map_9c00: raw & 0x08 != 0,
+            obj_size_16: raw & 0x04 != 0,
+            obj_enable: raw & 0x02 != 0,
+            bg_enable: raw & 0x01 != 0,
+        }
+    }
+}
+
+/// STAT (0xff41) split into its bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stat {
+    pub raw: u8,
+    pub lyc_interrupt: bool,
+    pub oam_interrupt: bool,
+    pub vblank_interrupt: bool,
+    pub hblank_interrupt: bool,
+    /// LY == LYC.
+    pub lyc_equal: bool,
+    /// PPU mode, 0 to 3.
+    pub mode: u8,
+}
+
+impl From<u8> for Stat {
+    fn from(raw: u8) -> Self {
+        Stat {
+            raw,
+            lyc_interrupt: raw & 0x40 != 0,
+            oam_interrupt: raw & 0x20 != 0,
+            vblank_interrupt: raw & 0x10 != 0,
+            hblank_interrupt: raw & 0x08 != 0,
+            lyc_equal: raw & 0x04 != 0,
+            mode: raw & 0x03,
+        }
+    }
+}
+
+/// TAC (0xff07) split into its bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tac {
+    pub raw: u8,
+    pub enabled: bool,
+    /// CPU cycles per TIMA increment.
+    pub divider: u16,
+}
+
+impl From<u8> for Tac {
+    fn from(raw: u8) -> Self {
+        Tac {
+            raw,
+            enabled: raw & 0x04 != 0,
+            divider: match raw & 0x03 {
+                0 => 1024,
+                1 => 16,
+                2 => 64,
+                _ => 256,
+            },
+        }
+    }
+}
+
+/// IE (0xffff) or IF (0xff0f) with the interrupts named.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterruptBits {
+    pub raw: u8,
+    pub vblank: bool,
+    pub lcd_stat: bool,
+    pub timer: bool,
+    pub serial: bool,
+    pub joypad: bool,
+}
+
+impl From<u8> for InterruptBits {
+    fn from(raw: u8) -> Self {
+        InterruptBits {
+            raw,
+            vblank: raw & 0x01 != 0,
+            lcd_stat: raw & 0x02 != 0,
+            timer: raw & 0x04 != 0,
+            serial: raw & 0x08 != 0,
+            joypad: raw & 0x10 != 0,
+        }
+    }
+}
+
+/// Every IO register at one point in time, decoded where it has fields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IoSnapshot {
+    pub joyp: u8,
+    pub sb: u8,
+    pub sc: u8,
+    pub div: u8,
+    pub tima: u8,
+    pub tma: u8,
+    pub tac: Tac,
+    pub interrupt_flag: InterruptBits,
+    /// NR10 to NR52 (0xff10..=0xff26).
+    pub audio: [u8; 0x17],
+    pub wave_ram: [u8; 0x10],
+    pub lcdc: Lcdc,
+    pub stat: Stat,
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub lyc: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+    pub wy: u8,
+    pub wx: u8,
+    pub interrupt_enable: InterruptBits,
+}
+
+impl IoSnapshot {
+    /// Reads the registers the way the CPU would.
+    pub fn capture<B: Bus>(bus: &B) -> Self {
+        let mut audio = [0; 0x17];
+        for (i, value) in audio.iter_mut().enumerate() {
+            *value = bus.read_byte(0xff10 + i as u16);
+        }
+        let mut wave_ram = [0; 0x10];
+        for (i, value) in wave_ram.iter_mut().enumerate() {
+            *value = bus.read_byte(0xff30 + i as u16);
+        }
+
+        IoSnapshot {
+            joyp: bus.read_byte(0xff00),
+            sb: bus.read_byte(0xff01),
+            sc: bus.read_byte(0xff02),
+            div: bus.read_byte(0xff04),
+            tima: bus.read_byte(0xff05),
+            tma: bus.read_byte(0xff06),
+            tac: bus.read_byte(0xff07).into(),
+            interrupt_flag: bus.read_byte(0xff0f).into(),
+            audio,
+            wave_ram,
+            lcdc: bus.read_byte(0xff40).into(),
+            stat: bus.read_byte(0xff41).into(),
+            scy: bus.read_byte(0xff42),
+            scx: bus.read_byte(0xff43),
+            ly: bus.read_byte(0xff44),
+            lyc: bus.read_byte(0xff45),
+            bgp: bus.read_byte(0xff47),
+            obp0: bus.read_byte(0xff48),
+            obp1: bus.read_byte(0xff49),
+            wy: bus.read_byte(0xff4a),
+            wx: bus.read_byte(0xff4b),
+            interrupt_enable: bus.read_byte(0xffff).into(),
+        }
+    }
+}
+
+fn flag(f: &mut fmt::Formatter, name: &str, set: bool) -> fmt::Result {
+    if set {
+        write!(f, " {}", name)
+    } else {
+        Ok(())
+    }
+}
+
+impl fmt::Display for InterruptBits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:02x}", self.raw)?;
+        flag(f, "VBLANK", self.vblank)?;
+        flag(f, "STAT", self.lcd_stat)?;
+        flag(f, "TIMER", self.timer)?;
+        flag(f, "SERIAL", self.serial)?;
+        flag(f, "JOYPAD", self.joypad)
+    }
+}
+
+/// Multi-line listing for bug reports.
+impl fmt::Display for IoSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let l = &self.lcdc;
+        write!(f, "LCDC 0x{:02x}", l.raw)?;
+        flag(f, "LCD", l.lcd_enable)?;
+        flag(f, "WIN_MAP=9C00", l.window_map_9c00)?;
+        flag(f, "WIN", l.window_enable)?;
+        flag(f, "TILES=8000", l.tile_data_8000)?;
+        flag(f, "BG_MAP=9C00", l.bg_map_9c00)?;
+        flag(f, "OBJ=8x16", l.obj_size_16)?;
+        flag(f, "OBJ", l.obj_enable)?;
+        flag(f, "BG", l.bg_enable)?;
+        writeln!(f)?;
+
+        let s = &self.stat;
+        write!(f, "STAT 0x{:02x} mode {}", s.raw, s.mode)?;
+        flag(f, "LYC=LY", s.lyc_equal)?;
+        flag(f, "INT_LYC", s.lyc_interrupt)?;
+        flag(f, "INT_OAM", s.oam_interrupt)?;
+        flag(f, "INT_VBLANK", s.vblank_interrupt)?;
+        flag(f, "INT_HBLANK", s.hblank_interrupt)?;
+        writeln!(f)?;
+
+        writeln!(
+            f,
+            "LY 0x{:02x} LYC 0x{:02x} SCY 0x{:02x} SCX 0x{:02x} WY 0x{:02x} WX 0x{:02x}",
+            self.ly, self.lyc, self.scy, self.scx, self.wy, self.wx
+        )?;
+        writeln!(
+            f,
+            "BGP 0x{:02x} OBP0 0x{:02x} OBP1 0x{:02x}",
+            self.bgp, self.obp0, self.obp1
+        )?;
+        writeln!(
+            f,
+            "DIV 0x{:02x} TIMA 0x{:02x} TMA 0x{:02x} TAC 0x{:02x} {} /{}",
+            self.div,
+            self.tima,
+            self.tma,
+            self.tac.raw,
+            if self.tac.enabled { "on" } else { "off" },
+            self.tac.divider
+        )?;
+        writeln!(f, "IE {}", self.interrupt_enable)?;
+        writeln!(f, "IF {}", self.interrupt_flag)?;
+        writeln!(
+            f,
+            "JOYP 0x{:02x} SB 0x{:02x} SC 0x{:02x}",
+            self.joyp, self.sb, self.sc
+        )?;
+        write!(f, "NR10-NR52")?;
+        for value in &self.audio {
+            write!(f, " {:02x}", value)?;
+        }
+        writeln!(f)?;
+        write!(f, "WAVE")?;
+        for value in &self.wave_ram {
+            write!(f, " {:02x}", value)?;
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::FlatBus;
+
+    #[test]
+    fn test_decodes_registers() {
+        let mut bus = FlatBus::new();
+        bus.write_byte(0xff40, 0x91);
+        bus.write_byte(0xff41, 0x45);
+        bus.write_byte(0xff07, 0x05);
+        bus.write_byte(0xffff, 0x05);
+
+        let io = IoSnapshot::capture(&bus);
+        assert!(io.lcdc.lcd_enable && io.lcdc.tile_data_8000 && io.lcdc.bg_enable);
+        assert!(!io.lcdc.window_enable && !io.lcdc.obj_enable);
+        assert_eq!(io.stat.mode, 1);
+        assert!(io.stat.lyc_interrupt && io.stat.lyc_equal);
+        assert_eq!(
+            io.tac,
+            Tac {
+                raw: 0x05,
+                enabled: true,
+                divider: 16
+            }
+        );
+        assert!(io.interrupt_enable.vblank && io.interrupt_enable.timer);
+        assert_eq!(io.interrupt_enable.to_string(), "0x05 VBLANK TIMER");
+    }
+}
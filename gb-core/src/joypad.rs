@@ -0,0 +1,450 @@
+use crate::sgb::{self, PacketReceiver};
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// Joypad
+pub struct Joypad {
+    /// Joypad
+    joyp: u8,
+    /// Keypress state
+    key_state: u8,
+    /// Buttons held according to the frontend, which may include opposite
+    /// directions that `key_state` leaves out.
+    requested: ButtonState,
+    /// Resolve opposite directions as a real D-pad does, on by default
+    filter_opposites: bool,
+    /// Answer Super Game Boy packets, see `set_sgb`.
+    sgb: bool,
+    packets: PacketReceiver,
+    /// Controllers asked for with MLT_REQ: 1, 2 or 4.
+    players: u8,
+    /// Controller read next, 0 for the first.
+    player: u8,
+    /// Buttons held on controllers 2 to 4.
+    others: [ButtonState; 3],
+    /// Interrupt request, set when one of the input lines of the selected
+    /// groups goes from high to low
+    pub irq: bool,
+}
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum Key {
+    Down,
+    Up,
+    Left,
+    Right,
+    Start,
+    Select,
+    B,
+    A,
+}
+
+impl Key {
+    pub const ALL: [Key; 8] = [
+        Key::Down,
+        Key::Up,
+        Key::Left,
+        Key::Right,
+        Key::Start,
+        Key::Select,
+        Key::B,
+        Key::A,
+    ];
+
+    /// Bit of the key in `key_state` and `ButtonState`.
+    fn mask(self) -> u8 {
+        match self {
+            Key::Down => 0x80,
+            Key::Up => 0x40,
+            Key::Left => 0x20,
+            Key::Right => 0x10,
+            Key::Start => 0x08,
+            Key::Select => 0x04,
+            Key::B => 0x02,
+            Key::A => 0x01,
+        }
+    }
+}
+
+/// State of all eight buttons at once, a set bit means pressed.
+///
+/// Bits 7-4 are Down, Up, Left, Right and bits 3-0 are Start, Select, B, A.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq)]
+pub struct ButtonState(u8);
+
+impl ButtonState {
+    pub fn from_bits(bits: u8) -> Self {
+        ButtonState(bits)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_pressed(self, key: Key) -> bool {
+        self.0 & key.mask() != 0
+    }
+
+    pub fn set(&mut self, key: Key, pressed: bool) {
+        if pressed {
+            self.0 |= key.mask();
+        } else {
+            self.0 &= !key.mask();
+        }
+    }
+
+    /// Returns a copy with `key` pressed.
+    pub fn with(mut self, key: Key) -> Self {
+        self.set(key, true);
+        self
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    /// Creates a new `Joypad`.
+    pub fn new() -> Self {
+        Joypad {
+            joyp: 0xff,
+            key_state: 0xff,
+            requested: ButtonState::default(),
+            filter_opposites: true,
+            sgb: false,
+            packets: PacketReceiver::default(),
+            players: 1,
+            player: 0,
+            others: [ButtonState::default(); 3],
+            irq: false,
+        }
+    }
+
+    /// Releases every button, keeping the settings.
+    pub fn reset(&mut self) {
+        *self = Joypad {
+            filter_opposites: self.filter_opposites,
+            sgb: self.sgb,
+            ..Joypad::new()
+        };
+    }
+
+    /// Lets Left+Right and Up+Down through when off, for TAS work that
+    /// relies on the glitches they cause. On by default.
+    pub fn set_filter_opposites(&mut self, enabled: bool) {
+        self.filter_opposites = enabled;
+    }
+
+    /// Decodes the Super Game Boy packets games send through JOYP and
+    /// multiplexes up to four controllers when they ask with MLT_REQ, as
+    /// the SGB does. Other SGB commands are ignored. Off by default.
+    pub fn set_sgb(&mut self, enabled: bool) {
+        self.sgb = enabled;
+        self.packets = PacketReceiver::default();
+        self.players = 1;
+        self.player = 0;
+    }
+
+    /// Controllers the game reads, 1 unless it asked for more through the SGB.
+    pub fn players(&self) -> u8 {
+        self.players
+    }
+
+    /// Sets the buttons of controller `player`, 0 to 3. Controller 0 is
+    /// the one `set_state` sets. Opposite directions are only resolved on
+    /// controller 0.
+    pub fn set_player_state(&mut self, player: usize, buttons: ButtonState) {
+        match player {
+            0 => self.set_state(buttons),
+            1..=3 => self.update_lines(|joypad| joypad.others[player - 1] = buttons),
+            _ => panic!("Invalid controller: {}", player),
+        }
+    }
+
+    pub fn keydown(&mut self, key: Key) {
+        self.set_state(self.requested.with(key));
+    }
+
+    pub fn keyup(&mut self, key: Key) {
+        let mut buttons = self.requested;
+        buttons.set(key, false);
+        self.set_state(buttons);
+    }
+
+    /// Sets all buttons at once, e.g. once per frame for input playback.
+    ///
+    /// Requests the joypad interrupt when a button goes from released to
+    /// pressed while its group is selected, as the input lines of a real
+    /// joypad only go low then.
+    ///
+    /// A real D-pad cannot press Left+Right or Up+Down. Unless turned off
+    /// with `set_filter_opposites`, the direction pressed last wins, and
+    /// neither does if both are pressed at once.
+    pub fn set_state(&mut self, buttons: ButtonState) {
+        let resolved = self.resolve_opposites(buttons);
+        self.requested = buttons;
+        self.update_lines(|joypad| joypad.key_state = !resolved.bits());
+    }
+
+    /// Applies `change` and requests the interrupt if any of the four input
+    /// lines went low, from a button pressed or a group selected with a
+    /// button of it held.
+    fn update_lines(&mut self, change: impl FnOnce(&mut Joypad)) {
+        let before = self.input_lines();
+        change(self);
+        if before & !self.input_lines() != 0 {
+            self.irq = true;
+        }
+    }
+
+    /// The four input lines of JOYP, a clear bit for each one pulled low
+    /// by a pressed button of a selected group.
+    fn input_lines(&self) -> u8 {
+        let key_state = self.player_key_state();
+        let mut lines = 0x0f;
+        if self.joyp & 0x10 == 0 {
+            lines &= key_state >> 4;
+        }
+        if self.joyp & 0x20 == 0 {
+            lines &= key_state & 0x0f;
+        }
+        lines
+    }
+
+    /// `key_state` of the controller read next.
+    fn player_key_state(&self) -> u8 {
+        match self.player {
+            0 => self.key_state,
+            n => !self.others[n as usize - 1].bits(),
+        }
+    }
+
+    fn resolve_opposites(&self, buttons: ButtonState) -> ButtonState {
+        if !self.filter_opposites {
+            return buttons;
+        }
+        let current = self.state();
+        let mut resolved = buttons;
+        for &(a, b) in &[(Key::Left, Key::Right), (Key::Up, Key::Down)] {
+            if !(buttons.is_pressed(a) && buttons.is_pressed(b)) {
+                continue;
+            }
+            let (keep_a, keep_b) =
+                match (self.requested.is_pressed(a), self.requested.is_pressed(b)) {
+                    // Still both held, as decided before
+                    (true, true) => (current.is_pressed(a), current.is_pressed(b)),
+                    (true, false) => (false, true),
+                    (false, true) => (true, false),
+                    (false, false) => (false, false),
+                };
+            resolved.set(a, keep_a);
+            resolved.set(b, keep_b);
+        }
+        resolved
+    }
+
+    /// Buttons currently held.
+    pub fn state(&self) -> ButtonState {
+        ButtonState(!self.key_state)
+    }
+}
+
+impl Joypad {
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.joyp);
+        w.write_u8(self.key_state);
+        w.write_bool(self.irq);
+        w.write_u8(self.players);
+        w.write_u8(self.player);
+        for buttons in &self.others {
+            w.write_u8(buttons.bits());
+        }
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.joyp = r.read_u8()?;
+        self.key_state = r.read_u8()?;
+        self.irq = r.read_bool()?;
+        self.requested = self.state();
+        self.players = match r.read_u8()? {
+            n @ (1 | 2 | 4) => n,
+            _ => 1,
+        };
+        self.player = r.read_u8()? % self.players;
+        for buttons in &mut self.others {
+            *buttons = ButtonState::from_bits(r.read_u8()?);
+        }
+        self.packets = PacketReceiver::default();
+        Ok(())
+    }
+
+    pub(crate) fn write_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xff00 => {
+                let before = self.joyp;
+                self.update_lines(|joypad| joypad.joyp = (joypad.joyp & 0xcf) | (value & 0x30));
+                if self.sgb {
+                    self.sgb_write(before);
+                }
+            }
+            _ => panic!("Invalid address: 0x{:04x}", addr),
+        }
+    }
+
+    fn sgb_write(&mut self, before: u8) {
+        if let Some(packet) = self.packets.write(self.joyp) {
+            if sgb::command(&packet) == sgb::MLT_REQ {
+                self.players = [1, 2, 1, 4][(packet[1] & 0x03) as usize];
+                self.player = 0;
+            }
+        } else if before & 0x20 == 0 && self.joyp & 0x20 != 0 {
+            // Raising P15 after reading the buttons moves to the next controller
+            self.player = (self.player + 1) % self.players;
+        }
+    }
+
+    pub(crate) fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0xff00 => {
+                let key_state = self.player_key_state();
+                // Direction keys selected
+                if self.joyp & 0x10 == 0 {
+                    (self.joyp & 0xf0) | (key_state >> 4) & 0x0f
+                // Button keys selected
+                } else if self.joyp & 0x20 == 0 {
+                    (self.joyp & 0xf0) | key_state & 0x0f
+                // With several controllers the SGB answers with the ID of
+                // the one read next
+                } else if self.players > 1 {
+                    (self.joyp & 0xf0) | (0x0f - self.player)
+                } else {
+                    self.joyp
+                }
+            }
+            _ => panic!("Invalid address: 0x{:04x}", addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_state() {
+        let mut joypad = Joypad::new();
+        joypad.set_state(ButtonState::default().with(Key::A).with(Key::Down));
+        assert!(joypad.state().is_pressed(Key::A));
+
+        // Buttons selected
+        joypad.write_byte(0xff00, 0x10);
+        assert_eq!(joypad.read_byte(0xff00) & 0x0f, 0x0e);
+        // Directions selected
+        joypad.write_byte(0xff00, 0x20);
+        assert_eq!(joypad.read_byte(0xff00) & 0x0f, 0x07);
+
+        // Holding the same buttons does not request another interrupt
+        joypad.irq = false;
+        joypad.set_state(ButtonState::default().with(Key::A));
+        assert!(!joypad.irq);
+    }
+
+    #[test]
+    fn test_interrupt_lines() {
+        let mut joypad = Joypad::new();
+        let a = ButtonState::default().with(Key::A);
+
+        // Nothing selected, no line goes low
+        joypad.set_state(a);
+        assert!(!joypad.irq);
+        joypad.set_state(ButtonState::default());
+
+        // Directions selected, A is in the other group
+        joypad.write_byte(0xff00, 0x20);
+        joypad.set_state(a);
+        assert!(!joypad.irq);
+        joypad.set_state(a.with(Key::Right));
+        assert!(joypad.irq);
+
+        // Selecting the buttons with A held pulls its line low
+        joypad.set_state(a);
+        joypad.irq = false;
+        joypad.write_byte(0xff00, 0x10);
+        assert!(joypad.irq);
+
+        // With both groups selected, A's line is already low when Right
+        // goes down
+        joypad.irq = false;
+        joypad.write_byte(0xff00, 0x00);
+        joypad.set_state(a.with(Key::Right));
+        assert!(!joypad.irq);
+
+        // Releasing does not request it, neither do other controllers
+        // while controller 1 is read
+        joypad.set_state(ButtonState::default());
+        joypad.set_player_state(1, a);
+        assert!(!joypad.irq);
+    }
+
+    #[test]
+    fn test_sgb_multiplayer() {
+        let mut packet = [0; sgb::PACKET_LEN];
+        packet[0] = sgb::MLT_REQ << 3 | 1;
+        packet[1] = 0x01;
+        let mut joypad = Joypad::new();
+        joypad.set_player_state(1, ButtonState::default().with(Key::Start));
+        let send = |joypad: &mut Joypad| {
+            for select in sgb::tests::packet_writes(&packet) {
+                joypad.write_byte(0xff00, select);
+            }
+        };
+
+        // A DMG ignores the packet
+        send(&mut joypad);
+        assert_eq!(joypad.players(), 1);
+
+        joypad.set_sgb(true);
+        send(&mut joypad);
+        assert_eq!(joypad.players(), 2);
+        let mut read = |select| {
+            joypad.write_byte(0xff00, select);
+            joypad.read_byte(0xff00) & 0x0f
+        };
+        assert_eq!(read(0x30), 0x0f);
+        assert_eq!(read(0x10), 0x0f);
+        // Controller 2 is read after P15 goes high
+        assert_eq!(read(0x30), 0x0e);
+        assert_eq!(read(0x10), 0x07);
+        assert_eq!(read(0x30), 0x0f);
+    }
+
+    #[test]
+    fn test_opposite_directions() {
+        let left = ButtonState::default().with(Key::Left);
+        let right = ButtonState::default().with(Key::Right);
+        let both = left.with(Key::Right);
+
+        let mut joypad = Joypad::new();
+        joypad.set_state(both.with(Key::A));
+        assert_eq!(joypad.state(), ButtonState::default().with(Key::A));
+
+        // The newer direction wins for as long as both are held
+        joypad.set_state(left);
+        joypad.set_state(both);
+        assert_eq!(joypad.state(), right);
+        joypad.set_state(both);
+        assert_eq!(joypad.state(), right);
+        joypad.keyup(Key::Right);
+        assert_eq!(joypad.state(), left);
+
+        joypad.keydown(Key::Right);
+        assert_eq!(joypad.state(), right);
+
+        joypad.set_filter_opposites(false);
+        joypad.reset();
+        joypad.set_state(both);
+        assert_eq!(joypad.state(), both);
+    }
+}
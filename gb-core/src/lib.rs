@@ -0,0 +1,59 @@
+// Unit tests link std for the test harness either way
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod accuracy;
+pub mod analog;
+pub mod apu;
+pub mod auto_split;
+pub mod battery;
+mod bess;
+pub mod boot_animation;
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+#[cfg(feature = "std")]
+pub mod crash_report;
+pub mod cycles;
+pub mod event_log;
+#[cfg(feature = "std")]
+pub mod frame_dump;
+#[cfg(feature = "std")]
+pub mod frame_limiter;
+#[cfg(feature = "gym")]
+pub mod gym;
+pub mod heatmap;
+pub mod input;
+pub mod io;
+pub mod joypad;
+pub mod link;
+#[cfg(feature = "std")]
+pub mod loader;
+pub mod memory_watch;
+pub mod mmu;
+pub mod palette;
+pub mod patch;
+pub mod ppu;
+pub mod quirks;
+pub mod register;
+pub mod resample;
+pub mod rewind;
+pub mod rng;
+pub mod romdb;
+mod rtc;
+mod scheduler;
+mod serial;
+pub mod sgb;
+pub mod speed;
+pub mod state;
+pub mod state_diff;
+pub mod stats;
+pub mod strict;
+mod timer;
+pub mod utils;
+#[cfg(feature = "std")]
+pub mod vgm;
+#[cfg(feature = "std")]
+pub mod wav;
+pub mod workbench;
@@ -0,0 +1,224 @@
+//! Devices on the link port. Without one the serial line reads high, which
+//! is right for an empty port but makes some games wait forever for the
+//! accessory they were sold with. The stubs here answer just enough for
+//! those to go on.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// Something plugged into the link port, exchanging one byte per transfer.
+pub trait LinkDevice {
+    /// Name used by `LinkRegistry`.
+    fn name(&self) -> &str;
+
+    /// The Game Boy clocks out `sent` with its internal clock. Returns the
+    /// byte shifted in at the same time.
+    fn exchange(&mut self, sent: u8) -> u8;
+
+    /// The Game Boy waits on the external clock with `sent` in SB. Returns
+    /// the byte to clock in if the device has one to send now. Asked when
+    /// the game starts waiting and then once a frame.
+    fn drive(&mut self, _sent: u8) -> Option<u8> {
+        None
+    }
+
+    /// The user pressed the device's button, e.g. swiped a barcode.
+    fn press(&mut self) {}
+}
+
+/// Builds a device from the text after its name, e.g. the codes of
+/// `barcode-boy:4901234567894`.
+pub type LinkFactory = fn(&str) -> Result<Box<dyn LinkDevice>, String>;
+
+/// Devices by name, so frontends can offer them all, including ones they
+/// register themselves.
+pub struct LinkRegistry {
+    factories: Vec<(&'static str, LinkFactory)>,
+}
+
+impl LinkRegistry {
+    /// Creates a new `LinkRegistry` with the devices of this module.
+    pub fn new() -> Self {
+        let mut registry = LinkRegistry {
+            factories: Vec::new(),
+        };
+        registry.register("barcode-boy", |codes| {
+            BarcodeBoy::new(codes).map(|d| Box::new(d) as Box<dyn LinkDevice>)
+        });
+        registry.register("reply", |byte| {
+            let byte = u8::from_str_radix(byte.trim_start_matches("0x"), 16)
+                .map_err(|e| format!("bad byte {}: {}", byte, e))?;
+            Ok(Box::new(Reply(byte)))
+        });
+        registry
+    }
+
+    /// Adds a device, replacing one with the same name.
+    pub fn register(&mut self, name: &'static str, factory: LinkFactory) {
+        self.factories.retain(|&(n, _)| n != name);
+        self.factories.push((name, factory));
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.factories.iter().map(|&(name, _)| name)
+    }
+
+    /// Builds the device `spec` names, `NAME` or `NAME:ARGS`.
+    pub fn create(&self, spec: &str) -> Result<Box<dyn LinkDevice>, String> {
+        let (name, args) = spec.split_once(':').unwrap_or((spec, ""));
+        let &(_, factory) = self
+            .factories
+            .iter()
+            .find(|&&(n, _)| n == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = self.names().collect();
+                format!(
+                    "unknown link device {}, expected one of: {}",
+                    name,
+                    names.join(", ")
+                )
+            })?;
+        factory(args)
+    }
+}
+
+impl Default for LinkRegistry {
+    fn default() -> Self {
+        LinkRegistry::new()
+    }
+}
+
+/// Answers every transfer with the same byte, for games that only check
+/// something is there.
+pub struct Reply(pub u8);
+
+impl LinkDevice for Reply {
+    fn name(&self) -> &str {
+        "reply"
+    }
+
+    fn exchange(&mut self, _sent: u8) -> u8 {
+        self.0
+    }
+}
+
+/// The Game Boy's half of the Barcode Boy handshake, and the device's.
+const BARCODE_HANDSHAKE: [(u8, u8); 4] = [(0x10, 0xff), (0x07, 0xff), (0x10, 0x10), (0x07, 0x07)];
+
+/// Namco's Barcode Boy, which reads the JAN-13 barcodes on cards. After
+/// the game's handshake it waits on the external clock, and each press
+/// swipes the next of the given codes: STX, the 13 digits in ASCII, ETX.
+pub struct BarcodeBoy {
+    codes: Vec<String>,
+    next_code: usize,
+    /// Handshake bytes matched so far.
+    handshake: usize,
+    /// Bytes of the swiped code not clocked in yet.
+    pending: VecDeque<u8>,
+}
+
+impl BarcodeBoy {
+    /// Creates a new `BarcodeBoy` swiping the comma-separated 13-digit
+    /// `codes` in turn.
+    pub fn new(codes: &str) -> Result<Self, String> {
+        let codes: Vec<String> = codes
+            .split(',')
+            .filter(|code| !code.is_empty())
+            .map(str::to_string)
+            .collect();
+        if let Some(code) = codes
+            .iter()
+            .find(|code| code.len() != 13 || !code.bytes().all(|b| b.is_ascii_digit()))
+        {
+            return Err(format!("barcode {} is not 13 digits", code));
+        }
+        if codes.is_empty() {
+            return Err("expected barcode-boy:CODE,...".to_string());
+        }
+        Ok(BarcodeBoy {
+            codes,
+            next_code: 0,
+            handshake: 0,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+impl LinkDevice for BarcodeBoy {
+    fn name(&self) -> &str {
+        "barcode-boy"
+    }
+
+    fn exchange(&mut self, sent: u8) -> u8 {
+        match BARCODE_HANDSHAKE.get(self.handshake) {
+            Some(&(expected, reply)) if sent == expected => {
+                self.handshake += 1;
+                reply
+            }
+            _ => {
+                // Out of step, the game starts over
+                self.handshake = usize::from(sent == BARCODE_HANDSHAKE[0].0);
+                0xff
+            }
+        }
+    }
+
+    fn drive(&mut self, _sent: u8) -> Option<u8> {
+        self.pending.pop_front()
+    }
+
+    fn press(&mut self) {
+        if self.handshake < BARCODE_HANDSHAKE.len() || !self.pending.is_empty() {
+            return;
+        }
+        let code = &self.codes[self.next_code];
+        self.next_code = (self.next_code + 1) % self.codes.len();
+        self.pending.push_back(0x02);
+        self.pending.extend(code.bytes());
+        self.pending.push_back(0x03);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let registry = LinkRegistry::new();
+        assert_eq!(
+            registry.names().collect::<Vec<_>>(),
+            ["barcode-boy", "reply"]
+        );
+        let mut reply = registry.create("reply:5a").unwrap();
+        assert_eq!(reply.exchange(0x12), 0x5a);
+        assert!(registry.create("barcode-boy:123").is_err());
+        assert!(registry.create("printer").is_err());
+    }
+
+    #[test]
+    fn test_barcode_boy() {
+        let mut device = BarcodeBoy::new("4901234567894").unwrap();
+        // Nothing to swipe before the handshake
+        device.press();
+        assert_eq!(device.drive(0x00), None);
+
+        let replies: Vec<u8> = [0x10, 0x07, 0x10, 0x07]
+            .iter()
+            .map(|&b| device.exchange(b))
+            .collect();
+        assert_eq!(replies, [0xff, 0xff, 0x10, 0x07]);
+        assert_eq!(device.drive(0x00), None);
+
+        device.press();
+        let mut swiped = Vec::new();
+        while let Some(byte) = device.drive(0x00) {
+            swiped.push(byte);
+        }
+        assert_eq!(swiped, b"\x024901234567894\x03");
+    }
+}
@@ -0,0 +1,164 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// Bytes read between two progress reports.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reads all of `reader`, calling `on_progress` with the bytes read so far
+/// and `total` after every chunk. `total` is only used for the reports.
+pub fn read_with_progress<R: Read>(
+    mut reader: R,
+    total: u64,
+    mut on_progress: impl FnMut(u64, u64),
+) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(total as usize);
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => return Ok(data),
+            Ok(n) => {
+                data.extend_from_slice(&chunk[..n]);
+                on_progress(data.len() as u64, total.max(data.len() as u64));
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+enum LoadEvent {
+    Progress { read: u64, total: u64 },
+    Done(io::Result<Vec<u8>>),
+}
+
+/// Where a `RomLoader` is at.
+#[derive(Debug)]
+pub enum LoadStatus {
+    Loading { read: u64, total: u64 },
+    Done(io::Result<Vec<u8>>),
+}
+
+/// Reads a ROM file on another thread, so that a frontend can keep its
+/// window responsive and show progress while a large cartridge loads. The
+/// `Cpu` is then built from the bytes with `Cpu::with_rom`.
+pub struct RomLoader {
+    events: Receiver<LoadEvent>,
+    read: u64,
+    total: u64,
+}
+
+impl RomLoader {
+    /// Starts reading `path` in the background.
+    pub fn spawn<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        RomLoader::spawn_with(move |on_progress| {
+            let file = File::open(&path)?;
+            let total = file.metadata()?.len();
+            read_with_progress(file, total, on_progress)
+        })
+    }
+
+    /// Starts reading `reader` to its end in the background, e.g. stdin.
+    /// The size is not known up front, so progress reports bytes read as
+    /// the total.
+    pub fn from_reader<R: Read + Send + 'static>(reader: R) -> Self {
+        RomLoader::spawn_with(move |on_progress| read_with_progress(reader, 0, on_progress))
+    }
+
+    fn spawn_with<F>(read: F) -> Self
+    where
+        F: FnOnce(&mut dyn FnMut(u64, u64)) -> io::Result<Vec<u8>> + Send + 'static,
+    {
+        let (sender, events) = mpsc::channel();
+        thread::spawn(move || {
+            let result = read(&mut |read, total| {
+                // The receiver may have given up, which is fine
+                let _ = sender.send(LoadEvent::Progress { read, total });
+            });
+            let _ = sender.send(LoadEvent::Done(result));
+        });
+        RomLoader {
+            events,
+            read: 0,
+            total: 0,
+        }
+    }
+
+    /// Returns the latest progress without blocking, or the ROM once it has
+    /// been read. `Done` is returned only once.
+    pub fn poll(&mut self) -> LoadStatus {
+        loop {
+            match self.events.try_recv() {
+                Ok(LoadEvent::Progress { read, total }) => {
+                    self.read = read;
+                    self.total = total;
+                }
+                Ok(LoadEvent::Done(result)) => return LoadStatus::Done(result),
+                Err(TryRecvError::Empty) => {
+                    return LoadStatus::Loading {
+                        read: self.read,
+                        total: self.total,
+                    }
+                }
+                Err(TryRecvError::Disconnected) => {
+                    let e = io::Error::other("ROM loader stopped");
+                    return LoadStatus::Done(Err(e));
+                }
+            }
+        }
+    }
+
+    /// Blocks until the ROM has been read.
+    pub fn wait(self) -> io::Result<Vec<u8>> {
+        for event in self.events {
+            if let LoadEvent::Done(result) = event {
+                return result;
+            }
+        }
+        Err(io::Error::other("ROM loader stopped"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::write_temp_rom;
+    use std::fs;
+
+    #[test]
+    fn test_read_with_progress() {
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let mut reports = Vec::new();
+        let read = read_with_progress(&data[..], data.len() as u64, |read, total| {
+            reports.push((read, total))
+        })
+        .unwrap();
+        assert_eq!(read, data);
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[2], (data.len() as u64, data.len() as u64));
+    }
+
+    #[test]
+    fn test_rom_loader() {
+        let rom = vec![0x42; CHUNK_SIZE * 3];
+        let path = write_temp_rom("loader", &rom);
+        let mut loader = RomLoader::spawn(&path);
+        let result = loop {
+            match loader.poll() {
+                LoadStatus::Loading { read, total } => assert!(read <= total),
+                LoadStatus::Done(result) => break result,
+            }
+        };
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), rom);
+
+        let missing = RomLoader::spawn(path).wait();
+        assert_eq!(missing.unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let streamed = RomLoader::from_reader(io::Cursor::new(rom.clone())).wait();
+        assert_eq!(streamed.unwrap(), rom);
+    }
+}
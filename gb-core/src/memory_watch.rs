@@ -0,0 +1,186 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+use crate::mmu::Mmu;
+
+/// How the bytes of a watched value make a number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    U8,
+    I8,
+    /// Two bytes, little-endian as the SM83 stores them.
+    U16,
+}
+
+impl WatchKind {
+    pub const ALL: [WatchKind; 3] = [WatchKind::U8, WatchKind::I8, WatchKind::U16];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WatchKind::U8 => "u8",
+            WatchKind::I8 => "i8",
+            WatchKind::U16 => "u16",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<WatchKind> {
+        WatchKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// A game variable to sample, e.g. the player's X position.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watch {
+    pub name: String,
+    pub addr: u16,
+    pub kind: WatchKind,
+}
+
+impl Watch {
+    fn read(&self, mmu: &Mmu) -> i32 {
+        match self.kind {
+            WatchKind::U8 => mmu.peek(self.addr) as i32,
+            WatchKind::I8 => mmu.peek(self.addr) as i8 as i32,
+            WatchKind::U16 => {
+                let high = mmu.peek(self.addr.wrapping_add(1));
+                u16::from_le_bytes([mmu.peek(self.addr), high]) as i32
+            }
+        }
+    }
+}
+
+/// Samples memory once a frame into a ring buffer, to graph how game
+/// variables such as speed or position change over time without writing a
+/// script. Sampling peeks, so it shows neither in the heatmap nor in
+/// strict mode.
+pub struct MemoryWatch {
+    watches: Vec<Watch>,
+    /// Frames kept, oldest dropped first.
+    capacity: usize,
+    /// Frame number and the value of every watch, oldest first.
+    rows: VecDeque<(u64, Vec<i32>)>,
+}
+
+impl MemoryWatch {
+    /// Creates a new `MemoryWatch` keeping the latest `capacity` frames.
+    pub fn new(capacity: usize) -> Self {
+        MemoryWatch {
+            watches: Vec::new(),
+            capacity: capacity.max(1),
+            rows: VecDeque::new(),
+        }
+    }
+
+    /// Starts sampling `watch`. The samples so far are dropped, since
+    /// they do not have it.
+    pub fn add(&mut self, watch: Watch) {
+        self.watches.push(watch);
+        self.rows.clear();
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    /// Takes a sample of every watch, e.g. after each `Cpu::run_frame`.
+    pub fn sample(&mut self, frame: u64, mmu: &Mmu) {
+        if self.rows.len() == self.capacity {
+            self.rows.pop_front();
+        }
+        let values = self.watches.iter().map(|watch| watch.read(mmu)).collect();
+        self.rows.push_back((frame, values));
+    }
+
+    /// Frames sampled and kept.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Frame number and value of the watch at `index` in every sample kept,
+    /// oldest first, e.g. to plot it.
+    pub fn series(&self, index: usize) -> impl Iterator<Item = (u64, i32)> + '_ {
+        self.rows
+            .iter()
+            .map(move |(frame, values)| (*frame, values[index]))
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.clear();
+    }
+
+    /// Writes a `frame,<name>,...` header and a row per sample, oldest
+    /// first.
+    #[cfg(feature = "std")]
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        write!(w, "frame")?;
+        for watch in &self.watches {
+            write!(w, ",{}", watch.name)?;
+        }
+        writeln!(w)?;
+        for (frame, values) in &self.rows {
+            write!(w, "{}", frame)?;
+            for value in values {
+                write!(w, ",{}", value)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::rom_with_header;
+
+    #[test]
+    fn test_sample() {
+        let mut mmu = Mmu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        let mut watch = MemoryWatch::new(2);
+        for (name, addr, kind) in [
+            ("x", 0xc000, WatchKind::U16),
+            ("speed", 0xc002, WatchKind::I8),
+            ("lives", 0xff80, WatchKind::U8),
+        ] {
+            watch.add(Watch {
+                name: name.to_string(),
+                addr,
+                kind,
+            });
+        }
+        for frame in 0..3 {
+            mmu.write_byte(0xc000, 0x10 + frame as u8);
+            mmu.write_byte(0xc001, 0x01);
+            mmu.write_byte(0xc002, 0xfe);
+            mmu.write_byte(0xff80, 3);
+            watch.sample(frame, &mmu);
+        }
+        assert_eq!(watch.len(), 2);
+        assert_eq!(
+            watch.series(0).collect::<Vec<_>>(),
+            vec![(1, 0x111), (2, 0x112)]
+        );
+
+        #[cfg(feature = "std")]
+        {
+            let mut csv = Vec::new();
+            watch.write_csv(&mut csv).unwrap();
+            assert_eq!(
+                String::from_utf8(csv).unwrap(),
+                "frame,x,speed,lives\n1,273,-2,3\n2,274,-2,3\n"
+            );
+        }
+        assert_eq!(WatchKind::from_name("U16"), Some(WatchKind::U16));
+        assert_eq!(WatchKind::from_name("u32"), None);
+    }
+}
@@ -0,0 +1,1267 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::io::Read;
+
+use crate::apu::{Apu, ApuWrite};
+use crate::bus::Bus;
+use crate::cartridge::{self, Cartridge, CartridgeError};
+use crate::cpu::Interrupt;
+use crate::event_log::{EventLog, LogEvent};
+use crate::heatmap::AccessHeatmap;
+use crate::io::IoSnapshot;
+use crate::joypad::Joypad;
+use crate::link::LinkDevice;
+use crate::ppu::{Ppu, PpuReg, VramWrite};
+use crate::rng::Rng;
+use crate::scheduler::{Event, Scheduler};
+use crate::serial::{self, Serial};
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::stats::Stats;
+use crate::strict::{self, Violation};
+use crate::timer::{SystemCounter, Timer};
+
+/// Memory that debuggers can view and edit directly, see `Mmu::memory`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryRegion {
+    /// Every bank of cartridge RAM, enabled or not.
+    CartridgeRam,
+    Wram,
+    Hram,
+}
+
+impl MemoryRegion {
+    pub const ALL: [MemoryRegion; 3] = [
+        MemoryRegion::CartridgeRam,
+        MemoryRegion::Wram,
+        MemoryRegion::Hram,
+    ];
+
+    /// Address the region starts at. Cartridge RAM banks all start here.
+    pub fn base(self) -> u16 {
+        match self {
+            MemoryRegion::CartridgeRam => 0xa000,
+            MemoryRegion::Wram => 0xc000,
+            MemoryRegion::Hram => 0xff80,
+        }
+    }
+
+    /// Bytes the CPU sees of the region at once.
+    pub fn bank_len(self) -> usize {
+        match self {
+            MemoryRegion::CartridgeRam | MemoryRegion::Wram => 0x2000,
+            MemoryRegion::Hram => 0x7f,
+        }
+    }
+}
+
+pub struct Mmu {
+    pub cartridge: Box<dyn Cartridge>,
+    pub ppu: Ppu,
+    pub joypad: Joypad,
+    pub apu: Apu,
+    serial: Serial,
+    timer: Timer,
+    ram: [u8; 0x2000],
+    pub interrupt_flag: u8,
+    pub interrupt_enable: u8,
+    hram: [u8; 0x7f],
+    /// Counts cycles of the PPU and APU, which CGB double speed leaves
+    /// alone: the CPU and the timer then run two cycles to each of these.
+    scheduler: Scheduler,
+    /// CGB double speed, switched by STOP once armed through KEY1.
+    double_speed: bool,
+    /// KEY1 bit 0, set to switch speed on the next STOP.
+    speed_switch_armed: bool,
+    /// Cycle the PPU was last brought up to date at.
+    ppu_synced: u64,
+    /// Cycle the timer was last brought up to date at.
+    timer_synced: u64,
+    /// Cycle the APU was last brought up to date at.
+    apu_synced: u64,
+    /// Cycles run before the last reset, which restarts the scheduler's
+    /// clock, so logs running across it keep counting. See `log_now`.
+    reset_cycles: u64,
+    /// Off unless enabled with `enable_event_log`.
+    event_log: Option<EventLog>,
+    /// Off unless set with `set_heatmap`.
+    heatmap: Option<AccessHeatmap>,
+    /// Cycle the log was started at and the writes since the last take.
+    /// Off unless started with `start_apu_log`.
+    apu_log: Option<(u64, Vec<ApuWrite>)>,
+    /// Off unless started with `start_vram_log`.
+    vram_log: Option<VramLog>,
+    /// Bytes sent since the last take. Off unless started with
+    /// `start_serial_log`.
+    serial_log: Option<Vec<u8>>,
+    rng: Rng,
+    /// Interrupts, DMAs and bank switches; the CPU fills in the rest.
+    pub(crate) stats: Stats,
+    /// Fill WRAM and HRAM from `rng` on power-on instead of zeroing them.
+    random_ram: bool,
+    /// Report `Violation`s, see `set_strict`.
+    strict: bool,
+    /// Filled from reads too, which only borrow the `Mmu`.
+    violations: RefCell<Vec<Violation>>,
+    /// Plugged in with `connect_link`, kept across resets.
+    link: Option<Box<dyn LinkDevice>>,
+}
+
+/// VRAM writes logged since the last `Mmu::take_vram_writes`.
+struct VramLog {
+    /// Cycle the log was started at.
+    start: u64,
+    range: Range<u16>,
+    writes: Vec<VramWrite>,
+}
+
+impl Mmu {
+    #[cfg(feature = "std")]
+    pub fn new(cartridge_name: &str) -> Self {
+        Mmu::with_cartridge(cartridge::new(cartridge_name))
+    }
+
+    /// Creates a new `Mmu` for a ROM image already in memory, e.g. a
+    /// patched one. Battery RAM is loaded as for a ROM file.
+    pub fn with_rom(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        let mut cartridge = cartridge::from_bytes(rom)?;
+        cartridge.load_save_data();
+        Ok(Mmu::with_cartridge(cartridge))
+    }
+
+    /// Creates a new `Mmu` for a ROM image read from `reader`, see
+    /// `cartridge::from_reader`. Battery RAM is loaded as for a ROM file.
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, CartridgeError> {
+        let mut cartridge = cartridge::from_reader(reader)?;
+        cartridge.load_save_data();
+        Ok(Mmu::with_cartridge(cartridge))
+    }
+
+    fn with_cartridge(cartridge: Box<dyn Cartridge>) -> Self {
+        let mut mmu = Mmu {
+            cartridge,
+            ppu: Ppu::new(),
+            joypad: Joypad::new(),
+            apu: Apu::new(),
+            serial: Serial::new(),
+            timer: Timer::new(),
+            ram: [0; 0x2000],
+            interrupt_flag: 0,
+            interrupt_enable: 0,
+            hram: [0; 0x7f],
+            scheduler: Scheduler::new(),
+            double_speed: false,
+            speed_switch_armed: false,
+            ppu_synced: 0,
+            timer_synced: 0,
+            apu_synced: 0,
+            reset_cycles: 0,
+            event_log: None,
+            heatmap: None,
+            apu_log: None,
+            vram_log: None,
+            serial_log: None,
+            rng: Rng::default(),
+            stats: Stats::default(),
+            random_ram: false,
+            strict: false,
+            violations: RefCell::new(Vec::new()),
+            link: None,
+        };
+        mmu.schedule_ppu();
+        mmu.schedule_timer();
+        mmu.schedule_apu();
+        mmu
+    }
+
+    /// Resets every peripheral to its power-on state.
+    ///
+    /// Cartridge RAM survives a soft reset and is cleared on a hard reset.
+    pub fn reset(&mut self, hard: bool) {
+        self.cartridge.reset(hard);
+        self.ppu.reset();
+        self.joypad.reset();
+        self.apu.reset();
+        self.serial = Serial::new();
+        self.timer = Timer::new();
+        self.init_ram();
+        self.interrupt_flag = 0;
+        self.interrupt_enable = 0;
+        self.reset_cycles += self.scheduler.now();
+        self.scheduler = Scheduler::new();
+        self.double_speed = false;
+        self.speed_switch_armed = false;
+        self.ppu_synced = 0;
+        self.timer_synced = 0;
+        self.apu_synced = 0;
+        self.schedule_ppu();
+        self.schedule_timer();
+        self.schedule_apu();
+    }
+
+    /// Restarts the random numbers from `seed`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Real hardware powers on with garbage in WRAM and HRAM, which can
+    /// expose games that read memory before writing it. When enabled, both
+    /// are filled with random bytes now and on every reset.
+    pub fn set_random_ram(&mut self, enabled: bool) {
+        self.random_ram = enabled;
+        self.init_ram();
+    }
+
+    /// In strict mode, accesses that real hardware ignores or garbles are
+    /// collected for `take_violations`, to warn homebrew developers early.
+    /// The emulation itself stays the same.
+    pub fn set_strict(&mut self, enabled: bool) {
+        self.strict = enabled;
+        if !enabled {
+            self.violations.borrow_mut().clear();
+        }
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Takes the violations found since the last call, oldest first.
+    pub fn take_violations(&mut self) -> Vec<Violation> {
+        self.violations.take()
+    }
+
+    /// Reports VRAM and OAM accesses while the PPU has them locked.
+    fn check_access(&self, addr: u16, write: bool) {
+        if !self.strict {
+            return;
+        }
+        let mode = self.ppu.get_register(PpuReg::Stat) & 0x03;
+        let violation = match addr {
+            0x8000..=0x9fff if mode == 3 => Violation::VramAccess { addr, write },
+            0xfe00..=0xfe9f if mode >= 2 => Violation::OamAccess { addr, write, mode },
+            _ => return,
+        };
+        self.report(violation);
+    }
+
+    /// Whether the header asks for CGB features, of which KEY1 and palette
+    /// RAM are emulated.
+    fn is_cgb(&self) -> bool {
+        self.cartridge.rom()[0x143] & 0x80 != 0
+    }
+
+    /// CPU cycles to each cycle of the PPU and APU.
+    fn cpu_rate(&self) -> u64 {
+        if self.double_speed {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn has_no_mapper(&self) -> bool {
+        matches!(self.cartridge.rom()[0x147], 0x00 | 0x08 | 0x09)
+    }
+
+    fn report(&self, violation: Violation) {
+        let mut violations = self.violations.borrow_mut();
+        if violations.len() < strict::MAX_PENDING {
+            violations.push(violation);
+        }
+    }
+
+    fn init_ram(&mut self) {
+        if self.random_ram {
+            self.rng.fill(&mut self.ram);
+            self.rng.fill(&mut self.hram);
+        } else {
+            self.ram = [0; 0x2000];
+            self.hram = [0; 0x7f];
+        }
+    }
+
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.begin_section("cartridge");
+        self.cartridge.save_state(w);
+        self.ppu.save_state(w);
+        w.begin_section("joypad");
+        self.joypad.save_state(w);
+        w.begin_section("apu");
+        self.apu.save_state(w);
+        w.begin_section("serial");
+        self.serial.save_state(w);
+        w.begin_section("timer");
+        self.timer.save_state(w);
+        w.write_bool(self.double_speed);
+        w.write_bool(self.speed_switch_armed);
+        w.begin_section("wram");
+        w.write_bytes(&self.ram);
+        w.begin_section("interrupts");
+        w.write_u8(self.interrupt_flag);
+        w.write_u8(self.interrupt_enable);
+        w.begin_section("hram");
+        w.write_bytes(&self.hram);
+        w.begin_section("scheduler");
+        self.scheduler.save_state(w);
+        w.write_u64(self.ppu_synced);
+        w.write_u64(self.timer_synced);
+        w.write_u64(self.apu_synced);
+        w.begin_section("rng");
+        self.rng.save_state(w);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cartridge.load_state(r)?;
+        self.ppu.load_state(r)?;
+        self.joypad.load_state(r)?;
+        self.apu.load_state(r)?;
+        self.serial.load_state(r)?;
+        self.timer.load_state(r)?;
+        self.double_speed = r.read_bool()?;
+        self.speed_switch_armed = r.read_bool()?;
+        r.read_bytes(&mut self.ram)?;
+        self.interrupt_flag = r.read_u8()?;
+        self.interrupt_enable = r.read_u8()?;
+        r.read_bytes(&mut self.hram)?;
+        self.scheduler.load_state(r)?;
+        self.ppu_synced = r.read_u64()?;
+        self.timer_synced = r.read_u64()?;
+        self.apu_synced = r.read_u64()?;
+        self.rng.load_state(r)?;
+        Ok(())
+    }
+
+    /// Brings the PPU up to the current cycle.
+    fn sync_ppu(&mut self) {
+        let dots = self.scheduler.now() - self.ppu_synced;
+        // The PPU only idles for longer than this while the LCD is off
+        self.ppu.update(dots.min(u16::MAX as u64) as u16);
+        self.ppu_synced = self.scheduler.now();
+    }
+
+    /// Brings the timer up to the current cycle.
+    fn sync_timer(&mut self) {
+        self.timer
+            .advance((self.scheduler.now() - self.timer_synced) * self.cpu_rate());
+        self.timer_synced = self.scheduler.now();
+    }
+
+    /// Brings the APU up to the current cycle.
+    fn sync_apu(&mut self) {
+        let counter = self.counter_at(self.apu_synced);
+        self.apu
+            .update(self.scheduler.now() - self.apu_synced, counter);
+        self.apu_synced = self.scheduler.now();
+    }
+
+    /// The system counter at cycle `time`, which the timer keeps as of
+    /// the cycle it was last brought up to date at. The APU's frame
+    /// sequencer is clocked by the same counter.
+    fn counter_at(&self, time: u64) -> SystemCounter {
+        let counter = SystemCounter {
+            value: self.timer.counter(),
+            double_speed: self.double_speed,
+        };
+        if time >= self.timer_synced {
+            counter.after(time - self.timer_synced)
+        } else {
+            counter.before(self.timer_synced - time)
+        }
+    }
+
+    /// Clears the system counter. Everything clocked by it is brought up
+    /// to the current cycle first, so all of them see the reset at once.
+    fn reset_div(&mut self) {
+        self.sync_timer();
+        self.sync_apu();
+        self.apu.div_reset(self.counter_at(self.timer_synced));
+        self.timer.reset_counter();
+        self.schedule_timer();
+        self.schedule_apu();
+    }
+
+    /// Swaps in a rebuilt ROM image, e.g. after reassembling a homebrew game,
+    /// and keeps the rest of the machine running.
+    ///
+    /// Cartridge RAM carries over unless the new header changes its size,
+    /// in which case the battery save is loaded. Banking carries over too.
+    pub fn reload_rom(&mut self, rom: Vec<u8>) -> Result<(), CartridgeError> {
+        let mut cartridge = cartridge::from_bytes(rom)?;
+        if cartridge.ram().len() == self.cartridge.ram().len() {
+            cartridge.ram_mut().copy_from_slice(self.cartridge.ram());
+        } else {
+            cartridge.load_save_data();
+        }
+        for (addr, value) in self.cartridge.mbc_writes() {
+            cartridge.write(addr, value);
+        }
+        self.cartridge = cartridge;
+        Ok(())
+    }
+
+    /// Pulls the cartridge out and puts `cartridge` in while the machine
+    /// keeps running, as in cart-swap tricks. The running code is not told;
+    /// it goes on fetching from the new ROM.
+    ///
+    /// Returns the old cartridge, e.g. to write its battery save.
+    pub fn swap_cartridge(&mut self, cartridge: Box<dyn Cartridge>) -> Box<dyn Cartridge> {
+        core::mem::replace(&mut self.cartridge, cartridge)
+    }
+
+    /// Contents of `region`, without going through the mapper: cartridge
+    /// RAM is there even while disabled, with all banks one after another.
+    pub fn memory(&self, region: MemoryRegion) -> &[u8] {
+        match region {
+            MemoryRegion::CartridgeRam => self.cartridge.ram(),
+            MemoryRegion::Wram => &self.ram,
+            MemoryRegion::Hram => &self.hram,
+        }
+    }
+
+    /// Lets debuggers edit `region`, see `memory`.
+    pub fn memory_mut(&mut self, region: MemoryRegion) -> &mut [u8] {
+        match region {
+            MemoryRegion::CartridgeRam => self.cartridge.ram_mut(),
+            MemoryRegion::Wram => &mut self.ram,
+            MemoryRegion::Hram => &mut self.hram,
+        }
+    }
+
+    /// Plugs `device` into the link port, or unplugs the one there with
+    /// `None`.
+    pub fn connect_link(&mut self, device: Option<Box<dyn LinkDevice>>) {
+        self.link = device;
+    }
+
+    pub fn link_device_mut(&mut self) -> Option<&mut (dyn LinkDevice + 'static)> {
+        self.link.as_deref_mut()
+    }
+
+    /// Lets the link device start a transfer if the game waits on the
+    /// external clock.
+    fn drive_link(&mut self) {
+        if !self.serial.is_waiting_for_clock() {
+            return;
+        }
+        let sent = self.serial.data();
+        if let Some(byte) = self.link.as_mut().and_then(|device| device.drive(sent)) {
+            self.serial.start_external(byte);
+            self.schedule_serial();
+        }
+    }
+
+    /// Starts recording interrupts, LCDC/STAT/IF/IE writes, bank switches
+    /// and DMA, keeping the latest `capacity` of them.
+    pub fn enable_event_log(&mut self, capacity: usize) {
+        self.event_log = Some(EventLog::new(capacity));
+    }
+
+    pub fn disable_event_log(&mut self) {
+        self.event_log = None;
+    }
+
+    pub fn event_log(&self) -> Option<&EventLog> {
+        self.event_log.as_ref()
+    }
+
+    /// Starts counting memory accesses into `heatmap`, or stops with `None`.
+    pub fn set_heatmap(&mut self, heatmap: Option<AccessHeatmap>) {
+        self.heatmap = heatmap;
+    }
+
+    pub fn heatmap(&self) -> Option<&AccessHeatmap> {
+        self.heatmap.as_ref()
+    }
+
+    pub fn heatmap_mut(&mut self) -> Option<&mut AccessHeatmap> {
+        self.heatmap.as_mut()
+    }
+
+    /// Starts logging APU register writes for `take_apu_writes`, e.g. to
+    /// save the music with `vgm::VgmWriter`.
+    ///
+    /// The log opens with writes recreating the sound already set up:
+    /// power, volume, panning and wave RAM. Channels playing now are heard
+    /// once the game triggers them again.
+    pub fn start_apu_log(&mut self) {
+        let mut writes = Vec::new();
+        let mut log = |addr, value| {
+            writes.push(ApuWrite {
+                cycles: 0,
+                addr,
+                value,
+            })
+        };
+        if self.apu.read(0xff26) & 0x80 != 0 {
+            log(0xff26, 0x80);
+            log(0xff24, self.apu.read(0xff24));
+            log(0xff25, self.apu.read(0xff25));
+        }
+        for addr in 0xff30..=0xff3f {
+            log(addr, self.apu.read(addr));
+        }
+        self.apu_log = Some((self.log_now(), writes));
+    }
+
+    pub fn stop_apu_log(&mut self) {
+        self.apu_log = None;
+    }
+
+    /// Takes the APU writes logged since the last call.
+    pub fn take_apu_writes(&mut self) -> Vec<ApuWrite> {
+        match &mut self.apu_log {
+            Some((_, writes)) => core::mem::take(writes),
+            None => Vec::new(),
+        }
+    }
+
+    /// Cycles since `start_apu_log`, or `None` when not logging.
+    pub fn apu_log_cycles(&self) -> Option<u64> {
+        let (start, _) = self.apu_log.as_ref()?;
+        Some(self.log_now().saturating_sub(*start))
+    }
+
+    /// Cycles since power-on, not restarted by `reset`, for timestamping
+    /// the APU and VRAM logs.
+    fn log_now(&self) -> u64 {
+        self.reset_cycles + self.scheduler.now()
+    }
+
+    /// Starts logging CPU writes to the VRAM addresses in `range` for
+    /// `take_vram_writes`, e.g. to watch a game stream animated tiles or
+    /// check a homebrew copy routine.
+    pub fn start_vram_log(&mut self, range: Range<u16>) {
+        self.vram_log = Some(VramLog {
+            start: self.log_now(),
+            range,
+            writes: Vec::new(),
+        });
+    }
+
+    pub fn stop_vram_log(&mut self) {
+        self.vram_log = None;
+    }
+
+    /// Takes the VRAM writes logged since the last call.
+    pub fn take_vram_writes(&mut self) -> Vec<VramWrite> {
+        match &mut self.vram_log {
+            Some(log) => core::mem::take(&mut log.writes),
+            None => Vec::new(),
+        }
+    }
+
+    /// Starts logging the bytes the game sends on the link port for
+    /// `take_serial_bytes`, e.g. test ROMs printing their results.
+    pub fn start_serial_log(&mut self) {
+        self.serial_log = Some(Vec::new());
+    }
+
+    pub fn stop_serial_log(&mut self) {
+        self.serial_log = None;
+    }
+
+    pub fn is_serial_logging(&self) -> bool {
+        self.serial_log.is_some()
+    }
+
+    /// Takes the bytes sent since the last call, each logged as its
+    /// transfer starts.
+    pub fn take_serial_bytes(&mut self) -> Vec<u8> {
+        self.serial_log
+            .as_mut()
+            .map(core::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn log_vram_write(&mut self, addr: u16, value: u8) {
+        let now = self.log_now();
+        let log = match &mut self.vram_log {
+            Some(log) if log.range.contains(&addr) => log,
+            _ => return,
+        };
+        log.writes.push(VramWrite {
+            cycles: now.saturating_sub(log.start),
+            addr,
+            value,
+            rom_bank: self.cartridge.rom_bank(),
+            // As the PPU sees it when deciding whether the write lands
+            mode: self.ppu.get_register(PpuReg::Stat) & 0x03,
+            ly: self.ppu.get_register(PpuReg::Ly),
+        });
+    }
+
+    /// Decoded copy of all IO registers, for debuggers and bug reports.
+    pub fn io_snapshot(&self) -> IoSnapshot {
+        IoSnapshot::capture(self)
+    }
+
+    fn log_event(&mut self, event: LogEvent) {
+        if let Some(log) = &mut self.event_log {
+            log.push(self.scheduler.now(), event);
+        }
+    }
+
+    /// Takes the audio samples produced so far, see `Apu::take_samples`.
+    pub fn take_audio_samples(&mut self) -> Vec<i16> {
+        self.sync_apu();
+        self.apu.take_samples()
+    }
+
+    fn schedule_ppu(&mut self) {
+        match self.ppu.cycles_until_next_event() {
+            Some(dots) => self
+                .scheduler
+                .schedule(Event::Ppu, self.ppu_synced + dots as u64),
+            None => self.scheduler.cancel(Event::Ppu),
+        }
+    }
+
+    fn schedule_timer(&mut self) {
+        let rate = self.cpu_rate();
+        match self.timer.cycles_until_overflow().map(u64::from) {
+            Some(cycles) => self
+                .scheduler
+                .schedule(Event::Timer, self.timer_synced + cycles.div_ceil(rate)),
+            None => self.scheduler.cancel(Event::Timer),
+        }
+    }
+
+    fn schedule_apu(&mut self) {
+        let counter = self.counter_at(self.apu_synced);
+        let at = self.apu_synced + self.apu.cycles_until_frame_step(counter) as u64;
+        self.scheduler.schedule(Event::Apu, at);
+    }
+
+    fn schedule_serial(&mut self) {
+        if self.serial.is_transferring() {
+            let at = self.scheduler.now() + serial::BIT_CYCLES;
+            self.scheduler.schedule(Event::Serial, at);
+        } else {
+            self.scheduler.cancel(Event::Serial);
+        }
+    }
+
+    #[rustfmt::skip]
+    pub fn reset_interrupt(&mut self, interrupt_type: Interrupt) {
+        self.log_event(LogEvent::Interrupt(interrupt_type));
+        self.stats.count_interrupt(interrupt_type);
+        match interrupt_type {
+            Interrupt::VBlank  => self.interrupt_flag &= 0b1111_1110,
+            Interrupt::LCDStat => self.interrupt_flag &= 0b1111_1101,
+            Interrupt::Timer   => self.interrupt_flag &= 0b1111_1011,
+            Interrupt::Serial  => self.interrupt_flag &= 0b1111_0111,
+            Interrupt::Joypad  => self.interrupt_flag &= 0b1110_1111,
+        }
+    }
+
+    /// Copies 0xa0 bytes from `val` * 0x100 to OAM. The DMA has its own
+    /// path to memory, so the PPU mode locks neither VRAM nor OAM for it.
+    fn do_dma(&mut self, val: u8) {
+        let src_base = (val as u16) << 8;
+        for i in 0..0xa0 {
+            let value = self.dma_read(src_base | i);
+            self.ppu.oam_mut()[i as usize] = value;
+            if let Some(heatmap) = &self.heatmap {
+                heatmap.record_read(src_base | i);
+                heatmap.record_write(0xfe00 | i);
+            }
+        }
+    }
+
+    /// A byte as the DMA sees it. From 0xe000 up it sees WRAM, like the
+    /// echo area, where the CPU would see OAM and the registers.
+    fn dma_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7fff | 0xa000..=0xbfff => self.cartridge.read(addr),
+            0x8000..=0x9fff => self.ppu.vram()[(addr & 0x1fff) as usize],
+            0xc000..=0xffff => self.ram[(addr & 0x1fff) as usize],
+        }
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        if let Some(heatmap) = &self.heatmap {
+            heatmap.record_read(addr);
+        }
+        self.check_access(addr, false);
+        self.peek(addr)
+    }
+
+    /// A byte as the CPU would read it, without counting the read in the
+    /// heatmap or checking it in strict mode, e.g. for tools sampling
+    /// memory as the game runs.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7fff => self.cartridge.read(addr),
+            0x8000..=0x9fff => self.ppu.read(addr),
+            0xa000..=0xbfff => self.cartridge.read(addr),
+            0xc000..=0xdfff => self.ram[(addr & 0x1fff) as usize],
+            0xe000..=0xfdff => self.ram[((addr - 0x2000) & 0x1fff) as usize],
+            0xfe00..=0xfe9f => self.ppu.read(addr),
+            0xfea0..=0xfeff => 0x00, // Not usable
+            0xff00 => self.joypad.read_byte(addr),
+            0xff01..=0xff02 => self.serial.read(addr),
+            0xff0f => self.interrupt_flag,
+            0xff10..=0xff3f => self.apu.read(addr),
+            0xff04..=0xff07 => {
+                let mut timer = self.timer.clone();
+                let rate = self.cpu_rate();
+                timer.advance((self.scheduler.now() - self.timer_synced) * rate);
+                timer.read(addr)
+            }
+            0xff40..=0xff45 | 0xff47..=0xff4b => self.ppu.read(addr),
+            // KEY1: the current speed in bit 7, the rest reads as 1
+            0xff4d if self.is_cgb() => {
+                0x7e | (self.double_speed as u8) << 7 | self.speed_switch_armed as u8
+            }
+            0xff68..=0xff6b if self.is_cgb() => self.ppu.read(addr),
+            0xff80..=0xfffe => self.hram[(addr & 0x7f) as usize],
+            0xffff => self.interrupt_enable,
+            _ => 0x00,
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, value: u8) {
+        if let Some(heatmap) = &self.heatmap {
+            heatmap.record_write(addr);
+        }
+        if self.strict {
+            match addr {
+                0x0000..=0x7fff if self.has_no_mapper() => {
+                    self.report(Violation::RomWrite { addr, value })
+                }
+                0xff46 if value >= 0xe0 => self.report(Violation::DmaSource(value)),
+                _ => self.check_access(addr, true),
+            }
+        }
+        match addr {
+            0x0000..=0x7fff if self.cartridge.is_bank_register(addr) => {
+                self.stats.bank_switches += 1;
+                self.log_event(LogEvent::BankSwitch { addr, value })
+            }
+            0xff0f | 0xff40 | 0xff41 | 0xffff => self.log_event(LogEvent::IoWrite { addr, value }),
+            0xff46 => {
+                self.stats.dma_transfers += 1;
+                self.log_event(LogEvent::Dma { source: value })
+            }
+            _ => (),
+        }
+
+        match addr {
+            0x0000..=0x7fff => self.cartridge.write(addr, value),
+            0x8000..=0x9fff => {
+                self.log_vram_write(addr, value);
+                self.ppu.write(addr, value)
+            }
+            0xa000..=0xbfff => self.cartridge.write(addr, value),
+            0xc000..=0xdfff => self.ram[(addr & 0x1fff) as usize] = value,
+            0xe000..=0xfdff => self.ram[((addr - 0x2000) & 0x1fff) as usize] = value,
+            0xfe00..=0xfe9f => self.ppu.write(addr, value),
+            0xfea0..=0xfeff => (), // Not usable
+            0xff00 => self.joypad.write_byte(addr, value),
+            0xff0f => self.interrupt_flag = value,
+            0xff01..=0xff02 => {
+                self.serial.write(addr, value);
+                if addr == 0xff02 {
+                    if let (true, Some(log)) = (value & 0x80 != 0, &mut self.serial_log) {
+                        log.push(self.serial.data());
+                    }
+                    if let (true, Some(device)) = (self.serial.is_transferring(), &mut self.link) {
+                        self.serial
+                            .set_incoming(device.exchange(self.serial.data()));
+                    }
+                    self.schedule_serial();
+                    self.drive_link();
+                }
+            }
+            0xff10..=0xff3f => {
+                self.sync_apu();
+                self.apu.write(addr, value);
+                let now = self.log_now();
+                if let Some((start, writes)) = &mut self.apu_log {
+                    writes.push(ApuWrite {
+                        cycles: now.saturating_sub(*start),
+                        addr,
+                        value,
+                    });
+                }
+            }
+            0xff04 => self.reset_div(),
+            0xff05..=0xff07 => {
+                self.sync_timer();
+                self.timer.write(addr, value);
+                self.schedule_timer();
+            }
+            0xff40..=0xff45 | 0xff47..=0xff4b => {
+                self.sync_ppu();
+                self.ppu.write(addr, value);
+                self.schedule_ppu();
+            }
+            0xff46 => self.do_dma(value),
+            0xff4d if self.is_cgb() => self.speed_switch_armed = value & 0x01 != 0,
+            0xff68..=0xff6b if self.is_cgb() => {
+                self.sync_ppu();
+                self.ppu.write(addr, value);
+            }
+            0xff80..=0xfffe => self.hram[(addr & 0x7f) as usize] = value,
+            0xffff => self.interrupt_enable = value,
+            _ => (),
+        }
+    }
+
+    /// Advances the machine by `clock` CPU cycles, running every event
+    /// that falls due on the way. In double speed that is half as many
+    /// cycles of the rest of the machine; the CPU's come in fours.
+    pub fn update(&mut self, clock: u16) {
+        let cycles = clock as u64 / self.cpu_rate();
+        let until = self.scheduler.now() + cycles;
+        while let Some(event) = self.scheduler.pop_due(until) {
+            match event {
+                Event::Ppu => {
+                    self.sync_ppu();
+                    self.schedule_ppu();
+                }
+                Event::Timer => {
+                    self.sync_timer();
+                    self.schedule_timer();
+                }
+                Event::Serial => {
+                    self.serial.shift_bit();
+                    self.schedule_serial();
+                }
+                Event::Apu => {
+                    self.sync_apu();
+                    self.schedule_apu();
+                }
+            }
+        }
+        self.scheduler.advance_to(until);
+
+        if self.ppu.is_irq_vblank() {
+            self.interrupt_flag |= 0x1;
+            self.ppu.set_irq_vblank(false);
+            self.drive_link();
+        }
+
+        if self.ppu.is_irq_lcdc() {
+            self.interrupt_flag |= 0x2;
+            self.ppu.set_irq_lcdc(false);
+        }
+
+        if self.timer.is_irq_timer() {
+            self.interrupt_flag |= 0x4;
+            self.timer.set_irq_timer(false);
+        }
+
+        if self.serial.irq {
+            self.interrupt_flag |= 0x8;
+            self.serial.irq = false;
+        }
+
+        if self.joypad.irq {
+            self.interrupt_flag |= 0x10;
+            self.joypad.irq = false;
+        }
+    }
+}
+
+impl Bus for Mmu {
+    fn read_byte(&self, addr: u16) -> u8 {
+        Mmu::read_byte(self, addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        Mmu::peek(self, addr)
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        Mmu::write_byte(self, addr, value)
+    }
+
+    fn update(&mut self, clock: u16) {
+        Mmu::update(self, clock)
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.interrupt_flag & self.interrupt_enable & 0x1f
+    }
+
+    fn reset_interrupt(&mut self, interrupt: Interrupt) {
+        Mmu::reset_interrupt(self, interrupt)
+    }
+
+    fn cycles_until_next_event(&self) -> Option<u64> {
+        let rate = self.cpu_rate();
+        self.scheduler.next_event_in().map(|cycles| cycles * rate)
+    }
+
+    fn idu_access(&mut self, addr: u16) {
+        if (0xfe00..=0xfeff).contains(&addr) {
+            self.sync_ppu();
+            self.ppu.corrupt_oam();
+        }
+    }
+
+    fn stop(&mut self) {
+        self.reset_div();
+        if self.speed_switch_armed {
+            // The counter was just cleared at the old speed, so it only
+            // counts at the new one from here
+            self.double_speed = !self.double_speed;
+            self.speed_switch_armed = false;
+            self.schedule_timer();
+            self.schedule_apu();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::{fix_checksum, rom_with_header};
+    use crate::link::Reply;
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_reload_rom_keeps_ram_and_bank() {
+        use crate::cartridge::tests::write_temp_rom;
+        use std::fs;
+
+        let mut rom = rom_with_header(0x03, 0x02);
+        rom.resize(0x10000, 0);
+        rom[0x148] = 0x01;
+        fix_checksum(&mut rom);
+        let path = write_temp_rom("reload", &rom);
+        let mut mmu = Mmu::new(&path);
+        fs::remove_file(&path).unwrap();
+
+        mmu.write_byte(0x0000, 0x0a);
+        mmu.write_byte(0xa000, 0x42);
+        mmu.write_byte(0xc000, 0x24);
+        mmu.write_byte(0x2000, 0x03);
+
+        // The rebuilt game has new code in bank 3
+        rom[0xc000] = 0x99;
+        mmu.reload_rom(rom).unwrap();
+
+        assert_eq!(mmu.read_byte(0x4000), 0x99);
+        assert_eq!(mmu.read_byte(0xa000), 0x42);
+        assert_eq!(mmu.read_byte(0xc000), 0x24);
+    }
+
+    #[test]
+    fn test_swap_cartridge() {
+        let mut mmu = Mmu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        mmu.write_byte(0xc000, 0x24);
+        let mut rom = rom_with_header(0x09, 0x02);
+        rom[0x0000] = 0x42;
+        let old = mmu.swap_cartridge(cartridge::from_bytes(rom).unwrap());
+
+        assert_eq!(old.read(0x0000), 0x00);
+        assert_eq!(mmu.read_byte(0x0000), 0x42);
+        mmu.write_byte(0xa000, 0x12);
+        assert_eq!(mmu.read_byte(0xa000), 0x12);
+        // Everything but the cartridge carries on
+        assert_eq!(mmu.read_byte(0xc000), 0x24);
+    }
+
+    #[test]
+    fn test_memory_regions() {
+        let mut mmu = Mmu::with_rom(rom_with_header(0x03, 0x03)).unwrap();
+        mmu.memory_mut(MemoryRegion::CartridgeRam)[0x2001] = 0x12;
+        mmu.memory_mut(MemoryRegion::Wram)[0x0002] = 0x34;
+        mmu.memory_mut(MemoryRegion::Hram)[0x0003] = 0x56;
+
+        assert_eq!(mmu.memory(MemoryRegion::CartridgeRam).len(), 0x8000);
+        assert_eq!(mmu.read_byte(0xc002), 0x34);
+        assert_eq!(mmu.read_byte(0xff83), 0x56);
+        // Bank 1 of the RAM, once enabled and selected
+        mmu.write_byte(0x0000, 0x0a);
+        mmu.write_byte(0x6000, 0x01);
+        mmu.write_byte(0x4000, 0x01);
+        assert_eq!(mmu.read_byte(0xa001), 0x12);
+        assert_eq!(
+            MemoryRegion::Hram.bank_len(),
+            mmu.memory(MemoryRegion::Hram).len()
+        );
+    }
+
+    #[test]
+    fn test_stop_resets_div() {
+        let mut mmu = Mmu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        mmu.write_byte(0xff07, 0x05);
+        mmu.update(1000);
+        assert_eq!(mmu.read_byte(0xff04), 3);
+        let tima = mmu.read_byte(0xff05);
+
+        Bus::stop(&mut mmu);
+        assert_eq!(mmu.read_byte(0xff04), 0);
+        // Bit 3 of 1000 was set, so clearing it counted once
+        let tima = tima.wrapping_add(1);
+        assert_eq!(mmu.read_byte(0xff05), tima);
+        // The counter starts over, so TIMA takes a full period to tick
+        mmu.update(15);
+        assert_eq!(mmu.read_byte(0xff05), tima);
+        mmu.update(1);
+        assert_eq!(mmu.read_byte(0xff05), tima.wrapping_add(1));
+    }
+
+    #[test]
+    fn test_speed_switch() {
+        let mut rom = rom_with_header(0x00, 0x00);
+        rom[0x143] = 0x80;
+        fix_checksum(&mut rom);
+        for double_speed in [false, true] {
+            let mut mmu = Mmu::with_rom(rom.clone()).unwrap();
+            assert_eq!(mmu.read_byte(0xff4d), 0x7e);
+            if double_speed {
+                mmu.write_byte(0xff4d, 0x01);
+                assert_eq!(mmu.read_byte(0xff4d), 0x7f);
+                Bus::stop(&mut mmu);
+                assert_eq!(mmu.read_byte(0xff4d), 0xfe);
+            }
+            // CPU cycles to one cycle of the PPU and APU
+            let rate = 1 + double_speed as u16;
+            mmu.write_byte(0xff07, 0x05);
+            // Square 2 with length 63 and the length counter enabled
+            mmu.write_byte(0xff26, 0x80);
+            mmu.write_byte(0xff17, 0xf0);
+            mmu.write_byte(0xff16, 0xbf);
+            mmu.write_byte(0xff19, 0xc7);
+
+            // DIV and TIMA count CPU cycles at either speed
+            mmu.update(1024);
+            assert_eq!(mmu.read_byte(0xff04), 4);
+            assert_eq!(mmu.read_byte(0xff05), 64);
+
+            // The frame sequencer steps every 8192 cycles of the APU, the
+            // first step ending the sound
+            mmu.update(8192 * rate - 1024 - 4);
+            assert_eq!(mmu.read_byte(0xff26) & 0x02, 0x02);
+            mmu.update(4);
+            assert_eq!(mmu.read_byte(0xff26) & 0x02, 0x00);
+        }
+    }
+
+    #[test]
+    fn test_key1_needs_cgb_header() {
+        let mut mmu = Mmu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        mmu.write_byte(0xff4d, 0x01);
+        Bus::stop(&mut mmu);
+        assert_eq!(mmu.read_byte(0xff4d), 0x00);
+        mmu.write_byte(0xff07, 0x05);
+        mmu.update(1024);
+        assert_eq!(mmu.read_byte(0xff04), 4);
+    }
+
+    #[test]
+    fn test_apu_log() {
+        let mut mmu = Mmu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        mmu.write_byte(0xff26, 0x80);
+        mmu.write_byte(0xff24, 0x77);
+        mmu.write_byte(0xff12, 0xf0);
+        mmu.update(100);
+        mmu.start_apu_log();
+
+        let start = mmu.take_apu_writes();
+        assert_eq!(start.len(), 3 + 16);
+        assert_eq!(
+            start[1],
+            ApuWrite {
+                cycles: 0,
+                addr: 0xff24,
+                value: 0x77
+            }
+        );
+
+        mmu.update(40);
+        mmu.write_byte(0xff14, 0x80);
+        mmu.write_byte(0xff40, 0x00);
+        assert_eq!(
+            mmu.take_apu_writes(),
+            vec![ApuWrite {
+                cycles: 40,
+                addr: 0xff14,
+                value: 0x80
+            }]
+        );
+        assert_eq!(mmu.apu_log_cycles(), Some(40));
+
+        // A reset restarts the clock, not the log's
+        mmu.update(10);
+        mmu.reset(false);
+        mmu.update(30);
+        mmu.write_byte(0xff26, 0x80);
+        assert_eq!(
+            mmu.take_apu_writes(),
+            vec![ApuWrite {
+                cycles: 80,
+                addr: 0xff26,
+                value: 0x80
+            }]
+        );
+        assert_eq!(mmu.apu_log_cycles(), Some(80));
+
+        mmu.stop_apu_log();
+        mmu.write_byte(0xff14, 0x80);
+        assert!(mmu.take_apu_writes().is_empty());
+    }
+
+    #[test]
+    fn test_link_device() {
+        let mut mmu = Mmu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        mmu.connect_link(Some(Box::new(Reply(0x5a))));
+        mmu.write_byte(0xff01, 0x12);
+        mmu.write_byte(0xff02, 0x81);
+        mmu.update((8 * serial::BIT_CYCLES) as u16);
+        assert_eq!(mmu.read_byte(0xff01), 0x5a);
+        assert_ne!(mmu.interrupt_flag & 0x08, 0);
+
+        // A device that only answers never clocks a transfer itself
+        mmu.write_byte(0xff02, 0x80);
+        mmu.update((8 * serial::BIT_CYCLES) as u16);
+        assert_eq!(mmu.read_byte(0xff02), 0xfe);
+    }
+
+    #[test]
+    fn test_vram_log() {
+        let mut mmu = Mmu::with_rom(rom_with_header(0x01, 0x00)).unwrap();
+        // With the LCD off the PPU stays in mode 0 on line 0
+        mmu.write_byte(0xff40, 0x00);
+        mmu.write_byte(0x8000, 0x11);
+        mmu.start_vram_log(0x8000..0x9000);
+
+        mmu.update(20);
+        mmu.write_byte(0x8010, 0x3c);
+        mmu.write_byte(0x9800, 0x01);
+        assert_eq!(
+            mmu.take_vram_writes(),
+            vec![VramWrite {
+                cycles: 20,
+                addr: 0x8010,
+                value: 0x3c,
+                rom_bank: 1,
+                mode: 0,
+                ly: 0
+            }]
+        );
+        assert_eq!(mmu.take_vram_writes(), vec![]);
+
+        // Timestamps keep counting across a reset
+        mmu.update(10);
+        mmu.reset(false);
+        mmu.write_byte(0xff40, 0x00);
+        mmu.update(5);
+        mmu.write_byte(0x8020, 0x7e);
+        let writes = mmu.take_vram_writes();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].cycles, 35);
+
+        mmu.stop_vram_log();
+        mmu.write_byte(0x8010, 0x3c);
+        assert_eq!(mmu.take_vram_writes(), vec![]);
+    }
+
+    #[test]
+    fn test_dma_sources() {
+        let mut mmu = Mmu::with_rom(rom_with_header(0x03, 0x02)).unwrap();
+        mmu.write_byte(0xff40, 0x00);
+        mmu.write_byte(0x0000, 0x0a);
+        for i in 0..0xa0 {
+            mmu.write_byte(0x8000 + i, i as u8);
+            mmu.write_byte(0xa000 + i, 0x20 + i as u8);
+            mmu.write_byte(0xde00 + i, 0x40 + i as u8);
+        }
+        // Into mode 3 of the first line, where the CPU is locked out of both
+        mmu.write_byte(0xff40, 0x91);
+        mmu.update(100);
+        assert_eq!(mmu.read_byte(0xff41) & 0x03, 3);
+
+        for (source, first) in [(0x80, 0x00), (0xa0, 0x20), (0xde, 0x40), (0xfe, 0x40)] {
+            mmu.write_byte(0xff46, source);
+            let expected: Vec<u8> = (0..0xa0).map(|i| first + i as u8).collect();
+            assert_eq!(
+                mmu.ppu.oam()[..0xa0],
+                expected[..],
+                "source 0x{:02x}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_heatmap_counts_dma() {
+        let mut mmu = Mmu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        mmu.set_heatmap(Some(AccessHeatmap::new()));
+        mmu.write_byte(0xff46, 0xc1);
+        let heatmap = mmu.heatmap().unwrap();
+        assert_eq!(heatmap.page(0xc1).reads, 0xa0);
+        assert_eq!(heatmap.page(0xfe).writes, 0xa0);
+    }
+
+    #[test]
+    fn test_strict_mode() {
+        let mut mmu = Mmu::with_rom(rom_with_header(0x00, 0x00)).unwrap();
+        mmu.write_byte(0x2000, 0x01);
+        mmu.write_byte(0xff46, 0xfe);
+        assert!(mmu.take_violations().is_empty());
+
+        mmu.set_strict(true);
+        mmu.write_byte(0x2000, 0x01);
+        mmu.write_byte(0xff46, 0xc1);
+        mmu.write_byte(0xff46, 0xfe);
+        mmu.write_byte(0xff40, 0x91);
+        // Into mode 3 of the first line
+        mmu.update(100);
+        mmu.read_byte(0x8000);
+        mmu.write_byte(0xfe00, 0x12);
+        assert_eq!(
+            mmu.take_violations(),
+            vec![
+                Violation::RomWrite {
+                    addr: 0x2000,
+                    value: 0x01
+                },
+                Violation::DmaSource(0xfe),
+                Violation::VramAccess {
+                    addr: 0x8000,
+                    write: false
+                },
+                Violation::OamAccess {
+                    addr: 0xfe00,
+                    write: true,
+                    mode: 3
+                },
+            ]
+        );
+        assert!(mmu.take_violations().is_empty());
+    }
+
+    #[test]
+    fn test_random_ram_is_seeded() {
+        let rom = rom_with_header(0x00, 0x00);
+        let power_on = |seed| {
+            let mut mmu = Mmu::with_rom(rom.clone()).unwrap();
+            mmu.set_seed(seed);
+            mmu.set_random_ram(true);
+            (mmu.ram, mmu.hram)
+        };
+        assert_eq!(power_on(1), power_on(1));
+        assert_ne!(power_on(1), power_on(2));
+
+        // Resets after loading a state draw the same numbers again
+        let mut mmu = Mmu::with_rom(rom).unwrap();
+        mmu.set_random_ram(true);
+        let mut w = StateWriter::new();
+        mmu.save_state(&mut w);
+        mmu.reset(false);
+        let after_reset = mmu.ram;
+        mmu.load_state(&mut StateReader::new(&w.into_bytes()))
+            .unwrap();
+        mmu.reset(false);
+        assert_eq!(mmu.ram, after_reset);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::ppu::Rgb;
+
+/// A set of colors for the four DMG shades, lightest first.
+pub struct Palette {
+    pub name: &'static str,
+    pub shades: [Rgb; 4],
+}
+
+/// Packs `color` as RGB565, 5 bits of red in the high bits, for LCDs and
+/// framebuffers that take 16-bit pixels.
+pub const fn rgb565(color: Rgb) -> u16 {
+    (color[0] as u16 >> 3) << 11 | (color[1] as u16 >> 2) << 5 | color[2] as u16 >> 3
+}
+
+/// Expands a CGB palette RAM color, 5 bits of red in the low bits, to
+/// RGB24 by repeating the high bits of each channel in the low ones.
+pub const fn bgr555(color: u16) -> Rgb {
+    [
+        expand5((color & 0x1f) as u8),
+        expand5((color >> 5 & 0x1f) as u8),
+        expand5((color >> 10 & 0x1f) as u8),
+    ]
+}
+
+const fn expand5(value: u8) -> u8 {
+    value << 3 | value >> 2
+}
+
+/// Palettes shipped with the emulator. The first one is the default.
+///
+/// The color-blind palettes keep the shades in the same lightness order as
+/// the original and avoid the red/green axis, so no two shades can be
+/// confused.
+pub const BUILTIN_PALETTES: &[Palette] = &[
+    Palette {
+        name: "grayscale",
+        shades: [
+            [0xff, 0xff, 0xff],
+            [0xaa, 0xaa, 0xaa],
+            [0x55, 0x55, 0x55],
+            [0x00, 0x00, 0x00],
+        ],
+    },
+    Palette {
+        name: "dmg-green",
+        shades: [
+            [0x9b, 0xbc, 0x0f],
+            [0x8b, 0xac, 0x0f],
+            [0x30, 0x62, 0x30],
+            [0x0f, 0x38, 0x0f],
+        ],
+    },
+    Palette {
+        name: "deuteranopia",
+        shades: [
+            [0xff, 0xf5, 0xc0],
+            [0xe6, 0x9f, 0x00],
+            [0x00, 0x72, 0xb2],
+            [0x1a, 0x1a, 0x40],
+        ],
+    },
+    Palette {
+        name: "protanopia",
+        shades: [
+            [0xf5, 0xf5, 0xdc],
+            [0xf0, 0xe4, 0x42],
+            [0x00, 0x72, 0xb2],
+            [0x00, 0x00, 0x00],
+        ],
+    },
+    Palette {
+        name: "high-contrast",
+        shades: [
+            [0xff, 0xff, 0xff],
+            [0xc0, 0xc0, 0xc0],
+            [0x40, 0x40, 0x40],
+            [0x00, 0x00, 0x00],
+        ],
+    },
+];
+
+/// Looks up a built-in palette by name, returning its index in
+/// `BUILTIN_PALETTES` so frontends can cycle on from it.
+pub fn find(name: &str) -> Option<usize> {
+    BUILTIN_PALETTES.iter().position(|p| p.name == name)
+}
@@ -0,0 +1,304 @@
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::error;
+use core::fmt;
+
+use crate::romdb::crc32;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatchError {
+    /// Neither an IPS nor a BPS patch.
+    UnknownFormat,
+    /// The patch ended in the middle of a record.
+    Truncated,
+    /// A BPS patch was made for another ROM.
+    SourceMismatch,
+    /// The patch or its output fails the BPS checksums.
+    BadChecksum,
+    /// A BPS copy reaches outside the source or target.
+    OutOfRange,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatchError::UnknownFormat => write!(f, "not an IPS or BPS patch"),
+            PatchError::Truncated => write!(f, "patch is truncated"),
+            PatchError::SourceMismatch => write!(f, "patch is for a different ROM"),
+            PatchError::BadChecksum => write!(f, "patch is corrupt"),
+            PatchError::OutOfRange => write!(f, "patch copies outside the ROM"),
+        }
+    }
+}
+
+impl error::Error for PatchError {}
+
+/// Applies an IPS or BPS patch to `rom`, telling them apart by their magic.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+/// Reads patch bytes front to back.
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.pos.checked_add(len).ok_or(PatchError::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(PatchError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Big-endian number of `len` bytes, as IPS stores them.
+    fn read_be(&mut self, len: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0, |acc, &b| (acc << 8) | b as usize))
+    }
+
+    /// BPS variable-length number.
+    fn read_varint(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.take(1)?[0] as usize;
+            value = value
+                .checked_add((byte & 0x7f) * shift)
+                .ok_or(PatchError::OutOfRange)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::OutOfRange)?;
+            value = value.checked_add(shift).ok_or(PatchError::OutOfRange)?;
+        }
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut out = rom.to_vec();
+    let mut cursor = Cursor {
+        data: patch,
+        pos: 5,
+    };
+    loop {
+        if cursor.data.get(cursor.pos..cursor.pos + 3) == Some(b"EOF") {
+            cursor.pos += 3;
+            break;
+        }
+        let offset = cursor.read_be(3)?;
+        let size = cursor.read_be(2)?;
+        let (len, bytes) = if size == 0 {
+            // Run-length record
+            let len = cursor.read_be(2)?;
+            (len, None)
+        } else {
+            (size, Some(cursor.take(size)?))
+        };
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        match bytes {
+            Some(bytes) => out[offset..offset + len].copy_from_slice(bytes),
+            None => {
+                let value = cursor.take(1)?[0];
+                out[offset..offset + len].fill(value);
+            }
+        }
+    }
+    // Lunar IPS extension: the size to truncate the result to
+    if let Ok(size) = cursor.read_be(3) {
+        out.truncate(size);
+    }
+    Ok(out)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.len() < 4 + 12 {
+        return Err(PatchError::Truncated);
+    }
+    let footer = patch.len() - 12;
+    let checksum = |at: usize| u32::from_le_bytes(patch[at..at + 4].try_into().unwrap());
+    if crc32(&patch[..footer + 8]) != checksum(footer + 8) {
+        return Err(PatchError::BadChecksum);
+    }
+    if crc32(rom) != checksum(footer) {
+        return Err(PatchError::SourceMismatch);
+    }
+
+    let mut cursor = Cursor {
+        data: &patch[..footer],
+        pos: 4,
+    };
+    let source_size = cursor.read_varint()?;
+    let target_size = cursor.read_varint()?;
+    let metadata_size = cursor.read_varint()?;
+    cursor.take(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(PatchError::SourceMismatch);
+    }
+
+    // The size comes from the patch, so only reserve what a patch of this
+    // size could plausibly write
+    let mut out = Vec::with_capacity(target_size.min(patch.len() + rom.len()));
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    // Relative offsets are stored as magnitude and sign bit
+    let relative = |base: usize, data: usize| {
+        let delta = data >> 1;
+        if data & 1 != 0 {
+            base.checked_sub(delta)
+        } else {
+            base.checked_add(delta)
+        }
+        .ok_or(PatchError::OutOfRange)
+    };
+    while cursor.pos < footer {
+        let data = cursor.read_varint()?;
+        let len = (data >> 2) + 1;
+        if out
+            .len()
+            .checked_add(len)
+            .is_none_or(|end| end > target_size)
+        {
+            return Err(PatchError::OutOfRange);
+        }
+        match data & 3 {
+            // SourceRead
+            0 => {
+                let at = out.len();
+                out.extend_from_slice(rom.get(at..at + len).ok_or(PatchError::OutOfRange)?);
+            }
+            // TargetRead
+            1 => out.extend_from_slice(cursor.take(len)?),
+            // SourceCopy
+            2 => {
+                source_offset = relative(source_offset, cursor.read_varint()?)?;
+                let bytes = source_offset
+                    .checked_add(len)
+                    .and_then(|end| rom.get(source_offset..end))
+                    .ok_or(PatchError::OutOfRange)?;
+                out.extend_from_slice(bytes);
+                source_offset += len;
+            }
+            // TargetCopy, which may overlap what it is writing
+            _ => {
+                target_offset = relative(target_offset, cursor.read_varint()?)?;
+                if target_offset >= out.len() {
+                    return Err(PatchError::OutOfRange);
+                }
+                for _ in 0..len {
+                    out.push(out[target_offset]);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if out.len() != target_size || crc32(&out) != checksum(footer + 4) {
+        return Err(PatchError::BadChecksum);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut value: usize, out: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte | 0x80);
+                return;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    #[test]
+    fn test_ips() {
+        let rom = vec![0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        // 2 bytes at 1
+        patch.extend_from_slice(&[0, 0, 1, 0, 2, 0xaa, 0xbb]);
+        // 3 times 0xcc at 6, growing the ROM
+        patch.extend_from_slice(&[0, 0, 6, 0, 0, 0, 3, 0xcc]);
+        patch.extend_from_slice(b"EOF");
+
+        assert_eq!(
+            apply(&rom, &patch),
+            Ok(vec![0, 0xaa, 0xbb, 0, 0, 0, 0xcc, 0xcc, 0xcc])
+        );
+        assert_eq!(
+            apply(&rom, &patch[..patch.len() - 4]),
+            Err(PatchError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_bps() {
+        let rom = b"abcdefgh".to_vec();
+        let target = b"abcXYXYXgh".to_vec();
+        let mut patch = b"BPS1".to_vec();
+        varint(rom.len(), &mut patch);
+        varint(target.len(), &mut patch);
+        varint(0, &mut patch);
+        // SourceRead "abc"
+        varint(2 << 2, &mut patch);
+        // TargetRead "XY"
+        varint((1 << 2) | 1, &mut patch);
+        patch.extend_from_slice(b"XY");
+        // TargetCopy "XYX" from 3, overlapping its own output
+        varint((2 << 2) | 3, &mut patch);
+        varint(3 << 1, &mut patch);
+        // SourceCopy "gh" from 6
+        varint((1 << 2) | 2, &mut patch);
+        varint(6 << 1, &mut patch);
+        patch.extend_from_slice(&crc32(&rom).to_le_bytes());
+        patch.extend_from_slice(&crc32(&target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+
+        assert_eq!(apply(&rom, &patch), Ok(target));
+        assert_eq!(apply(b"abcdefgX", &patch), Err(PatchError::SourceMismatch));
+        assert_eq!(apply(&rom, b"nope"), Err(PatchError::UnknownFormat));
+    }
+
+    /// Appends the three checksums, the patch's being valid.
+    fn finish_bps(rom: &[u8], patch: &mut Vec<u8>) {
+        patch.extend_from_slice(&crc32(rom).to_le_bytes());
+        patch.extend_from_slice(&0u32.to_le_bytes());
+        patch.extend_from_slice(&crc32(patch).to_le_bytes());
+    }
+
+    #[test]
+    fn test_bps_bad_sizes() {
+        let rom = b"abcdefgh".to_vec();
+        // A target size no allocation could hold
+        let mut patch = b"BPS1".to_vec();
+        varint(rom.len(), &mut patch);
+        varint(usize::MAX >> 1, &mut patch);
+        varint(0, &mut patch);
+        varint(7 << 2, &mut patch);
+        finish_bps(&rom, &mut patch);
+        assert_eq!(apply(&rom, &patch), Err(PatchError::BadChecksum));
+
+        // A varint running past the largest usize
+        let mut patch = b"BPS1".to_vec();
+        patch.extend_from_slice(&[0x7f; 12]);
+        patch.push(0x80);
+        finish_bps(&rom, &mut patch);
+        assert_eq!(apply(&rom, &patch), Err(PatchError::OutOfRange));
+    }
+}
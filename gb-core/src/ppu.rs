@@ -0,0 +1,1851 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use log::debug;
+
+#[cfg(feature = "rgb565")]
+use crate::palette::rgb565;
+use crate::palette::{bgr555, BUILTIN_PALETTES};
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::utils::fnv1a_64;
+
+/// Log target for PPU modes and registers.
+const LOG_TARGET: &str = "gb::ppu";
+
+/// Dots per scanline, shared by modes 2, 3 and 0.
+pub const DOTS_PER_LINE: u16 = 456;
+/// Mode 2 always takes 80 dots.
+pub const OAM_SEARCH_DOTS: u16 = 80;
+/// Shortest possible mode 3.
+pub const MIN_DRAWING_DOTS: u16 = 172;
+/// Dots from turning the LCD on until mode 3 of line 0. Line 0 skips mode 2
+/// and is 4 dots shorter than a regular line.
+const LCD_ENABLE_DOTS: u16 = OAM_SEARCH_DOTS - 4;
+
+/// Dots spent in each mode on a visible scanline.
+///
+/// Mode 3 stretches with SCX, the window and objects; mode 0 shrinks by the same
+/// amount so the line always adds up to `DOTS_PER_LINE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineTiming {
+    /// Mode 2
+    pub oam_search: u16,
+    /// Mode 3
+    pub drawing: u16,
+    /// Mode 0
+    pub hblank: u16,
+}
+
+impl LineTiming {
+    fn with_drawing(drawing: u16) -> Self {
+        LineTiming {
+            oam_search: OAM_SEARCH_DOTS,
+            drawing,
+            hblank: DOTS_PER_LINE - OAM_SEARCH_DOTS - drawing,
+        }
+    }
+}
+
+/// The PPU's registers, for tools that would otherwise spell out their
+/// addresses. DMA (0xff46) belongs to the MMU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PpuReg {
+    Lcdc,
+    Stat,
+    Scy,
+    Scx,
+    Ly,
+    Lyc,
+    Bgp,
+    Obp0,
+    Obp1,
+    Wy,
+    Wx,
+}
+
+impl PpuReg {
+    /// In address order.
+    pub const ALL: [PpuReg; 11] = [
+        PpuReg::Lcdc,
+        PpuReg::Stat,
+        PpuReg::Scy,
+        PpuReg::Scx,
+        PpuReg::Ly,
+        PpuReg::Lyc,
+        PpuReg::Bgp,
+        PpuReg::Obp0,
+        PpuReg::Obp1,
+        PpuReg::Wy,
+        PpuReg::Wx,
+    ];
+
+    pub fn addr(self) -> u16 {
+        match self {
+            PpuReg::Lcdc => 0xff40,
+            PpuReg::Stat => 0xff41,
+            PpuReg::Scy => 0xff42,
+            PpuReg::Scx => 0xff43,
+            PpuReg::Ly => 0xff44,
+            PpuReg::Lyc => 0xff45,
+            PpuReg::Bgp => 0xff47,
+            PpuReg::Obp0 => 0xff48,
+            PpuReg::Obp1 => 0xff49,
+            PpuReg::Wy => 0xff4a,
+            PpuReg::Wx => 0xff4b,
+        }
+    }
+
+    /// The name pan docs use, e.g. "LCDC".
+    pub fn name(self) -> &'static str {
+        match self {
+            PpuReg::Lcdc => "LCDC",
+            PpuReg::Stat => "STAT",
+            PpuReg::Scy => "SCY",
+            PpuReg::Scx => "SCX",
+            PpuReg::Ly => "LY",
+            PpuReg::Lyc => "LYC",
+            PpuReg::Bgp => "BGP",
+            PpuReg::Obp0 => "OBP0",
+            PpuReg::Obp1 => "OBP1",
+            PpuReg::Wy => "WY",
+            PpuReg::Wx => "WX",
+        }
+    }
+
+    pub fn from_addr(addr: u16) -> Option<PpuReg> {
+        PpuReg::ALL.iter().copied().find(|reg| reg.addr() == addr)
+    }
+
+    /// Looks a register up by its name, in any case.
+    pub fn from_name(name: &str) -> Option<PpuReg> {
+        PpuReg::ALL
+            .iter()
+            .copied()
+            .find(|reg| reg.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// A CPU write to VRAM, logged by `Mmu::start_vram_log`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VramWrite {
+    /// Cycles since the log was started.
+    pub cycles: u64,
+    pub addr: u16,
+    pub value: u8,
+    /// ROM bank at 0x4000-0x7fff, where the code or tiles being copied
+    /// usually are.
+    pub rom_bank: usize,
+    /// PPU mode the write came in, 0 to 3. Writes in mode 3 are dropped.
+    pub mode: u8,
+    pub ly: u8,
+}
+
+pub struct Ppu {
+    vram: [u8; 0x2000],
+    oam: [u8; 0xa0],
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    dma: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    /// RGB24, row-major, the frame being drawn. The three frame buffers
+    /// stay empty with the `rgb565` feature.
+    frame: Vec<u8>,
+    /// The last frame drawn in full, swapped with `frame` at VBlank
+    ready: Vec<u8>,
+    /// The frame `take_completed_frame` last returned
+    front: Vec<u8>,
+    /// Set while `ready` is newer than `front`
+    frame_ready: bool,
+    counter: u16,
+    line_timing: LineTiming,
+    /// Set from LCD enable until mode 3 of the first line.
+    lcd_enabling: bool,
+    /// The first frame after LCD enable is not displayed.
+    skip_frame: bool,
+    /// Internal window line counter
+    window_line: u8,
+    /// Set once LY has matched WY in the current frame
+    wy_triggered: bool,
+    /// BG/window color indices of the line being drawn, for object priority
+    bg_line: [u8; 160],
+    /// Debug switches, not part of the emulated hardware
+    show_bg: bool,
+    show_window: bool,
+    show_sprites: bool,
+    /// Cleared to skip drawing pixels altogether, e.g. for benchmarks
+    rendering: bool,
+    /// DMG quirk: writing STAT briefly enables every STAT interrupt source.
+    /// CGB hardware does not have it.
+    stat_write_bug: bool,
+    /// DMG OAM corruption by INC rr/DEC rr during mode 2, off by default
+    oam_bug: bool,
+    pixel_transform: Option<Box<dyn PixelTransform>>,
+    /// Source of every pixel of the frame, while recording them
+    attribution: Option<Vec<Option<PixelSource>>>,
+    /// Colors of the four DMG shades, lightest first
+    dmg_palette: [Rgb; 4],
+    /// CGB palette RAM behind BCPS/BCPD and OCPS/OCPD. Kept for palette
+    /// viewers; the PPU draws with the DMG palettes.
+    bg_palette_ram: PaletteRam,
+    obj_palette_ram: PaletteRam,
+    /// RGB565, the line drawn last, see `render_into`
+    #[cfg(feature = "rgb565")]
+    line: [u16; SCREEN_WIDTH],
+    /// LY of `line` until `render_into` copies it
+    #[cfg(feature = "rgb565")]
+    line_drawn: Option<u8>,
+    /// Set when the frame was blanked until `render_into` copies it
+    #[cfg(feature = "rgb565")]
+    blanked: bool,
+    irq_lcdc: bool,
+    irq_vblank: bool,
+    /// Frames completed since power-on, counted at VBlank
+    frame_count: u64,
+    /// Set on entering VBlank, cleared by `take_vblank_entered`
+    vblank_entered: bool,
+}
+
+/// Width of the LCD in pixels.
+pub const SCREEN_WIDTH: usize = 160;
+/// Height of the LCD in pixels.
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// RGB color.
+pub type Rgb = [u8; 3];
+
+/// Hook applied to every pixel as the PPU commits it to the framebuffer.
+///
+/// Lets downstream crates recolor the output (accessibility palettes,
+/// highlighting a single layer, ...) without touching the renderer.
+pub trait PixelTransform {
+    /// `palette_index` is the 2-bit color number looked up in BGP/OBP0/OBP1,
+    /// `color` is what the PPU would write without the hook.
+    fn transform(&mut self, layer: Layer, palette_index: u8, color: Rgb) -> Rgb;
+}
+
+/// Layers composed into each scanline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Background,
+    Window,
+    Sprites,
+}
+
+/// Palette register a pixel's color went through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteRegister {
+    Bgp,
+    Obp0,
+    Obp1,
+}
+
+/// The colors each palette gives color numbers 0-3, see
+/// `Ppu::debug_palettes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DebugPalettes {
+    /// The colors of the four DMG shades, lightest first.
+    pub shades: [Rgb; 4],
+    /// BG and window palettes: BGP.
+    pub bg: Vec<[Rgb; 4]>,
+    /// Object palettes: OBP0 and OBP1. Color 0 is transparent but decoded
+    /// all the same.
+    pub obj: Vec<[Rgb; 4]>,
+    /// The 8 BG palettes in CGB palette RAM.
+    pub cgb_bg: [[Rgb; 4]; 8],
+    /// The 8 object palettes in CGB palette RAM.
+    pub cgb_obj: [[Rgb; 4]; 8],
+}
+
+/// CGB palette RAM: 8 palettes of 4 colors, each color two bytes of
+/// BGR555, little-endian.
+#[derive(Clone)]
+struct PaletteRam {
+    data: [u8; 0x40],
+    /// BCPS or OCPS: the index into `data` in bits 0-5, and in bit 7
+    /// whether writes step it.
+    spec: u8,
+}
+
+impl PaletteRam {
+    fn new() -> Self {
+        PaletteRam {
+            data: [0; 0x40],
+            spec: 0,
+        }
+    }
+
+    fn read_spec(&self) -> u8 {
+        // Bit 6 is unused and reads as 1
+        self.spec | 0x40
+    }
+
+    fn write_spec(&mut self, value: u8) {
+        self.spec = value & 0xbf;
+    }
+
+    fn read_data(&self) -> u8 {
+        self.data[(self.spec & 0x3f) as usize]
+    }
+
+    /// Writes at the index unless the PPU has the RAM `locked`; the index
+    /// steps either way.
+    fn write_data(&mut self, value: u8, locked: bool) {
+        if !locked {
+            self.data[(self.spec & 0x3f) as usize] = value;
+        }
+        if self.spec & 0x80 != 0 {
+            self.spec = 0x80 | (self.spec + 1) & 0x3f;
+        }
+    }
+
+    fn decode(&self) -> [[Rgb; 4]; 8] {
+        core::array::from_fn(|palette| {
+            core::array::from_fn(|color| {
+                let offset = palette * 8 + color * 2;
+                bgr555(u16::from_le_bytes([
+                    self.data[offset],
+                    self.data[offset + 1],
+                ]))
+            })
+        })
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.data);
+        w.write_u8(self.spec);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.read_bytes(&mut self.data)?;
+        self.spec = r.read_u8()? & 0xbf;
+        Ok(())
+    }
+}
+
+/// Where a pixel of the frame came from, see `Ppu::set_pixel_attribution`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelSource {
+    pub layer: Layer,
+    /// Tile number from the tile map or OAM, for 8x16 objects the one of
+    /// the half holding the pixel. `None` for the blank line drawn while
+    /// LCDC bit 0 is clear.
+    pub tile: Option<u8>,
+    /// OAM index of the object that drew the pixel.
+    pub sprite: Option<u8>,
+    /// Color number (0-3) before the palette lookup.
+    pub color: u8,
+    pub palette: PaletteRegister,
+}
+
+/// A white RGB24 frame, or none with the `rgb565` feature, which draws
+/// lines into the caller's buffer instead.
+fn rgb24_buffer() -> Vec<u8> {
+    if cfg!(feature = "rgb565") {
+        Vec::new()
+    } else {
+        vec![0xff; SCREEN_WIDTH * SCREEN_HEIGHT * 3]
+    }
+}
+
+enum MapArea {
+    Base1800,
+    Base1C00,
+}
+
+enum TileArea {
+    Base1000,
+    Base0000,
+}
+
+enum Mode {
+    HBlank,       // Mode0
+    VBlank,       // Mode1
+    SearchingOAM, // Mode2
+    Drawing,      // Mode3
+}
+
+impl Ppu {
+    pub(crate) fn new() -> Self {
+        Ppu {
+            vram: [0; 0x2000],
+            oam: [0; 0xa0],
+            lcdc: 0x80,
+            stat: 0x02,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            dma: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            frame: rgb24_buffer(),
+            ready: rgb24_buffer(),
+            front: rgb24_buffer(),
+            frame_ready: false,
+            counter: 0,
+            line_timing: LineTiming::with_drawing(MIN_DRAWING_DOTS),
+            lcd_enabling: false,
+            skip_frame: false,
+            window_line: 0,
+            wy_triggered: false,
+            bg_line: [0; 160],
+            show_bg: true,
+            show_window: true,
+            show_sprites: true,
+            rendering: true,
+            stat_write_bug: true,
+            oam_bug: false,
+            pixel_transform: None,
+            attribution: None,
+            dmg_palette: BUILTIN_PALETTES[0].shades,
+            bg_palette_ram: PaletteRam::new(),
+            obj_palette_ram: PaletteRam::new(),
+            #[cfg(feature = "rgb565")]
+            line: [0; SCREEN_WIDTH],
+            #[cfg(feature = "rgb565")]
+            line_drawn: None,
+            #[cfg(feature = "rgb565")]
+            blanked: false,
+            irq_lcdc: false,
+            irq_vblank: false,
+            frame_count: 0,
+            vblank_entered: false,
+        }
+    }
+    /// Resets the hardware state, keeping frontend settings such as the
+    /// palette, layer toggles and pixel transform.
+    pub(crate) fn reset(&mut self) {
+        let mut ppu = Ppu::new();
+        ppu.show_bg = self.show_bg;
+        ppu.show_window = self.show_window;
+        ppu.show_sprites = self.show_sprites;
+        ppu.rendering = self.rendering;
+        ppu.stat_write_bug = self.stat_write_bug;
+        ppu.oam_bug = self.oam_bug;
+        ppu.pixel_transform = self.pixel_transform.take();
+        ppu.attribution = self.attribution.take();
+        ppu.dmg_palette = self.dmg_palette;
+        *self = ppu;
+        self.blank_frame();
+    }
+
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.begin_section("vram");
+        w.write_bytes(&self.vram);
+        w.begin_section("oam");
+        w.write_bytes(&self.oam);
+        w.begin_section("ppu");
+        w.write_u8(self.lcdc);
+        w.write_u8(self.stat);
+        w.write_u8(self.scy);
+        w.write_u8(self.scx);
+        w.write_u8(self.ly);
+        w.write_u8(self.lyc);
+        w.write_u8(self.dma);
+        w.write_u8(self.bgp);
+        w.write_u8(self.obp0);
+        w.write_u8(self.obp1);
+        w.write_u8(self.wy);
+        w.write_u8(self.wx);
+        self.bg_palette_ram.save_state(w);
+        self.obj_palette_ram.save_state(w);
+        w.begin_section("frame");
+        w.write_bytes(self.get_frame());
+        w.begin_section("ppu");
+        w.write_u16(self.counter);
+        w.write_u16(self.line_timing.drawing);
+        w.write_bool(self.lcd_enabling);
+        w.write_bool(self.skip_frame);
+        w.write_u8(self.window_line);
+        w.write_bool(self.wy_triggered);
+        w.write_bool(self.irq_lcdc);
+        w.write_bool(self.irq_vblank);
+        w.write_u64(self.frame_count);
+        w.write_bool(self.vblank_entered);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.read_bytes(&mut self.vram)?;
+        r.read_bytes(&mut self.oam)?;
+        self.lcdc = r.read_u8()?;
+        self.stat = r.read_u8()?;
+        self.scy = r.read_u8()?;
+        self.scx = r.read_u8()?;
+        self.ly = r.read_u8()?;
+        self.lyc = r.read_u8()?;
+        self.dma = r.read_u8()?;
+        self.bgp = r.read_u8()?;
+        self.obp0 = r.read_u8()?;
+        self.obp1 = r.read_u8()?;
+        self.wy = r.read_u8()?;
+        self.wx = r.read_u8()?;
+        self.bg_palette_ram.load_state(r)?;
+        self.obj_palette_ram.load_state(r)?;
+        r.read_bytes(&mut self.ready)?;
+        self.frame.copy_from_slice(&self.ready);
+        self.front.copy_from_slice(&self.ready);
+        self.frame_ready = true;
+        self.counter = r.read_u16()?;
+        let drawing = r.read_u16()?;
+        self.line_timing = LineTiming::with_drawing(drawing.min(DOTS_PER_LINE - OAM_SEARCH_DOTS));
+        self.lcd_enabling = r.read_bool()?;
+        self.skip_frame = r.read_bool()?;
+        self.window_line = r.read_u8()?;
+        self.wy_triggered = r.read_bool()?;
+        self.irq_lcdc = r.read_bool()?;
+        self.irq_vblank = r.read_bool()?;
+        self.frame_count = r.read_u64()?;
+        self.vblank_entered = r.read_bool()?;
+        Ok(())
+    }
+
+    /// Shows or hides a layer, to find out which one a graphical glitch comes
+    /// from. Hidden layers are drawn blank but still take part in priority.
+    pub fn set_layer_visible(&mut self, layer: Layer, visible: bool) {
+        match layer {
+            Layer::Background => self.show_bg = visible,
+            Layer::Window => self.show_window = visible,
+            Layer::Sprites => self.show_sprites = visible,
+        }
+    }
+
+    pub fn is_layer_visible(&self, layer: Layer) -> bool {
+        match layer {
+            Layer::Background => self.show_bg,
+            Layer::Window => self.show_window,
+            Layer::Sprites => self.show_sprites,
+        }
+    }
+
+    /// VRAM as it is, whatever the mode, e.g. for tile viewers. The CPU
+    /// reads 0xff from it while the PPU draws.
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    pub(crate) fn vram_mut(&mut self) -> &mut [u8] {
+        &mut self.vram
+    }
+
+    /// OAM as it is, whatever the mode, e.g. for object viewers.
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    pub(crate) fn oam_mut(&mut self) -> &mut [u8] {
+        &mut self.oam
+    }
+
+    /// Turns pixel output on or off. Timing and interrupts are the same
+    /// either way, the frame just stops changing.
+    pub fn set_rendering(&mut self, enabled: bool) {
+        self.rendering = enabled;
+    }
+
+    /// Turns the DMG STAT write bug on (the default) or off. Some games,
+    /// e.g. Road Rash and Zerd no Densetsu, depend on it.
+    pub fn set_stat_write_bug(&mut self, enabled: bool) {
+        self.stat_write_bug = enabled;
+    }
+
+    pub fn stat_write_bug(&self) -> bool {
+        self.stat_write_bug
+    }
+
+    /// Turns on the DMG OAM corruption bug, for test ROMs that check it.
+    /// Games avoid triggering it, so it is off by default.
+    pub fn set_oam_bug(&mut self, enabled: bool) {
+        self.oam_bug = enabled;
+    }
+
+    pub fn oam_bug(&self) -> bool {
+        self.oam_bug
+    }
+
+    /// The CPU's 16-bit incrementer put an OAM address on the bus. During
+    /// mode 2 this garbles the OAM row the PPU is reading, mixing it with
+    /// the row before it (the pan-docs "write" pattern).
+    pub(crate) fn corrupt_oam(&mut self) {
+        if !self.oam_bug || !self.is_lcd_and_ppu_enable() || self.stat & 0x3 != 2 {
+            return;
+        }
+        // One 8-byte row per M-cycle, the first row is never affected
+        let row = (self.counter / 4) as usize;
+        if row == 0 || row >= 20 {
+            return;
+        }
+        let current = row * 8;
+        let previous = current - 8;
+        let word = |oam: &[u8], i: usize| u16::from_le_bytes([oam[i], oam[i + 1]]);
+        let a = word(&self.oam, current);
+        let b = word(&self.oam, previous);
+        let c = word(&self.oam, previous + 4);
+        let first = ((a ^ c) & (b ^ c)) ^ c;
+        self.oam[current..current + 2].copy_from_slice(&first.to_le_bytes());
+        self.oam
+            .copy_within(previous + 2..previous + 8, current + 2);
+    }
+
+    /// Mode budgets of the current (or last) visible scanline.
+    pub fn line_timing(&self) -> LineTiming {
+        self.line_timing
+    }
+
+    /// Sets the colors of the four DMG shades, lightest first.
+    pub fn set_dmg_palette(&mut self, shades: [Rgb; 4]) {
+        self.dmg_palette = shades;
+    }
+
+    pub fn dmg_palette(&self) -> [Rgb; 4] {
+        self.dmg_palette
+    }
+
+    /// Decodes the palettes to RGB for palette viewers, e.g. to tell a
+    /// wrong palette from wrong tile data: the DMG registers and all 16
+    /// palettes in CGB palette RAM. A `PixelTransform` is not applied.
+    pub fn debug_palettes(&self) -> DebugPalettes {
+        let decode = |register: u8| {
+            [0, 1, 2, 3].map(|color| self.dmg_palette[((register >> (color * 2)) & 0x3) as usize])
+        };
+        DebugPalettes {
+            shades: self.dmg_palette,
+            bg: vec![decode(self.bgp)],
+            obj: vec![decode(self.obp0), decode(self.obp1)],
+            cgb_bg: self.bg_palette_ram.decode(),
+            cgb_obj: self.obj_palette_ram.decode(),
+        }
+    }
+
+    /// Copies what was drawn since the last call into `buffer`, 160x144
+    /// RGB565 pixels: the line drawn last, or the whole screen in the
+    /// lightest shade once the LCD was blanked. The PPU draws a line at a
+    /// time and keeps no frame of its own, so calling this after every
+    /// instruction, as `Cpu::run_frame_into` does, leaves `buffer` holding
+    /// the screen, e.g. for microcontrollers that send it straight to an
+    /// SPI LCD. The pixels go through the palette and pixel transform as
+    /// usual.
+    #[cfg(feature = "rgb565")]
+    pub fn render_into(&mut self, buffer: &mut [u16]) {
+        assert!(buffer.len() >= SCREEN_WIDTH * SCREEN_HEIGHT);
+        if core::mem::take(&mut self.blanked) {
+            buffer[..SCREEN_WIDTH * SCREEN_HEIGHT].fill(rgb565(self.dmg_palette[0]));
+        }
+        if let Some(ly) = self.line_drawn.take() {
+            let start = ly as usize * SCREEN_WIDTH;
+            buffer[start..start + SCREEN_WIDTH].copy_from_slice(&self.line);
+        }
+    }
+
+    /// Fills the frame with the lightest shade, as shown by a disabled LCD.
+    fn blank_frame(&mut self) {
+        let color = self.dmg_palette[0];
+        #[cfg(feature = "rgb565")]
+        {
+            self.blanked = true;
+            self.line_drawn = None;
+        }
+        for pixel in self.frame.chunks_mut(3) {
+            pixel.copy_from_slice(&color);
+        }
+        self.complete_frame();
+        if let Some(attribution) = self.attribution.as_mut() {
+            attribution.fill(None);
+        }
+    }
+
+    /// Installs (or removes) the hook run on every committed pixel.
+    pub fn set_pixel_transform(&mut self, transform: Option<Box<dyn PixelTransform>>) {
+        self.pixel_transform = transform;
+    }
+
+    /// Background scroll position as (SCX, SCY).
+    pub fn scroll(&self) -> (u8, u8) {
+        (self.scx, self.scy)
+    }
+
+    /// Window position as (WX, WY), where WX is the screen X plus 7.
+    /// `None` while the window or the BG is disabled in LCDC.
+    pub fn window_position(&self) -> Option<(u8, u8)> {
+        if self.is_window_enable() && self.lcdc & 0x1 > 0 {
+            Some((self.wx, self.wy))
+        } else {
+            None
+        }
+    }
+
+    /// Starts or stops recording which layer, tile, object and palette
+    /// produced each pixel, for debugging priority problems.
+    pub fn set_pixel_attribution(&mut self, enabled: bool) {
+        self.attribution = if enabled {
+            Some(vec![None; SCREEN_WIDTH * SCREEN_HEIGHT])
+        } else {
+            None
+        };
+    }
+
+    /// Source of the pixel at `x`, `y` of the current frame. `None` unless
+    /// attribution is on, and for pixels of a hidden layer or a blank frame.
+    pub fn pixel_source(&self, x: usize, y: usize) -> Option<PixelSource> {
+        if x >= SCREEN_WIDTH || y >= SCREEN_HEIGHT {
+            return None;
+        }
+        self.attribution.as_ref()?[y * SCREEN_WIDTH + x]
+    }
+
+    /// Stable 64-bit hash (FNV-1a) of `get_frame`.
+    ///
+    /// The value only depends on the pixels, so it is the same across
+    /// platforms and releases, but it does change with the DMG palette.
+    pub fn frame_hash(&self) -> u64 {
+        fnv1a_64(self.get_frame())
+    }
+
+    /// The last completed frame as RGB24, 160x144 row-major. Never one
+    /// being drawn, so reading it between scanlines does not tear. Empty
+    /// with the `rgb565` feature, see `render_into`.
+    pub fn get_frame(&self) -> &[u8] {
+        if self.frame_ready {
+            &self.ready
+        } else {
+            &self.front
+        }
+    }
+
+    /// The last completed frame, if one was completed since the last call,
+    /// e.g. for a frontend to present each frame once. The PPU draws into
+    /// a third buffer, so the one returned stays as it is until the next
+    /// call.
+    pub fn take_completed_frame(&mut self) -> Option<&[u8]> {
+        if !self.frame_ready {
+            return None;
+        }
+        core::mem::swap(&mut self.ready, &mut self.front);
+        self.frame_ready = false;
+        Some(&self.front)
+    }
+
+    /// Number of times the PPU has entered VBlank since power-on, i.e.
+    /// frames completed. It stands still while the LCD is off.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Whether VBlank was entered since the last call, so an embedder can
+    /// present each finished frame exactly once.
+    pub fn take_vblank_entered(&mut self) -> bool {
+        core::mem::take(&mut self.vblank_entered)
+    }
+
+    pub fn is_irq_vblank(&self) -> bool {
+        self.irq_vblank
+    }
+
+    pub fn is_irq_lcdc(&self) -> bool {
+        self.irq_lcdc
+    }
+
+    pub fn set_irq_vblank(&mut self, flag: bool) {
+        self.irq_vblank = flag;
+    }
+
+    pub fn set_irq_lcdc(&mut self, flag: bool) {
+        self.irq_lcdc = flag;
+    }
+
+    fn is_lcd_and_ppu_enable(&self) -> bool {
+        ((self.lcdc >> 7) & 1) == 1
+    }
+
+    fn window_map_area(&self) -> MapArea {
+        match ((self.lcdc >> 6) & 1) == 1 {
+            false => MapArea::Base1800,
+            true => MapArea::Base1C00,
+        }
+    }
+
+    fn is_window_enable(&self) -> bool {
+        ((self.lcdc >> 5) & 1) == 1
+    }
+
+    fn get_tile_area(&self) -> TileArea {
+        match ((self.lcdc >> 4) & 1) == 1 {
+            false => TileArea::Base1000,
+            true => TileArea::Base0000,
+        }
+    }
+
+    fn bg_map_area(&self) -> MapArea {
+        match ((self.lcdc >> 3) & 1) == 1 {
+            false => MapArea::Base1800,
+            true => MapArea::Base1C00,
+        }
+    }
+
+    fn is_obj_square(&self) -> bool {
+        (self.lcdc & 0x04) == 0
+    }
+
+    fn is_obj_enable(&self) -> bool {
+        ((self.lcdc >> 1) & 1) == 1
+    }
+
+    fn get_mode_flag(&self) -> Mode {
+        match self.stat & 0x03 {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::SearchingOAM,
+            3 => Mode::Drawing,
+            _ => panic!("Invalid mode: {}", self.stat & 0x03),
+        }
+    }
+
+    fn set_mode_flag(&mut self, mode: Mode) {
+        match mode {
+            Mode::HBlank => self.stat &= 0xf8,
+            Mode::VBlank => self.stat = (self.stat & 0xf8) | 1,
+            Mode::SearchingOAM => self.stat = (self.stat & 0xf8) | 2,
+            Mode::Drawing => self.stat = (self.stat & 0xf8) | 3,
+        }
+    }
+
+    fn get_bg_window_tile_row(
+        &self,
+        tile_x: u8,
+        tile_y: u8,
+        offset_y: u8,
+        window_flag: bool,
+    ) -> (u8, u8, u8) {
+        let tile_map_index = (tile_x as u16) + (tile_y as u16) * 32;
+        let tile_map_addr = if window_flag {
+            match self.window_map_area() {
+                MapArea::Base1800 => 0x1800 + tile_map_index,
+                MapArea::Base1C00 => 0x1C00 + tile_map_index,
+            }
+        } else {
+            match self.bg_map_area() {
+                MapArea::Base1800 => 0x1800 + tile_map_index,
+                MapArea::Base1C00 => 0x1C00 + tile_map_index,
+            }
+        };
+
+        let tile_no = self.vram[tile_map_addr as usize];
+        let mut tile_addr = match self.get_tile_area() {
+            TileArea::Base0000 => (tile_no as u16) * 16,
+            TileArea::Base1000 => (0x1000_i16).wrapping_add((tile_no as i8 as i16) * 16) as u16,
+        };
+        tile_addr = tile_addr.wrapping_add((offset_y as u16) * 2);
+
+        let tile_row_low = self.vram[tile_addr as usize];
+        let tile_row_high = self.vram[(tile_addr + 1) as usize];
+        debug!(
+            target: LOG_TARGET,
+            "tile_map_addr: {}, tile_low: {}, tile_high: {}",
+            tile_addr, tile_row_low, tile_row_high
+        );
+
+        (tile_no, tile_row_low, tile_row_high)
+    }
+
+    fn get_sprite_tile_row(&mut self, tile_no: u8, offset_y: u8) -> (u8, u8) {
+        // println!("tile_no: {}, offset_y: {}", tile_no, offset_y);
+        let tile_addr = (tile_no as usize) * 16 + (offset_y as usize) * 2;
+        let tile_row_low = self.vram[tile_addr];
+        let tile_row_high = self.vram[tile_addr + 1];
+
+        (tile_row_low, tile_row_high)
+    }
+
+    /// Shade (0-3) of an object color through OBP0/OBP1.
+    fn get_sprite_shade(&self, tile_color: u8, sprite_flag: u8) -> u8 {
+        let palette = if sprite_flag & 0x10 > 0 {
+            self.obp1
+        } else {
+            self.obp0
+        };
+
+        (palette >> (tile_color << 1)) & 0x3
+    }
+
+    fn get_tile_color(&self, tile_row_low: u8, tile_row_high: u8, offset_x: u8) -> u8 {
+        let shift_num = 7 - offset_x;
+        let bit_low = (tile_row_low >> shift_num) & 1;
+        let bit_high = (tile_row_high >> shift_num) & 1;
+
+        bit_high << 1 | bit_low
+    }
+
+    /// Shade (0-3) of a BG/window color through BGP.
+    fn get_bg_shade(&self, tile_color: u8) -> u8 {
+        (self.bgp >> (tile_color << 1)) & 0x3
+    }
+
+    /// Makes the frame drawn so far the completed one and starts drawing
+    /// into the oldest buffer, which the next frame overwrites in full.
+    fn complete_frame(&mut self) {
+        core::mem::swap(&mut self.frame, &mut self.ready);
+        self.frame_ready = true;
+    }
+
+    fn put_pixel(&mut self, x: u8, color: Rgb) {
+        #[cfg(feature = "rgb565")]
+        {
+            self.line[x as usize] = rgb565(color);
+        }
+        #[cfg(not(feature = "rgb565"))]
+        {
+            let index = (x as usize) + (self.ly as usize) * SCREEN_WIDTH;
+            self.frame[index * 3..index * 3 + 3].copy_from_slice(&color);
+        }
+    }
+
+    /// Writes a pixel of the current line through the pixel transform.
+    fn commit_pixel(&mut self, x: u8, layer: Layer, palette_index: u8, shade: u8) {
+        let mut color = self.dmg_palette[shade as usize];
+        if let Some(transform) = self.pixel_transform.as_mut() {
+            color = transform.transform(layer, palette_index, color);
+        }
+        self.put_pixel(x, color);
+    }
+
+    /// Records the source of a pixel of the current line.
+    fn attribute(&mut self, x: u8, source: Option<PixelSource>) {
+        if let Some(attribution) = self.attribution.as_mut() {
+            attribution[self.ly as usize * SCREEN_WIDTH + x as usize] = source;
+        }
+    }
+
+    /// Whether the window covers part of the current line.
+    fn is_window_on_line(&self) -> bool {
+        self.is_window_enable() && self.wy_triggered && self.wx <= 166
+    }
+
+    fn render_bg(&mut self) {
+        let window_on_line = self.is_window_on_line();
+
+        for x in 0..160u8 {
+            // WX is the window's screen position plus 7
+            let window_flag = window_on_line && (x as u16 + 7 >= self.wx as u16);
+
+            let pixel_x;
+            let pixel_y;
+            if window_flag {
+                pixel_x = (x + 7).wrapping_sub(self.wx);
+                pixel_y = self.window_line;
+            } else {
+                pixel_x = self.scx.wrapping_add(x);
+                pixel_y = self.scy.wrapping_add(self.ly);
+            }
+
+            let tile_x = pixel_x >> 3;
+            let tile_y = pixel_y >> 3;
+            let offset_x = pixel_x & 0x07;
+            let offset_y = pixel_y & 0x07;
+
+            let (tile_no, tile_row_low, tile_row_high) =
+                self.get_bg_window_tile_row(tile_x, tile_y, offset_y, window_flag);
+
+            let tile_color = self.get_tile_color(tile_row_low, tile_row_high, offset_x);
+            let shade = self.get_bg_shade(tile_color);
+            debug!(
+                target: LOG_TARGET,
+                "render scan tile_x: {}, tile_y: {}, offset_x: {}, offset_y: {}, x: {}, shade: {}",
+                tile_x, tile_y, offset_x, offset_y, x, shade
+            );
+            debug!(
+                target: LOG_TARGET,
+                "tile_low, tile_high: {}, {}, window_flag: {}",
+                tile_row_low, tile_row_high, window_flag
+            );
+            self.bg_line[x as usize] = tile_color;
+
+            let (layer, visible) = if window_flag {
+                (Layer::Window, self.show_window)
+            } else {
+                (Layer::Background, self.show_bg)
+            };
+            if visible {
+                self.commit_pixel(x, layer, tile_color, shade);
+                self.attribute(
+                    x,
+                    Some(PixelSource {
+                        layer,
+                        tile: Some(tile_no),
+                        sprite: None,
+                        color: tile_color,
+                        palette: PaletteRegister::Bgp,
+                    }),
+                );
+            } else {
+                self.put_pixel(x, self.dmg_palette[0]);
+                self.attribute(x, None);
+            }
+        }
+
+        // The window keeps its own line counter, which only advances on lines
+        // where it was actually drawn.
+        if window_on_line {
+            self.window_line = self.window_line.wrapping_add(1);
+        }
+    }
+
+    /// With BG disabled (LCDC bit 0) a DMG draws a blank line and objects
+    /// always win over it.
+    fn render_blank_bg(&mut self) {
+        for x in 0..SCREEN_WIDTH as u8 {
+            if self.show_bg {
+                self.commit_pixel(x, Layer::Background, 0, 0);
+                self.attribute(
+                    x,
+                    Some(PixelSource {
+                        layer: Layer::Background,
+                        tile: None,
+                        sprite: None,
+                        color: 0,
+                        palette: PaletteRegister::Bgp,
+                    }),
+                );
+            } else {
+                self.put_pixel(x, self.dmg_palette[0]);
+                self.attribute(x, None);
+            }
+        }
+        self.bg_line = [0; 160];
+    }
+
+    fn sprite_height(&self) -> u8 {
+        if self.is_obj_square() {
+            8
+        } else {
+            16
+        }
+    }
+
+    /// OAM indices of the objects selected for the current line.
+    ///
+    /// Selection scans OAM in order and stops at 10 objects. The X coordinate
+    /// plays no part, so off-screen objects still use up a slot.
+    fn line_sprites(&self) -> Vec<usize> {
+        let height = self.sprite_height() as u16;
+        let line = self.ly as u16 + 16;
+
+        (0..40)
+            .filter(|i| {
+                let oam_y = self.oam[i * 4] as u16;
+                oam_y <= line && line < oam_y + height
+            })
+            .take(10)
+            .collect()
+    }
+
+    fn render_sprites(&mut self) {
+        let height = self.sprite_height();
+
+        // (x, tile_row_low, tile_row_high, flags, OAM index, tile) in
+        // priority order: the object with the smaller X wins, then the one
+        // earlier in OAM.
+        let mut sprites: Vec<(u8, u8, u8, u8, u8, u8)> = self
+            .line_sprites()
+            .into_iter()
+            .map(|i| {
+                let sprite_addr = i * 4;
+                let oam_y = self.oam[sprite_addr];
+                let oam_x = self.oam[sprite_addr + 1];
+                // Bit 0 of the tile index is ignored for 8x16 objects
+                let tile_no =
+                    self.oam[sprite_addr + 2] & if self.is_obj_square() { 0xff } else { 0xfe };
+                let sprite_flag = self.oam[sprite_addr + 3];
+
+                // Y flip mirrors the whole object, across both tiles in 8x16 mode
+                let line = self.ly.wrapping_add(16).wrapping_sub(oam_y);
+                let offset_y = if sprite_flag & 0x40 > 0 {
+                    height - 1 - line
+                } else {
+                    line
+                };
+                let (tile_row_low, tile_row_high) = self.get_sprite_tile_row(tile_no, offset_y);
+
+                let tile = tile_no + offset_y / 8;
+                (
+                    oam_x,
+                    tile_row_low,
+                    tile_row_high,
+                    sprite_flag,
+                    i as u8,
+                    tile,
+                )
+            })
+            .collect();
+        sprites.sort_by_key(|&(oam_x, ..)| oam_x);
+
+        for x in 0..160u8 {
+            let screen_x = x as u16 + 8;
+
+            for &(oam_x, tile_row_low, tile_row_high, sprite_flag, index, tile) in &sprites {
+                let oam_x = oam_x as u16;
+                if screen_x < oam_x || screen_x >= oam_x + 8 {
+                    continue;
+                }
+
+                let offset_x = (screen_x - oam_x) as u8;
+                let index_x = if sprite_flag & 0x20 > 0 {
+                    7 - offset_x
+                } else {
+                    offset_x
+                };
+                let tile_color = self.get_tile_color(tile_row_low, tile_row_high, index_x);
+
+                // Transparent pixels let lower priority objects show through
+                if tile_color == 0 {
+                    continue;
+                }
+
+                // The first opaque object pixel decides, even when it is hidden
+                // behind the background.
+                let bg_window_priority_flag = sprite_flag & 0x80 > 0;
+                if !(bg_window_priority_flag && self.bg_line[x as usize] != 0) {
+                    let shade = self.get_sprite_shade(tile_color, sprite_flag);
+                    debug!(target: LOG_TARGET, "Sprite shade: {}, x: {}", shade, x);
+                    self.commit_pixel(x, Layer::Sprites, tile_color, shade);
+                    self.attribute(
+                        x,
+                        Some(PixelSource {
+                            layer: Layer::Sprites,
+                            tile: Some(tile),
+                            sprite: Some(index),
+                            color: tile_color,
+                            palette: if sprite_flag & 0x10 > 0 {
+                                PaletteRegister::Obp1
+                            } else {
+                                PaletteRegister::Obp0
+                            },
+                        }),
+                    );
+                }
+                break;
+            }
+        }
+    }
+
+    /// Length of mode 3 on the current line.
+    ///
+    /// Follows the pan-docs model: SCX fine scroll discards pixels, the window
+    /// restarts the fetcher and every object fetch stalls the pixel FIFO.
+    fn drawing_dots(&self) -> u16 {
+        let mut dots = MIN_DRAWING_DOTS + (self.scx & 0x07) as u16;
+
+        if self.is_window_on_line() {
+            dots += 6;
+        }
+
+        if self.is_obj_enable() {
+            let mut fetched_tiles: Vec<u16> = Vec::with_capacity(10);
+
+            for i in self.line_sprites() {
+                let oam_x = self.oam[i * 4 + 1];
+                if oam_x >= 168 {
+                    continue;
+                }
+                if oam_x == 0 {
+                    dots += 11;
+                    continue;
+                }
+
+                let bg_x = (oam_x as u16 + self.scx as u16).wrapping_sub(8) & 0xff;
+                let tile = bg_x >> 3;
+                if !fetched_tiles.contains(&tile) {
+                    fetched_tiles.push(tile);
+                    let pixels_right = 7 - (bg_x & 0x07);
+                    dots += pixels_right.saturating_sub(2);
+                }
+                dots += 6;
+            }
+        }
+
+        dots
+    }
+
+    /// Sets up mode 3 of the current line.
+    fn start_drawing(&mut self) {
+        // The window starts once LY has matched WY during the frame
+        if self.ly == self.wy {
+            self.wy_triggered = true;
+        }
+        self.line_timing = LineTiming::with_drawing(self.drawing_dots());
+        self.set_mode_flag(Mode::Drawing);
+        self.render_scan();
+    }
+
+    fn render_scan(&mut self) {
+        if self.skip_frame || !self.rendering {
+            return;
+        }
+        if self.lcdc & 0x1 > 0 {
+            self.render_bg();
+        } else {
+            self.render_blank_bg();
+        }
+        if self.is_obj_enable() && self.show_sprites {
+            self.render_sprites();
+        }
+        #[cfg(feature = "rgb565")]
+        {
+            self.line_drawn = Some(self.ly);
+        }
+    }
+
+    pub fn get_register(&self, reg: PpuReg) -> u8 {
+        self.read(reg.addr())
+    }
+
+    /// Writes `reg` as the CPU would, so turning the LCD off blanks the
+    /// screen, LY stays read-only and so do the low 3 bits of STAT.
+    pub fn set_register(&mut self, reg: PpuReg, value: u8) {
+        self.write(reg.addr(), value)
+    }
+
+    pub(crate) fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9fff => {
+                if self.stat & 0x3 != 3 {
+                    self.vram[(addr & 0x1fff) as usize]
+                } else {
+                    0xff
+                }
+            }
+
+            0xfe00..=0xfe9f => {
+                if self.stat & 0x3 == 0 || self.stat & 0x3 == 1 {
+                    self.oam[(addr & 0x00ff) as usize]
+                } else {
+                    0xff
+                }
+            }
+
+            // IO registers
+            0xff40 => self.lcdc,
+            0xff41 => self.stat,
+            0xff42 => self.scy,
+            0xff43 => self.scx,
+            0xff44 => self.ly,
+            0xff45 => self.lyc,
+            0xff46 => self.dma,
+            0xff47 => self.bgp,
+            0xff48 => self.obp0,
+            0xff49 => self.obp1,
+            0xff4a => self.wy,
+            0xff4b => self.wx,
+            // CGB palette RAM, out of reach while drawing
+            0xff68 => self.bg_palette_ram.read_spec(),
+            0xff69 if self.stat & 0x3 != 3 => self.bg_palette_ram.read_data(),
+            0xff6a => self.obj_palette_ram.read_spec(),
+            0xff6b if self.stat & 0x3 != 3 => self.obj_palette_ram.read_data(),
+            0xff69 | 0xff6b => 0xff,
+
+            _ => panic!("Invalid address: 0x{:04x}", addr),
+        }
+    }
+
+    pub(crate) fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9fff => {
+                if self.stat & 0x3 != 3 {
+                    debug!(
+                        target: LOG_TARGET,
+                        "VRAM write addr: 0x{:04x}, value: 0x{:02x}",
+                        addr & 0x1fff,
+                        value
+                    );
+                    self.vram[(addr & 0x1fff) as usize] = value
+                }
+            }
+
+            0xfe00..=0xfe9f => {
+                if self.stat & 0x3 == 0 || self.stat & 0x3 == 1 {
+                    self.oam[(addr & 0x00ff) as usize] = value;
+                }
+            }
+
+            0xff40 => {
+                if self.lcdc & 0x80 != value & 0x80 {
+                    // LY stays at 0 and STAT reports mode 0 while the LCD is off
+                    self.ly = 0;
+                    self.counter = 0;
+                    self.set_mode_flag(Mode::HBlank);
+
+                    if value & 0x80 > 0 {
+                        self.window_line = 0;
+                        self.wy_triggered = false;
+                        self.lcd_enabling = true;
+                        self.skip_frame = true;
+                        self.update_lyc_interrupt();
+                    } else {
+                        self.lcd_enabling = false;
+                        // A disabled LCD shows a blank screen
+                        self.blank_frame();
+                    }
+                }
+
+                self.lcdc = value;
+            }
+            0xff41 => {
+                // The mode and LYC=LY bits are read-only
+                self.stat = (value & 0xf8) | (self.stat & 0x7);
+                // For one cycle the write acts like 0xff, so HBlank, VBlank
+                // and LYC=LY raise an interrupt whatever the new value enables
+                if self.stat_write_bug
+                    && self.is_lcd_and_ppu_enable()
+                    && (self.stat & 0x3 <= 1 || self.stat & 0x4 != 0)
+                {
+                    self.irq_lcdc = true;
+                }
+            }
+            0xff42 => self.scy = value,
+            0xff43 => self.scx = value,
+            0xff44 => (),
+            0xff45 => {
+                if self.lyc != value {
+                    self.lyc = value;
+                    self.update_lyc_interrupt();
+                }
+            }
+            0xff47 => self.bgp = value,
+            0xff48 => self.obp0 = value,
+            0xff49 => self.obp1 = value,
+            0xff4a => self.wy = value,
+            0xff4b => self.wx = value,
+            0xff68 => self.bg_palette_ram.write_spec(value),
+            0xff69 => {
+                let locked = self.stat & 0x3 == 3;
+                self.bg_palette_ram.write_data(value, locked);
+            }
+            0xff6a => self.obj_palette_ram.write_spec(value),
+            0xff6b => {
+                let locked = self.stat & 0x3 == 3;
+                self.obj_palette_ram.write_data(value, locked);
+            }
+
+            _ => panic!("Invalid address: 0x{:04x}", addr),
+        }
+    }
+
+    fn update_lyc_interrupt(&mut self) {
+        // LYC=LY coincidence interrupt
+        if self.ly == self.lyc {
+            self.stat |= 0x4;
+            self.irq_lcdc = true;
+        } else {
+            self.stat &= !0x4;
+        }
+    }
+
+    /// Checks LCD mode interrupt.
+    fn update_mode_interrupt(&mut self) {
+        // Mode interrupts
+        match self.stat & 0x3 {
+            // H-Blank interrupt
+            0 if self.stat & 0x8 > 0 => self.irq_lcdc = true,
+            // V-Blank interrupt
+            1 if self.stat & 0x10 > 0 => self.irq_lcdc = true,
+            // OAM Search interrupt
+            2 if self.stat & 0x20 > 0 => self.irq_lcdc = true,
+            _ => (),
+        }
+    }
+
+    /// Dots until the next mode change, or `None` while the LCD is off.
+    pub(crate) fn cycles_until_next_event(&self) -> Option<u16> {
+        if !self.is_lcd_and_ppu_enable() {
+            return None;
+        }
+        let target = if self.lcd_enabling {
+            LCD_ENABLE_DOTS
+        } else {
+            match self.get_mode_flag() {
+                Mode::SearchingOAM => OAM_SEARCH_DOTS,
+                Mode::Drawing => self.line_timing.drawing,
+                Mode::HBlank => self.line_timing.hblank,
+                Mode::VBlank => DOTS_PER_LINE,
+            }
+        };
+        Some(target.saturating_sub(self.counter))
+    }
+
+    pub(crate) fn update(&mut self, clock: u16) {
+        debug!(
+            target: LOG_TARGET,
+            "PPU update ly: {}, scx: {}, scy: {}",
+            self.ly, self.scx, self.scy
+        );
+        debug!(target: LOG_TARGET, "lcdc: 0x{:02x}, stat: 0x{:02x}", self.lcdc, self.stat);
+        debug!(
+            target: LOG_TARGET,
+            "bgp: {}, obp0: {}, obp1: {}, wy: {}, wx: {}",
+            self.bgp, self.obp0, self.obp1, self.wy, self.wx
+        );
+        debug!(target: LOG_TARGET, "mmu_clock: {}, update_clock: {}", self.counter, clock);
+
+        if !self.is_lcd_and_ppu_enable() {
+            debug!(target: LOG_TARGET, "LCD and PPU is not enable");
+            return;
+        }
+
+        self.counter += clock;
+
+        if self.lcd_enabling {
+            if self.counter >= LCD_ENABLE_DOTS {
+                self.counter -= LCD_ENABLE_DOTS;
+                self.lcd_enabling = false;
+                self.start_drawing();
+            }
+            return;
+        }
+
+        match self.get_mode_flag() {
+            Mode::SearchingOAM => {
+                if self.counter >= OAM_SEARCH_DOTS {
+                    self.counter -= OAM_SEARCH_DOTS;
+                    self.start_drawing();
+                    debug!(target: LOG_TARGET, "Render mode: searching oam");
+                }
+            }
+            Mode::Drawing => {
+                if self.counter >= self.line_timing.drawing {
+                    self.counter -= self.line_timing.drawing;
+                    self.set_mode_flag(Mode::HBlank);
+                    self.update_mode_interrupt();
+                    debug!(target: LOG_TARGET, "Render mode: drawing");
+                }
+            }
+            Mode::HBlank => {
+                if self.counter >= self.line_timing.hblank {
+                    self.counter -= self.line_timing.hblank;
+                    self.ly += 1;
+                    if self.ly >= 144 {
+                        self.set_mode_flag(Mode::VBlank);
+                        self.irq_vblank = true;
+                        // Skipped frames and frames not rendered leave the
+                        // last completed frame on screen
+                        if !self.skip_frame && self.rendering {
+                            self.complete_frame();
+                        }
+                        self.skip_frame = false;
+                        self.frame_count += 1;
+                        self.vblank_entered = true;
+                    } else {
+                        self.set_mode_flag(Mode::SearchingOAM);
+                    }
+                    debug!(target: LOG_TARGET, "Render mode HBlank");
+
+                    self.update_lyc_interrupt();
+                    self.update_mode_interrupt();
+                }
+            }
+            Mode::VBlank => {
+                if self.counter >= DOTS_PER_LINE {
+                    self.counter -= DOTS_PER_LINE;
+                    self.ly += 1;
+
+                    if self.ly >= 154 {
+                        self.set_mode_flag(Mode::SearchingOAM);
+                        self.ly = 0;
+                        self.window_line = 0;
+                        self.wy_triggered = false;
+
+                        self.update_mode_interrupt();
+                    }
+
+                    self.update_lyc_interrupt();
+                    debug!(target: LOG_TARGET, "Render mode VBlank");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_sprite(ppu: &mut Ppu, i: usize, y: u8, x: u8) {
+        ppu.oam[i * 4] = y;
+        ppu.oam[i * 4 + 1] = x;
+    }
+
+    #[test]
+    fn test_line_timing_adds_up() {
+        let timing = LineTiming::with_drawing(200);
+        assert_eq!(
+            timing.oam_search + timing.drawing + timing.hblank,
+            DOTS_PER_LINE
+        );
+        assert_eq!(timing.hblank, 176);
+    }
+
+    #[test]
+    fn test_registers_by_name() {
+        let mut ppu = Ppu::new();
+        for (i, &reg) in PpuReg::ALL.iter().enumerate() {
+            assert_eq!(PpuReg::from_addr(reg.addr()), Some(reg));
+            assert_eq!(PpuReg::from_name(&reg.name().to_lowercase()), Some(reg));
+            if reg != PpuReg::Lcdc && reg != PpuReg::Stat && reg != PpuReg::Ly {
+                ppu.set_register(reg, 0x10 + i as u8);
+                assert_eq!(ppu.get_register(reg), 0x10 + i as u8);
+                assert_eq!(ppu.read(reg.addr()), 0x10 + i as u8);
+            }
+        }
+        assert_eq!(PpuReg::from_addr(0xff46), None);
+        assert_eq!(PpuReg::from_name("DMA"), None);
+
+        ppu.set_register(PpuReg::Lcdc, 0x00);
+        ppu.set_register(PpuReg::Ly, 0x12);
+        ppu.set_register(PpuReg::Stat, 0x43);
+        assert_eq!(ppu.get_register(PpuReg::Ly), 0);
+        assert_eq!(ppu.get_register(PpuReg::Stat), 0x40);
+    }
+
+    #[test]
+    fn test_lcd_enable_skips_mode_2() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xff40, 0x00);
+        assert_eq!(ppu.read(0xff44), 0);
+        assert_eq!(ppu.read(0xff41) & 0x3, 0);
+
+        ppu.write(0xff40, 0x80);
+        ppu.update(72);
+        assert_eq!(ppu.read(0xff41) & 0x3, 0);
+        ppu.update(4);
+        assert_eq!(ppu.read(0xff41) & 0x3, 3);
+        assert_eq!(ppu.read(0xff44), 0);
+    }
+
+    #[test]
+    fn test_frame_count() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xff40, 0x00);
+        ppu.write(0xff40, 0x80);
+        while ppu.read(0xff44) != 144 {
+            assert!(!ppu.take_vblank_entered());
+            ppu.update(4);
+        }
+        assert_eq!(ppu.frame_count(), 1);
+        assert!(ppu.take_vblank_entered());
+        assert!(!ppu.take_vblank_entered());
+
+        for _ in 0..154 {
+            for _ in 0..DOTS_PER_LINE / 4 {
+                ppu.update(4);
+            }
+        }
+        assert_eq!(ppu.frame_count(), 2);
+        assert!(ppu.take_vblank_entered());
+    }
+
+    #[test]
+    #[cfg(not(feature = "rgb565"))]
+    fn test_completed_frames() {
+        let mut ppu = Ppu::new();
+        let run_to_line = |ppu: &mut Ppu, line: u8| {
+            ppu.update(4);
+            while ppu.read(0xff44) != line {
+                ppu.update(4);
+            }
+        };
+        ppu.write(0xff40, 0x00);
+        let blank = ppu.take_completed_frame().unwrap().to_vec();
+        assert!(ppu.take_completed_frame().is_none());
+
+        // Tile 0 all color 3, over the whole background
+        ppu.vram[..16].fill(0xff);
+        ppu.write(0xff47, 0xe4);
+        ppu.write(0xff40, 0x91);
+        // The first frame after LCD enable is not shown
+        run_to_line(&mut ppu, 144);
+        assert!(ppu.take_completed_frame().is_none());
+
+        // Half a frame in, the old frame is still the one shown
+        run_to_line(&mut ppu, 72);
+        assert_eq!(ppu.get_frame(), &blank[..]);
+        assert_ne!(ppu.frame[..3], blank[..3]);
+        run_to_line(&mut ppu, 144);
+        let frame = ppu.take_completed_frame().unwrap().to_vec();
+        assert_eq!(ppu.get_frame(), &frame[..]);
+        assert_ne!(frame[..3], blank[..3]);
+        assert!(frame.chunks(3).all(|pixel| pixel == &frame[..3]));
+
+        // The next frame is drawn into another buffer
+        run_to_line(&mut ppu, 72);
+        assert_eq!(ppu.get_frame(), &frame[..]);
+        assert!(ppu.take_completed_frame().is_none());
+    }
+
+    #[test]
+    fn test_stat_write_bug() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xff45, 0x90);
+        ppu.write(0xff40, 0x00);
+        ppu.write(0xff40, 0x80);
+        ppu.update(76);
+        // Mode 3 is not affected
+        ppu.write(0xff41, 0x00);
+        assert!(!ppu.is_irq_lcdc());
+
+        ppu.update(ppu.line_timing().drawing);
+        ppu.write(0xff41, 0x00);
+        assert!(ppu.is_irq_lcdc());
+
+        ppu.set_irq_lcdc(false);
+        ppu.set_stat_write_bug(false);
+        ppu.write(0xff41, 0x00);
+        assert!(!ppu.is_irq_lcdc());
+    }
+
+    #[test]
+    fn test_oam_bug() {
+        let mut ppu = Ppu::new();
+        for (i, value) in ppu.oam.iter_mut().enumerate() {
+            *value = i as u8;
+        }
+        ppu.write(0xff40, 0x00);
+        ppu.write(0xff40, 0x80);
+        // Start of OAM search on line 1
+        while ppu.read(0xff41) & 0x3 != 2 {
+            ppu.update(1);
+        }
+
+        // Off by default
+        ppu.update(8);
+        ppu.corrupt_oam();
+        assert_eq!(ppu.oam[16], 16);
+
+        ppu.set_oam_bug(true);
+        ppu.corrupt_oam();
+        // Row 2 mixed with row 1: a = 0x1110, b = 0x0908, c = 0x0d0c
+        assert_eq!(ppu.oam[16..18], [0x08, 0x09]);
+        assert_eq!(ppu.oam[18..24], [10, 11, 12, 13, 14, 15]);
+        assert_eq!(ppu.oam[8..16], [8, 9, 10, 11, 12, 13, 14, 15]);
+    }
+
+    #[test]
+    fn test_cycles_until_next_event() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xff40, 0x00);
+        assert_eq!(ppu.cycles_until_next_event(), None);
+
+        ppu.write(0xff40, 0x80);
+        ppu.update(70);
+        assert_eq!(ppu.cycles_until_next_event(), Some(6));
+        ppu.update(6);
+        assert_eq!(
+            ppu.cycles_until_next_event(),
+            Some(ppu.line_timing().drawing)
+        );
+    }
+
+    #[test]
+    fn test_line_sprites_limit_counts_offscreen() {
+        let mut ppu = Ppu::new();
+        for i in 0..12 {
+            // The first five objects are off-screen horizontally
+            let x = if i < 5 { 0xff } else { 8 * i as u8 };
+            put_sprite(&mut ppu, i, 16, x);
+        }
+        assert_eq!(ppu.line_sprites(), (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[cfg(not(feature = "rgb565"))]
+    fn test_sprite_priority_lower_x_wins() {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = 0x82;
+        ppu.obp0 = 0b1110_0100;
+        ppu.obp1 = 0b0001_1011;
+        // Tile 0: color 3, tile 1: color 1
+        ppu.vram[0] = 0xff;
+        ppu.vram[1] = 0xff;
+        ppu.vram[16] = 0xff;
+        // Later in OAM but further left, so it wins where they overlap
+        put_sprite(&mut ppu, 0, 16, 12);
+        put_sprite(&mut ppu, 1, 16, 10);
+        ppu.oam[4 + 2] = 1;
+        ppu.oam[4 + 3] = 0x10;
+
+        ppu.render_scan();
+        // Object 1 uses OBP1: color 1 -> shade 2
+        assert_eq!(ppu.frame[4 * 3], 0x55);
+        // Only object 0 covers x=10: color 3 via OBP0 -> shade 3
+        assert_eq!(ppu.frame[10 * 3], 0x00);
+    }
+
+    #[cfg(not(feature = "rgb565"))]
+    struct Tint;
+
+    #[cfg(not(feature = "rgb565"))]
+    impl PixelTransform for Tint {
+        fn transform(&mut self, layer: Layer, palette_index: u8, color: Rgb) -> Rgb {
+            match layer {
+                Layer::Sprites => [0xff, 0x00, palette_index],
+                _ => color,
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "rgb565"))]
+    fn test_pixel_transform() {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = 0x83;
+        ppu.obp0 = 0b1110_0100;
+        ppu.vram[0] = 0xff;
+        ppu.vram[1] = 0xff;
+        ppu.vram[0x1800] = 1;
+        put_sprite(&mut ppu, 0, 16, 8);
+        ppu.set_pixel_transform(Some(Box::new(Tint)));
+
+        ppu.render_scan();
+        assert_eq!(&ppu.frame[0..3], &[0xff, 0x00, 3]);
+        assert_eq!(&ppu.frame[8 * 3..9 * 3], &[0xff, 0xff, 0xff]);
+    }
+
+    #[cfg(feature = "rgb565")]
+    #[test]
+    fn test_rgb565_buffer() {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = 0x91;
+        ppu.bgp = 0b1110_0100;
+        // Tile 0 row 0: colors 3, 2, 1, 0, ...
+        ppu.vram[0] = 0b1010_0000;
+        ppu.vram[1] = 0b1100_0000;
+        ppu.set_dmg_palette([
+            [0xff, 0xff, 0xff],
+            [0xff, 0x00, 0x00],
+            [0x00, 0xff, 0x00],
+            [0x00, 0x00, 0xff],
+        ]);
+        let mut buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        ppu.blank_frame();
+        ppu.render_scan();
+        ppu.render_into(&mut buffer);
+        assert_eq!(buffer[..4], [0x001f, 0x07e0, 0xf800, 0xffff]);
+        assert_eq!(buffer[SCREEN_WIDTH..], [0xffff; SCREEN_WIDTH * 143]);
+        // Nothing new drawn: the buffer is left alone
+        buffer[0] = 0;
+        ppu.render_into(&mut buffer);
+        assert_eq!(buffer[0], 0);
+        assert!(ppu.get_frame().is_empty());
+    }
+
+    #[test]
+    fn test_pixel_attribution() {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = 0x93;
+        ppu.vram[16] = 0xff;
+        ppu.vram[0x1800] = 1;
+        put_sprite(&mut ppu, 3, 16, 12);
+        ppu.oam[3 * 4 + 2] = 1;
+        ppu.oam[3 * 4 + 3] = 0x10;
+        ppu.set_pixel_attribution(true);
+
+        ppu.render_scan();
+        let bg = PixelSource {
+            layer: Layer::Background,
+            tile: Some(1),
+            sprite: None,
+            color: 1,
+            palette: PaletteRegister::Bgp,
+        };
+        assert_eq!(ppu.pixel_source(0, 0), Some(bg));
+        assert_eq!(
+            ppu.pixel_source(4, 0),
+            Some(PixelSource {
+                layer: Layer::Sprites,
+                sprite: Some(3),
+                palette: PaletteRegister::Obp1,
+                ..bg
+            })
+        );
+        assert_eq!(ppu.pixel_source(12, 0).unwrap().tile, Some(0));
+
+        ppu.set_pixel_attribution(false);
+        assert_eq!(ppu.pixel_source(0, 0), None);
+    }
+
+    #[test]
+    fn test_debug_palettes() {
+        let mut ppu = Ppu::new();
+        let shades = [[0xe0; 3], [0xa0; 3], [0x60; 3], [0x20; 3]];
+        ppu.set_dmg_palette(shades);
+        ppu.bgp = 0b1110_0100;
+        ppu.obp0 = 0b0001_1011;
+        ppu.obp1 = 0b1111_0000;
+
+        // Pure red into BG palette 1 color 2, stepping the index
+        ppu.write(0xff68, 0x8c);
+        ppu.write(0xff69, 0x1f);
+        ppu.write(0xff69, 0x00);
+        assert_eq!(ppu.read(0xff68), 0xce);
+        // And into object palette 7 color 3 without stepping it, so the
+        // second write lands on the first
+        ppu.write(0xff6a, 0x3e);
+        ppu.write(0xff6b, 0x00);
+        ppu.write(0xff6b, 0x1f);
+        assert_eq!(ppu.read(0xff6a), 0x7e);
+        assert_eq!(ppu.read(0xff6b), 0x1f);
+
+        let palettes = ppu.debug_palettes();
+        let red = [0xff, 0x00, 0x00];
+        let black = [0x00; 3];
+        for (i, palette) in palettes.cgb_bg.iter().enumerate() {
+            let expected = if i == 1 {
+                [black, black, red, black]
+            } else {
+                [black; 4]
+            };
+            assert_eq!(*palette, expected);
+        }
+        assert_eq!(palettes.cgb_obj[7], [black, black, black, red]);
+        assert_eq!(palettes.shades, shades);
+        assert_eq!(palettes.bg, [shades]);
+        assert_eq!(
+            palettes.obj,
+            [
+                [shades[3], shades[2], shades[1], shades[0]],
+                [shades[0], shades[0], shades[3], shades[3]],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_drawing_dots_scx() {
+        let mut ppu = Ppu::new();
+        assert_eq!(ppu.drawing_dots(), 172);
+        ppu.scx = 3;
+        assert_eq!(ppu.drawing_dots(), 175);
+    }
+
+    #[test]
+    fn test_drawing_dots_sprites() {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = 0x82;
+        // Leftmost pixel at x=0: 7 pixels to the right, 5 extra dots
+        put_sprite(&mut ppu, 0, 16, 8);
+        assert_eq!(ppu.drawing_dots(), 172 + 6 + 5);
+        // Same tile, flat penalty only
+        put_sprite(&mut ppu, 1, 16, 10);
+        assert_eq!(ppu.drawing_dots(), 172 + 6 + 5 + 6);
+        // OAM X=0 always costs 11 dots
+        put_sprite(&mut ppu, 2, 16, 0);
+        assert_eq!(ppu.drawing_dots(), 172 + 6 + 5 + 6 + 11);
+    }
+}
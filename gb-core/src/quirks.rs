@@ -0,0 +1,221 @@
+//! Games that need special handling to run, looked up by header checksum
+//! when they are loaded. A small table is built in, and frontends can add
+//! to it or override it, e.g. the SDL one from a `quirks.toml`.
+
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::cartridge;
+use crate::cpu::{Cpu, Registers};
+
+/// Special handling for one game.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Quirk {
+    /// For people reading the table, it takes no part in the lookup.
+    pub title: String,
+    /// As stored at 0x14d.
+    pub header_checksum: u8,
+    /// As stored at 0x14e, or any with `None`.
+    pub global_checksum: Option<u16>,
+    /// Cartridge type to use instead of the one at 0x147, e.g. for a
+    /// bootleg with a wrong header.
+    pub cartridge_type: Option<u8>,
+    /// Registers to start with instead of zeroes and 0x100.
+    pub registers: Option<Registers>,
+    /// Runs a game flagged for the CGB as a DMG game, without the CGB
+    /// registers the core has, e.g. one that misbehaves with them.
+    pub force_dmg: bool,
+}
+
+impl Quirk {
+    fn matches(&self, rom: &[u8]) -> bool {
+        rom.len() >= 0x150
+            && rom[0x14d] == self.header_checksum
+            && self
+                .global_checksum
+                .is_none_or(|global| u16::from_be_bytes([rom[0x14e], rom[0x14f]]) == global)
+    }
+
+    /// Fixes up a ROM image before it is loaded. A changed header gets a
+    /// new header checksum so it still validates.
+    pub fn patch_rom(&self, rom: &mut [u8]) {
+        let mut changed = false;
+        if let Some(cartridge_type) = self.cartridge_type {
+            rom[0x147] = cartridge_type;
+            changed = true;
+        }
+        if self.force_dmg && rom[0x143] & 0x80 != 0 {
+            // The CGB flag goes, leaving what a DMG game has there
+            rom[0x143] = 0x00;
+            changed = true;
+        }
+        if changed {
+            rom[0x14d] = cartridge::header_checksum(rom);
+        }
+    }
+
+    /// Fixes up a machine the patched ROM was loaded into.
+    pub fn apply(&self, cpu: &mut Cpu) {
+        if let Some(registers) = self.registers {
+            cpu.set_start_registers(registers);
+        }
+    }
+}
+
+/// Quirks to look games up in. Later quirks win over earlier ones, so a
+/// user file read after the built-in table overrides it.
+#[derive(Clone, Debug, Default)]
+pub struct QuirkDatabase {
+    quirks: Vec<Quirk>,
+}
+
+impl QuirkDatabase {
+    pub fn new(quirks: Vec<Quirk>) -> Self {
+        QuirkDatabase { quirks }
+    }
+
+    /// The quirks built into the emulator.
+    pub fn built_in() -> Self {
+        QuirkDatabase::new(vec![
+            // The registers the DMG boot ROM leaves, where this core leaves
+            // zeroes
+            Quirk {
+                title: "TETRIS".to_string(),
+                header_checksum: 0x0a,
+                global_checksum: Some(0x16bf),
+                cartridge_type: None,
+                registers: Some(Registers {
+                    a: 0x01,
+                    f: 0xb0,
+                    b: 0x00,
+                    c: 0x13,
+                    d: 0x00,
+                    e: 0xd8,
+                    h: 0x01,
+                    l: 0x4d,
+                    sp: 0xfffe,
+                    pc: 0x100,
+                }),
+                force_dmg: false,
+            },
+        ])
+    }
+
+    /// Adds the quirks of `other` over these.
+    pub fn extend(&mut self, other: QuirkDatabase) {
+        self.quirks.extend(other.quirks);
+    }
+
+    /// The quirk for a ROM image, by the checksums in its header.
+    pub fn lookup(&self, rom: &[u8]) -> Option<&Quirk> {
+        self.quirks.iter().rev().find(|quirk| quirk.matches(rom))
+    }
+
+    pub fn len(&self) -> usize {
+        self.quirks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quirks.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::{fix_checksum, rom_with_header};
+
+    #[test]
+    fn test_built_in() {
+        let quirks = QuirkDatabase::built_in();
+        assert!(!quirks.is_empty());
+        let mut rom = rom_with_header(0x00, 0x00);
+        rom[0x14d] = 0x0a;
+        rom[0x14e..0x150].copy_from_slice(&[0x16, 0xbf]);
+        let quirk = quirks.lookup(&rom).unwrap();
+        assert_eq!(quirk.title, "TETRIS");
+        assert_eq!(quirk.registers.unwrap().sp, 0xfffe);
+        // Another game with the same header checksum
+        rom[0x14f] = 0x00;
+        assert_eq!(quirks.lookup(&rom), None);
+    }
+
+    #[test]
+    fn test_extend() {
+        let quirk = |cartridge_type| Quirk {
+            title: String::new(),
+            header_checksum: 0x42,
+            global_checksum: None,
+            cartridge_type,
+            registers: None,
+            force_dmg: false,
+        };
+        let mut quirks = QuirkDatabase::new(vec![quirk(Some(0x01))]);
+        quirks.extend(QuirkDatabase::new(vec![quirk(None)]));
+        assert_eq!(quirks.len(), 2);
+        let mut rom = rom_with_header(0x00, 0x00);
+        rom[0x14d] = 0x42;
+        // The later quirk wins, whatever the global checksum
+        assert_eq!(quirks.lookup(&rom), Some(&quirk(None)));
+        rom[0x14d] = 0x43;
+        assert_eq!(quirks.lookup(&rom), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut rom = rom_with_header(0x00, 0x00);
+        rom[0x134..0x138].copy_from_slice(b"GAME");
+        fix_checksum(&mut rom);
+        let quirk = Quirk {
+            title: "GAME".to_string(),
+            header_checksum: rom[0x14d],
+            global_checksum: None,
+            cartridge_type: Some(0x01),
+            registers: Some(Registers {
+                a: 0x01,
+                sp: 0xfffe,
+                pc: 0x100,
+                ..Registers::default()
+            }),
+            force_dmg: false,
+        };
+        quirk.patch_rom(&mut rom);
+        // MBC1, which the header did not say, with a header still valid
+        assert_eq!(rom[0x147], 0x01);
+        let mut cpu = Cpu::with_rom(rom).unwrap();
+        quirk.apply(&mut cpu);
+        assert_eq!(cpu.registers().sp, 0xfffe);
+
+        cpu.reset(false);
+        let registers = cpu.registers();
+        assert_eq!(
+            (registers.a, registers.sp, registers.pc),
+            (0x01, 0xfffe, 0x100)
+        );
+    }
+
+    #[test]
+    fn test_force_dmg() {
+        let mut rom = rom_with_header(0x00, 0x00);
+        rom[0x143] = 0xc0;
+        fix_checksum(&mut rom);
+        let quirk = Quirk {
+            title: String::new(),
+            header_checksum: rom[0x14d],
+            global_checksum: None,
+            cartridge_type: None,
+            registers: None,
+            force_dmg: true,
+        };
+        let cpu = Cpu::with_rom(rom.clone()).unwrap();
+        assert_eq!(cpu.mmu.peek(0xff4d), 0x7e);
+
+        quirk.patch_rom(&mut rom);
+        assert_eq!(rom[0x143], 0x00);
+        // KEY1 is gone as on a DMG
+        let cpu = Cpu::with_rom(rom).unwrap();
+        assert_eq!(cpu.mmu.peek(0xff4d), 0x00);
+    }
+}
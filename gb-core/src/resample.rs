@@ -0,0 +1,259 @@
+//! Turns the APU output into samples at the output rate. Past the kernel
+//! table everything is integer arithmetic, and the table only takes basic
+//! float operations, which IEEE 754 rounds the same everywhere, so the
+//! samples are bit-identical on every platform, e.g. for audio regression
+//! tests and recordings.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::f64::consts::PI;
+
+use crate::apu::CPU_CLOCK_HZ;
+
+/// Cycles between the values the interpolating resamplers take of the APU
+/// output, 131072 Hz.
+pub const TICK_CYCLES: u32 = 32;
+/// Positions between two input values the sinc kernel is tabulated at.
+const PHASES: u64 = 64;
+/// Zero crossings of the sinc kernel on each side, at the output rate.
+const ZERO_CROSSINGS: f64 = 8.0;
+/// Fraction bits of the kernel coefficients.
+const COEF_BITS: u32 = 14;
+
+/// How the APU output becomes samples at the output rate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Resampler {
+    /// The output as it is at each sample. The cheapest, but high notes
+    /// alias into lower ones.
+    #[default]
+    Nearest,
+    /// Interpolates between the values taken every `TICK_CYCLES`.
+    Linear,
+    /// Low-pass filters the values taken every `TICK_CYCLES` with a
+    /// windowed sinc, cutting what the output rate cannot carry before it
+    /// aliases. The costliest.
+    Sinc,
+}
+
+impl Resampler {
+    pub const ALL: [Resampler; 3] = [Resampler::Nearest, Resampler::Linear, Resampler::Sinc];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Resampler::Nearest => "nearest",
+            Resampler::Linear => "linear",
+            Resampler::Sinc => "sinc",
+        }
+    }
+}
+
+/// Resamples stereo values taken every `TICK_CYCLES` for `Resampler::Linear`
+/// and `Resampler::Sinc`.
+pub(crate) struct Stream {
+    input_rate: u64,
+    output_rate: u64,
+    /// Inputs used on each side of an output position.
+    half: usize,
+    /// `PHASES` rows of `2 * half` sinc coefficients, empty for linear.
+    kernel: Vec<i32>,
+    history: VecDeque<[i32; 2]>,
+    /// Input index of `history[0]`.
+    first: u64,
+    /// Input index of the next output sample times `output_rate`.
+    next: u64,
+}
+
+impl Stream {
+    /// Creates a new `Stream` for `resampler`, which must not be `Nearest`,
+    /// producing `output_rate` samples a second.
+    pub(crate) fn new(resampler: Resampler, output_rate: u32) -> Self {
+        let input_rate = (CPU_CLOCK_HZ / TICK_CYCLES) as u64;
+        let output_rate = output_rate as u64;
+        let (half, kernel) = match resampler {
+            Resampler::Nearest => unreachable!("nearest takes no stream"),
+            Resampler::Linear => (1, Vec::new()),
+            Resampler::Sinc => sinc_kernel(input_rate as f64 / output_rate as f64),
+        };
+        // Silence before the first input, so the first output has all the
+        // inputs it needs
+        let history = core::iter::repeat_n([0; 2], half).collect();
+        Stream {
+            input_rate,
+            output_rate,
+            half,
+            kernel,
+            history,
+            first: 0,
+            next: (half as u64 - 1) * output_rate,
+        }
+    }
+
+    /// Adds the next input value and hands `output` every sample that has
+    /// all its inputs now.
+    pub(crate) fn push(&mut self, value: [i32; 2], mut output: impl FnMut([i32; 2])) {
+        self.history.push_back(value);
+        let end = self.first + self.history.len() as u64;
+        loop {
+            let index = self.next / self.output_rate;
+            if index + self.half as u64 >= end {
+                break;
+            }
+            let frac = self.next % self.output_rate;
+            // First input used
+            let start = (index + 1 - self.half as u64 - self.first) as usize;
+            output(self.sample(start, frac));
+            self.next += self.input_rate;
+        }
+        let keep_from = (self.next / self.output_rate + 1).saturating_sub(self.half as u64);
+        while self.first < keep_from && !self.history.is_empty() {
+            self.history.pop_front();
+            self.first += 1;
+        }
+    }
+
+    /// The sample `frac / output_rate` past the input at `start + half -
+    /// 1` in `history`.
+    fn sample(&self, start: usize, frac: u64) -> [i32; 2] {
+        let mut sample = [0; 2];
+        if self.kernel.is_empty() {
+            let (a, b) = (self.history[start], self.history[start + 1]);
+            for side in 0..2 {
+                let delta = (b[side] - a[side]) as i64 * frac as i64 / self.output_rate as i64;
+                sample[side] = a[side] + delta as i32;
+            }
+            return sample;
+        }
+        let taps = 2 * self.half;
+        let phase = (frac * PHASES / self.output_rate) as usize;
+        let coefs = &self.kernel[phase * taps..(phase + 1) * taps];
+        let mut acc = [0i64; 2];
+        for (coef, value) in coefs.iter().zip(self.history.range(start..start + taps)) {
+            for side in 0..2 {
+                acc[side] += *coef as i64 * value[side] as i64;
+            }
+        }
+        for side in 0..2 {
+            sample[side] = (acc[side] >> COEF_BITS) as i32;
+        }
+        sample
+    }
+}
+
+/// Inputs on each side and the coefficients of a Hann-windowed sinc
+/// low-pass for `ratio` inputs per output, see `Stream::kernel`. Each row
+/// sums to exactly 1 so silence stays silent.
+fn sinc_kernel(ratio: f64) -> (usize, Vec<i32>) {
+    let ratio = ratio.max(1.0);
+    // Cycles per input, a little under the output's Nyquist frequency
+    let cutoff = 0.45 / ratio;
+    let half = libm::ceil(ZERO_CROSSINGS * ratio) as usize;
+    let taps = 2 * half;
+    let mut kernel = Vec::with_capacity(PHASES as usize * taps);
+    for phase in 0..PHASES {
+        let frac = phase as f64 / PHASES as f64;
+        let row: Vec<f64> = (0..taps)
+            .map(|tap| {
+                let x = tap as f64 + 1.0 - half as f64 - frac;
+                let window = sin_pi(0.5 - x / (2 * half) as f64);
+                2.0 * cutoff * sinc(2.0 * cutoff * x) * window * window
+            })
+            .collect();
+        let sum: f64 = row.iter().sum();
+        let mut coefs: Vec<i32> = row
+            .iter()
+            .map(|h| libm::round(h / sum * (1 << COEF_BITS) as f64) as i32)
+            .collect();
+        // Rounding leftovers go to the largest tap
+        let error = (1 << COEF_BITS) - coefs.iter().sum::<i32>();
+        let largest = (0..taps).max_by_key(|&tap| coefs[tap]).unwrap();
+        coefs[largest] += error;
+        kernel.extend(coefs);
+    }
+    (half, kernel)
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        sin_pi(x) / (PI * x)
+    }
+}
+
+/// sin(pi * x) from basic operations only, unlike `f64::sin`, whose last
+/// bits depend on the platform's math library.
+fn sin_pi(x: f64) -> f64 {
+    // Into -1..=1, a period
+    let x = x - 2.0 * libm::round(x / 2.0);
+    // Into -0.5..=0.5, since sin(pi * (1 - x)) = sin(pi * x)
+    let x = if x > 0.5 {
+        1.0 - x
+    } else if x < -0.5 {
+        -1.0 - x
+    } else {
+        x
+    };
+    // Taylor series, within 1e-12 up to pi / 2
+    let t = x * PI;
+    let mut term = t;
+    let mut sum = t;
+    for n in 1..9 {
+        term *= -t * t / ((2 * n) * (2 * n + 1)) as f64;
+        sum += term;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sin_pi() {
+        for i in -40..=40 {
+            let x = i as f64 / 8.0;
+            assert!((sin_pi(x) - (PI * x).sin()).abs() < 1e-12, "{}", x);
+        }
+    }
+
+    /// Resamples one second of `input`, a function of the input index.
+    fn resample(resampler: Resampler, rate: u32, input: impl Fn(u64) -> i32) -> Vec<[i32; 2]> {
+        let mut stream = Stream::new(resampler, rate);
+        let mut output = Vec::new();
+        for i in 0..(CPU_CLOCK_HZ / TICK_CYCLES) as u64 {
+            let value = input(i);
+            stream.push([value, -value], |sample| output.push(sample));
+        }
+        output
+    }
+
+    #[test]
+    fn test_rate_and_dc() {
+        for &resampler in &[Resampler::Linear, Resampler::Sinc] {
+            for &rate in &[11_025, 48_000, 192_000] {
+                let output = resample(resampler, rate, |_| 1000);
+                // Short of the last few the kernel still waits for
+                let missing = rate as usize - output.len();
+                assert!(missing <= 20, "{:?} {} {}", resampler, rate, missing);
+                assert_eq!(output[rate as usize / 2], [1000, -1000]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_linear() {
+        // Half the input rate, so every output sample lands on an input
+        let output = resample(Resampler::Linear, 65_536, |i| i as i32);
+        assert_eq!(output[..4], [[0, 0], [1, -1], [3, -3], [5, -5]]);
+    }
+
+    #[test]
+    fn test_sinc_cuts_high_tones() {
+        // 16384 Hz, far above the 5512 Hz an 11025 Hz output can carry
+        let square = |i: u64| if i & 4 == 0 { 8000 } else { -8000 };
+        let peak =
+            |output: &[[i32; 2]]| output[1000..2000].iter().map(|s| s[0].abs()).max().unwrap();
+        assert!(peak(&resample(Resampler::Linear, 11_025, square)) > 4000);
+        assert!(peak(&resample(Resampler::Sinc, 11_025, square)) < 400);
+    }
+}
@@ -0,0 +1,221 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::cpu::Cpu;
+use crate::input::{InputProvider, Players, PollPoint, PollRate};
+use crate::state::StateError;
+
+/// A savestate taken as a frame started, or when stepping started mid-frame,
+/// with the buttons of every poll since.
+struct Snapshot {
+    /// Instructions run through `Rewind::step_frame` before it was taken.
+    position: u64,
+    state: Vec<u8>,
+    polls: Vec<Players>,
+}
+
+/// Runs a game one instruction at a time like `Cpu::step_frame`, keeping a
+/// savestate of each of the latest frames and the buttons polled since, so
+/// a debugger can step backwards: `step_back` loads the snapshot before the
+/// previous instruction and runs forward to it with the same buttons, which
+/// gives the exact same machine state the game was in.
+///
+/// Clear it after anything else changes the machine, e.g. a reset or
+/// loading a state, since the snapshots lead somewhere else then.
+pub struct Rewind {
+    rate: PollRate,
+    /// Snapshots kept, oldest dropped first.
+    capacity: usize,
+    snapshots: VecDeque<Snapshot>,
+    /// Instructions run through `step_frame`, less those stepped back.
+    position: u64,
+}
+
+impl Rewind {
+    /// Creates a new `Rewind` keeping `capacity` frames, polling input at
+    /// `rate`.
+    pub fn new(capacity: usize, rate: PollRate) -> Self {
+        Rewind {
+            rate,
+            capacity: capacity.max(1),
+            snapshots: VecDeque::new(),
+            position: 0,
+        }
+    }
+
+    pub fn rate(&self) -> PollRate {
+        self.rate
+    }
+
+    /// Instructions run since the `Rewind` was created or cleared, less
+    /// those stepped back.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Instructions `step_back` can go back.
+    pub fn depth(&self) -> u64 {
+        self.snapshots
+            .front()
+            .map_or(0, |oldest| self.position - oldest.position)
+    }
+
+    /// Forgets every snapshot.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.position = 0;
+    }
+
+    /// Runs one instruction like `Cpu::step_frame`, taking a snapshot first
+    /// if a frame starts. Returns whether the frame ended.
+    pub fn step_frame(&mut self, cpu: &mut Cpu, input: &mut dyn InputProvider) -> bool {
+        let snapshot_due = match self.snapshots.back() {
+            Some(last) => cpu.frame_cycles() == 0 && last.position != self.position,
+            None => true,
+        };
+        if snapshot_due {
+            if self.snapshots.len() == self.capacity {
+                self.snapshots.pop_front();
+            }
+            self.snapshots.push_back(Snapshot {
+                position: self.position,
+                state: cpu.save_state(),
+                polls: Vec::new(),
+            });
+        }
+        let snapshot = self.snapshots.back_mut().unwrap();
+        let mut recorder = Recorder {
+            input,
+            polls: &mut snapshot.polls,
+        };
+        let done = cpu.step_frame(&mut recorder, self.rate);
+        self.position += 1;
+        done
+    }
+
+    /// Takes `cpu` back to the state before the last instruction run
+    /// through `step_frame`. Returns `false`, with `cpu` left alone, if
+    /// that instruction is older than the oldest snapshot.
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> Result<bool, StateError> {
+        if self.depth() == 0 {
+            return Ok(false);
+        }
+        let target = self.position - 1;
+        while self.snapshots.back().is_some_and(|s| s.position > target) {
+            self.snapshots.pop_back();
+        }
+        let snapshot = self.snapshots.back_mut().unwrap();
+        cpu.load_state(&snapshot.state)?;
+        let mut replay = Replay {
+            polls: &snapshot.polls,
+            next: 0,
+        };
+        for _ in snapshot.position..target {
+            cpu.step_frame(&mut replay, self.rate);
+        }
+        // Stepping on from here may press other buttons
+        let polled = replay.next;
+        snapshot.polls.truncate(polled);
+        self.position = target;
+        Ok(true)
+    }
+}
+
+/// Passes on the buttons of another `InputProvider` and records them.
+struct Recorder<'a> {
+    input: &'a mut dyn InputProvider,
+    polls: &'a mut Vec<Players>,
+}
+
+impl InputProvider for Recorder<'_> {
+    fn poll(&mut self, at: PollPoint) -> Players {
+        let players = self.input.poll(at);
+        self.polls.push(players);
+        players
+    }
+}
+
+/// Gives the recorded buttons back in order.
+struct Replay<'a> {
+    polls: &'a [Players],
+    next: usize,
+}
+
+impl InputProvider for Replay<'_> {
+    fn poll(&mut self, _at: PollPoint) -> Players {
+        let players = self.polls.get(self.next).copied();
+        self.next += 1;
+        players.unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::{fix_checksum, rom_with_header};
+    use crate::input::ScriptedInput;
+    use crate::joypad::{ButtonState, Key};
+
+    #[test]
+    fn test_step_back() {
+        let mut rom = rom_with_header(0x00, 0x00);
+        // Count in B forever, reading the joypad into C on the way
+        rom[0x100..0x109].copy_from_slice(&[
+            0x04, // INC B
+            0x3e, 0x10, // LD A, 0x10
+            0xe0, 0x00, // LDH (0x00), A
+            0xf0, 0x00, // LDH A, (0x00)
+            0x4f, // LD C, A
+            0x18, // JR 0x100
+        ]);
+        rom[0x109] = 0xf6;
+        fix_checksum(&mut rom);
+        let mut input = ScriptedInput::new();
+        input.hold(3, 0, ButtonState::default().with(Key::A));
+        let run = |input: &mut ScriptedInput, keep_from: u64| {
+            let mut cpu = Cpu::with_rom(rom.clone()).unwrap();
+            let mut rewind = Rewind::new(2, PollRate::Lines(16));
+            let mut frame_starts = vec![0];
+            let mut states = Vec::new();
+            while frame_starts.len() < 4 || rewind.position() < frame_starts[3] + 10 {
+                if rewind.position() >= keep_from {
+                    states.push((rewind.position(), cpu.save_state()));
+                }
+                if rewind.step_frame(&mut cpu, input) {
+                    frame_starts.push(rewind.position());
+                }
+            }
+            (cpu, rewind, frame_starts, states)
+        };
+
+        // Three frames and 10 instructions into the fourth, then again
+        // keeping the states of the last 30 instructions
+        let (_, rewind, _, _) = run(&mut input, u64::MAX);
+        let (mut cpu, mut rewind, frame_starts, states) = run(&mut input, rewind.position() - 30);
+        assert_eq!(states.len(), 30);
+        // The snapshots of the frames 2 and 3 are kept
+        assert_eq!(rewind.depth(), rewind.position() - frame_starts[2]);
+
+        // Back over the start of frame 3, where A was pressed
+        for (position, state) in states.iter().rev() {
+            assert!(rewind.step_back(&mut cpu).unwrap());
+            assert_eq!(rewind.position(), *position);
+            assert!(cpu.save_state() == *state, "at {}", position);
+        }
+
+        // And forward again the same way
+        let end = rewind.position() + 30;
+        for (position, state) in &states {
+            assert_eq!(rewind.position(), *position);
+            assert!(cpu.save_state() == *state, "at {}", position);
+            rewind.step_frame(&mut cpu, &mut input);
+        }
+        assert_eq!(rewind.position(), end);
+
+        let mut rewind = Rewind::new(1, PollRate::Frame);
+        assert!(!rewind.step_back(&mut cpu).unwrap());
+        rewind.step_frame(&mut cpu, &mut input);
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert!(!rewind.step_back(&mut cpu).unwrap());
+    }
+}
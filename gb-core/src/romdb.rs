@@ -0,0 +1,152 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// What a dat file knows about one ROM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DatEntry {
+    /// Canonical name, e.g. "Tetris (World) (Rev 1)".
+    pub name: String,
+    /// First parenthesized part of the name, e.g. "World".
+    pub region: Option<String>,
+    /// Marked as a bad dump by the dat.
+    pub bad_dump: bool,
+}
+
+/// ROM names from a No-Intro style (Logiqx XML) dat file, looked up by the
+/// CRC32 of the whole ROM like the dat itself does. Header checksums are
+/// shared by revisions and hacks, so they cannot tell dumps apart.
+#[derive(Debug, Default)]
+pub struct RomDatabase {
+    entries: BTreeMap<u32, DatEntry>,
+}
+
+impl RomDatabase {
+    /// Reads a dat file.
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(RomDatabase::parse_dat(&fs::read_to_string(path)?))
+    }
+
+    /// Collects every `<rom>` with a `crc` inside a `<game>`. Anything else
+    /// in the file is ignored.
+    pub fn parse_dat(xml: &str) -> Self {
+        let mut entries = BTreeMap::new();
+        let mut game: Option<String> = None;
+        for tag in xml.split('<').filter_map(|s| s.split('>').next()) {
+            if tag.starts_with("game ") || tag.starts_with("machine ") {
+                game = attribute(tag, "name");
+            } else if tag.starts_with("/game") || tag.starts_with("/machine") {
+                game = None;
+            } else if tag.starts_with("rom ") {
+                let name = match &game {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let crc = attribute(tag, "crc").and_then(|crc| u32::from_str_radix(&crc, 16).ok());
+                if let Some(crc) = crc {
+                    let bad_dump = attribute(tag, "status").as_deref() == Some("baddump")
+                        || name.contains("[b]");
+                    entries.insert(
+                        crc,
+                        DatEntry {
+                            name: name.clone(),
+                            region: region(name),
+                            bad_dump,
+                        },
+                    );
+                }
+            }
+        }
+        RomDatabase { entries }
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&DatEntry> {
+        self.entries.get(&crc32(rom))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Value of `name="..."` in the inside of a tag, with entities decoded.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = tag[start..].find('"')?;
+    Some(
+        tag[start..start + len]
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&"),
+    )
+}
+
+fn region(name: &str) -> Option<String> {
+    let start = name.find('(')? + 1;
+    let len = name[start..].find(')')?;
+    Some(name[start..start + len].to_string())
+}
+
+/// CRC-32 (IEEE), as used by zip and dat files.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_parse_dat() {
+        let xml = r#"<?xml version="1.0"?>
+<datafile>
+    <header><name>Nintendo - Game Boy</name></header>
+    <game name="Foo &amp; Bar (Japan) (Rev 1)">
+        <description>Foo &amp; Bar (Japan) (Rev 1)</description>
+        <rom name="Foo.gb" size="9" crc="CBF43926" md5="x"/>
+    </game>
+    <game name="Baz (USA)">
+        <rom name="Baz.gb" size="4" crc="12345678" status="baddump"/>
+    </game>
+</datafile>"#;
+        let db = RomDatabase::parse_dat(xml);
+        assert_eq!(db.len(), 2);
+        assert_eq!(
+            db.lookup(b"123456789"),
+            Some(&DatEntry {
+                name: "Foo & Bar (Japan) (Rev 1)".to_string(),
+                region: Some("Japan".to_string()),
+                bad_dump: false,
+            })
+        );
+        assert!(db.entries[&0x1234_5678].bad_dump);
+        assert_eq!(db.lookup(b"other"), None);
+    }
+}
@@ -0,0 +1,127 @@
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::state::{StateError, StateReader, StateWriter};
+
+pub struct Rtc {
+    s: u8,
+    m: u8,
+    h: u8,
+    dl: u8,
+    dh: u8,
+    /// When the clock read zero. Without the `std` feature there is no wall
+    /// clock, and the registers keep what the game wrote.
+    #[cfg(feature = "std")]
+    initialization_time: SystemTime,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Rtc {
+            s: 0,
+            m: 0,
+            h: 0,
+            dl: 0,
+            dh: 0,
+            #[cfg(feature = "std")]
+            initialization_time: SystemTime::now(),
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.s);
+        w.write_u8(self.m);
+        w.write_u8(self.h);
+        w.write_u8(self.dl);
+        w.write_u8(self.dh);
+        // The wall-clock start differs between machines started at
+        // different times, so it gets a section of its own to leave out of
+        // `Cpu::state_hashes`. Only MBC3 has an RTC, inside "cartridge".
+        w.begin_section("rtc-base");
+        #[cfg(feature = "std")]
+        let timestamp = match self.initialization_time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        #[cfg(not(feature = "std"))]
+        let timestamp = 0;
+        w.write_i64(timestamp);
+        w.begin_section("cartridge");
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.s = r.read_u8()?;
+        self.m = r.read_u8()?;
+        self.h = r.read_u8()?;
+        self.dl = r.read_u8()?;
+        self.dh = r.read_u8()?;
+        let timestamp = r.read_i64()?;
+        #[cfg(feature = "std")]
+        self.set_base(timestamp);
+        #[cfg(not(feature = "std"))]
+        let _ = timestamp;
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    fn set_base(&mut self, timestamp: i64) {
+        let since = Duration::from_secs(timestamp.unsigned_abs());
+        let time = if timestamp >= 0 {
+            UNIX_EPOCH.checked_add(since)
+        } else {
+            UNIX_EPOCH.checked_sub(since)
+        };
+        if let Some(time) = time {
+            self.initialization_time = time;
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0008 => self.s,
+            0x0009 => self.m,
+            0x000a => self.h,
+            0x000b => self.dl,
+            0x000c => self.dh,
+            _ => panic!("Invalid address: 0x{:04x}, RTC read", addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0008 => self.s = value,
+            0x0009 => self.m = value,
+            0x000a => self.h = value,
+            0x000b => self.dl = value,
+            0x000c => self.dh = value,
+            _ => panic!("Invalid address: 0x{:04x}, RTC write", addr),
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub fn tic(&mut self) {}
+
+    #[cfg(feature = "std")]
+    pub fn tic(&mut self) {
+        // A clock set back before the start counts as no time passed
+        let seconds = SystemTime::now()
+            .duration_since(self.initialization_time)
+            .map_or(0, |diff| diff.as_secs());
+
+        self.s = seconds as u8;
+        self.m = (seconds / 60) as u8;
+        self.h = (seconds / 3600) as u8;
+        let days_diff = (seconds / 86400) as u16;
+        self.dl = (days_diff % 256) as u8;
+        match days_diff {
+            0x0000..=0x00ff => {}
+            0x0100..=0x01ff => {
+                self.dh |= 0x01;
+            }
+            _ => {
+                self.dh |= 0x01;
+                self.dh |= 0x80;
+            }
+        }
+    }
+}
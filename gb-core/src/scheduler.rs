@@ -0,0 +1,139 @@
+use alloc::vec::Vec;
+
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// Something a peripheral needs to do at a known future cycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The PPU reaches the end of its current mode.
+    Ppu,
+    /// TIMA overflows.
+    Timer,
+    /// The serial port shifts the next bit.
+    Serial,
+    /// The APU frame sequencer steps.
+    Apu,
+}
+
+impl Event {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Event::Ppu),
+            1 => Some(Event::Timer),
+            2 => Some(Event::Serial),
+            3 => Some(Event::Apu),
+            _ => None,
+        }
+    }
+}
+
+/// Keeps the machine time and the pending peripheral events.
+///
+/// Peripherals are only stepped when one of their events is due or when the
+/// CPU touches their registers, instead of on every instruction.
+pub struct Scheduler {
+    /// Cycles since power-on.
+    now: u64,
+    /// At most one entry per event kind, unordered.
+    events: Vec<(u64, Event)>,
+}
+
+impl Scheduler {
+    /// Creates a new `Scheduler`.
+    pub fn new() -> Self {
+        Scheduler {
+            now: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.write_u64(self.now);
+        w.write_u8(self.events.len() as u8);
+        for &(at, event) in &self.events {
+            w.write_u64(at);
+            w.write_u8(event as u8);
+        }
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.now = r.read_u64()?;
+        self.events.clear();
+        for _ in 0..r.read_u8()? {
+            let at = r.read_u64()?;
+            let event = Event::from_u8(r.read_u8()?).ok_or(StateError::Corrupt)?;
+            self.events.push((at, event));
+        }
+        Ok(())
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Schedules `event` at cycle `at`, replacing a pending one of the same kind.
+    pub fn schedule(&mut self, event: Event, at: u64) {
+        self.cancel(event);
+        self.events.push((at, event));
+    }
+
+    pub fn cancel(&mut self, event: Event) {
+        self.events.retain(|&(_, e)| e != event);
+    }
+
+    /// Cycles until the earliest pending event.
+    pub fn next_event_in(&self) -> Option<u64> {
+        self.events
+            .iter()
+            .map(|&(at, _)| at.saturating_sub(self.now))
+            .min()
+    }
+
+    /// Removes the earliest event due at or before `until` and moves the
+    /// time forward to it.
+    pub fn pop_due(&mut self, until: u64) -> Option<Event> {
+        let (i, &(at, event)) = self
+            .events
+            .iter()
+            .enumerate()
+            .filter(|(_, &(at, _))| at <= until)
+            .min_by_key(|(_, &(at, _))| at)?;
+        self.events.swap_remove(i);
+        self.now = self.now.max(at);
+        Some(event)
+    }
+
+    /// Moves the time forward to `until` once no event is due.
+    pub fn advance_to(&mut self, until: u64) {
+        self.now = until;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pop_due_in_order() {
+        let mut s = Scheduler::new();
+        s.schedule(Event::Timer, 30);
+        s.schedule(Event::Ppu, 10);
+        assert_eq!(s.next_event_in(), Some(10));
+
+        assert_eq!(s.pop_due(40), Some(Event::Ppu));
+        assert_eq!(s.now(), 10);
+        assert_eq!(s.pop_due(20), None);
+        assert_eq!(s.pop_due(40), Some(Event::Timer));
+        assert_eq!(s.now(), 30);
+        assert_eq!(s.pop_due(40), None);
+    }
+
+    #[test]
+    fn test_schedule_replaces() {
+        let mut s = Scheduler::new();
+        s.schedule(Event::Ppu, 10);
+        s.schedule(Event::Ppu, 50);
+        assert_eq!(s.pop_due(20), None);
+        assert_eq!(s.next_event_in(), Some(50));
+    }
+}
@@ -0,0 +1,161 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// Cycles per bit with the internal 8192 Hz clock.
+pub const BIT_CYCLES: u64 = 512;
+
+pub struct Serial {
+    data: u8,
+    control: u8,
+    /// Bits left in the current transfer.
+    bits_left: u8,
+    /// Bits still to shift in from the link partner, highest first.
+    incoming: u8,
+    /// Interrupt request
+    pub irq: bool,
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Self {
+            data: 0x00,
+            control: 0x00,
+            bits_left: 0,
+            incoming: 0xff,
+            irq: false,
+        }
+    }
+
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.data);
+        w.write_u8(self.control);
+        w.write_u8(self.bits_left);
+        w.write_u8(self.incoming);
+        w.write_bool(self.irq);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.data = r.read_u8()?;
+        self.control = r.read_u8()?;
+        self.bits_left = r.read_u8()?;
+        self.incoming = r.read_u8()?;
+        self.irq = r.read_bool()?;
+        Ok(())
+    }
+
+    /// Whether a transfer is in progress.
+    pub fn is_transferring(&self) -> bool {
+        self.bits_left > 0
+    }
+
+    /// Whether a transfer was started on the external clock and waits for
+    /// the link partner to clock it.
+    pub(crate) fn is_waiting_for_clock(&self) -> bool {
+        self.control & 0x81 == 0x80 && self.bits_left == 0
+    }
+
+    /// Byte in SB, the one shifted out next.
+    pub(crate) fn data(&self) -> u8 {
+        self.data
+    }
+
+    /// Sets the byte the link partner shifts in during the transfer just
+    /// started. Without a partner the line reads high, so a finished
+    /// transfer leaves 0xff in SB.
+    pub(crate) fn set_incoming(&mut self, byte: u8) {
+        self.incoming = byte;
+    }
+
+    /// The link partner starts clocking a transfer that shifts in `byte`.
+    pub(crate) fn start_external(&mut self, byte: u8) {
+        self.bits_left = 8;
+        self.incoming = byte;
+    }
+
+    /// Shifts out one bit and shifts in one from the link partner.
+    pub(crate) fn shift_bit(&mut self) {
+        if self.bits_left == 0 {
+            return;
+        }
+        self.data = (self.data << 1) | (self.incoming >> 7);
+        self.incoming = (self.incoming << 1) | 1;
+        self.bits_left -= 1;
+        if self.bits_left == 0 {
+            self.control &= 0x7f;
+            self.irq = true;
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        // println!("Serial read address: 0x{:04x}", addr);
+        match addr {
+            0xff01 => self.data,
+            0xff02 => self.control | 0x7e,
+            _ => panic!("Invalid serial address 0x{:04x}", addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        // println!(
+        //     "Serial write address: 0x{:04x}, value: 0x{:02x}",
+        //     addr, value
+        // );
+        match addr {
+            0xff01 => self.data = value,
+            0xff02 => {
+                self.control = value;
+                // With the external clock the transfer waits for a partner
+                // that never clocks it
+                self.bits_left = if value & 0x81 == 0x81 { 8 } else { 0 };
+                self.incoming = 0xff;
+            }
+            _ => panic!("Ivalid serial address 0x{:04x}", addr),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disconnected_transfer() {
+        let mut serial = Serial::new();
+        serial.write(0xff01, 0x42);
+        serial.write(0xff02, 0x81);
+        for _ in 0..7 {
+            serial.shift_bit();
+        }
+        assert!(serial.is_transferring());
+        assert!(!serial.irq);
+        assert_eq!(serial.read(0xff01), 0x7f);
+
+        serial.shift_bit();
+        assert!(!serial.is_transferring());
+        assert!(serial.irq);
+        assert_eq!(serial.read(0xff01), 0xff);
+        assert_eq!(serial.read(0xff02), 0x7f);
+    }
+
+    #[test]
+    fn test_external_transfer() {
+        let mut serial = Serial::new();
+        serial.write(0xff01, 0x42);
+        serial.write(0xff02, 0x80);
+        assert!(serial.is_waiting_for_clock());
+        serial.start_external(0xa5);
+        for _ in 0..8 {
+            serial.shift_bit();
+        }
+        assert!(serial.irq);
+        assert_eq!(serial.read(0xff01), 0xa5);
+        assert_eq!(serial.read(0xff02), 0x7e);
+    }
+
+    #[test]
+    fn test_external_clock_never_completes() {
+        let mut serial = Serial::new();
+        serial.write(0xff02, 0x80);
+        assert!(!serial.is_transferring());
+        assert_eq!(serial.read(0xff02), 0xfe);
+    }
+}
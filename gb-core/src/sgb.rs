@@ -0,0 +1,103 @@
+//! Super Game Boy command packets, which games send through JOYP.
+//!
+//! Only the packets are decoded; of the commands, the joypad handles
+//! MLT_REQ. Borders, palettes and sound are not emulated.
+
+/// Bytes in a packet; the first holds the command and packet count.
+pub const PACKET_LEN: usize = 16;
+
+/// Command asking for 1, 2 or 4 controllers.
+pub const MLT_REQ: u8 = 0x11;
+
+/// Collects the bits a game pulses on P14 (a 0) and P15 (a 1).
+///
+/// A packet starts with both lines pulled low, then sends 128 bits, least
+/// significant first, with both lines high between pulses, and ends with
+/// a 0 bit.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PacketReceiver {
+    /// Bits received, or `None` when not in a packet.
+    received: Option<usize>,
+    packet: [u8; PACKET_LEN],
+    /// Both lines went high since the last pulse.
+    ready: bool,
+}
+
+impl PacketReceiver {
+    /// Takes the P14/P15 select bits of a JOYP write. Returns the packet
+    /// once its stop bit arrives.
+    pub(crate) fn write(&mut self, select: u8) -> Option<[u8; PACKET_LEN]> {
+        match select & 0x30 {
+            0x00 => {
+                self.received = Some(0);
+                self.packet = [0; PACKET_LEN];
+                self.ready = false;
+                None
+            }
+            0x30 => {
+                self.ready = true;
+                None
+            }
+            pulse => {
+                let received = self.received?;
+                if !core::mem::replace(&mut self.ready, false) {
+                    return None;
+                }
+                let bit = pulse == 0x10;
+                if received == PACKET_LEN * 8 {
+                    // A 1 where the stop bit belongs garbles the packet
+                    self.received = None;
+                    return if bit { None } else { Some(self.packet) };
+                }
+                self.packet[received / 8] |= u8::from(bit) << (received % 8);
+                self.received = Some(received + 1);
+                None
+            }
+        }
+    }
+}
+
+/// Command number of a packet.
+pub fn command(packet: &[u8; PACKET_LEN]) -> u8 {
+    packet[0] >> 3
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// JOYP writes sending `packet`, stop bit included.
+    pub(crate) fn packet_writes(packet: &[u8; PACKET_LEN]) -> Vec<u8> {
+        let mut writes = vec![0x00, 0x30];
+        for i in 0..PACKET_LEN * 8 {
+            let bit = packet[i / 8] >> (i % 8) & 1;
+            writes.push(if bit == 1 { 0x10 } else { 0x20 });
+            writes.push(0x30);
+        }
+        writes.extend_from_slice(&[0x20, 0x30]);
+        writes
+    }
+
+    #[test]
+    fn test_receive_packet() {
+        let mut packet = [0; PACKET_LEN];
+        packet[0] = MLT_REQ << 3 | 1;
+        packet[1] = 0x03;
+        packet[15] = 0x80;
+
+        let mut receiver = PacketReceiver::default();
+        let received: Vec<_> = packet_writes(&packet)
+            .into_iter()
+            .filter_map(|select| receiver.write(select))
+            .collect();
+        assert_eq!(received, vec![packet]);
+        assert_eq!(command(&received[0]), MLT_REQ);
+
+        // Nothing without the reset pulse, or with a 1 as the stop bit
+        let writes = packet_writes(&packet);
+        assert!(writes[1..].iter().all(|&s| receiver.write(s).is_none()));
+        let mut writes = packet_writes(&packet);
+        writes[2 + PACKET_LEN * 16] = 0x10;
+        assert!(writes.iter().all(|&s| receiver.write(s).is_none()));
+    }
+}
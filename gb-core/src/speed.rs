@@ -0,0 +1,70 @@
+use core::time::Duration;
+
+/// Slowest emulation speed, as a fraction of real hardware.
+pub const MIN_SPEED: f32 = 0.25;
+/// Fastest emulation speed that is still paced against real time.
+pub const MAX_SPEED: f32 = 1.0;
+/// Change of one `faster`/`slower` step.
+pub const SPEED_STEP: f32 = 0.25;
+
+/// Emulation speed relative to real hardware, for slow motion.
+///
+/// The emulated machine is unaffected. The frontend stretches the time it
+/// spends on each frame instead.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Speed(f32);
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed(MAX_SPEED)
+    }
+}
+
+impl Speed {
+    /// Creates a new `Speed`, clamped to `MIN_SPEED..=MAX_SPEED`.
+    pub fn new(factor: f32) -> Self {
+        Speed(factor.clamp(MIN_SPEED, MAX_SPEED))
+    }
+
+    pub fn factor(self) -> f32 {
+        self.0
+    }
+
+    pub fn faster(self) -> Self {
+        Speed::new(self.0 + SPEED_STEP)
+    }
+
+    pub fn slower(self) -> Self {
+        Speed::new(self.0 - SPEED_STEP)
+    }
+
+    /// Real time one frame should take at this speed, given its duration at
+    /// full speed.
+    pub fn frame_duration(self, full_speed: Duration) -> Duration {
+        let nanos = full_speed.as_nanos() as f64 / self.0 as f64;
+        Duration::from_nanos(libm::round(nanos) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_is_clamped() {
+        assert_eq!(Speed::new(0.1).factor(), MIN_SPEED);
+        assert_eq!(Speed::new(4.0).factor(), MAX_SPEED);
+        assert_eq!(Speed::default().faster(), Speed::default());
+        assert_eq!(Speed::new(0.5).slower().slower().factor(), MIN_SPEED);
+    }
+
+    #[test]
+    fn test_frame_duration() {
+        let frame = Duration::from_micros(16_000);
+        assert_eq!(Speed::default().frame_duration(frame), frame);
+        assert_eq!(
+            Speed::new(0.5).frame_duration(frame),
+            Duration::from_micros(32_000)
+        );
+    }
+}
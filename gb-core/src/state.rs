@@ -0,0 +1,244 @@
+use alloc::vec::Vec;
+use core::error;
+use core::fmt;
+use core::ops::Range;
+
+/// Magic bytes at the start of every native savestate.
+const MAGIC: &[u8; 4] = b"RGBS";
+/// Bumped whenever the layout of any component changes.
+pub const STATE_VERSION: u32 = 17;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The data ended before all fields were read.
+    UnexpectedEof,
+    /// The data is not a savestate.
+    BadMagic,
+    /// The savestate was written by an incompatible version.
+    UnsupportedVersion(u32),
+    /// The savestate belongs to another ROM.
+    RomMismatch,
+    /// A field holds a value no component can produce.
+    Corrupt,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::UnexpectedEof => write!(f, "savestate is truncated"),
+            StateError::BadMagic => write!(f, "not a savestate"),
+            StateError::UnsupportedVersion(v) => write!(f, "unsupported savestate version {}", v),
+            StateError::RomMismatch => write!(f, "savestate belongs to another ROM"),
+            StateError::Corrupt => write!(f, "savestate is corrupt"),
+        }
+    }
+}
+
+impl error::Error for StateError {}
+
+/// Serializes emulator components into a flat little-endian byte buffer.
+pub struct StateWriter {
+    buf: Vec<u8>,
+    /// Where each named section starts, see `begin_section`.
+    sections: Vec<(&'static str, usize)>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateWriter {
+    /// Creates a new `StateWriter`.
+    pub fn new() -> Self {
+        StateWriter {
+            buf: Vec::new(),
+            sections: Vec::new(),
+        }
+    }
+
+    /// Marks the start of the bytes belonging to `name`, up to the next
+    /// section. Only used to tell components apart, e.g. when diffing
+    /// states; the output is the same without it.
+    pub fn begin_section(&mut self, name: &'static str) {
+        self.sections.push((name, self.buf.len()));
+    }
+
+    /// Name and byte range of every section, in order. A name can appear
+    /// more than once.
+    pub fn sections(&self) -> Vec<(&'static str, Range<usize>)> {
+        let ends = self
+            .sections
+            .iter()
+            .skip(1)
+            .map(|&(_, start)| start)
+            .chain(Some(self.buf.len()));
+        self.sections
+            .iter()
+            .zip(ends)
+            .map(|(&(name, start), end)| (name, start..end))
+            .collect()
+    }
+
+    pub fn write_header(&mut self, rom_hash: u64) {
+        self.buf.extend_from_slice(MAGIC);
+        self.write_u32(STATE_VERSION);
+        self.write_u64(rom_hash);
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.buf.push(u8::from(value));
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a fixed-size block. The reader must know its length.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Writes a length-prefixed block.
+    pub fn write_vec(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads back data written by `StateWriter`.
+pub struct StateReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// Creates a new `StateReader`.
+    pub fn new(buf: &'a [u8]) -> Self {
+        StateReader { buf, pos: 0 }
+    }
+
+    /// Checks the magic, version and ROM hash.
+    pub fn read_header(&mut self, rom_hash: u64) -> Result<(), StateError> {
+        if self.take(4)? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = self.read_u32()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if self.read_u64()? != rom_hash {
+            return Err(StateError::RomMismatch);
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.pos.checked_add(len).ok_or(StateError::UnexpectedEof)?;
+        let bytes = self
+            .buf
+            .get(self.pos..end)
+            .ok_or(StateError::UnexpectedEof)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(i64::from_le_bytes(bytes))
+    }
+
+    /// Fills `dst` with the next `dst.len()` bytes.
+    pub fn read_bytes(&mut self, dst: &mut [u8]) -> Result<(), StateError> {
+        dst.copy_from_slice(self.take(dst.len())?);
+        Ok(())
+    }
+
+    /// Reads a length-prefixed block.
+    pub fn read_vec(&mut self) -> Result<Vec<u8>, StateError> {
+        let len = self.read_u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut w = StateWriter::new();
+        w.write_header(0x1234);
+        w.write_u8(0xab);
+        w.write_bool(true);
+        w.write_u16(0xbeef);
+        w.write_vec(&[1, 2, 3]);
+        let data = w.into_bytes();
+
+        let mut r = StateReader::new(&data);
+        r.read_header(0x1234).unwrap();
+        assert_eq!(r.read_u8(), Ok(0xab));
+        assert_eq!(r.read_bool(), Ok(true));
+        assert_eq!(r.read_u16(), Ok(0xbeef));
+        assert_eq!(r.read_vec(), Ok(vec![1, 2, 3]));
+        assert_eq!(r.read_u8(), Err(StateError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_rom_mismatch() {
+        let mut w = StateWriter::new();
+        w.write_header(0x1234);
+        let data = w.into_bytes();
+
+        let mut r = StateReader::new(&data);
+        assert_eq!(r.read_header(0x5678), Err(StateError::RomMismatch));
+    }
+}
@@ -0,0 +1,254 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+
+use crate::cpu::Cpu;
+use crate::state::StateError;
+
+/// Differing bytes closer together than this are reported as one range.
+const MERGE_GAP: usize = 16;
+
+/// Sections that hold nothing but memory, which `Difference::Memory`
+/// already describes byte by byte.
+const MEMORY_SECTIONS: [&str; 4] = ["vram", "oam", "wram", "hram"];
+
+/// Something that is not the same in two savestates.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Difference {
+    /// A CPU register, IME, HALT or IE, with its value in either state.
+    Register { name: &'static str, a: u16, b: u16 },
+    /// Bytes in `range` of a memory area differ. Addresses are bus
+    /// addresses, except for cartridge RAM which counts from 0.
+    Memory {
+        area: &'static str,
+        range: Range<usize>,
+    },
+    /// Mapper register writes that rebuild the banking of either state.
+    Mbc {
+        a: Vec<(u16, u8)>,
+        b: Vec<(u16, u8)>,
+    },
+    /// The serialized state of a component differs in `bytes` bytes. This
+    /// covers internals such as PPU timing and overlaps with the other
+    /// kinds of difference.
+    Component { name: &'static str, bytes: usize },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Register { name, a, b } => {
+                write!(f, "{:<10} 0x{:04x} -> 0x{:04x}", name, a, b)
+            }
+            Difference::Memory { area, range } => write!(
+                f,
+                "{:<10} 0x{:04x}-0x{:04x}",
+                area,
+                range.start,
+                range.end - 1
+            ),
+            Difference::Mbc { a, b } => {
+                write!(f, "{:<10} {:02x?} -> {:02x?}", "mbc", a, b)
+            }
+            Difference::Component { name, bytes } => {
+                write!(f, "{:<10} {} bytes", name, bytes)
+            }
+        }
+    }
+}
+
+/// What `diff` compares, taken from a loaded state.
+struct Snapshot {
+    registers: Vec<(&'static str, u16)>,
+    memory: Vec<(&'static str, usize, Vec<u8>)>,
+    mbc: Vec<(u16, u8)>,
+    /// Serialized bytes per section name, in order of first appearance.
+    sections: Vec<(&'static str, Vec<u8>)>,
+}
+
+impl Snapshot {
+    fn capture(cpu: &Cpu) -> Self {
+        let regs = cpu.registers();
+        let mmu = &cpu.mmu;
+        let registers = vec![
+            ("A", regs.a as u16),
+            ("F", regs.f as u16),
+            ("B", regs.b as u16),
+            ("C", regs.c as u16),
+            ("D", regs.d as u16),
+            ("E", regs.e as u16),
+            ("H", regs.h as u16),
+            ("L", regs.l as u16),
+            ("SP", regs.sp),
+            ("PC", regs.pc),
+            ("IME", cpu.ime() as u16),
+            ("HALT", cpu.halted() as u16),
+            ("IE", mmu.interrupt_enable as u16),
+        ];
+
+        let bus_range = |range: Range<u16>| range.map(|addr| mmu.read_byte(addr)).collect();
+        let memory = vec![
+            ("vram", 0x8000, mmu.ppu.vram().to_vec()),
+            ("cart ram", 0, mmu.cartridge.ram().to_vec()),
+            ("wram", 0xc000, bus_range(0xc000..0xe000)),
+            ("oam", 0xfe00, mmu.ppu.oam().to_vec()),
+            ("io", 0xff00, bus_range(0xff00..0xff80)),
+            ("hram", 0xff80, bus_range(0xff80..0xffff)),
+        ];
+
+        let w = cpu.write_state();
+        let ranges = w.sections();
+        let data = w.into_bytes();
+        let mut sections: Vec<(&'static str, Vec<u8>)> = Vec::new();
+        for (name, range) in ranges {
+            match sections.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, bytes)) => bytes.extend_from_slice(&data[range]),
+                None => sections.push((name, data[range].to_vec())),
+            }
+        }
+
+        Snapshot {
+            registers,
+            memory,
+            mbc: mmu.cartridge.mbc_writes(),
+            sections,
+        }
+    }
+}
+
+/// Groups the offsets where `a` and `b` differ into ranges.
+fn differing_ranges(a: &[u8], b: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let len = a.len().max(b.len());
+    for i in (0..len).filter(|&i| a.get(i) != b.get(i)) {
+        match ranges.last_mut() {
+            Some(last) if i - last.end < MERGE_GAP => last.end = i + 1,
+            _ => ranges.push(i..i + 1),
+        }
+    }
+    ranges
+}
+
+fn count_differing(a: &[u8], b: &[u8]) -> usize {
+    let common = a.iter().zip(b).filter(|(x, y)| x != y).count();
+    common + a.len().abs_diff(b.len())
+}
+
+fn compare(a: &Snapshot, b: &Snapshot) -> Vec<Difference> {
+    let mut diffs = Vec::new();
+
+    for (&(name, va), &(_, vb)) in a.registers.iter().zip(&b.registers) {
+        if va != vb {
+            diffs.push(Difference::Register { name, a: va, b: vb });
+        }
+    }
+
+    for ((area, base, ma), (_, _, mb)) in a.memory.iter().zip(&b.memory) {
+        for range in differing_ranges(ma, mb) {
+            diffs.push(Difference::Memory {
+                area,
+                range: base + range.start..base + range.end,
+            });
+        }
+    }
+
+    if a.mbc != b.mbc {
+        diffs.push(Difference::Mbc {
+            a: a.mbc.clone(),
+            b: b.mbc.clone(),
+        });
+    }
+
+    for (name, sa) in &a.sections {
+        if MEMORY_SECTIONS.contains(name) {
+            continue;
+        }
+        let sb = b
+            .sections
+            .iter()
+            .find(|(n, _)| n == name)
+            .map_or(&[][..], |(_, bytes)| bytes);
+        let bytes = count_differing(sa, sb);
+        if bytes > 0 {
+            diffs.push(Difference::Component { name, bytes });
+        }
+    }
+
+    diffs
+}
+
+/// Lists what differs between two native savestates of the ROM loaded in
+/// `cpu`, for tracking down nondeterminism.
+///
+/// Both states are loaded into `cpu` in turn, and its own state is restored
+/// afterwards.
+pub fn diff(cpu: &mut Cpu, a: &[u8], b: &[u8]) -> Result<Vec<Difference>, StateError> {
+    let original = cpu.save_state();
+    let mut capture = |data: &[u8]| {
+        cpu.load_state(data)?;
+        Ok(Snapshot::capture(cpu))
+    };
+    let snapshots = capture(a).and_then(|sa| Ok((sa, capture(b)?)));
+    cpu.load_state(&original)
+        .expect("state saved just now must load");
+    let (sa, sb) = snapshots?;
+    Ok(compare(&sa, &sb))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::rom_with_header;
+
+    #[test]
+    fn test_differing_ranges() {
+        let a = [0u8; 64];
+        let mut b = a;
+        b[1] = 1;
+        b[3] = 1;
+        b[40] = 1;
+        assert_eq!(differing_ranges(&a, &b), vec![1..4, 40..41]);
+        assert_eq!(differing_ranges(&a, &a), vec![]);
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut cpu = Cpu::with_rom(rom_with_header(0x03, 0x02)).unwrap();
+
+        let a = cpu.save_state();
+        cpu.mmu.write_byte(0xc010, 0x42);
+        cpu.mmu.write_byte(0x0000, 0x0a);
+        cpu.mmu.write_byte(0xa005, 0x24);
+        let mut regs = cpu.registers();
+        regs.pc = 0x1234;
+        cpu.set_registers(regs);
+        let b = cpu.save_state();
+
+        let diffs = diff(&mut cpu, &a, &b).unwrap();
+        assert!(diffs.contains(&Difference::Register {
+            name: "PC",
+            a: 0x0100,
+            b: 0x1234
+        }));
+        assert!(diffs.contains(&Difference::Memory {
+            area: "wram",
+            range: 0xc010..0xc011
+        }));
+        assert!(diffs.contains(&Difference::Memory {
+            area: "cart ram",
+            range: 0x0005..0x0006
+        }));
+        // Memory-only sections are left to `Difference::Memory`
+        assert!(!diffs
+            .iter()
+            .any(|d| matches!(d, Difference::Component { name: "wram", .. })));
+        assert!(diffs
+            .iter()
+            .any(|d| matches!(d, Difference::Component { name: "cpu", .. })));
+
+        // The machine is back where it was
+        assert_eq!(cpu.save_state(), b);
+        assert_eq!(diff(&mut cpu, &b, &b).unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,60 @@
+use core::fmt;
+
+use crate::cpu::Interrupt;
+
+/// Running totals of what the emulated machine did, for benchmarks and
+/// debugging dashboards. Counting starts at power-on and again after
+/// `Cpu::reset_stats`; resetting the game does not clear them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Instructions executed, not counting cycles spent halted.
+    pub instructions: u64,
+    /// CPU cycles emulated, including halted ones.
+    pub cycles: u64,
+    /// Interrupts dispatched, indexed as `Stats::interrupts` reads them.
+    pub interrupts: [u64; 5],
+    /// OAM DMA transfers started.
+    pub dma_transfers: u64,
+    /// Writes to the mapper registers selecting a ROM or RAM bank, see
+    /// `Cartridge::is_bank_register`.
+    pub bank_switches: u64,
+}
+
+impl Stats {
+    fn interrupt_index(interrupt: Interrupt) -> usize {
+        match interrupt {
+            Interrupt::VBlank => 0,
+            Interrupt::LCDStat => 1,
+            Interrupt::Timer => 2,
+            Interrupt::Serial => 3,
+            Interrupt::Joypad => 4,
+        }
+    }
+
+    /// Number of `interrupt` dispatched.
+    pub fn interrupts(&self, interrupt: Interrupt) -> u64 {
+        self.interrupts[Stats::interrupt_index(interrupt)]
+    }
+
+    pub(crate) fn count_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupts[Stats::interrupt_index(interrupt)] += 1;
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "instructions:  {}", self.instructions)?;
+        writeln!(f, "cycles:        {}", self.cycles)?;
+        writeln!(
+            f,
+            "interrupts:    vblank {}, stat {}, timer {}, serial {}, joypad {}",
+            self.interrupts[0],
+            self.interrupts[1],
+            self.interrupts[2],
+            self.interrupts[3],
+            self.interrupts[4]
+        )?;
+        writeln!(f, "dma transfers: {}", self.dma_transfers)?;
+        write!(f, "bank switches: {}", self.bank_switches)
+    }
+}
@@ -0,0 +1,50 @@
+use core::fmt;
+
+/// Violations kept until `Mmu::take_violations`; later ones are dropped.
+pub(crate) const MAX_PENDING: usize = 64;
+
+/// Something a game did that the emulator tolerates but real hardware
+/// would punish, reported in strict mode, see `Mmu::set_strict`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// A write to the ROM area of a cartridge without a mapper.
+    RomWrite { addr: u16, value: u8 },
+    /// OAM accessed while the PPU is scanning or drawing, PPU mode 2 or 3.
+    /// Reads see 0xff and writes are lost.
+    OamAccess { addr: u16, write: bool, mode: u8 },
+    /// VRAM accessed while the PPU is drawing. Reads see 0xff and writes
+    /// are lost.
+    VramAccess { addr: u16, write: bool },
+    /// OAM DMA started from 0xe000 or above, which copies from WRAM 0x2000
+    /// lower down.
+    DmaSource(u8),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = |write: bool| if write { "write to" } else { "read from" };
+        match *self {
+            Violation::RomWrite { addr, value } => write!(
+                f,
+                "write of 0x{:02x} to ROM at 0x{:04x} without a mapper",
+                value, addr
+            ),
+            Violation::OamAccess { addr, write, mode } => write!(
+                f,
+                "{} OAM at 0x{:04x} in PPU mode {}",
+                access(write),
+                addr,
+                mode
+            ),
+            Violation::VramAccess { addr, write } => write!(
+                f,
+                "{} VRAM at 0x{:04x} while the PPU is drawing",
+                access(write),
+                addr
+            ),
+            Violation::DmaSource(source) => {
+                write!(f, "OAM DMA from 0x{:02x}00, which is not WRAM", source)
+            }
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use log::debug;
+
+use crate::state::{StateError, StateReader, StateWriter};
+
+const LOG_TARGET: &str = "gb::timer";
+
+// pub struct Timer {
+//     div_counter: u16,
+//     tima: u8,
+//     tima_total_count: u16,
+//     tma: u8,
+//     tac: u8,
+//     irq_timer: bool,
+// }
+
+// impl Timer {
+//     pub fn new() -> Self {
+//         Timer {
+//             div_counter: 0,
+//             tima: 0,
+//             tima_total_count: 0,
+//             tma: 0,
+//             tac: 0,
+//             irq_timer: false,
+//         }
+//     }
+
+//     pub fn is_irq_timer(&self) -> bool {
+//         self.irq_timer
+//     }
+
+//     pub fn set_irq_timer(&mut self, flag: bool) {
+//         self.irq_timer = flag;
+//     }
+
+//     pub fn read(&self, addr: u16) -> u8 {
+//         match addr {
+//             0xff04 => (self.div_counter >> 8) as u8,
+//             0xff05 => self.tima,
+//             0xff06 => self.tma,
+//             0xff07 => self.tac,
+//             _ => panic!("Invalid address: 0x{:04x}", addr),
+//         }
+//     }
+
+//     pub fn write(&mut self, addr: u16, value: u8) {
+//         match addr {
+//             0xff04 => self.div_counter = 0,
+//             0xff05 => self.tima = value,
+//             0xff06 => self.tma = value,
+//             0xff07 => self.tac = value & 7,
+//             _ => panic!("Invalid address: 0x{:04x}", addr),
+//         }
+//     }
+
+//     pub fn update(&mut self, clock: u8) {
+//         self.div_counter = self.div_counter.wrapping_add(clock as u16);
+
+//         if self.tac & 4 > 0 {
+//             self.tima_total_count = self.tima_total_count.wrapping_add(clock as u16);
+//             let divider = match self.tac & 3 {
+//                 0 => 1024,
+//                 1 => 16,
+//                 2 => 64,
+//                 3 => 256,
+//                 _ => panic!("Invalid tac: {}", self.tac & 3),
+//             };
+
+//             if self.tima_total_count >= divider {
+//                 self.tima_total_count -= divider;
+//                 let (res, overflow_flag) = self.tima.overflowing_add(1);
+
+//                 if overflow_flag {
+//                     self.tima = self.tma;
+//                     self.irq_timer = true;
+//                 } else {
+//                     self.tima = res;
+//                 }
+//             }
+//         }
+//     }
+// }
+
+/// The system counter at some cycle, for what it clocks besides the
+/// timer. Cycles here are those of the PPU and APU, which keep their pace
+/// in CGB double speed while the counter, clocked by the CPU, counts two
+/// per cycle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SystemCounter {
+    pub value: u16,
+    pub double_speed: bool,
+}
+
+impl SystemCounter {
+    /// Counts per cycle.
+    pub fn rate(self) -> u64 {
+        if self.double_speed {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// The counter `cycles` later.
+    pub fn after(self, cycles: u64) -> Self {
+        SystemCounter {
+            value: self.value.wrapping_add((cycles * self.rate()) as u16),
+            ..self
+        }
+    }
+
+    /// The counter `cycles` earlier.
+    pub fn before(self, cycles: u64) -> Self {
+        SystemCounter {
+            value: self.value.wrapping_sub((cycles * self.rate()) as u16),
+            ..self
+        }
+    }
+
+    /// Cycles until `bit` falls next, a full period of it if it just did.
+    pub fn cycles_until_fall(self, bit: u16) -> u32 {
+        let period = (bit as u32) << 1;
+        let counts = period - (self.value as u32 & (period - 1));
+        counts.div_ceil(self.rate() as u32)
+    }
+}
+
+#[derive(Clone)]
+pub struct Timer {
+    /// Timer counter
+    tima: u8,
+    /// Timer modulo
+    tma: u8,
+    /// Timer control
+    tac: u8,
+    /// The 16-bit system counter, counting every cycle. DIV is its upper
+    /// byte and TIMA counts falling edges of the bit TAC selects.
+    counter: u16,
+    /// Interrupt request
+    pub irq_timer: bool,
+}
+
+impl Timer {
+    /// Creates a new `Timer`.
+    pub fn new() -> Self {
+        Timer {
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            counter: 0,
+            irq_timer: false,
+        }
+    }
+}
+
+impl Timer {
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.tima);
+        w.write_u8(self.tma);
+        w.write_u8(self.tac);
+        w.write_u16(self.counter);
+        w.write_bool(self.irq_timer);
+    }
+
+    pub(crate) fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.tima = r.read_u8()?;
+        self.tma = r.read_u8()?;
+        self.tac = r.read_u8()?;
+        self.counter = r.read_u16()?;
+        self.irq_timer = r.read_bool()?;
+        Ok(())
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // DIV
+            0xff04 => self.reset_counter(),
+            // TIMA
+            0xff05 => self.tima = val,
+            // TMA
+            0xff06 => self.tma = val,
+            // TAC
+            0xff07 => {
+                // Turning the timer off or selecting another bit can make
+                // the input fall, which counts like any other falling edge
+                let input = self.input();
+                self.tac = val & 0x7;
+                if input && !self.input() {
+                    self.count(1);
+                }
+            }
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // DIV
+            0xff04 => (self.counter >> 8) as u8,
+            // TIMA
+            0xff05 => self.tima,
+            // TMA
+            0xff06 => self.tma,
+            // TAC
+            0xff07 => self.tac,
+            _ => unreachable!("Unexpected address: 0x{:04x}", addr),
+        }
+    }
+
+    /// Current value of the system counter.
+    pub(crate) fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    /// Clears the system counter, as writing DIV and STOP do. TIMA counts
+    /// once if the selected bit was set, since clearing it is a falling edge.
+    pub(crate) fn reset_counter(&mut self) {
+        let input = self.input();
+        self.counter = 0;
+        if input {
+            self.count(1);
+        }
+    }
+
+    /// What TIMA counts the falling edges of: the bit of the system counter
+    /// TAC selects, the one below `divider_bit`, ANDed with the enable bit.
+    /// Writes to DIV and TAC change it as well as the counter running.
+    fn input(&self) -> bool {
+        self.tac & 4 != 0 && self.counter & (1 << (self.divider_bit() - 1)) != 0
+    }
+
+    /// Adds `ticks` to TIMA, reloading it from TMA and requesting the
+    /// interrupt on overflow.
+    fn count(&mut self, ticks: u8) {
+        let (res, overflow) = self.tima.overflowing_add(ticks);
+        if overflow {
+            // Reload from TMA, then count the ticks after the overflow
+            self.tima = self.tma.wrapping_add(res);
+            self.irq_timer = true;
+        } else {
+            self.tima = res;
+        }
+    }
+
+    pub fn is_irq_timer(&self) -> bool {
+        self.irq_timer
+    }
+
+    pub fn set_irq_timer(&mut self, flag: bool) {
+        self.irq_timer = flag;
+    }
+
+    /// Cycles until TIMA overflows, or `None` while the timer is stopped.
+    pub(crate) fn cycles_until_overflow(&self) -> Option<u32> {
+        if self.tac & 4 == 0 {
+            return None;
+        }
+        let period = 1u32 << self.divider_bit();
+        let to_next_tick = period - (self.counter as u32 & (period - 1));
+        Some(to_next_tick + (0xff - self.tima as u32) * period)
+    }
+
+    fn divider_bit(&self) -> u16 {
+        match self.tac & 3 {
+            0 => 10,
+            1 => 4,
+            2 => 6,
+            _ => 8,
+        }
+    }
+
+    /// Runs the timer for `cycles`, which may be more than `update` can
+    /// handle at once. Expects at most one TIMA overflow on the way.
+    pub fn advance(&mut self, mut cycles: u64) {
+        if self.tac & 4 == 0 {
+            self.counter = self.counter.wrapping_add(cycles as u16);
+            return;
+        }
+        // `update` handles fewer than 256 ticks per call
+        let max_chunk = (0x80 << self.divider_bit()).min(0x8000);
+        while cycles > 0 {
+            let chunk = cycles.min(max_chunk);
+            self.update(chunk as u16);
+            cycles -= chunk;
+        }
+    }
+
+    pub fn update(&mut self, tick: u16) {
+        debug!(
+            target: LOG_TARGET,
+            "div: {}, tima: {}, tma: {}, tac: {}, irq_timer: {}",
+            self.counter, self.tima, self.tma, self.tac, self.irq_timer
+        );
+        let counter_prev = self.counter;
+
+        self.counter = self.counter.wrapping_add(tick);
+
+        if self.tac & 4 > 0 {
+            let divider = self.divider_bit();
+
+            let x = self.counter >> divider;
+            let y = counter_prev >> divider;
+            let mask = (1 << (16 - divider)) - 1;
+            let diff = x.wrapping_sub(y) & mask;
+
+            if diff > 0 {
+                self.count(diff as u8);
+            }
+        }
+        debug!(
+            target: LOG_TARGET,
+            "div: {}, tima: {}, tma: {}, tac: {}, irq_timer: {}",
+            self.counter, self.tima, self.tma, self.tac, self.irq_timer
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_glitches() {
+        let mut timer = Timer::new();
+        // Enabled at 262144 Hz, counting bit 3 of the counter
+        timer.write(0xff07, 0x05);
+        timer.update(0x08);
+        assert_eq!(timer.read(0xff05), 0);
+
+        // Resetting DIV with bit 3 set is a falling edge
+        timer.reset_counter();
+        assert_eq!(timer.read(0xff05), 1);
+        // But not with it clear
+        timer.reset_counter();
+        assert_eq!(timer.read(0xff05), 1);
+
+        // Disabling the timer with the bit set is one too
+        timer.update(0x08);
+        timer.write(0xff07, 0x01);
+        assert_eq!(timer.read(0xff05), 2);
+        // Enabling it is a rising edge, which does not count
+        timer.write(0xff07, 0x05);
+        assert_eq!(timer.read(0xff05), 2);
+
+        // Switching from bit 3, set, to bit 5, clear
+        timer.write(0xff07, 0x06);
+        assert_eq!(timer.read(0xff05), 3);
+        // And back to the set bit 3
+        timer.write(0xff07, 0x05);
+        assert_eq!(timer.read(0xff05), 3);
+
+        // Overflows reload TMA and request the interrupt
+        timer.write(0xff05, 0xff);
+        timer.write(0xff06, 0x80);
+        timer.reset_counter();
+        assert_eq!(timer.read(0xff05), 0x80);
+        assert!(timer.is_irq_timer());
+    }
+}
@@ -0,0 +1,23 @@
+pub fn get_addr_from_registers(high_register: u8, low_register: u8) -> u16 {
+    ((high_register as u16) << 8) + low_register as u16
+}
+
+/// 64-bit FNV-1a hash. Stable across platforms and releases.
+pub fn fnv1a_64(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a_64() {
+        // Reference values of the FNV-1a specification
+        assert_eq!(fnv1a_64(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a_64(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a_64(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::apu::{ApuWrite, CPU_CLOCK_HZ};
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::BufWriter;
+use std::io::{self, Seek, SeekFrom, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// Header size; the commands start right after it.
+const HEADER_LEN: u32 = 0x100;
+/// VGM 1.61, the first version with the Game Boy chip.
+const VERSION: u32 = 0x161;
+/// Waits are counted in samples at this rate, whatever the player uses.
+const VGM_RATE: u64 = 44100;
+const MAX_WAIT: u64 = 0xffff;
+
+const CMD_WAIT: u8 = 0x61;
+/// 0x70..=0x7f wait 1 to 16 samples.
+const CMD_SHORT_WAIT: u8 = 0x70;
+/// Followed by the register (0xff10 + n) and the value.
+const CMD_DMG_WRITE: u8 = 0xb3;
+const CMD_END: u8 = 0x66;
+
+/// Writes APU register writes, as `Mmu::take_apu_writes` returns them, to
+/// a .vgm file that chip music players and trackers can play back.
+///
+/// The length in the header is filled in by `finish`.
+pub struct VgmWriter<W: Write + Seek> {
+    writer: W,
+    /// Command bytes written so far.
+    data_len: u32,
+    /// Samples waited so far.
+    samples: u64,
+}
+
+#[cfg(feature = "std")]
+impl VgmWriter<BufWriter<File>> {
+    /// Creates the file at `path` and writes the header to it.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        VgmWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> VgmWriter<W> {
+    /// Creates a new `VgmWriter`.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        let mut header = [0; HEADER_LEN as usize];
+        header[0x00..0x04].copy_from_slice(b"Vgm ");
+        header[0x04..0x08].copy_from_slice(&(HEADER_LEN - 4).to_le_bytes());
+        header[0x08..0x0c].copy_from_slice(&VERSION.to_le_bytes());
+        header[0x34..0x38].copy_from_slice(&(HEADER_LEN - 0x34).to_le_bytes());
+        header[0x80..0x84].copy_from_slice(&CPU_CLOCK_HZ.to_le_bytes());
+        writer.write_all(&header)?;
+        Ok(VgmWriter {
+            writer,
+            data_len: 0,
+            samples: 0,
+        })
+    }
+
+    /// Appends `writes`, waiting between them as the game did. Writes
+    /// outside the APU registers are skipped.
+    pub fn write(&mut self, writes: &[ApuWrite]) -> io::Result<()> {
+        for write in writes {
+            let register = match write.addr {
+                0xff10..=0xff3f => (write.addr - 0xff10) as u8,
+                _ => continue,
+            };
+            self.wait_until(write.cycles)?;
+            self.emit(&[CMD_DMG_WRITE, register, write.value])?;
+        }
+        Ok(())
+    }
+
+    /// Seconds of music written so far.
+    pub fn duration(&self) -> f64 {
+        self.samples as f64 / VGM_RATE as f64
+    }
+
+    /// Waits until `cycles` after the start of the log, ends the commands
+    /// and fills in the header. Returns the writer.
+    pub fn finish(mut self, cycles: u64) -> io::Result<W> {
+        self.wait_until(cycles)?;
+        self.emit(&[CMD_END])?;
+        self.writer.seek(SeekFrom::Start(0x04))?;
+        self.writer
+            .write_all(&(HEADER_LEN - 4 + self.data_len).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(0x18))?;
+        self.writer
+            .write_all(&(self.samples.min(u32::MAX as u64) as u32).to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn wait_until(&mut self, cycles: u64) -> io::Result<()> {
+        let target = cycles * VGM_RATE / CPU_CLOCK_HZ as u64;
+        while self.samples < target {
+            let n = (target - self.samples).min(MAX_WAIT);
+            if n <= 16 {
+                self.emit(&[CMD_SHORT_WAIT + (n - 1) as u8])?;
+            } else {
+                let [low, high] = (n as u16).to_le_bytes();
+                self.emit(&[CMD_WAIT, low, high])?;
+            }
+            self.samples += n;
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.data_len = self.data_len.saturating_add(bytes.len() as u32);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::TryInto;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_vgm_file() {
+        let mut vgm = VgmWriter::new(Cursor::new(Vec::new())).unwrap();
+        let write = |cycles, addr, value| ApuWrite {
+            cycles,
+            addr,
+            value,
+        };
+        vgm.write(&[
+            write(0, 0xff26, 0x80),
+            // 10 samples later
+            write(952, 0xff12, 0xf0),
+            // 1000 samples later
+            write(96061, 0xff30, 0x12),
+        ])
+        .unwrap();
+        let data = vgm.finish(CPU_CLOCK_HZ as u64).unwrap().into_inner();
+
+        let word = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        assert_eq!(&data[0..4], b"Vgm ");
+        assert_eq!(word(0x04) as usize, data.len() - 4);
+        assert_eq!(word(0x18), 44100);
+        assert_eq!(word(0x34) + 0x34, 0x100);
+        assert_eq!(word(0x80), CPU_CLOCK_HZ);
+        assert_eq!(
+            &data[0x100..],
+            &[
+                0xb3, 0x16, 0x80, // NR52
+                0x79, 0xb3, 0x02, 0xf0, // 10 samples, NR12
+                0x61, 0xe8, 0x03, 0xb3, 0x20, 0x12, // 1000 samples, wave RAM
+                0x61, 0x52, 0xa8, 0x66, // the rest of the second
+            ][..]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::BufWriter;
+use std::io::{self, Seek, SeekFrom, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// Bytes in front of the samples.
+const HEADER_LEN: u32 = 44;
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// Writes interleaved stereo samples, as `Apu::take_samples` returns them,
+/// to a 16-bit PCM .wav file.
+///
+/// The sizes in the header are filled in by `finish`. A file that is not
+/// finished still holds the samples, but players may not see them all.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    /// Bytes of samples written so far.
+    data_len: u32,
+}
+
+#[cfg(feature = "std")]
+impl WavWriter<BufWriter<File>> {
+    /// Creates the file at `path` and writes the header to it.
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Creates a new `WavWriter` for samples at `sample_rate` Hz.
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+        let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(HEADER_LEN - 8).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&CHANNELS.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        writer.write_all(&header)?;
+        Ok(WavWriter {
+            writer,
+            data_len: 0,
+        })
+    }
+
+    /// Appends interleaved left/right samples.
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.writer.write_all(&bytes)?;
+        self.data_len = self.data_len.saturating_add(bytes.len() as u32);
+        Ok(())
+    }
+
+    /// Seconds of audio written so far at `sample_rate`.
+    pub fn duration(&self, sample_rate: u32) -> f64 {
+        let frames = self.data_len / (CHANNELS * BITS_PER_SAMPLE / 8) as u32;
+        frames as f64 / sample_rate.max(1) as f64
+    }
+
+    /// Fills in the sizes in the header and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(HEADER_LEN as u64 - 4))?;
+        self.writer.write_all(&self.data_len.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::TryInto;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wav_file() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 48000).unwrap();
+        wav.write_samples(&[1, -1, 0x1234, 0]).unwrap();
+        assert_eq!(wav.duration(48000), 2.0 / 48000.0);
+        let data = wav.finish().unwrap().into_inner();
+
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), 44);
+        assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()), 48000);
+        assert_eq!(u32::from_le_bytes(data[28..32].try_into().unwrap()), 192000);
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 8);
+        assert_eq!(&data[44..], &[1, 0, 0xff, 0xff, 0x34, 0x12, 0, 0]);
+    }
+}
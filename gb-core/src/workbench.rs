@@ -0,0 +1,201 @@
+//! Runs assembled SM83 snippets on their own, to study how instructions
+//! behave or to test them without building a ROM. Plain RAM stands in for
+//! the cartridge and everything else, so there are no peripherals and no
+//! interrupts.
+
+use alloc::vec::Vec;
+
+use crate::bus::{Bus, FlatBus};
+use crate::cpu::{Cpu, Registers};
+
+/// Return address pushed before the snippet runs. Reaching it means the
+/// snippet returned from its outermost level.
+pub const RETURN_ADDR: u16 = 0xfff0;
+
+/// Why `Workbench::run` stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The snippet returned to its caller.
+    Returned,
+    /// The PC reached a breakpoint.
+    Breakpoint(u16),
+    /// HALT with IME set, which no interrupt would wake up from.
+    Halted,
+    /// An illegal opcode hung the CPU.
+    Locked,
+    /// The step limit ran out first, e.g. in an endless loop.
+    StepLimit,
+}
+
+/// The state `Workbench::run` left the CPU in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunResult {
+    pub reason: StopReason,
+    pub registers: Registers,
+    pub ime: bool,
+    /// Instructions run.
+    pub steps: u64,
+    /// Clock cycles (T-cycles) taken.
+    pub cycles: u64,
+}
+
+/// A CPU on 64 KiB of zeroed RAM that code can be loaded into anywhere.
+pub struct Workbench {
+    cpu: Cpu<FlatBus>,
+    breakpoints: Vec<u16>,
+}
+
+impl Workbench {
+    /// Creates a new `Workbench` with zeroed registers and RAM, and SP at
+    /// 0xfffe.
+    pub fn new() -> Self {
+        let mut cpu = Cpu::with_flat_ram();
+        cpu.set_registers(Registers {
+            sp: 0xfffe,
+            ..Registers::default()
+        });
+        Workbench {
+            cpu,
+            breakpoints: Vec::new(),
+        }
+    }
+
+    /// Copies `code` to `addr`, wrapping at the end of the address space.
+    pub fn load(&mut self, addr: u16, code: &[u8]) {
+        for (i, &byte) in code.iter().enumerate() {
+            self.cpu.mmu.write_byte(addr.wrapping_add(i as u16), byte);
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        self.cpu.mmu.read_byte(addr)
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        self.cpu.mmu.write_byte(addr, value);
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.cpu.registers()
+    }
+
+    /// Sets the registers the snippet starts with. `run` replaces the PC
+    /// and pushes onto the stack at SP.
+    pub fn set_registers(&mut self, regs: Registers) {
+        self.cpu.set_registers(regs);
+    }
+
+    pub fn set_ime(&mut self, ime: bool) {
+        self.cpu.set_ime(ime);
+    }
+
+    /// Stops `run` before the instruction at `addr`.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.push(addr);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Calls the code at `start` as a subroutine, running at most
+    /// `max_steps` instructions until it returns or stops otherwise. A CPU
+    /// halted by an earlier run wakes up, one locked up stays locked.
+    pub fn run(&mut self, start: u16, max_steps: u64) -> RunResult {
+        self.cpu.set_halted(false);
+        let mut regs = self.cpu.registers();
+        regs.sp = regs.sp.wrapping_sub(2);
+        let [low, high] = RETURN_ADDR.to_le_bytes();
+        self.cpu.mmu.write_byte(regs.sp, low);
+        self.cpu.mmu.write_byte(regs.sp.wrapping_add(1), high);
+        regs.pc = start;
+        self.cpu.set_registers(regs);
+        self.resume(max_steps)
+    }
+
+    /// Goes on from where the last `run` stopped, e.g. past a breakpoint.
+    pub fn resume(&mut self, max_steps: u64) -> RunResult {
+        let mut steps = 0;
+        let mut cycles = 0;
+        let reason = loop {
+            if steps == max_steps {
+                break StopReason::StepLimit;
+            }
+            cycles += self.cpu.step() as u64;
+            steps += 1;
+            let pc = self.cpu.registers().pc;
+            if pc == RETURN_ADDR {
+                break StopReason::Returned;
+            }
+            if self.cpu.is_locked() {
+                break StopReason::Locked;
+            }
+            if self.cpu.halted() {
+                break StopReason::Halted;
+            }
+            if self.breakpoints.contains(&pc) {
+                break StopReason::Breakpoint(pc);
+            }
+        };
+        RunResult {
+            reason,
+            registers: self.cpu.registers(),
+            ime: self.cpu.ime(),
+            steps,
+            cycles,
+        }
+    }
+}
+
+impl Default for Workbench {
+    fn default() -> Self {
+        Workbench::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let mut bench = Workbench::new();
+        bench.load(
+            0xc000,
+            &[
+                0x3e, 0x00, // ld a, 0
+                0x06, 0x05, // ld b, 5
+                0x80, // loop: add a, b
+                0x05, // dec b
+                0x20, 0xfc, // jr nz, loop
+                0xcd, 0x00, 0xc1, // call double
+                0xc9, // ret
+            ],
+        );
+        bench.load(0xc100, &[0x87, 0xc9]); // double: add a, a / ret
+
+        let result = bench.run(0xc000, 1000);
+        assert_eq!(result.reason, StopReason::Returned);
+        assert_eq!(result.registers.a, 30);
+        assert_eq!(result.registers.sp, 0xfffe);
+        assert_eq!(result.steps, 2 + 5 * 3 + 4);
+        // The taken jumps cost 12 cycles, the last one 8
+        assert_eq!(result.cycles, 8 + 8 + 5 * 8 + 4 * 12 + 8 + 24 + 4 + 16 + 16);
+
+        bench.add_breakpoint(0xc100);
+        let result = bench.run(0xc000, 1000);
+        assert_eq!(result.reason, StopReason::Breakpoint(0xc100));
+        assert_eq!(result.registers.a, 15);
+        bench.clear_breakpoints();
+        assert_eq!(bench.resume(1000).reason, StopReason::Returned);
+
+        bench.load(0xd000, &[0x18, 0xfe]); // jr -2
+        assert_eq!(bench.run(0xd000, 100).reason, StopReason::StepLimit);
+        bench.load(0xd000, &[0x76]);
+        bench.set_ime(true);
+        assert_eq!(bench.run(0xd000, 100).reason, StopReason::Halted);
+        bench.set_ime(false);
+        bench.load(0xd000, &[0xd3]);
+        assert_eq!(bench.run(0xd000, 100).reason, StopReason::Locked);
+    }
+}
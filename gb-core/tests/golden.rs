@@ -0,0 +1,180 @@
+//! Golden-frame regression suite.
+//!
+//! Test ROMs and their reference images are read from `tests/roms`, or from
+//! the directory `RUST_GB_TEST_ROMS` points at instead. References are
+//! binary PGM files (e.g. `convert reference-dmg.png dmg-acid2.pgm`). Tests
+//! whose files are missing are skipped; `tests/roms/README` lists what
+//! belongs there.
+//!
+//! With `RUST_GB_DUMP_DIR` set, every frame of each ROM is also written as
+//! PNG to `$RUST_GB_DUMP_DIR/<rom>/`, to diff against other emulators frame
+//! by frame when a test fails.
+//!
+//! A failing test writes `target/test-output/<rom name>-diff.png` with the
+//! expected frame, the actual one and the pixels that differ side by side,
+//! for CI to keep as an artifact.
+//!
+//! Loading ROMs by path and dumping frames need the `std` feature.
+#![cfg(feature = "std")]
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rust_gb::cpu::Cpu;
+use rust_gb::frame_dump::{self, FrameDumper, FrameFormat};
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+const CYCLES_PER_FRAME: u64 = 456 * 154;
+
+struct GoldenTest {
+    rom: &'static str,
+    reference: &'static str,
+    frames: u64,
+}
+
+// cgb-acid2 needs CGB rendering, which the core does not emulate yet. The
+// mid-scanline ROMs come from `gb-testrom`, their references from other
+// emulators or hardware.
+const GOLDEN_TESTS: &[GoldenTest] = &[
+    GoldenTest {
+        rom: "dmg-acid2.gb",
+        reference: "dmg-acid2.pgm",
+        frames: 60,
+    },
+    GoldenTest {
+        rom: "scx-midline.gb",
+        reference: "scx-midline.pgm",
+        frames: 10,
+    },
+    GoldenTest {
+        rom: "palette-swap.gb",
+        reference: "palette-swap.pgm",
+        frames: 10,
+    },
+    GoldenTest {
+        rom: "window-toggle.gb",
+        reference: "window-toggle.pgm",
+        frames: 10,
+    },
+];
+
+/// Parses a binary 160x144 PGM image.
+fn read_pgm(data: &[u8]) -> Option<Vec<u8>> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while fields.len() < 4 {
+        while data.get(pos)?.is_ascii_whitespace() {
+            pos += 1;
+        }
+        if data[pos] == b'#' {
+            while *data.get(pos)? != b'\n' {
+                pos += 1;
+            }
+            continue;
+        }
+        let start = pos;
+        while !data.get(pos)?.is_ascii_whitespace() {
+            pos += 1;
+        }
+        fields.push(std::str::from_utf8(&data[start..pos]).ok()?);
+    }
+    if fields != ["P5", "160", "144", "255"] {
+        return None;
+    }
+    let pixels = data.get(pos + 1..pos + 1 + WIDTH * HEIGHT)?;
+    Some(pixels.to_vec())
+}
+
+fn run_frames(rom: &Path, frames: u64) -> Vec<u8> {
+    let mut cpu = Cpu::new(&rom.to_string_lossy());
+    let mut dumper = env::var_os("RUST_GB_DUMP_DIR").map(|dir| {
+        let dir = Path::new(&dir).join(rom.file_name().unwrap());
+        FrameDumper::create(dir, FrameFormat::Png).unwrap()
+    });
+    let mut cycles: u64 = 0;
+    for frame in 1..=frames {
+        while cycles < CYCLES_PER_FRAME * frame {
+            cycles += cpu.step() as u64;
+        }
+        if let Some(dumper) = &mut dumper {
+            dumper.write(cpu.mmu.ppu.get_frame()).unwrap();
+        }
+    }
+    // Reference images are grayscale, the default DMG shades are too
+    cpu.mmu.ppu.get_frame().iter().step_by(3).copied().collect()
+}
+
+/// Writes the grayscale frames `expected` and `actual` and their XOR next
+/// to each other to `target/test-output/<rom>-diff.png`. Pixels that differ
+/// are red in the XOR panel, brighter the more bits differ; the rest are
+/// black.
+fn write_diff(rom: &str, expected: &[u8], actual: &[u8]) -> io::Result<PathBuf> {
+    let mut rgb = Vec::with_capacity(3 * WIDTH * HEIGHT * 3);
+    for y in 0..HEIGHT {
+        let row = y * WIDTH..(y + 1) * WIDTH;
+        for &shade in expected[row.clone()].iter().chain(&actual[row.clone()]) {
+            rgb.extend_from_slice(&[shade; 3]);
+        }
+        for (e, a) in expected[row.clone()].iter().zip(&actual[row]) {
+            let red = match e ^ a {
+                0 => 0,
+                xor => 0x80 | xor,
+            };
+            rgb.extend_from_slice(&[red, 0, 0]);
+        }
+    }
+    let png = frame_dump::encode_png(&rgb, 3 * WIDTH, HEIGHT)?;
+
+    // Integration tests get <target>/tmp, which follows CARGO_TARGET_DIR
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .parent()
+        .unwrap()
+        .join("test-output");
+    fs::create_dir_all(&dir)?;
+    let stem = Path::new(rom).file_stem().unwrap().to_string_lossy();
+    let path = dir.join(format!("{}-diff.png", stem));
+    fs::write(&path, png)?;
+    Ok(path)
+}
+
+#[test]
+fn golden_frames() {
+    let dir = env::var_os("RUST_GB_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms"));
+
+    let mut failures = Vec::new();
+    for test in GOLDEN_TESTS {
+        let rom = Path::new(&dir).join(test.rom);
+        let reference = Path::new(&dir).join(test.reference);
+        if !rom.exists() || !reference.exists() {
+            eprintln!("skipping {}: ROM or reference missing", test.rom);
+            continue;
+        }
+
+        let expected = read_pgm(&fs::read(&reference).unwrap())
+            .unwrap_or_else(|| panic!("{:?} is not a 160x144 binary PGM", reference));
+        let actual = run_frames(&rom, test.frames);
+
+        let mismatches = expected
+            .iter()
+            .zip(actual.iter())
+            .filter(|(e, a)| e != a)
+            .count();
+        if mismatches > 0 {
+            let diff = match write_diff(test.rom, &expected, &actual) {
+                Ok(path) => format!("see {}", path.display()),
+                Err(e) => format!("cannot write the diff: {}", e),
+            };
+            failures.push(format!(
+                "{}: {} pixels differ, {}",
+                test.rom, mismatches, diff
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,141 @@
+//! Per-instruction tests against the SM83 JSON test vectors
+//! (<https://github.com/SingleStepTests/sm83>).
+//!
+//! The vectors are not part of the repository. Point `SM83_TEST_DATA` at the
+//! directory holding `00.json`, `cb 00.json`, ... and run:
+//!
+//!     SM83_TEST_DATA=path/to/v1 cargo test --features sm83-tests --test sm83
+//!
+//! Each vector sets the registers and RAM, executes one instruction on a flat
+//! 64 KiB RAM and compares the final state and the cycle count.
+#![cfg(feature = "sm83-tests")]
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use rust_gb::bus::Bus;
+use rust_gb::cpu::{Cpu, Registers};
+use serde_json::Value;
+
+/// Failures printed per file before the rest are only counted.
+const MAX_REPORTED_PER_FILE: usize = 3;
+
+fn field(state: &Value, name: &str) -> u16 {
+    state[name]
+        .as_u64()
+        .unwrap_or_else(|| panic!("missing field {}", name)) as u16
+}
+
+fn registers(state: &Value) -> Registers {
+    Registers {
+        a: field(state, "a") as u8,
+        f: field(state, "f") as u8,
+        b: field(state, "b") as u8,
+        c: field(state, "c") as u8,
+        d: field(state, "d") as u8,
+        e: field(state, "e") as u8,
+        h: field(state, "h") as u8,
+        l: field(state, "l") as u8,
+        sp: field(state, "sp"),
+        pc: field(state, "pc"),
+    }
+}
+
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    state["ram"]
+        .as_array()
+        .map(|entries| {
+            entries
+                .iter()
+                .map(|e| (e[0].as_u64().unwrap() as u16, e[1].as_u64().unwrap() as u8))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Runs one vector and describes the first mismatch, if any.
+fn run_vector(test: &Value) -> Result<(), String> {
+    let initial = &test["initial"];
+    let expected = &test["final"];
+
+    let mut cpu = Cpu::with_flat_ram();
+    cpu.set_registers(registers(initial));
+    cpu.set_ime(field(initial, "ime") != 0);
+    for (addr, value) in ram(initial) {
+        cpu.mmu.write_byte(addr, value);
+    }
+
+    let cycles = cpu.step() as usize;
+
+    let regs = cpu.registers();
+    if regs != registers(expected) {
+        return Err(format!(
+            "registers {:02x?}, expected {:02x?}",
+            regs,
+            registers(expected)
+        ));
+    }
+    if cpu.ime() != (field(expected, "ime") != 0) {
+        return Err(format!("ime {}, expected {}", cpu.ime(), !cpu.ime()));
+    }
+    for (addr, value) in ram(expected) {
+        let actual = cpu.mmu.read_byte(addr);
+        if actual != value {
+            return Err(format!(
+                "0x{:04x} = 0x{:02x}, expected 0x{:02x}",
+                addr, actual, value
+            ));
+        }
+    }
+    let expected_cycles = test["cycles"].as_array().map_or(0, |c| c.len()) * 4;
+    if cycles != expected_cycles {
+        return Err(format!("{} cycles, expected {}", cycles, expected_cycles));
+    }
+    Ok(())
+}
+
+#[test]
+fn sm83_vectors() {
+    let dir = match env::var("SM83_TEST_DATA") {
+        Ok(dir) => dir,
+        Err(_) => {
+            eprintln!("SM83_TEST_DATA is not set, skipping SM83 test vectors");
+            return;
+        }
+    };
+
+    let mut files: Vec<_> = fs::read_dir(Path::new(&dir))
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", dir, e))
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+
+    let mut failed_files = Vec::new();
+    for path in &files {
+        let tests: Value = serde_json::from_slice(&fs::read(path).unwrap())
+            .unwrap_or_else(|e| panic!("{:?} is not valid JSON: {}", path, e));
+        let tests = tests.as_array().expect("a file holds an array of tests");
+
+        let mut failures = 0;
+        for test in tests {
+            if let Err(e) = run_vector(test) {
+                if failures < MAX_REPORTED_PER_FILE {
+                    eprintln!("{}: {}", test["name"], e);
+                }
+                failures += 1;
+            }
+        }
+        if failures > 0 {
+            failed_files.push(format!(
+                "{}: {}/{} failed",
+                path.file_name().unwrap().to_string_lossy(),
+                failures,
+                tests.len()
+            ));
+        }
+    }
+
+    assert!(failed_files.is_empty(), "{}", failed_files.join("\n"));
+}
@@ -0,0 +1,625 @@
+//! A second window with egui panels for looking into the machine while the
+//! game runs: registers, disassembly, memory, tiles, OAM, palettes and the
+//! event log. Panels are egui windows, moved and resized inside it and
+//! opened or closed from the bar at the top.
+
+use std::mem;
+use std::time;
+
+use egui::{Color32, Pos2, RichText, ScrollArea, TextureHandle, TextureOptions};
+use gb_tools::disasm;
+use rust_gb::cpu::Cpu;
+use rust_gb::ppu::Rgb;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::{MouseButton, MouseWheelDirection};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::WindowCanvas;
+use sdl2::VideoSubsystem;
+
+use crate::painter::Painter;
+use crate::parse_address;
+
+const TITLE: &str = "rust-gb debug";
+const WIDTH: u32 = 1024;
+const HEIGHT: u32 = 768;
+/// Least time between redraws without input, as the software painter is
+/// slow next to the game.
+const REDRAW_INTERVAL: time::Duration = time::Duration::from_millis(33);
+/// Points scrolled for each step of the mouse wheel.
+const SCROLL_POINTS: f32 = 40.0;
+/// Instructions shown by the disassembly.
+const DISASSEMBLY_LINES: usize = 32;
+/// Tiles in VRAM, 16 bytes each from 0x8000.
+const TILES: usize = 384;
+/// Tiles on each row of the tile view.
+const TILES_PER_ROW: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Panel {
+    Registers,
+    Disassembly,
+    Memory,
+    Tiles,
+    Oam,
+    Palettes,
+    EventLog,
+}
+
+impl Panel {
+    const ALL: [Panel; 7] = [
+        Panel::Registers,
+        Panel::Disassembly,
+        Panel::Memory,
+        Panel::Tiles,
+        Panel::Oam,
+        Panel::Palettes,
+        Panel::EventLog,
+    ];
+
+    fn title(self) -> &'static str {
+        match self {
+            Panel::Registers => "Registers",
+            Panel::Disassembly => "Disassembly",
+            Panel::Memory => "Memory",
+            Panel::Tiles => "Tiles",
+            Panel::Oam => "OAM",
+            Panel::Palettes => "Palettes",
+            Panel::EventLog => "Event log",
+        }
+    }
+}
+
+/// What the panels show, kept between frames.
+struct Panels {
+    /// Whether each of `Panel::ALL` is open.
+    open: [bool; Panel::ALL.len()],
+    /// Disassemble from the PC rather than `disassembly_start`.
+    follow_pc: bool,
+    disassembly_start: u16,
+    disassembly_input: String,
+    memory_input: String,
+    /// Row to scroll the memory view to on the next frame.
+    memory_jump: Option<usize>,
+    tiles: Option<TextureHandle>,
+}
+
+pub struct DebugUi {
+    canvas: WindowCanvas,
+    ctx: egui::Context,
+    painter: Painter,
+    /// Input since the last redraw.
+    events: Vec<egui::Event>,
+    modifiers: egui::Modifiers,
+    focused: bool,
+    closed: bool,
+    start: time::Instant,
+    last_draw: Option<time::Instant>,
+    /// RGBA pixels of the window.
+    buf: Vec<u8>,
+    panels: Panels,
+}
+
+impl DebugUi {
+    /// Opens the debug window next to the game's.
+    pub fn new(video: &VideoSubsystem) -> Result<Self, String> {
+        let window = video
+            .window(TITLE, WIDTH, HEIGHT)
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        Ok(DebugUi {
+            canvas,
+            ctx: egui::Context::default(),
+            painter: Painter::default(),
+            events: Vec::new(),
+            modifiers: egui::Modifiers::default(),
+            focused: false,
+            closed: false,
+            start: time::Instant::now(),
+            last_draw: None,
+            buf: Vec::new(),
+            panels: Panels {
+                open: [true; Panel::ALL.len()],
+                follow_pc: true,
+                disassembly_start: 0x100,
+                disassembly_input: String::new(),
+                memory_input: String::new(),
+                memory_jump: None,
+                tiles: None,
+            },
+        })
+    }
+
+    /// Whether the debug window has the keyboard, so the game should not
+    /// take keys from it. Asked of SDL, as focus events may be taken by
+    /// whoever polls first.
+    pub fn has_focus(&self) -> bool {
+        let focus = sdl2::sys::SDL_WindowFlags::SDL_WINDOW_INPUT_FOCUS as u32;
+        self.canvas.window().window_flags() & focus != 0
+    }
+
+    /// Whether the user closed the debug window.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Takes `event` if it is for the debug window. Events for the game
+    /// window and ones for no window, such as quitting, are left alone.
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        if event.get_window_id() != Some(self.canvas.window().id()) {
+            return false;
+        }
+        match event {
+            Event::Window { win_event, .. } => match win_event {
+                WindowEvent::Leave => self.events.push(egui::Event::PointerGone),
+                WindowEvent::Close => self.closed = true,
+                // Resizing and exposing only need a redraw
+                _ => self.last_draw = None,
+            },
+            Event::MouseMotion { x, y, .. } => {
+                self.events
+                    .push(egui::Event::PointerMoved(Pos2::new(*x as f32, *y as f32)));
+            }
+            Event::MouseButtonDown {
+                mouse_btn, x, y, ..
+            }
+            | Event::MouseButtonUp {
+                mouse_btn, x, y, ..
+            } => {
+                if let Some(button) = translate_button(*mouse_btn) {
+                    self.events.push(egui::Event::PointerButton {
+                        pos: Pos2::new(*x as f32, *y as f32),
+                        button,
+                        pressed: matches!(event, Event::MouseButtonDown { .. }),
+                        modifiers: self.modifiers,
+                    });
+                }
+            }
+            Event::MouseWheel {
+                x, y, direction, ..
+            } => {
+                let sign = match direction {
+                    MouseWheelDirection::Flipped => -1.0,
+                    _ => 1.0,
+                };
+                let delta = egui::vec2(*x as f32, *y as f32) * sign * SCROLL_POINTS;
+                self.events.push(egui::Event::Scroll(delta));
+            }
+            Event::TextInput { text, .. } => self.events.push(egui::Event::Text(text.clone())),
+            Event::KeyDown {
+                keycode: Some(keycode),
+                keymod,
+                repeat,
+                ..
+            } => {
+                self.modifiers = translate_modifiers(*keymod);
+                let clipboard = self.canvas.window().subsystem().clipboard();
+                let event = match *keycode {
+                    Keycode::C if self.modifiers.command => egui::Event::Copy,
+                    Keycode::X if self.modifiers.command => egui::Event::Cut,
+                    Keycode::V if self.modifiers.command => {
+                        egui::Event::Paste(clipboard.clipboard_text().unwrap_or_default())
+                    }
+                    keycode => match translate_key(keycode) {
+                        Some(key) => egui::Event::Key {
+                            key,
+                            physical_key: None,
+                            pressed: true,
+                            repeat: *repeat,
+                            modifiers: self.modifiers,
+                        },
+                        None => return true,
+                    },
+                };
+                self.events.push(event);
+            }
+            Event::KeyUp {
+                keycode: Some(keycode),
+                keymod,
+                ..
+            } => {
+                self.modifiers = translate_modifiers(*keymod);
+                if let Some(key) = translate_key(*keycode) {
+                    self.events.push(egui::Event::Key {
+                        key,
+                        physical_key: None,
+                        pressed: false,
+                        repeat: false,
+                        modifiers: self.modifiers,
+                    });
+                }
+            }
+            _ => (),
+        }
+        true
+    }
+
+    /// Redraws the panels for the current state of `cpu`, unless it was
+    /// redrawn a moment ago and there was no input since.
+    pub fn draw(&mut self, cpu: &Cpu) {
+        if self.events.is_empty()
+            && self
+                .last_draw
+                .is_some_and(|last| last.elapsed() < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        self.last_draw = Some(time::Instant::now());
+        let focused = self.has_focus();
+        if focused != self.focused {
+            self.focused = focused;
+            self.events.push(egui::Event::WindowFocused(focused));
+        }
+
+        let (width, height) = self.canvas.window().size();
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                Pos2::ZERO,
+                egui::vec2(width as f32, height as f32),
+            )),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            modifiers: self.modifiers,
+            events: mem::take(&mut self.events),
+            focused: self.focused,
+            ..egui::RawInput::default()
+        };
+        let panels = &mut self.panels;
+        let output = self.ctx.run(input, |ctx| panels.show(ctx, cpu));
+
+        let video = self.canvas.window().subsystem();
+        if !output.platform_output.copied_text.is_empty() {
+            let _ = video
+                .clipboard()
+                .set_clipboard_text(&output.platform_output.copied_text);
+        }
+        // Only while a text field has the keyboard, as it may bring up an
+        // on-screen keyboard or IME
+        let text_input = video.text_input();
+        if self.ctx.wants_keyboard_input() != text_input.is_active() {
+            if text_input.is_active() {
+                text_input.stop();
+            } else {
+                text_input.start();
+            }
+        }
+
+        self.painter.set_textures(&output.textures_delta);
+        let primitives = self.ctx.tessellate(output.shapes, output.pixels_per_point);
+        let size = (width as usize, height as usize);
+        self.buf.resize(size.0 * size.1 * 4, 0);
+        let background = self.ctx.style().visuals.panel_fill;
+        self.painter.paint(
+            &primitives,
+            output.pixels_per_point,
+            background,
+            &mut self.buf,
+            size,
+        );
+        self.painter.free_textures(&output.textures_delta);
+
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture =
+            match texture_creator.create_texture_static(PixelFormatEnum::RGBA32, width, height) {
+                Ok(texture) => texture,
+                // Minimized to nothing
+                Err(_) => return,
+            };
+        texture.update(None, &self.buf, size.0 * 4).unwrap();
+        self.canvas.copy(&texture, None, None).unwrap();
+        self.canvas.present();
+    }
+}
+
+impl Panels {
+    fn show(&mut self, ctx: &egui::Context, cpu: &Cpu) {
+        egui::TopBottomPanel::top("panels").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                for (panel, open) in Panel::ALL.iter().zip(&mut self.open) {
+                    ui.toggle_value(open, panel.title());
+                }
+            });
+        });
+        for (i, panel) in Panel::ALL.iter().enumerate() {
+            let mut open = self.open[i];
+            egui::Window::new(panel.title())
+                .open(&mut open)
+                .show(ctx, |ui| match panel {
+                    Panel::Registers => show_registers(ui, cpu),
+                    Panel::Disassembly => self.show_disassembly(ui, cpu),
+                    Panel::Memory => self.show_memory(ui, cpu),
+                    Panel::Tiles => self.show_tiles(ui, cpu),
+                    Panel::Oam => show_oam(ui, cpu),
+                    Panel::Palettes => show_palettes(ui, cpu),
+                    Panel::EventLog => show_event_log(ui, cpu),
+                });
+            self.open[i] = open;
+        }
+    }
+
+    fn show_disassembly(&mut self, ui: &mut egui::Ui, cpu: &Cpu) {
+        let pc = cpu.registers().pc;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.follow_pc, "Follow PC");
+            if address_input(ui, &mut self.disassembly_input).is_some_and(|addr| {
+                self.disassembly_start = addr;
+                true
+            }) {
+                self.follow_pc = false;
+            }
+        });
+        let mut addr = if self.follow_pc {
+            pc
+        } else {
+            self.disassembly_start
+        };
+        for _ in 0..DISASSEMBLY_LINES {
+            let bytes = [0, 1, 2].map(|i| cpu.mmu.peek(addr.wrapping_add(i)));
+            // Three bytes always hold an instruction
+            let instruction = disasm::decode(&bytes, addr).unwrap();
+            let hex: Vec<String> = bytes[..instruction.len]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            let marker = if addr == pc { ">" } else { " " };
+            let line = format!(
+                "{} {:04x}  {:<9}{}",
+                marker,
+                addr,
+                hex.join(" "),
+                instruction.text
+            );
+            let text = RichText::new(line).monospace();
+            ui.label(if addr == pc { text.strong() } else { text });
+            addr = addr.wrapping_add(instruction.len as u16);
+        }
+    }
+
+    fn show_memory(&mut self, ui: &mut egui::Ui, cpu: &Cpu) {
+        ui.horizontal(|ui| {
+            ui.label("Go to");
+            if let Some(addr) = address_input(ui, &mut self.memory_input) {
+                self.memory_jump = Some(addr as usize / 16);
+            }
+        });
+        ui.label("As the CPU reads it, e.g. 0xff from VRAM while the PPU draws");
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let mut scroll = ScrollArea::vertical().auto_shrink([false; 2]);
+        if let Some(row) = self.memory_jump.take() {
+            let spacing = ui.spacing().item_spacing.y;
+            scroll = scroll.vertical_scroll_offset(row as f32 * (row_height + spacing));
+        }
+        scroll.show_rows(ui, row_height, 0x10000 / 16, |ui, rows| {
+            for row in rows {
+                let start = (row * 16) as u16;
+                let bytes: Vec<u8> = (0..16).map(|i| cpu.mmu.peek(start + i)).collect();
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                let ascii: String = bytes
+                    .iter()
+                    .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                    .collect();
+                ui.monospace(format!("{:04x}  {}  {}", start, hex.join(" "), ascii));
+            }
+        });
+    }
+
+    /// Every tile in VRAM in BGP colors, 16 to a row from 0x8000.
+    fn show_tiles(&mut self, ui: &mut egui::Ui, cpu: &Cpu) {
+        let vram = cpu.mmu.ppu.vram();
+        let shades = cpu.mmu.ppu.debug_palettes().bg[0];
+        let width = TILES_PER_ROW * 8;
+        let height = TILES / TILES_PER_ROW * 8;
+        let mut rgb = vec![0; width * height * 3];
+        for (tile, data) in vram[..TILES * 16].chunks(16).enumerate() {
+            let (left, top) = (tile % TILES_PER_ROW * 8, tile / TILES_PER_ROW * 8);
+            for (y, row) in data.chunks(2).enumerate() {
+                for x in 0..8 {
+                    let bit = 7 - x;
+                    let color = (row[0] >> bit) & 1 | ((row[1] >> bit) & 1) << 1;
+                    let offset = ((top + y) * width + left + x) * 3;
+                    rgb[offset..offset + 3].copy_from_slice(&shades[color as usize]);
+                }
+            }
+        }
+        let image = egui::ColorImage::from_rgb([width, height], &rgb);
+        let texture = match &mut self.tiles {
+            Some(texture) => {
+                texture.set(image, TextureOptions::NEAREST);
+                texture
+            }
+            None => self.tiles.insert(ui.ctx().load_texture(
+                "tiles",
+                image,
+                TextureOptions::NEAREST,
+            )),
+        };
+        ui.label("0x8000-0x97ff, 16 tiles to a row, in BGP colors");
+        ui.image((texture.id(), egui::vec2(width as f32, height as f32) * 2.0));
+    }
+}
+
+/// A hex address field, with the address once Enter is pressed in it.
+fn address_input(ui: &mut egui::Ui, text: &mut String) -> Option<u16> {
+    let response = ui.add(
+        egui::TextEdit::singleline(text)
+            .desired_width(48.0)
+            .hint_text("addr"),
+    );
+    if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+        parse_address(text.trim()).ok()
+    } else {
+        None
+    }
+}
+
+fn show_registers(ui: &mut egui::Ui, cpu: &Cpu) {
+    let r = cpu.registers();
+    let pair = |high: u8, low: u8| u16::from_be_bytes([high, low]);
+    let flags: String = [(7, 'Z'), (6, 'N'), (5, 'H'), (4, 'C')]
+        .iter()
+        .map(|&(bit, name)| if r.f >> bit & 1 == 1 { name } else { '-' })
+        .collect();
+    let io = |addr: u16| format!("{:02x}", cpu.mmu.peek(addr));
+    let rows = [
+        ("AF", format!("{:04x}", pair(r.a, r.f))),
+        ("BC", format!("{:04x}", pair(r.b, r.c))),
+        ("DE", format!("{:04x}", pair(r.d, r.e))),
+        ("HL", format!("{:04x}", pair(r.h, r.l))),
+        ("SP", format!("{:04x}", r.sp)),
+        ("PC", format!("{:04x}", r.pc)),
+        ("Flags", flags),
+        ("IME", cpu.ime().to_string()),
+        ("IE", io(0xffff)),
+        ("IF", io(0xff0f)),
+        ("LCDC", io(0xff40)),
+        ("STAT", io(0xff41)),
+        ("LY", io(0xff44)),
+        ("Frame", cpu.frames().to_string()),
+    ];
+    egui::Grid::new("registers").striped(true).show(ui, |ui| {
+        for (name, value) in rows {
+            ui.label(name);
+            ui.monospace(value);
+            ui.end_row();
+        }
+    });
+}
+
+/// The 40 objects as they are in OAM.
+fn show_oam(ui: &mut egui::Ui, cpu: &Cpu) {
+    let oam = cpu.mmu.ppu.oam();
+    ScrollArea::vertical().show(ui, |ui| {
+        egui::Grid::new("oam").striped(true).show(ui, |ui| {
+            for heading in ["#", "Y", "X", "Tile", "Flags"] {
+                ui.strong(heading);
+            }
+            ui.end_row();
+            for (i, object) in oam.chunks(4).enumerate() {
+                let flags = object[3];
+                let describe = [(7, "behind"), (6, "y-flip"), (5, "x-flip"), (4, "OBP1")]
+                    .iter()
+                    .filter(|&&(bit, _)| flags >> bit & 1 == 1)
+                    .map(|&(_, name)| name)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                ui.monospace(i.to_string());
+                ui.monospace(object[0].to_string());
+                ui.monospace(object[1].to_string());
+                ui.monospace(format!("{:02x}", object[2]));
+                ui.monospace(format!("{:02x} {}", flags, describe));
+                ui.end_row();
+            }
+        });
+    });
+}
+
+fn show_palettes(ui: &mut egui::Ui, cpu: &Cpu) {
+    let palettes = cpu.mmu.ppu.debug_palettes();
+    let rows = [
+        ("BGP", 0xff47, palettes.bg[0]),
+        ("OBP0", 0xff48, palettes.obj[0]),
+        ("OBP1", 0xff49, palettes.obj[1]),
+    ];
+    egui::Grid::new("palettes").show(ui, |ui| {
+        for (name, addr, colors) in rows {
+            ui.label(name);
+            ui.monospace(format!("{:02x}", cpu.mmu.peek(addr)));
+            for color in colors {
+                swatch(ui, color);
+            }
+            ui.end_row();
+        }
+        ui.label("Shades");
+        ui.label("");
+        for color in palettes.shades {
+            swatch(ui, color);
+        }
+        ui.end_row();
+        // CGB palette RAM
+        let cgb = palettes.cgb_bg.iter().map(|colors| ("BG", colors));
+        let cgb = cgb.chain(palettes.cgb_obj.iter().map(|colors| ("OBJ", colors)));
+        for (i, (name, colors)) in cgb.enumerate() {
+            ui.label(format!("{}{}", name, i % 8));
+            ui.label("");
+            for &color in colors {
+                swatch(ui, color);
+            }
+            ui.end_row();
+        }
+    });
+}
+
+fn swatch(ui: &mut egui::Ui, [r, g, b]: Rgb) {
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(24.0, 24.0), egui::Sense::hover());
+    ui.painter()
+        .rect_filled(rect, 2.0, Color32::from_rgb(r, g, b));
+    response.on_hover_text(format!("#{:02x}{:02x}{:02x}", r, g, b));
+}
+
+fn show_event_log(ui: &mut egui::Ui, cpu: &Cpu) {
+    let log = match cpu.mmu.event_log() {
+        Some(log) => log,
+        None => {
+            ui.label(
+                "Start with --event-log to record interrupts, IO writes, bank switches and DMA",
+            );
+            return;
+        }
+    };
+    let entries: Vec<String> = log.entries().map(ToString::to_string).collect();
+    let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+    ScrollArea::vertical()
+        .auto_shrink([false; 2])
+        .stick_to_bottom(true)
+        .show_rows(ui, row_height, entries.len(), |ui, rows| {
+            for entry in &entries[rows] {
+                ui.monospace(entry);
+            }
+        });
+}
+
+fn translate_button(button: MouseButton) -> Option<egui::PointerButton> {
+    match button {
+        MouseButton::Left => Some(egui::PointerButton::Primary),
+        MouseButton::Right => Some(egui::PointerButton::Secondary),
+        MouseButton::Middle => Some(egui::PointerButton::Middle),
+        _ => None,
+    }
+}
+
+fn translate_modifiers(keymod: Mod) -> egui::Modifiers {
+    let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+    let gui = keymod.intersects(Mod::LGUIMOD | Mod::RGUIMOD);
+    egui::Modifiers {
+        alt: keymod.intersects(Mod::LALTMOD | Mod::RALTMOD),
+        ctrl,
+        shift: keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+        mac_cmd: cfg!(target_os = "macos") && gui,
+        command: if cfg!(target_os = "macos") { gui } else { ctrl },
+    }
+}
+
+/// The keys text fields and scroll areas use. Letters and digits come as
+/// text.
+fn translate_key(keycode: Keycode) -> Option<egui::Key> {
+    Some(match keycode {
+        Keycode::Up => egui::Key::ArrowUp,
+        Keycode::Down => egui::Key::ArrowDown,
+        Keycode::Left => egui::Key::ArrowLeft,
+        Keycode::Right => egui::Key::ArrowRight,
+        Keycode::Backspace => egui::Key::Backspace,
+        Keycode::Delete => egui::Key::Delete,
+        Keycode::Return | Keycode::KpEnter => egui::Key::Enter,
+        Keycode::Escape => egui::Key::Escape,
+        Keycode::Tab => egui::Key::Tab,
+        Keycode::Home => egui::Key::Home,
+        Keycode::End => egui::Key::End,
+        Keycode::PageUp => egui::Key::PageUp,
+        Keycode::PageDown => egui::Key::PageDown,
+        Keycode::A => egui::Key::A,
+        Keycode::Z => egui::Key::Z,
+        Keycode::Y => egui::Key::Y,
+        _ => return None,
+    })
+}
@@ -0,0 +1,300 @@
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Something the user can ask of the emulator with a hotkey, independent of
+/// the frontend that reads the keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    /// Back to the launcher menu.
+    Menu,
+    SoftReset,
+    HardReset,
+    DumpEventLog,
+    ReloadRom,
+    SaveState,
+    PreviousSlot,
+    NextSlot,
+    LoadState,
+    ToggleBackground,
+    ToggleWindow,
+    ToggleSprites,
+    CyclePalette,
+    AudioLatencyDown,
+    AudioLatencyUp,
+    Slower,
+    Faster,
+    /// Runs unthrottled while the key is held.
+    FastForward,
+    Pause,
+    Screenshot,
+    ToggleInputDisplay,
+    ToggleGrid,
+    /// Starts or stops writing the audio to a .wav file.
+    ToggleRecording,
+    /// Writes the timings of the latest frames to a file.
+    DumpFrameTimes,
+    /// Shows or hides the hex editor over cartridge RAM, WRAM and HRAM.
+    ToggleMemoryEditor,
+    /// Runs one instruction while paused.
+    Step,
+    /// Goes back one instruction while paused.
+    StepBack,
+    /// Presses the button of the device on the link port.
+    LinkButton,
+}
+
+impl Action {
+    pub const ALL: [Action; 29] = [
+        Action::Quit,
+        Action::Menu,
+        Action::SoftReset,
+        Action::HardReset,
+        Action::DumpEventLog,
+        Action::ReloadRom,
+        Action::SaveState,
+        Action::PreviousSlot,
+        Action::NextSlot,
+        Action::LoadState,
+        Action::ToggleBackground,
+        Action::ToggleWindow,
+        Action::ToggleSprites,
+        Action::CyclePalette,
+        Action::AudioLatencyDown,
+        Action::AudioLatencyUp,
+        Action::Slower,
+        Action::Faster,
+        Action::FastForward,
+        Action::Pause,
+        Action::Screenshot,
+        Action::ToggleInputDisplay,
+        Action::ToggleGrid,
+        Action::ToggleRecording,
+        Action::DumpFrameTimes,
+        Action::ToggleMemoryEditor,
+        Action::Step,
+        Action::StepBack,
+        Action::LinkButton,
+    ];
+
+    /// Name used in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Menu => "menu",
+            Action::SoftReset => "soft-reset",
+            Action::HardReset => "hard-reset",
+            Action::DumpEventLog => "dump-event-log",
+            Action::ReloadRom => "reload-rom",
+            Action::SaveState => "save-state",
+            Action::PreviousSlot => "previous-slot",
+            Action::NextSlot => "next-slot",
+            Action::LoadState => "load-state",
+            Action::ToggleBackground => "toggle-background",
+            Action::ToggleWindow => "toggle-window",
+            Action::ToggleSprites => "toggle-sprites",
+            Action::CyclePalette => "cycle-palette",
+            Action::AudioLatencyDown => "audio-latency-down",
+            Action::AudioLatencyUp => "audio-latency-up",
+            Action::Slower => "slower",
+            Action::Faster => "faster",
+            Action::FastForward => "fast-forward",
+            Action::Pause => "pause",
+            Action::Screenshot => "screenshot",
+            Action::ToggleInputDisplay => "toggle-input-display",
+            Action::ToggleGrid => "toggle-grid",
+            Action::ToggleRecording => "toggle-recording",
+            Action::DumpFrameTimes => "dump-frame-times",
+            Action::ToggleMemoryEditor => "toggle-memory-editor",
+            Action::Step => "step",
+            Action::StepBack => "step-back",
+            Action::LinkButton => "link-button",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.iter().copied().find(|a| a.name() == name)
+    }
+}
+
+/// Bindings used when the config does not mention an action. Keys are
+/// named as SDL names their scancodes.
+const DEFAULT_BINDINGS: [(&str, Action); 29] = [
+    ("Escape", Action::Quit),
+    ("Backspace", Action::Menu),
+    ("F1", Action::SoftReset),
+    ("F2", Action::HardReset),
+    ("F3", Action::DumpEventLog),
+    ("F4", Action::ReloadRom),
+    ("F5", Action::SaveState),
+    ("F6", Action::PreviousSlot),
+    ("F7", Action::NextSlot),
+    ("F8", Action::LoadState),
+    ("F9", Action::ToggleBackground),
+    ("F10", Action::ToggleWindow),
+    ("F11", Action::ToggleSprites),
+    ("F12", Action::CyclePalette),
+    ("-", Action::AudioLatencyDown),
+    ("=", Action::AudioLatencyUp),
+    ("[", Action::Slower),
+    ("]", Action::Faster),
+    ("Tab", Action::FastForward),
+    ("P", Action::Pause),
+    ("PrintScreen", Action::Screenshot),
+    ("I", Action::ToggleInputDisplay),
+    ("G", Action::ToggleGrid),
+    ("R", Action::ToggleRecording),
+    ("T", Action::DumpFrameTimes),
+    ("M", Action::ToggleMemoryEditor),
+    ("N", Action::Step),
+    ("B", Action::StepBack),
+    ("L", Action::LinkButton),
+];
+
+#[derive(Debug)]
+pub enum HotkeyError {
+    Io(io::Error),
+    /// A line is not `action = key`.
+    Syntax {
+        line: usize,
+    },
+    UnknownAction {
+        line: usize,
+        name: String,
+    },
+}
+
+impl fmt::Display for HotkeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HotkeyError::Io(e) => write!(f, "{}", e),
+            HotkeyError::Syntax { line } => write!(f, "line {}: expected action = key", line),
+            HotkeyError::UnknownAction { line, name } => {
+                write!(f, "line {}: unknown action {}", line, name)
+            }
+        }
+    }
+}
+
+impl error::Error for HotkeyError {}
+
+impl From<io::Error> for HotkeyError {
+    fn from(e: io::Error) -> Self {
+        HotkeyError::Io(e)
+    }
+}
+
+/// Which key triggers which action. Key names are compared without case.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hotkeys {
+    bindings: HashMap<String, Action>,
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .map(|&(key, action)| (key.to_lowercase(), action))
+            .collect();
+        Hotkeys { bindings }
+    }
+}
+
+impl Hotkeys {
+    /// Reads a config file, falling back to the defaults if it does not
+    /// exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, HotkeyError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Hotkeys::parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Hotkeys::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Reads `action = key` lines, `#` starting a comment. Every action
+    /// named in `text` loses its default keys, so `pause =` unbinds it and
+    /// repeating an action binds several keys to it.
+    pub fn parse(text: &str) -> Result<Self, HotkeyError> {
+        let mut hotkeys = Hotkeys::default();
+        let mut configured = HashSet::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, key) = line
+                .split_once('=')
+                .ok_or(HotkeyError::Syntax { line: line_number })?;
+            let name = name.trim();
+            let action = Action::from_name(name).ok_or_else(|| HotkeyError::UnknownAction {
+                line: line_number,
+                name: name.to_string(),
+            })?;
+            if configured.insert(action) {
+                hotkeys.bindings.retain(|_, a| *a != action);
+            }
+            let key = key.trim();
+            if !key.is_empty() {
+                hotkeys.bindings.insert(key.to_lowercase(), action);
+            }
+        }
+        Ok(hotkeys)
+    }
+
+    /// Action bound to the key called `key`.
+    pub fn action(&self, key: &str) -> Option<Action> {
+        self.bindings.get(&key.to_lowercase()).copied()
+    }
+
+    /// Keys bound to `action`, lowercased and sorted.
+    pub fn keys(&self, action: Action) -> Vec<&str> {
+        let mut keys: Vec<&str> = self
+            .bindings
+            .iter()
+            .filter(|&(_, a)| *a == action)
+            .map(|(key, _)| key.as_str())
+            .collect();
+        keys.sort_unstable();
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let hotkeys = Hotkeys::default();
+        assert_eq!(hotkeys.action("F5"), Some(Action::SaveState));
+        assert_eq!(hotkeys.action("escape"), Some(Action::Quit));
+        assert_eq!(hotkeys.action("X"), None);
+        for action in Action::ALL {
+            assert_eq!(Action::from_name(action.name()), Some(action));
+            assert!(!hotkeys.keys(action).is_empty(), "{:?}", action);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = "# my keys\nsave-state = S\nsave-state = F5 # keep\n\npause =\n";
+        let hotkeys = Hotkeys::parse(text).unwrap();
+        assert_eq!(hotkeys.keys(Action::SaveState), ["f5", "s"]);
+        assert_eq!(hotkeys.action("P"), None);
+        assert_eq!(hotkeys.action("F8"), Some(Action::LoadState));
+
+        assert!(matches!(
+            Hotkeys::parse("pause P"),
+            Err(HotkeyError::Syntax { line: 1 })
+        ));
+        assert!(matches!(
+            Hotkeys::parse("\nsnooze = Z"),
+            Err(HotkeyError::UnknownAction { line: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, TimeZone};
+
+use rust_gb::cartridge::get_title;
+
+/// Names a game by its header title and global checksum, so every copy of
+/// the same dump shares one record. `None` if `rom` has no header.
+pub fn game_key(rom: &[u8]) -> Option<String> {
+    let header = rom.get(0x134..0x150)?;
+    let checksum = u16::from_be_bytes([header[0x1a], header[0x1b]]);
+    Some(format!("{}-{:04x}", get_title(&header[..0x10]), checksum))
+}
+
+/// How much and when a game was played.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlayRecord {
+    pub playtime: Duration,
+    pub last_played: Option<DateTime<Local>>,
+}
+
+/// "3h 05m, last played 2026-10-12"
+impl fmt::Display for PlayRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let minutes = self.playtime.as_secs() / 60;
+        write!(f, "{}h {:02}m", minutes / 60, minutes % 60)?;
+        if let Some(last) = self.last_played {
+            write!(f, ", last played {}", last.format("%Y-%m-%d"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Playtime records of every game, kept in a small text file with one
+/// `key<TAB>seconds<TAB>unix time` line per game.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Library {
+    records: BTreeMap<String, PlayRecord>,
+}
+
+impl Library {
+    /// Creates a new, empty `Library`.
+    pub fn new() -> Self {
+        Library::default()
+    }
+
+    /// Reads the library at `path`, which is empty if the file does not
+    /// exist yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Library::parse(&text)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Library::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads the text format, skipping lines it does not understand.
+    pub fn parse(text: &str) -> Self {
+        let records = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let key = fields.next()?;
+                let seconds = fields.next()?.parse().ok()?;
+                let last_played = match fields.next()?.parse().ok()? {
+                    0 => None,
+                    unix => Local.timestamp_opt(unix, 0).single(),
+                };
+                let record = PlayRecord {
+                    playtime: Duration::from_secs(seconds),
+                    last_played,
+                };
+                Some((key.to_string(), record))
+            })
+            .collect();
+        Library { records }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&PlayRecord> {
+        self.records.get(key)
+    }
+
+    /// Adds a session of `played` that ended at `ended`.
+    pub fn record_session(&mut self, key: &str, played: Duration, ended: DateTime<Local>) {
+        let record = self.records.entry(key.to_string()).or_default();
+        record.playtime += played;
+        record.last_played = Some(ended);
+    }
+}
+
+/// The text format read by `parse`.
+impl fmt::Display for Library {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (key, record) in &self.records {
+            writeln!(
+                f,
+                "{}\t{}\t{}",
+                key,
+                record.playtime.as_secs(),
+                record.last_played.map_or(0, |t| t.timestamp())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_game_key() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x138].copy_from_slice(b"TEST");
+        rom[0x14e] = 0x12;
+        rom[0x14f] = 0x34;
+        assert_eq!(game_key(&rom), Some("TEST-1234".to_string()));
+        assert_eq!(game_key(&rom[..0x100]), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let ended = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        let mut library = Library::new();
+        library.record_session("TEST-1234", Duration::from_secs(600), ended);
+        library.record_session("TEST-1234", Duration::from_secs(5), ended);
+
+        let record = library.get("TEST-1234").unwrap();
+        assert_eq!(record.playtime, Duration::from_secs(605));
+        assert_eq!(record.last_played, Some(ended));
+        assert_eq!(Library::parse(&library.to_string()), library);
+        assert_eq!(Library::parse("garbage\nA\t1\n"), Library::new());
+    }
+}
@@ -0,0 +1,2713 @@
+#[cfg(feature = "debug-ui")]
+mod debug_ui;
+mod hotkey;
+mod library;
+mod memory_editor;
+#[cfg(feature = "debug-ui")]
+mod painter;
+mod quirks;
+mod settings;
+mod slot;
+mod timing;
+
+#[cfg(feature = "debug-ui")]
+use debug_ui::DebugUi;
+use hotkey::{Action, Hotkeys};
+use library::Library;
+use memory_editor::{EditorKey, MemoryEditor};
+use settings::Settings;
+use slot::{SlotInfo, Thumbnail};
+use timing::{FrameTimes, FrameTiming};
+
+use rust_gb::accuracy::Accuracy;
+use rust_gb::analog::{StickMapping, DEFAULT_DEADZONE, DEFAULT_DIAGONAL_ANGLE, MAX_DIAGONAL_ANGLE};
+use rust_gb::auto_split::{AutoSplitter, Split, SplitCondition};
+use rust_gb::battery::SaveFormat;
+use rust_gb::boot_animation::BootAnimation;
+use rust_gb::cpu::{Cpu, CYCLES_PER_FRAME};
+use rust_gb::crash_report::CrashReport;
+use rust_gb::frame_dump::{FrameDumper, FrameFormat};
+use rust_gb::frame_limiter::{self, FrameLimiter, Pacing, SystemClock};
+use rust_gb::heatmap::AccessHeatmap;
+use rust_gb::input::{InputProvider, Players, PollPoint, PollRate};
+use rust_gb::joypad::{self, ButtonState};
+use rust_gb::link::LinkRegistry;
+use rust_gb::loader::{LoadStatus, RomLoader};
+use rust_gb::memory_watch::{MemoryWatch, Watch, WatchKind};
+use rust_gb::palette::{self, BUILTIN_PALETTES};
+use rust_gb::patch;
+use rust_gb::ppu::{Layer, PaletteRegister, PixelSource, Rgb, SCREEN_HEIGHT, SCREEN_WIDTH};
+use rust_gb::quirks::QuirkDatabase;
+use rust_gb::resample::Resampler;
+use rust_gb::rewind::Rewind;
+use rust_gb::rng::DEFAULT_SEED;
+use rust_gb::romdb::RomDatabase;
+use rust_gb::speed::{Speed, MAX_SPEED, MIN_SPEED};
+use rust_gb::state::StateError;
+use rust_gb::state_diff;
+use rust_gb::vgm::VgmWriter;
+use rust_gb::wav::WavWriter;
+// use sdl2::pixels::PixelFormatEnum;
+use std::env;
+#[cfg(feature = "watch")]
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "watch")]
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+use clap::Parser;
+use log::{debug, info, warn};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::messagebox::{self, ButtonData, ClickedButton, MessageBoxButtonFlag, MessageBoxFlag};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Texture, WindowCanvas};
+use sdl2::video::Window;
+use sdl2::{AudioSubsystem, EventPump};
+
+const LOG_TARGET: &str = "gb::frontend";
+const WINDOW_TITLE: &str = "rust-gameboy";
+const SAVE_STATE_DIR: &str = "save_states";
+const NUM_SAVE_STATE_SLOTS: u8 = 10;
+const GAME_SETTINGS_DIR: &str = "game_settings";
+const EVENT_LOG_DIR: &str = "event_logs";
+const HEATMAP_DIR: &str = "heatmaps";
+const MEMORY_WATCH_DIR: &str = "memory_watch";
+const FRAME_TIMES_DIR: &str = "frame_times";
+const CRASH_REPORT_DIR: &str = "crash_reports";
+/// Event log entries kept for crash reports when --event-log sets none.
+const CRASH_REPORT_EVENTS: usize = 256;
+/// ROM path that reads the ROM from stdin instead.
+const STDIN_PATH: &str = "-";
+const LIBRARY_PATH: &str = "library.tsv";
+const HOTKEYS_PATH: &str = "hotkeys.cfg";
+const QUIRKS_PATH: &str = "quirks.toml";
+const SCREENSHOT_DIR: &str = "screenshots";
+const RECORDING_DIR: &str = "recordings";
+/// How long the thumbnail of a newly selected slot stays on screen.
+const SLOT_PREVIEW_TIME: time::Duration = time::Duration::from_secs(2);
+/// How long a ROM may take to load before a progress bar is shown.
+const LOADING_INDICATOR_DELAY: time::Duration = time::Duration::from_millis(100);
+const AUDIO_SAMPLE_RATE: i32 = 48000;
+const MIN_AUDIO_LATENCY_MS: u32 = 20;
+const MAX_AUDIO_LATENCY_MS: u32 = 200;
+const AUDIO_LATENCY_STEP_MS: u32 = 10;
+/// Frames run per displayed frame while fast-forwarding.
+const FAST_FORWARD_FRAMES: u32 = 4;
+/// Scanlines between two reads of the keyboard within a frame.
+const INPUT_POLL_LINES: u32 = 8;
+/// Frames the step-back hotkey can go back.
+const REWIND_FRAMES: usize = 60;
+const CPU_CLOCK_HZ: f64 = 4_194_304.0;
+/// Quiet time after the last change to the ROM before it is reloaded, so a
+/// file still being written is not picked up.
+#[cfg(feature = "watch")]
+const WATCH_SETTLE: time::Duration = time::Duration::from_millis(200);
+
+#[derive(Parser)]
+struct Args {
+    /// ROM to start right away, or - to read it from stdin. The launcher
+    /// menu is shown when omitted.
+    file_path: Option<String>,
+    /// Directory listed by the launcher menu
+    #[arg(long, default_value = "cartridges")]
+    rom_dir: String,
+    /// DMG palette: grayscale, dmg-green, deuteranopia, protanopia or
+    /// high-contrast. Overrides the saved setting, grayscale by default
+    #[arg(long, value_parser = parse_palette)]
+    palette: Option<usize>,
+    /// Target audio latency in milliseconds, 50 by default. Higher values
+    /// resist glitches on slow machines. Games with their own setting (-/=
+    /// keys) override it.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(20..=200))]
+    audio_latency: Option<u32>,
+    /// Window size in multiples of 160x144, from 1 to 8, 3 by default
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=settings::MAX_SCALE as i64))]
+    scale: Option<u32>,
+    /// Emulation speed from 0.25 to 1 for slow motion ([/] keys)
+    #[arg(long, default_value_t = 1.0, value_parser = parse_speed)]
+    speed: f32,
+    /// Writes battery saves gzipped with a checksum instead of as raw .sav
+    /// data. Either kind is read back.
+    #[arg(long)]
+    compress_saves: bool,
+    /// Which save RAM to keep when a savestate holds other save RAM than
+    /// the game has. The game's is written to the battery save first either
+    /// way
+    #[arg(long, value_enum, default_value_t = SaveRamPolicy::Prompt)]
+    save_ram_policy: SaveRamPolicy,
+    /// Whether the game keys and hotkeys.cfg mean keys by where they are on
+    /// the keyboard or by what the layout prints on them
+    #[arg(long, value_enum, default_value_t = KeyMode::Scancode)]
+    key_mode: KeyMode,
+    /// Writes savestates with BESS blocks appended, so SameBoy and other
+    /// emulators can load them too
+    #[arg(long)]
+    bess: bool,
+    /// Records the last N interrupts, IO writes, bank switches and DMAs,
+    /// written to event_logs/ with F3
+    #[arg(long, value_name = "N")]
+    event_log: Option<usize>,
+    /// Writes a zip to crash_reports/ when the emulator panics or an illegal
+    /// opcode locks the CPU up, to attach to bug reports. Keeps the last 256
+    /// events unless --event-log keeps some other number
+    #[arg(long)]
+    crash_report: bool,
+    /// Times emulation, presenting and sleeping over the last N frames,
+    /// written to frame_times/ with T, to attach to stutter reports
+    #[arg(long, value_name = "N")]
+    frame_timing: Option<usize>,
+    /// Counts memory accesses per page and writes them to heatmaps/ as CSV
+    /// and PPM when the game is closed
+    #[arg(long)]
+    heatmap: bool,
+    /// Also counts every byte of an address range, e.g. c000-c0ff
+    #[arg(long, value_name = "START-END", value_parser = parse_address_range, requires = "heatmap")]
+    heatmap_range: Option<Range<u16>>,
+    /// Samples a game variable every frame, e.g. x=c0a0:u16, and writes the
+    /// samples to memory_watch/ as CSV when the game is closed. KIND is u8
+    /// (the default), i8 or u16; give it once per variable
+    #[arg(long, value_name = "NAME=ADDR[:KIND]", value_parser = parse_watch)]
+    watch_mem: Vec<Watch>,
+    /// Frames of samples kept for --watch-mem, oldest dropped first
+    #[arg(long, value_name = "N", default_value_t = 3600)]
+    watch_mem_frames: usize,
+    /// Prints `split<TAB>frame<TAB>NAME` to stdout for auto-splitter
+    /// scripts in the frame CONDITION starts to hold: bgp:VALUE,
+    /// obp0:VALUE or obp1:VALUE for a palette register, or
+    /// tiles:9800:T1,T2,...[@X,Y] for tiles side by side in a tile map row,
+    /// at column X and row Y or anywhere. Give it once per split
+    #[arg(long, value_name = "NAME=CONDITION", value_parser = parse_split)]
+    split: Vec<Split>,
+    /// Plugs a device into the link port: barcode-boy:CODE,... swipes the
+    /// given 13-digit barcodes in turn with L, reply:BYTE answers every
+    /// transfer with BYTE
+    #[arg(long, value_name = "DEVICE[:ARGS]", value_parser = parse_link)]
+    link: Option<String>,
+    /// When frames reach the display: right away, paced by sleeping, or
+    /// at the display's refresh, running two frames or none now and then
+    /// to keep 59.73 Hz
+    #[arg(long, value_enum, default_value_t = PresentMode::Immediate)]
+    present_mode: PresentMode,
+    /// Same as --present-mode vsync
+    #[arg(long, conflicts_with = "present_mode")]
+    vsync: bool,
+    /// Runs as fast as possible until N frames after power-on, then pauses
+    #[arg(long, value_name = "N")]
+    run_to_frame: Option<u64>,
+    /// Runs as fast as possible until the PC reaches the hex address ADDR,
+    /// e.g. 0x0150, then pauses before the instruction there
+    #[arg(long, value_name = "ADDR", value_parser = parse_address)]
+    break_at: Option<u16>,
+    /// IPS or BPS patch applied to FILE_PATH in memory, e.g. a translation
+    #[arg(long, value_name = "FILE", requires = "file_path")]
+    patch: Option<PathBuf>,
+    /// No-Intro style dat file used to name games and spot bad dumps
+    #[arg(long, value_name = "FILE")]
+    dat: Option<PathBuf>,
+    /// Runs N frames of FILE_PATH as fast as possible without a window and
+    /// prints the speed
+    #[arg(long, value_name = "N", requires = "file_path")]
+    bench: Option<u32>,
+    /// Skips drawing pixels during --bench
+    #[arg(long, requires = "bench")]
+    no_render: bool,
+    /// Writes the first N frames of FILE_PATH to --dump-dir without a
+    /// window, to diff against other emulators frame by frame
+    #[arg(long, value_name = "N", requires = "file_path")]
+    dump_frames: Option<u64>,
+    /// Where --dump-frames writes, one file per frame
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = "frame_dump",
+        requires = "dump_frames"
+    )]
+    dump_dir: PathBuf,
+    /// Format of --dump-frames: rgb (raw RGB24, 160x144) or png
+    #[arg(long, value_name = "FORMAT", default_value = "png", value_parser = parse_frame_format, requires = "dump_frames")]
+    dump_format: FrameFormat,
+    /// Prints what differs between two savestates of FILE_PATH
+    #[arg(long, num_args = 2, value_names = ["A", "B"], requires = "file_path")]
+    diff_states: Option<Vec<PathBuf>>,
+    /// Part of the analog stick's travel, 0 to 1, that does not move the D-pad
+    #[arg(long, default_value_t = DEFAULT_DEADZONE, value_parser = parse_deadzone)]
+    deadzone: f32,
+    /// Width in degrees of the stick's diagonal sectors, from 0 (4-way only)
+    /// to 90 (diagonals whenever both axes are off center)
+    #[arg(long, value_name = "DEGREES", default_value_t = DEFAULT_DIAGONAL_ANGLE, value_parser = parse_diagonal_angle)]
+    diagonal_angle: f32,
+    /// Fills WRAM and HRAM with random bytes on power-on, as real hardware
+    /// does, instead of zeros
+    #[arg(long)]
+    random_ram: bool,
+    /// Shows a short "GAME BOY" scroll before each game starts, in place
+    /// of the boot ROM's logo. Any key or button skips it
+    #[arg(long)]
+    boot_animation: bool,
+    /// Seed of the random numbers, for reproducible runs
+    #[arg(long, default_value_t = DEFAULT_SEED)]
+    seed: u64,
+    /// fast, balanced or accurate: how much speed to give up for accuracy,
+    /// e.g. fast on slow devices or accurate for test ROMs
+    #[arg(long, value_name = "PRESET", default_value = "balanced", value_parser = parse_accuracy)]
+    accuracy: Accuracy,
+    /// nearest, linear or sinc: how the sound is resampled to the output
+    /// rate. sinc keeps high notes from aliasing, nearest costs the least
+    #[arg(long, default_value = "nearest", value_parser = parse_resampler)]
+    resampler: Resampler,
+    /// Lets Left+Right and Up+Down reach the game, which a real D-pad
+    /// cannot press, for TAS work on the glitches they cause
+    #[arg(long)]
+    allow_opposite_directions: bool,
+    /// Shows the pressed buttons in a corner of the screen, for streams and
+    /// bug reports. Toggled with I
+    #[arg(long)]
+    input_display: bool,
+    /// Records which layer, tile, object and palette drew each pixel and
+    /// shows the one under the mouse pointer in the window title
+    #[arg(long)]
+    inspect_pixels: bool,
+    /// Draws the BG tile grid, the window outline and a map of where the
+    /// screen sits in the 256x256 BG, for lining up tilemaps. Toggled with G
+    #[arg(long)]
+    grid: bool,
+    /// Records the audio of every game played to FILE as .wav. R records
+    /// to recordings/ instead
+    #[arg(long, value_name = "FILE")]
+    record_audio: Option<PathBuf>,
+    /// Logs the writes to the sound registers of every game played to FILE
+    /// as .vgm, for chip music players and trackers
+    #[arg(long, value_name = "FILE")]
+    record_vgm: Option<PathBuf>,
+    /// Logs the CPU's writes to VRAM of every game played to FILE as
+    /// tab-separated values, with the ROM bank, PPU mode and LY of each
+    #[arg(long, value_name = "FILE")]
+    log_vram: Option<PathBuf>,
+    /// Logs only the writes to an address range, e.g. 8000-8fff for tiles
+    #[arg(long, value_name = "START-END", value_parser = parse_address_range, requires = "log_vram")]
+    log_vram_range: Option<Range<u16>>,
+    /// Reports ROM writes without a mapper, VRAM/OAM accesses the PPU
+    /// blocks and OAM DMA from above WRAM, for homebrew development
+    #[arg(long, value_enum, value_name = "ACTION")]
+    strict: Option<Strict>,
+    /// Answers Super Game Boy multiplayer requests, so SGB games can read
+    /// up to four controllers: the numeric keypad and extra gamepads
+    #[arg(long)]
+    sgb: bool,
+    /// What to do while the window is not focused: run, pause, or throttle
+    /// (mute and never run faster than real time)
+    #[arg(long, value_enum, default_value_t = Background::Run)]
+    background: Background,
+    /// Resets and reloads the ROM whenever its file changes
+    #[cfg(feature = "watch")]
+    #[arg(long)]
+    watch: bool,
+    /// Opens a second window with panels for registers, disassembly,
+    /// memory, tiles, OAM, palettes and the event log
+    #[cfg(feature = "debug-ui")]
+    #[arg(long)]
+    debug_ui: bool,
+}
+
+/// What `--strict` does about accesses real hardware would punish.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Strict {
+    /// Log a warning for each.
+    Warn,
+    /// Log a warning and pause at the end of the frame.
+    Break,
+}
+
+/// Save RAM kept when a savestate brings other save RAM along.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum SaveRamPolicy {
+    /// Take the state's, which overwrites the battery save on exit.
+    StateWins,
+    /// Keep the game's, which matches the battery save.
+    DiskWins,
+    /// Ask which to keep.
+    Prompt,
+}
+
+/// How frames are presented and paced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum PresentMode {
+    /// Show each frame as soon as it is drawn. Can tear.
+    Immediate,
+    /// Wait for the display's refresh, which never tears.
+    Vsync,
+    /// Vsync on displays where each frame lasts the same number of
+    /// refreshes, like 60 or 120 Hz ones, and immediate on others, like
+    /// 144 Hz ones, where vsync would judder.
+    Adaptive,
+}
+
+/// How keys are told apart for the game and the hotkeys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum KeyMode {
+    /// By position, named as on a US layout, so X and Z stay side by side
+    /// on AZERTY or Dvorak.
+    Scancode,
+    /// By the symbol the keyboard layout gives them.
+    Keycode,
+}
+
+impl KeyMode {
+    /// The key bindings refer to for a key event. Keycodes go by the
+    /// scancode of the same name, which a US layout would give them.
+    fn key(self, keycode: Option<Keycode>, scancode: Option<Scancode>) -> Option<Scancode> {
+        match self {
+            KeyMode::Scancode => scancode,
+            KeyMode::Keycode => Scancode::from_name(&keycode?.name()),
+        }
+    }
+}
+
+/// Behavior while the window does not have focus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Background {
+    /// Keep running as if focused.
+    Run,
+    /// Stop emulation and audio until focus comes back.
+    Pause,
+    /// Keep running muted, without catching up on late frames.
+    Throttle,
+}
+
+/// Maps a palette name to its index in `BUILTIN_PALETTES`.
+fn parse_palette(name: &str) -> Result<usize, String> {
+    palette::find(name).ok_or_else(|| {
+        let names: Vec<&str> = BUILTIN_PALETTES.iter().map(|p| p.name).collect();
+        format!("expected one of: {}", names.join(", "))
+    })
+}
+
+fn parse_accuracy(name: &str) -> Result<Accuracy, String> {
+    Accuracy::ALL
+        .iter()
+        .copied()
+        .find(|accuracy| accuracy.name() == name)
+        .ok_or_else(|| "expected fast, balanced or accurate".to_string())
+}
+
+fn parse_resampler(name: &str) -> Result<Resampler, String> {
+    Resampler::ALL
+        .iter()
+        .copied()
+        .find(|resampler| resampler.name() == name)
+        .ok_or_else(|| "expected nearest, linear or sinc".to_string())
+}
+
+fn parse_frame_format(name: &str) -> Result<FrameFormat, String> {
+    [FrameFormat::Rgb, FrameFormat::Png]
+        .iter()
+        .copied()
+        .find(|format| format.extension() == name)
+        .ok_or_else(|| "expected rgb or png".to_string())
+}
+
+/// Parses a hex address such as `c000` or `0x0150`.
+fn parse_address(s: &str) -> Result<u16, String> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|e| format!("bad address {}: {}", s, e))
+}
+
+/// Parses an inclusive range of hex addresses such as `c000-c0ff`.
+fn parse_address_range(s: &str) -> Result<Range<u16>, String> {
+    let (start, end) = s.split_once('-').ok_or("expected START-END")?;
+    let (start, end) = (parse_address(start)?, parse_address(end)?);
+    if start > end || end == 0xffff {
+        return Err("expected START <= END < ffff".to_string());
+    }
+    Ok(start..end + 1)
+}
+
+/// Parses a memory watch such as `x=c0a0:u16`.
+fn parse_watch(s: &str) -> Result<Watch, String> {
+    let (name, rest) = s.split_once('=').ok_or("expected NAME=ADDR[:KIND]")?;
+    if name.is_empty() || name.contains(',') {
+        return Err(format!("bad name {:?}", name));
+    }
+    let (addr, kind) = match rest.split_once(':') {
+        Some((addr, kind)) => {
+            let kind = WatchKind::from_name(kind).ok_or_else(|| {
+                let names: Vec<&str> = WatchKind::ALL.iter().map(|k| k.name()).collect();
+                format!(
+                    "unknown kind {}, expected one of: {}",
+                    kind,
+                    names.join(", ")
+                )
+            })?;
+            (addr, kind)
+        }
+        None => (rest, WatchKind::U8),
+    };
+    Ok(Watch {
+        name: name.to_string(),
+        addr: parse_address(addr)?,
+        kind,
+    })
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    u8::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|e| format!("bad byte {}: {}", s, e))
+}
+
+/// Parses an auto-splitter condition such as `bgp:00` or
+/// `tiles:9c00:12,13@4,5`.
+fn parse_split_condition(s: &str) -> Result<SplitCondition, String> {
+    let (kind, rest) = s.split_once(':').ok_or("expected KIND:...")?;
+    let register = match kind {
+        "bgp" => PaletteRegister::Bgp,
+        "obp0" => PaletteRegister::Obp0,
+        "obp1" => PaletteRegister::Obp1,
+        "tiles" => {
+            let (map, rest) = rest.split_once(':').ok_or("expected tiles:MAP:TILES")?;
+            let high_map = match map {
+                "9800" => false,
+                "9c00" => true,
+                _ => return Err(format!("unknown tile map {}, expected 9800 or 9c00", map)),
+            };
+            let (tiles, at) = match rest.split_once('@') {
+                Some((tiles, at)) => {
+                    let (x, y) = at.split_once(',').ok_or("expected @X,Y")?;
+                    let x: u8 = x.parse().map_err(|e| format!("bad column {}: {}", x, e))?;
+                    let y: u8 = y.parse().map_err(|e| format!("bad row {}: {}", y, e))?;
+                    if x >= 32 || y >= 32 {
+                        return Err("expected a column and row below 32".to_string());
+                    }
+                    (tiles, Some((x, y)))
+                }
+                None => (rest, None),
+            };
+            let tiles = tiles
+                .split(',')
+                .map(parse_byte)
+                .collect::<Result<Vec<u8>, String>>()?;
+            if tiles.len() > 32 {
+                return Err("a tile map row has 32 tiles".to_string());
+            }
+            return Ok(SplitCondition::Tiles {
+                high_map,
+                at,
+                tiles,
+            });
+        }
+        _ => {
+            return Err(format!(
+                "unknown condition {}, expected bgp, obp0, obp1 or tiles",
+                kind
+            ))
+        }
+    };
+    Ok(SplitCondition::Palette {
+        register,
+        value: parse_byte(rest)?,
+    })
+}
+
+/// Parses an auto-splitter split such as `boss=tiles:9800:12,13`.
+fn parse_split(s: &str) -> Result<Split, String> {
+    let (name, condition) = s.split_once('=').ok_or("expected NAME=CONDITION")?;
+    if name.is_empty() || name.contains('\t') {
+        return Err(format!("bad name {:?}", name));
+    }
+    Ok(Split {
+        name: name.to_string(),
+        condition: parse_split_condition(condition)?,
+    })
+}
+
+/// Checks that a link device spec such as `reply:00` builds.
+fn parse_link(s: &str) -> Result<String, String> {
+    LinkRegistry::new().create(s)?;
+    Ok(s.to_string())
+}
+
+fn parse_deadzone(s: &str) -> Result<f32, String> {
+    let deadzone: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if (0.0..=1.0).contains(&deadzone) {
+        Ok(deadzone)
+    } else {
+        Err("expected 0 to 1".to_string())
+    }
+}
+
+fn parse_diagonal_angle(s: &str) -> Result<f32, String> {
+    let angle: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if (0.0..=MAX_DIAGONAL_ANGLE).contains(&angle) {
+        Ok(angle)
+    } else {
+        Err(format!("expected 0 to {}", MAX_DIAGONAL_ANGLE))
+    }
+}
+
+fn parse_speed(s: &str) -> Result<f32, String> {
+    let speed: f32 = s.parse().map_err(|e| format!("{}", e))?;
+    if (MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        Ok(speed)
+    } else {
+        Err(format!("expected {} to {}", MIN_SPEED, MAX_SPEED))
+    }
+}
+
+/// Frontend settings that carry over from one game to the next.
+struct Options {
+    /// Settings from the settings file, which runtime changes are saved to.
+    settings: Settings,
+    /// `None` without a config directory, when nothing is saved.
+    settings_path: Option<PathBuf>,
+    /// Index into `BUILTIN_PALETTES`.
+    palette: usize,
+    /// Audio latency for games without their own setting.
+    default_audio_latency: u32,
+    speed: Speed,
+    /// How battery saves are written.
+    save_format: SaveFormat,
+    save_ram_policy: SaveRamPolicy,
+    key_mode: KeyMode,
+    /// Append BESS blocks to savestates.
+    bess: bool,
+    /// Entries kept by the event log, which is off when `None`.
+    event_log: Option<usize>,
+    /// Write a `CrashReport` on a panic or lock-up.
+    crash_report: bool,
+    /// Frames timed by `FrameTimes`, off when `None`.
+    frame_timing: Option<usize>,
+    /// Count memory accesses, per byte within the range if there is one.
+    heatmap: Option<Option<Range<u16>>>,
+    /// Game variables sampled every frame.
+    watch_mem: Vec<Watch>,
+    /// Frames of samples kept for `watch_mem`.
+    watch_mem_frames: usize,
+    /// Conditions checked every frame for auto-splitters.
+    splits: Vec<Split>,
+    /// `LinkRegistry` spec of the device on the link port.
+    link: Option<String>,
+    /// Pause once this many frames have run.
+    run_to_frame: Option<u64>,
+    /// Pause when the PC gets here.
+    break_at: Option<u16>,
+    /// Sleep between frames, or leave it to vsync.
+    pacing: Pacing,
+    /// Loaded from `--dat`.
+    dat: Option<RomDatabase>,
+    /// Built-in quirks with `quirks.toml` over them.
+    quirks: QuirkDatabase,
+    /// ROM given on the command line and the patch for it.
+    patch: Option<(PathBuf, PathBuf)>,
+    background: Background,
+    hotkeys: Hotkeys,
+    /// Seed of the emulator's random numbers.
+    seed: u64,
+    /// Options that trade accuracy for speed.
+    accuracy: Accuracy,
+    resampler: Resampler,
+    /// Power on with random WRAM and HRAM.
+    random_ram: bool,
+    /// Play `BootAnimation` before the game.
+    boot_animation: bool,
+    /// Resolve Left+Right and Up+Down before the game sees them.
+    filter_opposites: bool,
+    /// Draw the buttons the game sees over the frame.
+    input_display: bool,
+    /// Show the source of the pixel under the mouse pointer.
+    inspect_pixels: bool,
+    /// Draw the tile grid overlay.
+    grid: bool,
+    /// Multiplex controllers for SGB games.
+    sgb: bool,
+    /// React to suspicious accesses.
+    strict: Option<Strict>,
+    /// Record all audio to this file from the start of a game.
+    record_audio: Option<PathBuf>,
+    /// Log the sound registers to this file from the start of a game.
+    record_vgm: Option<PathBuf>,
+    /// Log the VRAM writes within the range to this file from the start of
+    /// a game.
+    log_vram: Option<(PathBuf, Range<u16>)>,
+    /// Reload the ROM when its file changes.
+    #[cfg(feature = "watch")]
+    watch: bool,
+    /// Show the debug window next to the game.
+    #[cfg(feature = "debug-ui")]
+    debug_ui: bool,
+}
+
+/// How a game session ended.
+enum SessionEnd {
+    Quit,
+    Menu,
+}
+
+fn translate_scancode(key: Scancode) -> Option<joypad::Key> {
+    match key {
+        Scancode::Down => Some(joypad::Key::Down),
+        Scancode::Up => Some(joypad::Key::Up),
+        Scancode::Left => Some(joypad::Key::Left),
+        Scancode::Right => Some(joypad::Key::Right),
+        Scancode::Return => Some(joypad::Key::Start),
+        Scancode::RShift => Some(joypad::Key::Select),
+        Scancode::X => Some(joypad::Key::A),
+        Scancode::Z => Some(joypad::Key::B),
+        _ => None,
+    }
+}
+
+/// Keys of the second controller, on the numeric keypad, for SGB
+/// multiplayer games.
+fn translate_player2_scancode(key: Scancode) -> Option<joypad::Key> {
+    match key {
+        Scancode::Kp2 => Some(joypad::Key::Down),
+        Scancode::Kp8 => Some(joypad::Key::Up),
+        Scancode::Kp4 => Some(joypad::Key::Left),
+        Scancode::Kp6 => Some(joypad::Key::Right),
+        Scancode::KpEnter => Some(joypad::Key::Start),
+        Scancode::KpPlus => Some(joypad::Key::Select),
+        Scancode::KpPeriod => Some(joypad::Key::A),
+        Scancode::Kp0 => Some(joypad::Key::B),
+        _ => None,
+    }
+}
+
+/// Handles key down event.
+fn handle_keydown(cpu: &mut Cpu, key: Scancode) {
+    if let Some(k) = translate_scancode(key) {
+        // Usually applied by `SdlInput` already
+        if !cpu.mmu.joypad.state().is_pressed(k) {
+            cpu.mmu.joypad.keydown(k)
+        }
+    }
+}
+
+/// Maps keys to the memory editor: arrows, Page Up/Down, Tab for the next
+/// region, Escape to close and 0-9, A-F to type.
+fn translate_editor_keycode(key: Keycode) -> Option<EditorKey> {
+    match key {
+        Keycode::Up => Some(EditorKey::Up),
+        Keycode::Down => Some(EditorKey::Down),
+        Keycode::Left => Some(EditorKey::Left),
+        Keycode::Right => Some(EditorKey::Right),
+        Keycode::PageUp => Some(EditorKey::PageUp),
+        Keycode::PageDown => Some(EditorKey::PageDown),
+        Keycode::Tab => Some(EditorKey::NextRegion),
+        Keycode::Escape => Some(EditorKey::Close),
+        _ => {
+            // Only single characters, or F1 would read as 0xf1
+            let name = key.name();
+            if name.len() != 1 {
+                return None;
+            }
+            let digit = u8::from_str_radix(&name, 16).ok()?;
+            Some(EditorKey::Digit(digit))
+        }
+    }
+}
+
+/// Handles key up event.
+fn handle_keyup(cpu: &mut Cpu, key: Scancode) {
+    if let Some(k) = translate_scancode(key) {
+        cpu.mmu.joypad.keyup(k)
+    }
+}
+
+/// A game controller and how its left stick maps to the D-pad.
+struct Gamepad {
+    controller: GameController,
+    stick: StickMapping,
+}
+
+impl Gamepad {
+    /// Opens the game controllers, one for each of the up to four
+    /// players of SGB multiplayer.
+    fn open_all(sdl_context: &sdl2::Sdl, stick: StickMapping) -> Vec<Self> {
+        let subsystem = match sdl_context.game_controller() {
+            Ok(subsystem) => subsystem,
+            Err(_) => return Vec::new(),
+        };
+        (0..subsystem.num_joysticks().unwrap_or(0))
+            .filter(|&i| subsystem.is_game_controller(i))
+            .filter_map(|i| match subsystem.open(i) {
+                Ok(controller) => {
+                    info!(target: LOG_TARGET, "Using gamepad {}", controller.name());
+                    Some(Gamepad { controller, stick })
+                }
+                Err(e) => {
+                    warn!(target: LOG_TARGET, "Cannot open gamepad: {}", e);
+                    None
+                }
+            })
+            .take(4)
+            .collect()
+    }
+
+    /// Buttons held on the gamepad, with the stick acting as the D-pad.
+    fn buttons(&self) -> ButtonState {
+        let c = &self.controller;
+        let mut buttons = self
+            .stick
+            .directions(c.axis(Axis::LeftX), c.axis(Axis::LeftY));
+        // SDL names face buttons by position on an Xbox pad, so its bottom
+        // A and right B sit where a Game Boy has B and A
+        let mapping = [
+            (Button::DPadDown, joypad::Key::Down),
+            (Button::DPadUp, joypad::Key::Up),
+            (Button::DPadLeft, joypad::Key::Left),
+            (Button::DPadRight, joypad::Key::Right),
+            (Button::Start, joypad::Key::Start),
+            (Button::Back, joypad::Key::Select),
+            (Button::A, joypad::Key::B),
+            (Button::B, joypad::Key::A),
+        ];
+        for (button, key) in mapping {
+            if c.button(button) {
+                buttons.set(key, true);
+            }
+        }
+        buttons
+    }
+}
+
+/// The keys and gamepads held right now, read each time the core polls
+/// rather than at the end of the frame.
+///
+/// Other events stay queued for the main event loop.
+struct SdlInput<'a> {
+    event_pump: &'a mut EventPump,
+    gamepads: &'a [Gamepad],
+    /// The keyboard is left out unless set, e.g. while it edits memory.
+    keyboard: bool,
+    key_mode: KeyMode,
+    /// Spreads the frame over its time in real time, so that the keyboard
+    /// is read between scanlines and presses land within the frame.
+    limiter: &'a mut FrameLimiter,
+    /// Time spent asleep for the pacing.
+    slept: time::Duration,
+}
+
+impl InputProvider for SdlInput<'_> {
+    fn poll(&mut self, at: PollPoint) -> Players {
+        self.slept += self.limiter.wait_for_line(at.line);
+
+        self.event_pump.pump_events();
+        let mut players = Players::default();
+        for (buttons, gamepad) in players.iter_mut().zip(self.gamepads) {
+            *buttons = gamepad.buttons();
+        }
+        if self.keyboard {
+            for scancode in self
+                .event_pump
+                .keyboard_state()
+                .pressed_scancodes()
+                .filter_map(|scancode| {
+                    self.key_mode
+                        .key(Keycode::from_scancode(scancode), Some(scancode))
+                })
+            {
+                if let Some(key) = translate_scancode(scancode) {
+                    players[0].set(key, true);
+                } else if let Some(key) = translate_player2_scancode(scancode) {
+                    players[1].set(key, true);
+                }
+            }
+        }
+        players
+    }
+}
+
+/// Flips the visibility of a PPU layer for debugging.
+fn toggle_layer(cpu: &mut Cpu, layer: Layer) {
+    let visible = !cpu.mmu.ppu.is_layer_visible(layer);
+    cpu.mmu.ppu.set_layer_visible(layer, visible);
+    info!(
+        target: LOG_TARGET,
+        "{:?} layer {}",
+        layer,
+        if visible { "shown" } else { "hidden" }
+    );
+}
+
+/// Writes the screen to `SCREENSHOT_DIR` as a PPM image.
+fn save_screenshot(cpu: &Cpu) {
+    let path = Path::new(SCREENSHOT_DIR).join(format!(
+        "{}-{}.ppm",
+        cpu.game_id(),
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    let mut data = format!("P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT).into_bytes();
+    data.extend_from_slice(cpu.mmu.ppu.get_frame());
+    let result = fs::create_dir_all(SCREENSHOT_DIR).and_then(|_| fs::write(&path, data));
+    match result {
+        Ok(()) => info!(target: LOG_TARGET, "Saved screenshot to {:?}", path),
+        Err(e) => warn!(target: LOG_TARGET, "Failed to save screenshot to {:?}: {}", path, e),
+    }
+}
+
+/// Starts writing the audio to `path`, at the rate the APU produces it.
+fn start_recording(cpu: &Cpu, path: &Path) -> Option<WavWriter<BufWriter<fs::File>>> {
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| WavWriter::create(path, cpu.mmu.apu.sample_rate()));
+    match result {
+        Ok(wav) => {
+            info!(target: LOG_TARGET, "Recording audio to {:?}", path);
+            Some(wav)
+        }
+        Err(e) => {
+            warn!(target: LOG_TARGET, "Cannot record to {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+fn stop_recording(cpu: &Cpu, wav: WavWriter<BufWriter<fs::File>>) {
+    let seconds = wav.duration(cpu.mmu.apu.sample_rate());
+    match wav.finish() {
+        Ok(_) => info!(target: LOG_TARGET, "Recorded {:.1} s of audio", seconds),
+        Err(e) => warn!(target: LOG_TARGET, "Failed to finish the recording: {}", e),
+    }
+}
+
+/// Starts logging the sound registers to `path` as .vgm.
+fn start_vgm_log(cpu: &mut Cpu, path: &Path) -> Option<VgmWriter<BufWriter<fs::File>>> {
+    match VgmWriter::create(path) {
+        Ok(vgm) => {
+            info!(target: LOG_TARGET, "Logging sound registers to {:?}", path);
+            cpu.mmu.start_apu_log();
+            Some(vgm)
+        }
+        Err(e) => {
+            warn!(target: LOG_TARGET, "Cannot log to {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+fn stop_vgm_log(cpu: &mut Cpu, mut vgm: VgmWriter<BufWriter<fs::File>>) {
+    let cycles = cpu.mmu.apu_log_cycles().unwrap_or(0);
+    let result = vgm
+        .write(&cpu.mmu.take_apu_writes())
+        .and_then(|_| vgm.finish(cycles));
+    cpu.mmu.stop_apu_log();
+    match result {
+        Ok(_) => info!(target: LOG_TARGET, "Logged {:.1} s of music", cycles as f64 / CPU_CLOCK_HZ),
+        Err(e) => warn!(target: LOG_TARGET, "Failed to finish the sound log: {}", e),
+    }
+}
+
+/// Starts logging the VRAM writes within `range` to `path`.
+fn start_vram_log(cpu: &mut Cpu, path: &Path, range: Range<u16>) -> Option<BufWriter<fs::File>> {
+    let result = fs::File::create(path)
+        .map(BufWriter::new)
+        .and_then(|mut w| {
+            writeln!(w, "cycles\tly\tmode\trom bank\taddr\tvalue")?;
+            Ok(w)
+        });
+    match result {
+        Ok(w) => {
+            info!(target: LOG_TARGET, "Logging VRAM writes to {:?}", path);
+            cpu.mmu.start_vram_log(range);
+            Some(w)
+        }
+        Err(e) => {
+            warn!(target: LOG_TARGET, "Cannot log to {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Writes the VRAM writes logged since the last call, one per line.
+fn write_vram_log(cpu: &mut Cpu, w: &mut impl Write) -> io::Result<()> {
+    for write in cpu.mmu.take_vram_writes() {
+        writeln!(
+            w,
+            "{}\t{}\t{}\t{:02x}\t{:04x}\t{:02x}",
+            write.cycles, write.ly, write.mode, write.rom_bank, write.addr, write.value
+        )?;
+    }
+    Ok(())
+}
+
+fn stop_vram_log(cpu: &mut Cpu, mut w: BufWriter<fs::File>) {
+    let result = write_vram_log(cpu, &mut w).and_then(|_| w.flush());
+    cpu.mmu.stop_vram_log();
+    if let Err(e) = result {
+        warn!(target: LOG_TARGET, "Failed to finish the VRAM log: {}", e);
+    }
+}
+
+/// Switches to the next built-in palette.
+fn cycle_palette(cpu: &mut Cpu, palette: &mut usize) {
+    *palette = (*palette + 1) % BUILTIN_PALETTES.len();
+    cpu.mmu
+        .ppu
+        .set_dmg_palette(BUILTIN_PALETTES[*palette].shades);
+    info!(target: LOG_TARGET, "Palette: {}", BUILTIN_PALETTES[*palette].name);
+}
+
+/// Writes the settings file after a change made at runtime.
+fn save_settings(options: &Options) {
+    if let Some(path) = &options.settings_path {
+        if let Err(e) = options.settings.save(path) {
+            warn!(target: LOG_TARGET, "Failed to save settings to {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Reads the settings file and the environment over it.
+fn load_settings(path: Option<&Path>) -> (Settings, Settings) {
+    let saved = path.map_or_else(Settings::default, |path| {
+        Settings::load(path).unwrap_or_else(|e| {
+            warn!(target: LOG_TARGET, "Ignoring {:?}: {}", path, e);
+            Settings::default()
+        })
+    });
+    let mut current = saved;
+    for error in current.apply_env(|var| env::var(var).ok()) {
+        warn!(target: LOG_TARGET, "Ignoring {}", error);
+    }
+    (saved, current)
+}
+
+/// `file`, e.g. `hotkeys.cfg`, in the working directory if there is one,
+/// else in the config directory.
+fn config_file(config_dir: Option<&Path>, file: &str) -> PathBuf {
+    let local = PathBuf::from(file);
+    match config_dir {
+        Some(dir) if !local.exists() => dir.join(file),
+        _ => local,
+    }
+}
+
+/// Lists the ROM files in `dir`, sorted by name.
+fn list_roms(dir: &str) -> Vec<PathBuf> {
+    let mut roms: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                matches!(
+                    path.extension().and_then(|ext| ext.to_str()),
+                    Some("gb") | Some("gbc")
+                )
+            })
+            .collect(),
+        Err(e) => {
+            warn!(target: LOG_TARGET, "Cannot read ROM directory {}: {}", dir, e);
+            Vec::new()
+        }
+    };
+    roms.sort();
+    roms
+}
+
+/// Path of a savestate slot. Slots are kept apart per game.
+fn save_state_path(cpu: &Cpu, slot: u8) -> PathBuf {
+    Path::new(SAVE_STATE_DIR)
+        .join(cpu.game_id())
+        .join(format!("slot{}.state", slot))
+}
+
+/// Path of the timestamp, title and thumbnail saved with a slot.
+fn slot_info_path(cpu: &Cpu, slot: u8) -> PathBuf {
+    save_state_path(cpu, slot).with_extension("meta")
+}
+
+/// Reads what is known about a slot, `None` if it is empty.
+fn load_slot_info(cpu: &Cpu, slot: u8) -> Option<SlotInfo> {
+    let path = slot_info_path(cpu, slot);
+    let data = fs::read(&path).ok()?;
+    match SlotInfo::from_bytes(&data) {
+        Ok(info) => Some(info),
+        Err(e) => {
+            warn!(target: LOG_TARGET, "Failed to read {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Logs what a newly selected slot holds and returns its thumbnail.
+fn select_slot(cpu: &Cpu, slot: u8) -> Option<Thumbnail> {
+    match load_slot_info(cpu, slot) {
+        Some(info) => {
+            info!(
+                target: LOG_TARGET,
+                "Savestate slot {}: {} ({})",
+                slot,
+                info.saved_at.format("%Y-%m-%d %H:%M:%S"),
+                info.title
+            );
+            Some(info.thumbnail)
+        }
+        None => {
+            info!(target: LOG_TARGET, "Savestate slot {}: empty", slot);
+            None
+        }
+    }
+}
+
+/// Draws `thumbnail` with a white border in the top-right corner of `buf`.
+fn draw_thumbnail(buf: &mut [u8], pitch: usize, thumbnail: &Thumbnail) {
+    const MARGIN: usize = 4;
+    let left = 160 - MARGIN - thumbnail.width;
+    for y in 0..thumbnail.height + 2 {
+        let row = &mut buf[(MARGIN - 1 + y) * pitch..];
+        for x in 0..thumbnail.width + 2 {
+            let dst = &mut row[(left - 1 + x) * 3..(left + x) * 3];
+            if y == 0 || x == 0 || y == thumbnail.height + 1 || x == thumbnail.width + 1 {
+                dst.fill(0xff);
+            } else {
+                let src = ((y - 1) * thumbnail.width + x - 1) * 3;
+                dst.copy_from_slice(&thumbnail.pixels[src..src + 3]);
+            }
+        }
+    }
+}
+
+/// Describes where the pixel at `x`, `y` came from, for `--inspect-pixels`.
+fn describe_pixel(x: usize, y: usize, source: Option<PixelSource>) -> String {
+    let source = match source {
+        Some(source) => source,
+        None => return format!("({}, {}) hidden layer", x, y),
+    };
+    let layer = match (source.layer, source.sprite) {
+        (Layer::Sprites, Some(index)) => format!("object {}", index),
+        (Layer::Window, _) => "window".to_string(),
+        _ => "background".to_string(),
+    };
+    let tile = match source.tile {
+        Some(tile) => format!("tile 0x{:02x}", tile),
+        None => "blank".to_string(),
+    };
+    let palette = match source.palette {
+        PaletteRegister::Bgp => "BGP",
+        PaletteRegister::Obp0 => "OBP0",
+        PaletteRegister::Obp1 => "OBP1",
+    };
+    format!(
+        "({}, {}) {} {}, color {} via {}",
+        x, y, layer, tile, source.color, palette
+    )
+}
+
+/// Fills a rectangle of the RGB24 frame `buf` with a gray `shade`.
+fn fill_rect(buf: &mut [u8], pitch: usize, (x, y, w, h): (usize, usize, usize, usize), shade: u8) {
+    for row in buf[y * pitch..].chunks_mut(pitch).take(h) {
+        row[x * 3..(x + w) * 3].fill(shade);
+    }
+}
+
+/// Mixes `color` half into the pixel at `x`, `y`, so lines drawn over the
+/// game leave it visible.
+fn blend_pixel(buf: &mut [u8], pitch: usize, x: usize, y: usize, color: [u8; 3]) {
+    let pixel = &mut buf[y * pitch + x * 3..y * pitch + x * 3 + 3];
+    for (channel, value) in pixel.iter_mut().zip(color) {
+        *channel = ((*channel as u16 + value as u16) / 2) as u8;
+    }
+}
+
+/// Draws the BG tile grid in red, the window outline in green, and in the
+/// top-left corner a map of the 32x32 tile BG with the visible part in blue.
+fn draw_grid(buf: &mut [u8], pitch: usize, (scx, scy): (u8, u8), window: Option<(u8, u8)>) {
+    const GRID: [u8; 3] = [0xff, 0x00, 0x00];
+    const WINDOW: [u8; 3] = [0x00, 0xc0, 0x00];
+    const VIEWPORT: [u8; 3] = [0x00, 0x40, 0xff];
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            if (x + scx as usize) & 7 == 0 || (y + scy as usize) & 7 == 0 {
+                blend_pixel(buf, pitch, x, y, GRID);
+            }
+        }
+    }
+
+    if let Some((wx, wy)) = window {
+        let (left, top) = (wx as isize - 7, wy as isize);
+        for y in top.max(0)..SCREEN_HEIGHT as isize {
+            for x in left.max(0)..SCREEN_WIDTH as isize {
+                if x == left || y == top {
+                    blend_pixel(buf, pitch, x as usize, y as usize, WINDOW);
+                }
+            }
+        }
+    }
+
+    // One pixel per tile; the visible 20x18 tiles wrap around the edges
+    const MAP: usize = 32;
+    let (view_x, view_y) = (scx as usize / 8, scy as usize / 8);
+    for y in 0..MAP {
+        for x in 0..MAP {
+            let dx = (x + MAP - view_x) % MAP;
+            let dy = (y + MAP - view_y) % MAP;
+            let inside = dx < SCREEN_WIDTH / 8 && dy < SCREEN_HEIGHT / 8;
+            let color = if inside { VIEWPORT } else { [0x00; 3] };
+            blend_pixel(buf, pitch, 1 + x, 1 + y, color);
+        }
+    }
+}
+
+/// Draws the D-pad, B, A, Select and Start in the bottom-left corner,
+/// pressed ones lit. Each button gets a black border to stand out from
+/// any background.
+fn draw_input_display(buf: &mut [u8], pitch: usize, buttons: ButtonState) {
+    const LEFT: usize = 4;
+    const TOP: usize = SCREEN_HEIGHT - 4 - 12;
+    // Position and size of each button, relative to the top left
+    const LAYOUT: [(joypad::Key, (usize, usize, usize, usize)); 8] = [
+        (joypad::Key::Up, (4, 0, 4, 4)),
+        (joypad::Key::Left, (0, 4, 4, 4)),
+        (joypad::Key::Right, (8, 4, 4, 4)),
+        (joypad::Key::Down, (4, 8, 4, 4)),
+        (joypad::Key::B, (18, 5, 4, 4)),
+        (joypad::Key::A, (24, 2, 4, 4)),
+        (joypad::Key::Select, (32, 9, 6, 2)),
+        (joypad::Key::Start, (40, 9, 6, 2)),
+    ];
+    for (key, (x, y, w, h)) in LAYOUT {
+        let (x, y) = (LEFT + x, TOP + y);
+        fill_rect(buf, pitch, (x - 1, y - 1, w + 2, h + 2), 0x00);
+        let shade = if buttons.is_pressed(key) { 0xff } else { 0x60 };
+        fill_rect(buf, pitch, (x, y, w, h), shade);
+    }
+}
+
+fn save_state(cpu: &Cpu, slot: u8, bess: bool) {
+    let path = save_state_path(cpu, slot);
+    let data = if bess {
+        cpu.save_state_bess()
+    } else {
+        cpu.save_state()
+    };
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, data));
+    match result {
+        Ok(()) => info!(target: LOG_TARGET, "Saved state to {:?}", path),
+        Err(e) => warn!(target: LOG_TARGET, "Failed to save state to {:?}: {}", path, e),
+    }
+    let info_path = slot_info_path(cpu, slot);
+    if let Err(e) = fs::write(&info_path, SlotInfo::capture(cpu).to_bytes()) {
+        warn!(target: LOG_TARGET, "Failed to save {:?}: {}", info_path, e);
+    }
+}
+
+fn load_state(cpu: &mut Cpu, slot: u8, options: &Options, window: &Window) {
+    let path = save_state_path(cpu, slot);
+    match fs::read(&path) {
+        Ok(data) => {
+            // The battery save gets the game's RAM before the state replaces
+            // it, so an old state cannot take progress with it unasked
+            let ram = cpu.mmu.cartridge.ram().to_vec();
+            if !ram.is_empty() {
+                cpu.mmu.cartridge.write_save_data(options.save_format);
+            }
+            let result = match cpu.load_state(&data) {
+                // States from other emulators only have the BESS blocks in common
+                Err(StateError::BadMagic) => cpu.load_state_bess(&data),
+                result => result,
+            };
+            match result {
+                Ok(()) => {
+                    info!(target: LOG_TARGET, "Loaded state from {:?}", path);
+                    if cpu.mmu.cartridge.ram() != ram.as_slice() {
+                        resolve_save_ram(cpu, &ram, options.save_ram_policy, window);
+                    }
+                }
+                Err(e) => warn!(target: LOG_TARGET, "Failed to load state from {:?}: {}", path, e),
+            }
+        }
+        Err(e) => warn!(target: LOG_TARGET, "Failed to read state {:?}: {}", path, e),
+    }
+}
+
+/// Decides between the save RAM a state brought along and `ram`, the
+/// game's from before.
+fn resolve_save_ram(cpu: &mut Cpu, ram: &[u8], policy: SaveRamPolicy, window: &Window) {
+    let keep_game_ram = match policy {
+        SaveRamPolicy::StateWins => false,
+        SaveRamPolicy::DiskWins => true,
+        SaveRamPolicy::Prompt => ask_keep_game_ram(window),
+    };
+    if keep_game_ram {
+        cpu.mmu.cartridge.ram_mut().copy_from_slice(ram);
+        info!(target: LOG_TARGET, "Kept the save RAM the game had");
+    } else {
+        warn!(
+            target: LOG_TARGET,
+            "Took the save RAM from the state, the battery save gets it on exit"
+        );
+    }
+}
+
+/// Asks whether to keep the game's save RAM over the state's. Closing the
+/// box keeps it.
+fn ask_keep_game_ram(window: &Window) -> bool {
+    let buttons = [
+        ButtonData {
+            flags: MessageBoxButtonFlag::RETURNKEY_DEFAULT
+                | MessageBoxButtonFlag::ESCAPEKEY_DEFAULT,
+            button_id: 0,
+            text: "Keep current save",
+        },
+        ButtonData {
+            flags: MessageBoxButtonFlag::NOTHING,
+            button_id: 1,
+            text: "Use the state's save",
+        },
+    ];
+    let clicked = messagebox::show_message_box(
+        MessageBoxFlag::WARNING,
+        &buttons,
+        "Save data differs",
+        "This savestate holds other save data than the game has now. Using \
+         the state's replaces the battery save when the game is closed.",
+        window,
+        None,
+    );
+    match clicked {
+        Ok(ClickedButton::CustomButton(button)) => button.button_id == 0,
+        Ok(ClickedButton::CloseButton) => true,
+        Err(e) => {
+            warn!(target: LOG_TARGET, "Cannot ask about the save data: {}", e);
+            true
+        }
+    }
+}
+
+/// Applies the patch file `patch` to `rom`.
+fn apply_patch(rom: &[u8], patch: &Path) -> Result<Vec<u8>, String> {
+    let data = fs::read(patch).map_err(|e| format!("{:?}: {}", patch, e))?;
+    patch::apply(rom, &data).map_err(|e| format!("{:?}: {}", patch, e))
+}
+
+/// Reads a ROM file and applies `patch` to it.
+fn read_rom(rom_path: &str, patch: Option<&Path>) -> Result<Vec<u8>, String> {
+    let rom = fs::read(rom_path).map_err(|e| e.to_string())?;
+    match patch {
+        Some(patch) => apply_patch(&rom, patch),
+        None => Ok(rom),
+    }
+}
+
+/// Draws a bar filled to `read` of `total` across the middle of the window.
+fn draw_loading(canvas: &mut WindowCanvas, read: u64, total: u64) {
+    let (width, height) = canvas.output_size().unwrap();
+    let (bar_width, bar_height) = (width * 3 / 4, (height / 24).max(4));
+    let (x, y) = (
+        ((width - bar_width) / 2) as i32,
+        ((height - bar_height) / 2) as i32,
+    );
+    let filled = (bar_width as u64 * read.min(total))
+        .checked_div(total)
+        .unwrap_or(0) as u32;
+
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    canvas.set_draw_color(Color::RGB(0xff, 0xff, 0xff));
+    let _ = canvas.draw_rect(Rect::new(x, y, bar_width, bar_height));
+    if filled > 0 {
+        let _ = canvas.fill_rect(Rect::new(x, y, filled, bar_height));
+    }
+    canvas.present();
+}
+
+/// Creates the `Cpu` for a ROM file, patched if `patch` is given. A patch
+/// that does not apply is skipped with a warning.
+///
+/// The file is read on another thread while the window keeps handling
+/// events, with a progress bar once loading takes a moment. Fails with the
+/// way the session should end if the user quits or the ROM cannot be used.
+fn load_cpu(
+    rom_path: &str,
+    patch: Option<&Path>,
+    quirks: &QuirkDatabase,
+    canvas: &mut WindowCanvas,
+    event_pump: &mut EventPump,
+) -> Result<Cpu, SessionEnd> {
+    let started = time::Instant::now();
+    let mut loader = if rom_path == STDIN_PATH {
+        RomLoader::from_reader(io::stdin())
+    } else {
+        RomLoader::spawn(rom_path)
+    };
+    let result = loop {
+        match loader.poll() {
+            LoadStatus::Done(result) => break result,
+            LoadStatus::Loading { read, total } => {
+                if started.elapsed() >= LOADING_INDICATOR_DELAY {
+                    draw_loading(canvas, read, total);
+                }
+            }
+        }
+        for event in event_pump.poll_iter() {
+            if let Event::Quit { .. } = event {
+                return Err(SessionEnd::Quit);
+            }
+        }
+        thread::sleep(time::Duration::from_millis(16));
+    };
+    let rom = result.map_err(|e| {
+        warn!(target: LOG_TARGET, "Cannot read {}: {}", rom_path, e);
+        SessionEnd::Menu
+    })?;
+    debug!(target: LOG_TARGET, "Read {} in {:?}", rom_path, started.elapsed());
+
+    let mut rom = match patch {
+        Some(patch) => match apply_patch(&rom, patch) {
+            Ok(patched) => {
+                info!(target: LOG_TARGET, "Applied patch {:?}", patch);
+                patched
+            }
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Running unpatched, cannot apply {}", e);
+                rom
+            }
+        },
+        None => rom,
+    };
+    let quirk = quirks.lookup(&rom).cloned();
+    if let Some(quirk) = &quirk {
+        info!(target: LOG_TARGET, "Applying quirks for {}", quirk.title);
+        quirk.patch_rom(&mut rom);
+    }
+    let mut cpu = Cpu::with_rom(rom).map_err(|e| {
+        warn!(target: LOG_TARGET, "Cannot load {}: {}", rom_path, e);
+        SessionEnd::Menu
+    })?;
+    if let Some(quirk) = &quirk {
+        quirk.apply(&mut cpu);
+    }
+    Ok(cpu)
+}
+
+/// Reads the ROM again from disk, keeping cartridge RAM and the running machine.
+fn reload_rom(cpu: &mut Cpu, rom_path: &str, patch: Option<&Path>) -> bool {
+    let result = read_rom(rom_path, patch)
+        .and_then(|rom| cpu.mmu.reload_rom(rom).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => {
+            info!(target: LOG_TARGET, "Reloaded {}", rom_path);
+            true
+        }
+        Err(e) => {
+            warn!(target: LOG_TARGET, "Failed to reload {}: {}", rom_path, e);
+            false
+        }
+    }
+}
+
+/// Notices when the ROM file is rewritten, e.g. by rgblink.
+#[cfg(feature = "watch")]
+struct RomWatcher {
+    // Events stop when the watcher is dropped
+    _watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    file_name: OsString,
+    changed_at: Option<time::Instant>,
+}
+
+#[cfg(feature = "watch")]
+impl RomWatcher {
+    fn new(rom_path: &str) -> notify::Result<Self> {
+        use notify::Watcher;
+
+        let path = Path::new(rom_path);
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        // Tools may replace the file instead of writing it in place, which
+        // would end a watch on the file itself
+        watcher.watch(dir, notify::RecursiveMode::NonRecursive)?;
+        Ok(RomWatcher {
+            _watcher: watcher,
+            events,
+            file_name: path.file_name().unwrap_or_default().to_owned(),
+            changed_at: None,
+        })
+    }
+
+    /// Returns true once the ROM has settled after a change.
+    fn poll(&mut self) -> bool {
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+                    let file_name = Some(self.file_name.as_os_str());
+                    if event.paths.iter().any(|p| p.file_name() == file_name) {
+                        self.changed_at = Some(time::Instant::now());
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!(target: LOG_TARGET, "Watch error: {}", e),
+            }
+        }
+        match self.changed_at {
+            Some(at) if at.elapsed() >= WATCH_SETTLE => {
+                self.changed_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Writes the event log to `event_logs/<game id>.log`.
+fn dump_event_log(cpu: &Cpu) {
+    let log = match cpu.mmu.event_log() {
+        Some(log) => log,
+        None => {
+            warn!(target: LOG_TARGET, "Event log is off, start with --event-log N");
+            return;
+        }
+    };
+    let path = Path::new(EVENT_LOG_DIR).join(format!("{}.log", cpu.game_id()));
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, log.to_string()));
+    match result {
+        Ok(()) => info!(target: LOG_TARGET, "Wrote event log to {:?}", path),
+        Err(e) => warn!(target: LOG_TARGET, "Failed to write event log to {:?}: {}", path, e),
+    }
+}
+
+/// Writes a crash report to `crash_reports/<game id>-<time>.zip`.
+fn write_crash_report(cpu: &Cpu, reason: &str) {
+    let path = Path::new(CRASH_REPORT_DIR).join(format!(
+        "{}-{}.zip",
+        cpu.game_id(),
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    let report = CrashReport::capture(cpu, reason);
+    let result = fs::create_dir_all(CRASH_REPORT_DIR)
+        .and_then(|_| fs::File::create(&path))
+        .and_then(|file| report.write_zip(BufWriter::new(file)));
+    match result {
+        Ok(()) => warn!(
+            target: LOG_TARGET,
+            "Wrote a crash report to {:?}, please attach it to the issue", path
+        ),
+        Err(e) => warn!(target: LOG_TARGET, "Failed to write a crash report to {:?}: {}", path, e),
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Writes the frame times to `frame_times/<game id>.csv` and logs a summary.
+fn dump_frame_times(cpu: &Cpu, frame_times: Option<&FrameTimes>) {
+    let frame_times = match frame_times {
+        Some(frame_times) => frame_times,
+        None => {
+            warn!(target: LOG_TARGET, "Frame timing is off, start with --frame-timing N");
+            return;
+        }
+    };
+    info!(target: LOG_TARGET, "Frame times, {}", frame_times.summary());
+    let path = Path::new(FRAME_TIMES_DIR).join(format!("{}.csv", cpu.game_id()));
+    let result =
+        fs::create_dir_all(FRAME_TIMES_DIR).and_then(|_| fs::write(&path, frame_times.to_csv()));
+    match result {
+        Ok(()) => {
+            info!(target: LOG_TARGET, "Wrote {} frame times to {:?}", frame_times.len(), path)
+        }
+        Err(e) => warn!(target: LOG_TARGET, "Failed to write frame times to {:?}: {}", path, e),
+    }
+}
+
+/// Writes the heatmap to `heatmaps/<game id>.csv` and `.ppm`.
+fn write_heatmap(cpu: &Cpu) {
+    let heatmap = match cpu.mmu.heatmap() {
+        Some(heatmap) => heatmap,
+        None => return,
+    };
+    let dir = Path::new(HEATMAP_DIR);
+    let csv = dir.join(format!("{}.csv", cpu.game_id()));
+    let ppm = dir.join(format!("{}.ppm", cpu.game_id()));
+    let create = |path: &Path| fs::File::create(path).map(BufWriter::new);
+    let result = fs::create_dir_all(dir)
+        .and_then(|_| heatmap.write_csv(create(&csv)?))
+        .and_then(|_| heatmap.write_ppm(create(&ppm)?));
+    match result {
+        Ok(()) => info!(target: LOG_TARGET, "Wrote heatmap to {:?} and {:?}", csv, ppm),
+        Err(e) => warn!(target: LOG_TARGET, "Failed to write heatmap to {:?}: {}", dir, e),
+    }
+}
+
+/// Writes the memory watch samples to `memory_watch/<game id>.csv`.
+fn write_memory_watch(cpu: &Cpu, watch: &MemoryWatch) {
+    let dir = Path::new(MEMORY_WATCH_DIR);
+    let path = dir.join(format!("{}.csv", cpu.game_id()));
+    let result = fs::create_dir_all(dir)
+        .and_then(|_| fs::File::create(&path))
+        .and_then(|file| watch.write_csv(BufWriter::new(file)));
+    match result {
+        Ok(()) => {
+            info!(target: LOG_TARGET, "Wrote {} frames of memory watch to {:?}", watch.len(), path)
+        }
+        Err(e) => warn!(target: LOG_TARGET, "Failed to write memory watch to {:?}: {}", path, e),
+    }
+}
+
+/// Path of the per-game audio latency setting.
+fn audio_latency_path(cpu: &Cpu) -> PathBuf {
+    Path::new(GAME_SETTINGS_DIR)
+        .join(cpu.game_id())
+        .join("audio_latency_ms")
+}
+
+/// Returns the game's own audio latency, or `default` if it has none.
+fn load_audio_latency(cpu: &Cpu, default: u32) -> u32 {
+    fs::read_to_string(audio_latency_path(cpu))
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+        .map_or(default, |ms| {
+            ms.clamp(MIN_AUDIO_LATENCY_MS, MAX_AUDIO_LATENCY_MS)
+        })
+}
+
+/// Changes the audio latency by `delta` steps and remembers it for the game.
+fn adjust_audio_latency(cpu: &Cpu, latency_ms: &mut u32, delta: i32) {
+    *latency_ms = (*latency_ms as i32 + delta * AUDIO_LATENCY_STEP_MS as i32)
+        .clamp(MIN_AUDIO_LATENCY_MS as i32, MAX_AUDIO_LATENCY_MS as i32) as u32;
+    info!(target: LOG_TARGET, "Audio latency: {} ms", latency_ms);
+
+    let path = audio_latency_path(cpu);
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, latency_ms.to_string()));
+    if let Err(e) = result {
+        warn!(target: LOG_TARGET, "Failed to save audio latency to {:?}: {}", path, e);
+    }
+}
+
+/// Runs the rest of the frame like `Cpu::run_frame`, but stops early when
+/// the PC reaches `break_at`. Returns the instructions executed and whether
+/// it stopped there.
+fn run_frame_to(
+    cpu: &mut Cpu,
+    rewind: &mut Rewind,
+    input: &mut SdlInput,
+    break_at: Option<u16>,
+) -> (u64, bool) {
+    let mut steps = 0;
+    loop {
+        steps += 1;
+        let done = rewind.step_frame(cpu, input);
+        if break_at.is_some_and(|addr| cpu.registers().pc == addr) {
+            return (steps, true);
+        }
+        if done {
+            return (steps, false);
+        }
+    }
+}
+
+/// Goes back one instruction for the step-back hotkey.
+fn step_back(cpu: &mut Cpu, rewind: &mut Rewind) {
+    match rewind.step_back(cpu) {
+        Ok(true) => {
+            // Sound made again on the way is not played
+            cpu.mmu.take_audio_samples();
+            log_step(cpu, rewind);
+        }
+        Ok(false) => info!(
+            target: LOG_TARGET,
+            "Cannot step back further than {} frames",
+            REWIND_FRAMES
+        ),
+        Err(e) => warn!(target: LOG_TARGET, "Cannot step back: {}", e),
+    }
+}
+
+fn log_step(cpu: &Cpu, rewind: &Rewind) {
+    info!(
+        target: LOG_TARGET,
+        "PC 0x{:04x} in frame {}, {} instructions to step back",
+        cpu.registers().pc,
+        cpu.frames(),
+        rewind.depth()
+    );
+}
+
+/// Queues one frame of samples, dropping it when more than `latency_ms`
+/// of audio is already waiting so the delay cannot build up.
+fn queue_audio(queue: &AudioQueue<i16>, samples: &[i16], latency_ms: u32) {
+    let spec = queue.spec();
+    let bytes_per_ms =
+        spec.freq as u32 * spec.channels as u32 * std::mem::size_of::<i16>() as u32 / 1000;
+    if queue.size() < latency_ms * bytes_per_ms {
+        queue.queue(samples);
+    } else {
+        debug!(target: LOG_TARGET, "Audio queue full, dropping {} samples", samples.len());
+    }
+}
+
+/// Sleeps until the window gets focus back, with audio paused. Returns
+/// `false` if the window was closed instead.
+fn wait_for_focus(event_pump: &mut EventPump, queue: &AudioQueue<i16>) -> bool {
+    info!(target: LOG_TARGET, "Paused in the background");
+    queue.pause();
+    loop {
+        match event_pump.wait_event() {
+            Event::Quit { .. } => return false,
+            Event::Window {
+                win_event: WindowEvent::FocusGained,
+                ..
+            } => break,
+            _ => (),
+        }
+    }
+    queue.resume();
+    info!(target: LOG_TARGET, "Resumed");
+    true
+}
+
+/// Matches the audio to `speed`. The APU produces proportionally more
+/// samples per frame so the sound slows down along with the picture.
+fn apply_speed(cpu: &mut Cpu, queue: &AudioQueue<i16>, limiter: &mut FrameLimiter, speed: Speed) {
+    limiter.set_speed(speed);
+    let rate = queue.spec().freq as f32 / speed.factor();
+    cpu.mmu.apu.set_sample_rate(rate.round() as u32);
+    info!(target: LOG_TARGET, "Speed: {}x", speed.factor());
+}
+
+/// Runs `frames` frames without input and writes each to `dir`.
+fn run_dump_frames(
+    rom_path: &str,
+    frames: u64,
+    accuracy: Accuracy,
+    dir: &Path,
+    format: FrameFormat,
+) {
+    let mut cpu = Cpu::new(rom_path);
+    cpu.set_accuracy(accuracy.options());
+    let mut dumper = match FrameDumper::create(dir, format) {
+        Ok(dumper) => dumper,
+        Err(e) => {
+            warn!(target: LOG_TARGET, "Cannot create {:?}: {}", dir, e);
+            return;
+        }
+    };
+    for _ in 0..frames {
+        cpu.run_frame_with(|_, _| {});
+        if let Err(e) = dumper.write(cpu.mmu.ppu.get_frame()) {
+            warn!(target: LOG_TARGET, "Stopped dumping frames: {}", e);
+            break;
+        }
+    }
+    info!(
+        target: LOG_TARGET,
+        "Wrote {} frames to {:?}",
+        dumper.frames(),
+        dir
+    );
+}
+
+/// Runs `frames` frames unthrottled and prints frames per second and the
+/// speed relative to real hardware.
+fn run_bench(rom_path: &str, frames: u32, accuracy: Accuracy, render: bool) {
+    let mut cpu = Cpu::new(rom_path);
+    cpu.set_accuracy(accuracy.options());
+    cpu.mmu.ppu.set_rendering(render);
+
+    let start = time::Instant::now();
+    for _ in 0..frames {
+        cpu.run_frame_with(|_, _| {});
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let emulated = frames as f64 * CYCLES_PER_FRAME as f64 / CPU_CLOCK_HZ;
+    println!(
+        "{} frames in {:.3} s: {:.1} frames/s, {:.2}x realtime",
+        frames,
+        elapsed,
+        frames as f64 / elapsed,
+        emulated / elapsed
+    );
+    println!("{}", cpu.stats());
+}
+
+/// Prints the differences between two savestates, one per line.
+fn run_diff_states(rom_path: &str, a: &Path, b: &Path) {
+    let read =
+        |path: &Path| fs::read(path).unwrap_or_else(|e| panic!("Cannot read {:?}: {}", path, e));
+    let mut cpu = Cpu::new(rom_path);
+    match state_diff::diff(&mut cpu, &read(a), &read(b)) {
+        Ok(diffs) if diffs.is_empty() => println!("The states are identical"),
+        Ok(diffs) => diffs.iter().for_each(|d| println!("{}", d)),
+        Err(e) => eprintln!("Cannot compare the states: {}", e),
+    }
+}
+
+/// Playtime summary of a ROM file for the launcher, if it was played before.
+fn play_summary(library: &Library, path: &Path) -> Option<String> {
+    // The header is enough to find the game
+    let mut header = Vec::with_capacity(0x150);
+    fs::File::open(path)
+        .and_then(|f| f.take(0x150).read_to_end(&mut header))
+        .ok()?;
+    let key = library::game_key(&header)?;
+    library.get(&key).map(|record| record.to_string())
+}
+
+/// Adds the session that just ended to the game's playtime.
+fn record_playtime(cpu: &Cpu, played: time::Duration) {
+    let key = match library::game_key(cpu.mmu.cartridge.rom()) {
+        Some(key) => key,
+        None => return,
+    };
+    let result = Library::load(LIBRARY_PATH).and_then(|mut library| {
+        library.record_session(&key, played, chrono::Local::now());
+        library.save(LIBRARY_PATH)
+    });
+    if let Err(e) = result {
+        warn!(target: LOG_TARGET, "Failed to update {}: {}", LIBRARY_PATH, e);
+    }
+}
+
+/// Shows the launcher menu in the window title until a ROM is picked.
+///
+/// Up/Down select a ROM, Return starts it, Escape quits.
+fn run_menu(
+    canvas: &mut WindowCanvas,
+    event_pump: &mut EventPump,
+    rom_dir: &str,
+) -> Option<PathBuf> {
+    let roms = list_roms(rom_dir);
+    let library = Library::load(LIBRARY_PATH).unwrap_or_else(|e| {
+        warn!(target: LOG_TARGET, "Cannot read {}: {}", LIBRARY_PATH, e);
+        Library::new()
+    });
+    let summaries: Vec<Option<String>> =
+        roms.iter().map(|rom| play_summary(&library, rom)).collect();
+    let mut selected = 0;
+
+    loop {
+        let title = match roms.get(selected) {
+            Some(rom) => format!(
+                "{} - [{}/{}] {}{} (Up/Down, Return)",
+                WINDOW_TITLE,
+                selected + 1,
+                roms.len(),
+                rom.file_name().unwrap_or_default().to_string_lossy(),
+                summaries[selected]
+                    .as_ref()
+                    .map_or(String::new(), |s| format!(" [{}]", s))
+            ),
+            None => format!("{} - no ROMs in {}", WINDOW_TITLE, rom_dir),
+        };
+        if canvas.window().title() != title {
+            canvas.window_mut().set_title(&title).unwrap();
+        }
+        canvas.clear();
+        canvas.present();
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return None,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Up),
+                    ..
+                } if !roms.is_empty() => selected = (selected + roms.len() - 1) % roms.len(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Down),
+                    ..
+                } if !roms.is_empty() => selected = (selected + 1) % roms.len(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    ..
+                } if !roms.is_empty() => return Some(roms[selected].clone()),
+                _ => (),
+            }
+        }
+
+        thread::sleep(time::Duration::from_millis(16));
+    }
+}
+
+/// Plays the boot animation in `shades` at the Game Boy's frame rate.
+/// Fails with `SessionEnd::Quit` if the window is closed, a key or button
+/// ends it early.
+fn play_boot_animation(
+    canvas: &mut WindowCanvas,
+    texture: &mut Texture,
+    event_pump: &mut EventPump,
+    shades: [Rgb; 4],
+) -> Result<(), SessionEnd> {
+    let animation = BootAnimation::new(shades);
+    let frame_time = time::Duration::from_secs_f64(1.0 / frame_limiter::FRAME_RATE);
+    let start = time::Instant::now();
+    for frame in 0..animation.frames() {
+        let fb = animation.render(frame);
+        texture
+            .with_lock(None, |buf: &mut [u8], pitch: usize| {
+                for (y, row) in fb.chunks(SCREEN_WIDTH * 3).enumerate() {
+                    buf[y * pitch..y * pitch + row.len()].copy_from_slice(row);
+                }
+            })
+            .unwrap();
+        canvas.clear();
+        canvas.copy(texture, None, None).unwrap();
+        canvas.present();
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => return Err(SessionEnd::Quit),
+                Event::KeyDown { .. } | Event::ControllerButtonDown { .. } => return Ok(()),
+                _ => (),
+            }
+        }
+        if let Some(wait) =
+            (start + frame_time * (frame + 1)).checked_duration_since(time::Instant::now())
+        {
+            thread::sleep(wait);
+        }
+    }
+    Ok(())
+}
+
+/// Runs one game until the user quits or goes back to the menu.
+///
+/// The `Cpu` is dropped on return, after its save data has been flushed.
+fn run_game(
+    rom_path: &str,
+    canvas: &mut WindowCanvas,
+    texture: &mut Texture,
+    event_pump: &mut EventPump,
+    audio: &AudioSubsystem,
+    gamepads: &[Gamepad],
+    options: &mut Options,
+) -> SessionEnd {
+    let patch = options
+        .patch
+        .as_ref()
+        .filter(|(rom, _)| rom == Path::new(rom_path))
+        .map(|(_, patch)| patch.clone());
+    let mut cpu = match load_cpu(
+        rom_path,
+        patch.as_deref(),
+        &options.quirks,
+        canvas,
+        event_pump,
+    ) {
+        Ok(cpu) => cpu,
+        Err(end) => return end,
+    };
+    let session_start = time::Instant::now();
+    cpu.mmu
+        .ppu
+        .set_dmg_palette(BUILTIN_PALETTES[options.palette].shades);
+    if options.boot_animation {
+        let shades = cpu.mmu.ppu.dmg_palette();
+        if let Err(end) = play_boot_animation(canvas, texture, event_pump, shades) {
+            return end;
+        }
+    }
+    cpu.mmu
+        .joypad
+        .set_filter_opposites(options.filter_opposites);
+    cpu.mmu.set_seed(options.seed);
+    cpu.set_accuracy(options.accuracy.options());
+    cpu.mmu.apu.set_resampler(options.resampler);
+    cpu.mmu.ppu.set_pixel_attribution(options.inspect_pixels);
+    cpu.mmu.set_random_ram(options.random_ram);
+    cpu.mmu.set_strict(options.strict.is_some());
+    cpu.mmu.joypad.set_sgb(options.sgb);
+    if let Some(capacity) = options.event_log {
+        cpu.mmu.enable_event_log(capacity);
+    }
+    if let Some(spec) = &options.link {
+        // Checked when parsing the arguments
+        cpu.mmu.connect_link(LinkRegistry::new().create(spec).ok());
+    }
+    if let Some(range) = &options.heatmap {
+        let heatmap = match range {
+            Some(range) => AccessHeatmap::with_detail(range.clone()),
+            None => AccessHeatmap::new(),
+        };
+        cpu.mmu.set_heatmap(Some(heatmap));
+    }
+    let mut memory_watch = if options.watch_mem.is_empty() {
+        None
+    } else {
+        let mut watch = MemoryWatch::new(options.watch_mem_frames);
+        for w in &options.watch_mem {
+            watch.add(w.clone());
+        }
+        Some(watch)
+    };
+    let mut auto_splitter = if options.splits.is_empty() {
+        None
+    } else {
+        let mut splitter = AutoSplitter::new();
+        for split in &options.splits {
+            splitter.add(split.clone());
+        }
+        Some(splitter)
+    };
+
+    let spec = AudioSpecDesired {
+        freq: Some(AUDIO_SAMPLE_RATE),
+        channels: Some(2),
+        samples: Some(512),
+    };
+    let audio_queue: AudioQueue<i16> = audio.open_queue(None, &spec).unwrap();
+    let mut limiter = FrameLimiter::new(SystemClock::new(), options.pacing);
+    apply_speed(&mut cpu, &audio_queue, &mut limiter, options.speed);
+    audio_queue.resume();
+    let mut audio_latency = load_audio_latency(&cpu, options.default_audio_latency);
+    info!(target: LOG_TARGET, "Audio latency: {} ms", audio_latency);
+    let mut slot: u8 = 0;
+    // Unfocused with `Background::Throttle`
+    let mut throttled = false;
+    let mut paused = false;
+    let mut rewind = Rewind::new(REWIND_FRAMES, PollRate::Lines(INPUT_POLL_LINES));
+    // Step or step back asked for while paused
+    let mut step_request: Option<Action> = None;
+    // Targets of --run-to-frame and --break-at not reached yet
+    let mut run_to_frame = options.run_to_frame;
+    let mut break_at = options.break_at;
+    // While the fast-forward key is held
+    let mut fast_forward = false;
+    // Thumbnail of the selected slot and when it was selected
+    let mut slot_preview: Option<(Thumbnail, time::Instant)> = None;
+    let mut step_count: u64 = 0;
+    let mut memory_editor = MemoryEditor::default();
+    #[cfg(feature = "watch")]
+    let mut watcher = if options.watch {
+        match RomWatcher::new(rom_path) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Cannot watch {}: {}", rom_path, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    #[cfg(feature = "debug-ui")]
+    let mut debug_ui = if options.debug_ui {
+        match DebugUi::new(canvas.window().subsystem()) {
+            Ok(debug_ui) => Some(debug_ui),
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Cannot open the debug window: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut name = cpu.mmu.cartridge.title();
+    if let Some(db) = &options.dat {
+        match db.lookup(cpu.mmu.cartridge.rom()) {
+            Some(entry) => {
+                info!(target: LOG_TARGET, "Identified as {}", entry.name);
+                if entry.bad_dump {
+                    warn!(target: LOG_TARGET, "{} is a known bad dump", entry.name);
+                }
+                name = entry.name.clone();
+            }
+            None => info!(target: LOG_TARGET, "Not in the dat file, may be a hack or bad dump"),
+        }
+    }
+    let window_title = format!("{} - {}", WINDOW_TITLE, name);
+    canvas.window_mut().set_title(&window_title).unwrap();
+    // Frame pixel under the mouse pointer with `--inspect-pixels`
+    let mut hovered: Option<(usize, usize)> = None;
+    let mut hovered_title = String::new();
+
+    let mut recording = options
+        .record_audio
+        .as_ref()
+        .and_then(|path| start_recording(&cpu, path));
+    let mut vgm_log = options
+        .record_vgm
+        .as_ref()
+        .and_then(|path| start_vgm_log(&mut cpu, path));
+    let mut vram_log = options
+        .log_vram
+        .clone()
+        .and_then(|(path, range)| start_vram_log(&mut cpu, &path, range));
+    let mut frame_times = options.frame_timing.map(FrameTimes::new);
+    // Locked up at the end of the last loop, so each lock-up is reported once
+    let mut was_locked = false;
+    // Hang shown in the window title
+    let mut hung = None;
+    let mut loop_start = time::Instant::now();
+    let session_end = 'running: loop {
+        // for _ in 0..1000 {
+        // info!(target: LOG_TARGET, "loop");
+        let mut timing = FrameTiming {
+            interval: loop_start.elapsed(),
+            ..FrameTiming::default()
+        };
+        loop_start = time::Instant::now();
+
+        #[cfg(feature = "watch")]
+        if watcher.as_mut().is_some_and(RomWatcher::poll)
+            && reload_rom(&mut cpu, rom_path, patch.as_deref())
+        {
+            cpu.reset(false);
+            rewind.clear();
+        }
+
+        // Heading for a target goes as fast as fast-forward, and silently
+        let seeking = run_to_frame.is_some() || break_at.is_some();
+        limiter.set_turbo(if fast_forward || seeking {
+            FAST_FORWARD_FRAMES
+        } else {
+            1
+        });
+        let mut frames = if paused {
+            0
+        } else {
+            limiter.frames_due() as u64
+        };
+        if let Some(target) = run_to_frame {
+            frames = frames.min(target.saturating_sub(cpu.frames()));
+        }
+        #[cfg(feature = "debug-ui")]
+        let debug_focused = debug_ui.as_ref().is_some_and(DebugUi::has_focus);
+        #[cfg(not(feature = "debug-ui"))]
+        let debug_focused = false;
+        let mut input = SdlInput {
+            event_pump: &mut *event_pump,
+            gamepads,
+            keyboard: !memory_editor.visible && !debug_focused,
+            key_mode: options.key_mode,
+            limiter: &mut limiter,
+            slept: time::Duration::ZERO,
+        };
+        for _ in 0..frames {
+            let run = panic::catch_unwind(AssertUnwindSafe(|| {
+                run_frame_to(&mut cpu, &mut rewind, &mut input, break_at)
+            }));
+            let (steps, hit) = match run {
+                Ok(result) => result,
+                Err(payload) => {
+                    if options.crash_report {
+                        write_crash_report(&cpu, panic_message(&*payload));
+                    }
+                    panic::resume_unwind(payload);
+                }
+            };
+            step_count += steps;
+            if let (Some(watch), false) = (&mut memory_watch, hit) {
+                watch.sample(cpu.frames(), &cpu.mmu);
+            }
+            if let (Some(splitter), false) = (&mut auto_splitter, hit) {
+                for split in splitter.check(&cpu.mmu) {
+                    info!(target: LOG_TARGET, "Split {} in frame {}", split.name, cpu.frames());
+                    let mut stdout = io::stdout().lock();
+                    let _ = writeln!(stdout, "split\t{}\t{}", cpu.frames(), split.name)
+                        .and_then(|_| stdout.flush());
+                }
+            }
+            if hit {
+                break_at = None;
+                paused = true;
+                let pc = cpu.registers().pc;
+                let keys = options.hotkeys.keys(Action::Pause).join(" or ");
+                info!(
+                    target: LOG_TARGET,
+                    "Paused at PC 0x{:04x} in frame {}, {} resumes",
+                    pc,
+                    cpu.frames(),
+                    keys
+                );
+                break;
+            }
+        }
+        if run_to_frame.is_some_and(|target| cpu.frames() >= target) {
+            run_to_frame = None;
+            paused = true;
+            let keys = options.hotkeys.keys(Action::Pause).join(" or ");
+            info!(target: LOG_TARGET, "Paused at frame {}, {} resumes", cpu.frames(), keys);
+        }
+        match step_request.take() {
+            Some(Action::Step) => {
+                rewind.step_frame(&mut cpu, &mut input);
+                log_step(&cpu, &rewind);
+            }
+            Some(Action::StepBack) => step_back(&mut cpu, &mut rewind),
+            _ => (),
+        }
+        let locked = cpu.is_locked();
+        if locked && !was_locked && options.crash_report {
+            let pc = cpu.registers().pc.wrapping_sub(1);
+            let reason = format!("illegal opcode 0x{:02x} at 0x{:04x}", cpu.mmu.peek(pc), pc);
+            write_crash_report(&cpu, &reason);
+        }
+        was_locked = locked;
+        let hang = cpu.hang();
+        if hang != hung {
+            let title = match hang {
+                Some(hang) => {
+                    let pc = cpu.registers().pc;
+                    let keys = options.hotkeys.keys(Action::Pause).join(" or ");
+                    warn!(
+                        target: LOG_TARGET,
+                        "Game appears hung at PC 0x{:04x} ({}), {} pauses to step through it",
+                        pc,
+                        hang,
+                        keys
+                    );
+                    format!(
+                        "{} - hung at PC 0x{:04x}, {} to debug",
+                        window_title, pc, keys
+                    )
+                }
+                None => window_title.clone(),
+            };
+            canvas.window_mut().set_title(&title).unwrap();
+            hung = hang;
+        }
+        let slept = input.slept;
+        timing.emulation = loop_start.elapsed().saturating_sub(slept);
+        debug!(target: LOG_TARGET, "==step_count: {}", step_count);
+
+        let violations = cpu.mmu.take_violations();
+        for violation in &violations {
+            warn!(target: LOG_TARGET, "Strict: {}", violation);
+        }
+        if !violations.is_empty() && options.strict == Some(Strict::Break) && !paused {
+            paused = true;
+            let keys = options.hotkeys.keys(Action::Pause).join(" or ");
+            info!(target: LOG_TARGET, "Paused by strict mode, {} resumes", keys);
+        }
+
+        let samples = cpu.mmu.take_audio_samples();
+        if let Some(wav) = recording.as_mut() {
+            if let Err(e) = wav.write_samples(&samples) {
+                warn!(target: LOG_TARGET, "Stopped recording: {}", e);
+                recording = None;
+            }
+        }
+        if let Some(vgm) = vgm_log.as_mut() {
+            if let Err(e) = vgm.write(&cpu.mmu.take_apu_writes()) {
+                warn!(target: LOG_TARGET, "Stopped the sound log: {}", e);
+                vgm_log = None;
+                cpu.mmu.stop_apu_log();
+            }
+        }
+        if let Some(log) = vram_log.as_mut() {
+            if let Err(e) = write_vram_log(&mut cpu, log) {
+                warn!(target: LOG_TARGET, "Stopped the VRAM log: {}", e);
+                vram_log = None;
+                cpu.mmu.stop_vram_log();
+            }
+        }
+        // Fast-forwarded sound would only be noise
+        if !throttled && !fast_forward && !seeking {
+            queue_audio(&audio_queue, &samples, audio_latency);
+        }
+
+        texture
+            .with_lock(None, |buf: &mut [u8], pitch: usize| {
+                let fb = cpu.mmu.ppu.get_frame();
+                // println!("frame {}", fb.len());
+
+                for (y, row) in fb.chunks(160 * 3).enumerate() {
+                    buf[y * pitch..y * pitch + row.len()].copy_from_slice(row);
+                }
+                if let Some((thumbnail, _)) = &slot_preview {
+                    draw_thumbnail(buf, pitch, thumbnail);
+                }
+                if options.grid {
+                    let ppu = &cpu.mmu.ppu;
+                    draw_grid(buf, pitch, ppu.scroll(), ppu.window_position());
+                }
+                if options.input_display {
+                    draw_input_display(buf, pitch, cpu.mmu.joypad.state());
+                }
+                if memory_editor.visible {
+                    memory_editor.draw(buf, pitch, cpu.mmu.memory(memory_editor.region()));
+                }
+            })
+            .unwrap();
+
+        if options.inspect_pixels {
+            let title = match hovered {
+                Some((x, y)) => format!(
+                    "{} - {}",
+                    window_title,
+                    describe_pixel(x, y, cpu.mmu.ppu.pixel_source(x, y))
+                ),
+                None => window_title.clone(),
+            };
+            if title != hovered_title {
+                canvas.window_mut().set_title(&title).unwrap();
+                hovered_title = title;
+            }
+        }
+
+        if slot_preview
+            .as_ref()
+            .is_some_and(|(_, shown)| shown.elapsed() >= SLOT_PREVIEW_TIME)
+        {
+            slot_preview = None;
+        }
+
+        let present_start = time::Instant::now();
+        canvas.clear();
+        canvas.copy(texture, None, None).unwrap();
+        canvas.present();
+        timing.present = present_start.elapsed();
+        #[cfg(feature = "debug-ui")]
+        if let Some(debug_ui) = &mut debug_ui {
+            debug_ui.draw(&cpu);
+        }
+
+        let mut background_pause = false;
+        for event in event_pump.poll_iter() {
+            #[cfg(feature = "debug-ui")]
+            if debug_ui.as_mut().is_some_and(|ui| ui.handle_event(&event)) {
+                continue;
+            }
+            // The editor gets the keys it knows before hotkeys and the game
+            if let Event::KeyDown {
+                keycode: Some(keycode),
+                ..
+            } = event
+            {
+                let region = memory_editor.region();
+                if translate_editor_keycode(keycode)
+                    .is_some_and(|key| memory_editor.handle_key(key, cpu.mmu.memory_mut(region)))
+                {
+                    continue;
+                }
+            }
+            match event {
+                Event::Quit { .. } => break 'running SessionEnd::Quit,
+                Event::Window {
+                    win_event: WindowEvent::FocusLost,
+                    ..
+                } => match options.background {
+                    Background::Run => (),
+                    Background::Pause => background_pause = true,
+                    Background::Throttle => {
+                        throttled = true;
+                        audio_queue.clear();
+                    }
+                },
+                Event::Window {
+                    win_event: WindowEvent::FocusGained,
+                    ..
+                } => throttled = false,
+                Event::MouseMotion { x, y, .. } if options.inspect_pixels => {
+                    let (width, height) = canvas.window().size();
+                    let scale = |pos: i32, size: u32, pixels: usize| {
+                        (pos.max(0) as usize * pixels / size.max(1) as usize).min(pixels - 1)
+                    };
+                    hovered = Some((
+                        scale(x, width, SCREEN_WIDTH),
+                        scale(y, height, SCREEN_HEIGHT),
+                    ));
+                }
+                Event::Window {
+                    win_event: WindowEvent::Leave,
+                    ..
+                } => hovered = None,
+                Event::KeyDown {
+                    keycode,
+                    scancode,
+                    repeat,
+                    ..
+                } => {
+                    if let Some(key) = options.key_mode.key(keycode, scancode) {
+                        match options.hotkeys.action(key.name()) {
+                            // Held, these go on stepping
+                            Some(action @ (Action::Step | Action::StepBack)) => {
+                                if paused {
+                                    step_request = Some(action);
+                                }
+                            }
+                            // Held hotkeys would toggle or save over and over, and
+                            // the game sees held buttons through `SdlInput`
+                            _ if repeat => (),
+                            Some(Action::Quit) => break 'running SessionEnd::Quit,
+                            Some(Action::Menu) => break 'running SessionEnd::Menu,
+                            Some(Action::SoftReset) => {
+                                cpu.reset(false);
+                                rewind.clear();
+                            }
+                            Some(Action::HardReset) => {
+                                cpu.reset(true);
+                                rewind.clear();
+                            }
+                            Some(Action::DumpEventLog) => dump_event_log(&cpu),
+                            Some(Action::DumpFrameTimes) => {
+                                dump_frame_times(&cpu, frame_times.as_ref())
+                            }
+                            // Live reload after rebuilding the ROM
+                            Some(Action::ReloadRom) => {
+                                reload_rom(&mut cpu, rom_path, patch.as_deref());
+                                rewind.clear();
+                            }
+                            Some(Action::SaveState) => save_state(&cpu, slot, options.bess),
+                            Some(Action::PreviousSlot) => {
+                                slot = (slot + NUM_SAVE_STATE_SLOTS - 1) % NUM_SAVE_STATE_SLOTS;
+                                slot_preview =
+                                    select_slot(&cpu, slot).map(|t| (t, time::Instant::now()));
+                            }
+                            Some(Action::NextSlot) => {
+                                slot = (slot + 1) % NUM_SAVE_STATE_SLOTS;
+                                slot_preview =
+                                    select_slot(&cpu, slot).map(|t| (t, time::Instant::now()));
+                            }
+                            Some(Action::LinkButton) => match cpu.mmu.link_device_mut() {
+                                Some(device) => {
+                                    device.press();
+                                    info!(target: LOG_TARGET, "Pressed the {} button", device.name());
+                                }
+                                None => {
+                                    warn!(target: LOG_TARGET, "Nothing on the link port, see --link")
+                                }
+                            },
+                            Some(Action::LoadState) => {
+                                load_state(&mut cpu, slot, options, canvas.window());
+                                rewind.clear();
+                            }
+                            Some(Action::ToggleBackground) => {
+                                toggle_layer(&mut cpu, Layer::Background)
+                            }
+                            Some(Action::ToggleWindow) => toggle_layer(&mut cpu, Layer::Window),
+                            Some(Action::ToggleSprites) => toggle_layer(&mut cpu, Layer::Sprites),
+                            Some(Action::CyclePalette) => {
+                                cycle_palette(&mut cpu, &mut options.palette);
+                                options.settings.palette = options.palette;
+                                save_settings(options);
+                            }
+                            Some(Action::AudioLatencyDown) => {
+                                adjust_audio_latency(&cpu, &mut audio_latency, -1)
+                            }
+                            Some(Action::AudioLatencyUp) => {
+                                adjust_audio_latency(&cpu, &mut audio_latency, 1)
+                            }
+                            Some(Action::Slower) => {
+                                options.speed = options.speed.slower();
+                                apply_speed(&mut cpu, &audio_queue, &mut limiter, options.speed);
+                            }
+                            Some(Action::Faster) => {
+                                options.speed = options.speed.faster();
+                                apply_speed(&mut cpu, &audio_queue, &mut limiter, options.speed);
+                            }
+                            Some(Action::FastForward) => fast_forward = true,
+                            Some(Action::Pause) => {
+                                paused = !paused;
+                                if paused {
+                                    let keys = options.hotkeys.keys(Action::Pause).join(" or ");
+                                    info!(target: LOG_TARGET, "Paused, {} resumes", keys);
+                                } else {
+                                    info!(target: LOG_TARGET, "Resumed");
+                                }
+                            }
+                            Some(Action::Screenshot) => save_screenshot(&cpu),
+                            Some(Action::ToggleGrid) => options.grid = !options.grid,
+                            Some(Action::ToggleRecording) => match recording.take() {
+                                Some(wav) => stop_recording(&cpu, wav),
+                                None => {
+                                    let path = Path::new(RECORDING_DIR).join(format!(
+                                        "{}-{}.wav",
+                                        cpu.game_id(),
+                                        chrono::Local::now().format("%Y%m%d-%H%M%S")
+                                    ));
+                                    recording = start_recording(&cpu, &path);
+                                }
+                            },
+                            Some(Action::ToggleInputDisplay) => {
+                                options.input_display = !options.input_display;
+                            }
+                            Some(Action::ToggleMemoryEditor) => {
+                                memory_editor.visible = !memory_editor.visible;
+                            }
+                            None => handle_keydown(&mut cpu, key),
+                        }
+                    }
+                }
+                Event::KeyUp {
+                    keycode, scancode, ..
+                } => {
+                    if let Some(key) = options.key_mode.key(keycode, scancode) {
+                        match options.hotkeys.action(key.name()) {
+                            Some(Action::FastForward) => fast_forward = false,
+                            Some(_) => (),
+                            None => handle_keyup(&mut cpu, key),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        #[cfg(feature = "debug-ui")]
+        {
+            // Focus moving to the debug window is not the game going to the
+            // background
+            if debug_ui.as_ref().is_some_and(DebugUi::has_focus) {
+                background_pause = false;
+                throttled = false;
+            }
+            if debug_ui.as_ref().is_some_and(DebugUi::is_closed) {
+                debug_ui = None;
+            }
+        }
+        if background_pause {
+            if !wait_for_focus(event_pump, &audio_queue) {
+                break 'running SessionEnd::Quit;
+            }
+            // Don't try to catch up on the time spent paused
+            limiter.resync();
+        }
+
+        // Not allowed to catch up while throttled
+        timing.sleep_error = limiter.end_frame(!throttled);
+        if let Some(frame_times) = &mut frame_times {
+            frame_times.push(timing);
+        }
+    };
+    if let Some(wav) = recording {
+        stop_recording(&cpu, wav);
+    }
+    if let Some(vgm) = vgm_log {
+        stop_vgm_log(&mut cpu, vgm);
+    }
+    if let Some(log) = vram_log {
+        stop_vram_log(&mut cpu, log);
+    }
+    cpu.mmu.cartridge.write_save_data(options.save_format);
+    write_heatmap(&cpu);
+    if let Some(watch) = &memory_watch {
+        write_memory_watch(&cpu, watch);
+    }
+    record_playtime(&cpu, session_start.elapsed());
+    session_end
+}
+
+fn main() {
+    // RUST_LOG overrides this, e.g. RUST_LOG=gb::cpu=debug
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args = Args::parse();
+    if let Some(frames) = args.bench {
+        // `requires` guarantees the ROM
+        let rom_path = args.file_path.as_deref().unwrap();
+        run_bench(rom_path, frames, args.accuracy, !args.no_render);
+        return;
+    }
+    if let Some(frames) = args.dump_frames {
+        // `requires` guarantees the ROM
+        let rom_path = args.file_path.as_deref().unwrap();
+        run_dump_frames(
+            rom_path,
+            frames,
+            args.accuracy,
+            &args.dump_dir,
+            args.dump_format,
+        );
+        return;
+    }
+    if let Some(states) = &args.diff_states {
+        // `num_args` guarantees two paths
+        run_diff_states(args.file_path.as_deref().unwrap(), &states[0], &states[1]);
+        return;
+    }
+
+    let config_dir = settings::config_dir();
+    let settings_path = config_dir
+        .as_ref()
+        .map(|dir| dir.join(settings::SETTINGS_FILE));
+    let (saved_settings, mut settings) = load_settings(settings_path.as_deref());
+    settings.scale = args.scale.unwrap_or(settings.scale);
+    settings.palette = args.palette.unwrap_or(settings.palette);
+    settings.audio_latency = args.audio_latency.unwrap_or(settings.audio_latency);
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let audio_subsystem = sdl_context.audio().unwrap();
+
+    let window = video_subsystem
+        .window(
+            WINDOW_TITLE,
+            SCREEN_WIDTH as u32 * settings.scale,
+            SCREEN_HEIGHT as u32 * settings.scale,
+        )
+        .position_centered()
+        .build()
+        .unwrap();
+
+    let present_mode = if args.vsync {
+        PresentMode::Vsync
+    } else {
+        args.present_mode
+    };
+    let vsync = match present_mode {
+        PresentMode::Immediate => false,
+        PresentMode::Vsync => true,
+        PresentMode::Adaptive => {
+            // 0 when SDL does not know
+            let refresh_hz = window.display_mode().map_or(0, |mode| mode.refresh_rate);
+            let vsync = frame_limiter::vsync_is_even(refresh_hz as f64);
+            info!(
+                target: LOG_TARGET,
+                "Display refreshes at {} Hz, {}",
+                refresh_hz,
+                if vsync { "using vsync" } else { "not using vsync" }
+            );
+            vsync
+        }
+    };
+    let mut canvas = window.into_canvas();
+    if vsync {
+        canvas = canvas.present_vsync();
+    }
+    let mut canvas = canvas.build().unwrap();
+
+    let texture_creator = canvas.texture_creator();
+
+    let mut texture = texture_creator
+        .create_texture_streaming(sdl2::pixels::PixelFormatEnum::RGB24, 160, 144)
+        .unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let gamepads = Gamepad::open_all(
+        &sdl_context,
+        StickMapping::new(args.deadzone, args.diagonal_angle),
+    );
+
+    let mut next_rom = args.file_path.map(PathBuf::from);
+    let log_vram_range = args.log_vram_range.clone().unwrap_or(0x8000..0xa000);
+    let mut options = Options {
+        settings: saved_settings,
+        settings_path,
+        palette: settings.palette,
+        default_audio_latency: settings.audio_latency,
+        speed: Speed::new(args.speed),
+        save_format: if args.compress_saves {
+            SaveFormat::Compressed
+        } else {
+            SaveFormat::Raw
+        },
+        save_ram_policy: args.save_ram_policy,
+        key_mode: args.key_mode,
+        bess: args.bess,
+        event_log: match args.event_log {
+            None if args.crash_report => Some(CRASH_REPORT_EVENTS),
+            capacity => capacity,
+        },
+        crash_report: args.crash_report,
+        frame_timing: args.frame_timing,
+        heatmap: if args.heatmap {
+            Some(args.heatmap_range.clone())
+        } else {
+            None
+        },
+        watch_mem: args.watch_mem.clone(),
+        watch_mem_frames: args.watch_mem_frames,
+        splits: args.split.clone(),
+        link: args.link.clone(),
+        run_to_frame: args.run_to_frame,
+        pacing: if vsync { Pacing::Vsync } else { Pacing::Sleep },
+        break_at: args.break_at,
+        background: args.background,
+        seed: args.seed,
+        accuracy: args.accuracy,
+        resampler: args.resampler,
+        random_ram: args.random_ram,
+        boot_animation: args.boot_animation,
+        filter_opposites: !args.allow_opposite_directions,
+        input_display: args.input_display,
+        inspect_pixels: args.inspect_pixels,
+        grid: args.grid,
+        record_audio: args.record_audio.clone(),
+        strict: args.strict,
+        sgb: args.sgb,
+        record_vgm: args.record_vgm.clone(),
+        log_vram: args.log_vram.clone().map(|path| (path, log_vram_range)),
+        hotkeys: {
+            let path = config_file(config_dir.as_deref(), HOTKEYS_PATH);
+            Hotkeys::load(&path).unwrap_or_else(|e| {
+                warn!(target: LOG_TARGET, "Ignoring {:?}: {}", path, e);
+                Hotkeys::default()
+            })
+        },
+        patch: args
+            .patch
+            .clone()
+            .and_then(|patch| Some((next_rom.clone()?, patch))),
+        dat: args
+            .dat
+            .as_ref()
+            .and_then(|path| match RomDatabase::load(path) {
+                Ok(db) => {
+                    info!(target: LOG_TARGET, "{} ROMs in {:?}", db.len(), path);
+                    Some(db)
+                }
+                Err(e) => {
+                    warn!(target: LOG_TARGET, "Cannot read {:?}: {}", path, e);
+                    None
+                }
+            }),
+        quirks: {
+            let mut database = QuirkDatabase::built_in();
+            let path = config_file(config_dir.as_deref(), QUIRKS_PATH);
+            match quirks::load(&path) {
+                Ok(user) => database.extend(user),
+                Err(e) => warn!(target: LOG_TARGET, "Ignoring {:?}: {}", path, e),
+            }
+            database
+        },
+        #[cfg(feature = "watch")]
+        watch: args.watch,
+        #[cfg(feature = "debug-ui")]
+        debug_ui: args.debug_ui,
+    };
+
+    loop {
+        let rom_path = match next_rom.take() {
+            Some(path) => path,
+            None => match run_menu(&mut canvas, &mut event_pump, &args.rom_dir) {
+                Some(path) => path,
+                None => break,
+            },
+        };
+
+        match run_game(
+            &rom_path.to_string_lossy(),
+            &mut canvas,
+            &mut texture,
+            &mut event_pump,
+            &audio_subsystem,
+            &gamepads,
+            &mut options,
+        ) {
+            SessionEnd::Quit => break,
+            SessionEnd::Menu => continue,
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use rust_gb::mmu::MemoryRegion;
+
+/// Bytes shown on each row.
+const ROW_BYTES: usize = 8;
+/// Rows that fit on the screen.
+const ROWS: usize = 20;
+const ROW_HEIGHT: usize = 7;
+/// Width of a glyph plus the space after it.
+const GLYPH_WIDTH: usize = 4;
+const LEFT: usize = 2;
+const TOP: usize = 2;
+/// Where the bytes start, after the bank and address.
+const BYTES_LEFT: usize = LEFT + 6 * GLYPH_WIDTH;
+
+/// 3x5 hex digits, a row per byte, the leftmost pixel in bit 2.
+const FONT: [[u8; 5]; 16] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b011, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+    [0b010, 0b101, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b011, 0b100, 0b100, 0b100, 0b011],
+    [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b110, 0b100, 0b111],
+    [0b111, 0b100, 0b110, 0b100, 0b100],
+];
+
+/// A key the editor understands, independent of the frontend that reads
+/// the keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditorKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    /// Moves on to the next `MemoryRegion`.
+    NextRegion,
+    Close,
+    /// A hex digit typed over the byte under the cursor, 0x0-0xf.
+    Digit(u8),
+}
+
+/// Hex view of a `MemoryRegion`, drawn over the game and redrawn every
+/// frame so that it follows the game's own writes. Typed digits go
+/// straight to memory, a nibble at a time.
+#[derive(Debug, Default)]
+pub struct MemoryEditor {
+    pub visible: bool,
+    /// Index into `MemoryRegion::ALL`.
+    region: usize,
+    /// Offset of the selected byte in the region.
+    cursor: usize,
+    /// First row on the screen.
+    top: usize,
+    /// The high nibble of the selected byte was typed already.
+    low_nibble: bool,
+}
+
+impl MemoryEditor {
+    pub fn region(&self) -> MemoryRegion {
+        MemoryRegion::ALL[self.region]
+    }
+
+    /// Handles `key` for the region's memory `data`. Returns whether the
+    /// editor used the key.
+    pub fn handle_key(&mut self, key: EditorKey, data: &mut [u8]) -> bool {
+        if !self.visible {
+            return false;
+        }
+        let page = ROWS * ROW_BYTES;
+        match key {
+            EditorKey::Up => self.move_cursor(-(ROW_BYTES as isize), data.len()),
+            EditorKey::Down => self.move_cursor(ROW_BYTES as isize, data.len()),
+            EditorKey::Left => self.move_cursor(-1, data.len()),
+            EditorKey::Right => self.move_cursor(1, data.len()),
+            EditorKey::PageUp => self.move_cursor(-(page as isize), data.len()),
+            EditorKey::PageDown => self.move_cursor(page as isize, data.len()),
+            EditorKey::NextRegion => {
+                self.region = (self.region + 1) % MemoryRegion::ALL.len();
+                self.cursor = 0;
+                self.top = 0;
+                self.low_nibble = false;
+            }
+            EditorKey::Close => self.visible = false,
+            // Not a digit, so the key is someone else's
+            EditorKey::Digit(digit) if digit > 0xf => return false,
+            EditorKey::Digit(digit) => {
+                let len = data.len();
+                if let Some(byte) = data.get_mut(self.cursor) {
+                    if self.low_nibble {
+                        *byte = *byte & 0xf0 | digit & 0x0f;
+                        self.move_cursor(1, len);
+                    } else {
+                        *byte = *byte & 0x0f | digit << 4;
+                        self.low_nibble = true;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Moves the cursor by `delta` bytes within `len`, scrolling to keep
+    /// it on the screen.
+    fn move_cursor(&mut self, delta: isize, len: usize) {
+        let last = len.saturating_sub(1) as isize;
+        self.cursor = (self.cursor as isize + delta).clamp(0, last) as usize;
+        self.low_nibble = false;
+        let row = self.cursor / ROW_BYTES;
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + ROWS {
+            self.top = row + 1 - ROWS;
+        }
+    }
+
+    /// Draws the rows on screen of `data` over the RGB24 frame `buf`, each
+    /// starting with the bank and address of its first byte.
+    pub fn draw(&self, buf: &mut [u8], pitch: usize, data: &[u8]) {
+        let region = self.region();
+        let (width, height) = (BYTES_LEFT + ROW_BYTES * 3 * GLYPH_WIDTH, ROWS * ROW_HEIGHT);
+        super::fill_rect(buf, pitch, (0, 0, width + LEFT, height + TOP * 2), 0x00);
+        let rows = data.chunks(ROW_BYTES).enumerate().skip(self.top).take(ROWS);
+        for (i, (row, bytes)) in rows.enumerate() {
+            let y = TOP + i * ROW_HEIGHT;
+            let offset = row * ROW_BYTES;
+            let bank = offset / region.bank_len();
+            let addr = region.base() as usize + offset % region.bank_len();
+            draw_hex(buf, pitch, (LEFT, y), bank, 1);
+            draw_hex(buf, pitch, (LEFT + 2 * GLYPH_WIDTH, y), addr, 4);
+            for (j, &byte) in bytes.iter().enumerate() {
+                let x = BYTES_LEFT + j * 3 * GLYPH_WIDTH;
+                if offset + j == self.cursor {
+                    let w = 2 * GLYPH_WIDTH + 1;
+                    super::fill_rect(buf, pitch, (x - 1, y - 1, w, ROW_HEIGHT), 0x60);
+                }
+                draw_hex(buf, pitch, (x, y), byte as usize, 2);
+            }
+        }
+    }
+}
+
+/// Draws the last `digits` hex digits of `value` in white.
+fn draw_hex(buf: &mut [u8], pitch: usize, (x, y): (usize, usize), value: usize, digits: usize) {
+    for i in 0..digits {
+        let digit = value >> ((digits - 1 - i) * 4) & 0xf;
+        for (dy, bits) in FONT[digit].iter().enumerate() {
+            for dx in 0..3 {
+                if bits >> (2 - dx) & 1 == 1 {
+                    let x = x + i * GLYPH_WIDTH + dx;
+                    super::fill_rect(buf, pitch, (x, y + dy, 1, 1), 0xff);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_editor() -> MemoryEditor {
+        MemoryEditor {
+            visible: true,
+            ..MemoryEditor::default()
+        }
+    }
+
+    #[test]
+    fn test_edit_nibbles() {
+        let mut editor = open_editor();
+        let mut data = [0x00; 0x10];
+        for key in [
+            EditorKey::Right,
+            EditorKey::Digit(0xa),
+            EditorKey::Digit(0x5),
+        ] {
+            assert!(editor.handle_key(key, &mut data));
+        }
+        editor.handle_key(EditorKey::Digit(0x7), &mut data);
+        assert_eq!(data[..3], [0x00, 0xa5, 0x70]);
+
+        // Moving away forgets the typed high nibble
+        editor.handle_key(EditorKey::Left, &mut data);
+        editor.handle_key(EditorKey::Digit(0x3), &mut data);
+        assert_eq!(data[1], 0x35);
+
+        assert!(!editor.handle_key(EditorKey::Digit(0xf1), &mut data));
+        assert_eq!(data[..3], [0x00, 0x35, 0x70]);
+
+        editor.handle_key(EditorKey::Close, &mut data);
+        assert!(!editor.handle_key(EditorKey::Digit(0x1), &mut data));
+        assert_eq!(data[1], 0x35);
+    }
+
+    #[test]
+    fn test_cursor_stays_on_screen() {
+        let mut editor = open_editor();
+        // No cartridge RAM to edit
+        assert!(editor.handle_key(EditorKey::Digit(0x1), &mut []));
+        assert!(editor.handle_key(EditorKey::Down, &mut []));
+
+        let mut data = vec![0x00; 0x2000];
+        editor.handle_key(EditorKey::Up, &mut data);
+        assert_eq!((editor.cursor, editor.top), (0, 0));
+        editor.handle_key(EditorKey::PageDown, &mut data);
+        assert_eq!((editor.cursor, editor.top), (ROWS * ROW_BYTES, 1));
+        for _ in 0..0x2000 {
+            editor.handle_key(EditorKey::Down, &mut data);
+        }
+        assert_eq!(editor.cursor, 0x1fff);
+        assert_eq!(editor.top, 0x2000 / ROW_BYTES - ROWS);
+
+        editor.handle_key(EditorKey::NextRegion, &mut data);
+        assert_eq!(editor.region(), MemoryRegion::Wram);
+        assert_eq!((editor.cursor, editor.top), (0, 0));
+    }
+}
@@ -0,0 +1,180 @@
+//! Draws egui output into an RGBA buffer on the CPU. The SDL2 bindings in
+//! use have no way to render triangles, so the meshes are filled here and
+//! the result goes to the window as one texture.
+
+use std::collections::HashMap;
+
+use egui::epaint::{ImageDelta, Primitive, Vertex};
+use egui::{ClippedPrimitive, Color32, ImageData, Pos2, TextureId, TexturesDelta};
+
+/// A texture egui asked for, in premultiplied RGBA.
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color32>,
+}
+
+impl Image {
+    /// The texel at `uv`, nearest to it.
+    fn sample(&self, u: f32, v: f32) -> Color32 {
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+}
+
+#[derive(Default)]
+pub struct Painter {
+    textures: HashMap<TextureId, Image>,
+}
+
+impl Painter {
+    /// Uploads new textures and patches, to be called before `paint`.
+    pub fn set_textures(&mut self, delta: &TexturesDelta) {
+        for (id, delta) in &delta.set {
+            self.set_texture(*id, delta);
+        }
+    }
+
+    /// Drops the textures egui is done with, to be called after `paint`.
+    pub fn free_textures(&mut self, delta: &TexturesDelta) {
+        for id in &delta.free {
+            self.textures.remove(id);
+        }
+    }
+
+    fn set_texture(&mut self, id: TextureId, delta: &ImageDelta) {
+        let [width, height] = delta.image.size();
+        let pixels: Vec<Color32> = match &delta.image {
+            ImageData::Color(image) => image.pixels.clone(),
+            ImageData::Font(image) => image.srgba_pixels(None).collect(),
+        };
+        match (delta.pos, self.textures.get_mut(&id)) {
+            (Some([x, y]), Some(texture)) => {
+                for (row, src) in pixels.chunks(width).enumerate() {
+                    let start = (y + row) * texture.width + x;
+                    texture.pixels[start..start + width].copy_from_slice(src);
+                }
+            }
+            _ => {
+                self.textures.insert(
+                    id,
+                    Image {
+                        width,
+                        height,
+                        pixels,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Fills `buf`, `width` x `height` RGBA pixels, with `primitives` over
+    /// `background`.
+    pub fn paint(
+        &self,
+        primitives: &[ClippedPrimitive],
+        pixels_per_point: f32,
+        background: Color32,
+        buf: &mut [u8],
+        (width, height): (usize, usize),
+    ) {
+        for pixel in buf.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&background.to_array());
+        }
+        let mut target = Target { buf, width };
+        for primitive in primitives {
+            let mesh = match &primitive.primitive {
+                Primitive::Mesh(mesh) => mesh,
+                Primitive::Callback(_) => continue,
+            };
+            let texture = match self.textures.get(&mesh.texture_id) {
+                Some(texture) => texture,
+                None => continue,
+            };
+            let clip = primitive.clip_rect;
+            let clip = (
+                (clip.min.x * pixels_per_point).max(0.0) as usize,
+                (clip.min.y * pixels_per_point).max(0.0) as usize,
+                ((clip.max.x * pixels_per_point).ceil().max(0.0) as usize).min(width),
+                ((clip.max.y * pixels_per_point).ceil().max(0.0) as usize).min(height),
+            );
+            for triangle in mesh.indices.chunks_exact(3) {
+                let vertices = [
+                    &mesh.vertices[triangle[0] as usize],
+                    &mesh.vertices[triangle[1] as usize],
+                    &mesh.vertices[triangle[2] as usize],
+                ];
+                target.fill(vertices, pixels_per_point, clip, texture);
+            }
+        }
+    }
+}
+
+struct Target<'a> {
+    buf: &'a mut [u8],
+    width: usize,
+}
+
+impl Target<'_> {
+    /// Blends a textured triangle into the buffer, sampled at pixel centers
+    /// inside `clip`, given as left, top, right and bottom.
+    fn fill(
+        &mut self,
+        vertices: [&Vertex; 3],
+        pixels_per_point: f32,
+        (left, top, right, bottom): (usize, usize, usize, usize),
+        texture: &Image,
+    ) {
+        let [a, b, c] = vertices.map(|v| v.pos * pixels_per_point);
+        let area = edge(a, b, c);
+        if area.abs() < f32::EPSILON {
+            return;
+        }
+        let us = vertices.map(|v| v.uv.x);
+        let vs = vertices.map(|v| v.uv.y);
+        let colors = [0, 1, 2, 3].map(|i| vertices.map(|v| v.color.to_array()[i] as f32));
+        let min_x = (a.x.min(b.x).min(c.x).floor().max(0.0) as usize).max(left);
+        let min_y = (a.y.min(b.y).min(c.y).floor().max(0.0) as usize).max(top);
+        let max_x = (a.x.max(b.x).max(c.x).ceil().max(0.0) as usize).min(right);
+        let max_y = (a.y.max(b.y).max(c.y).ceil().max(0.0) as usize).min(bottom);
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+                // Barycentric weights, all positive inside the triangle
+                // whichever way round it is
+                let wa = edge(b, c, p) / area;
+                let wb = edge(c, a, p) / area;
+                let wc = 1.0 - wa - wb;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                let weights = [wa, wb, wc];
+                let lerp = |values: [f32; 3]| {
+                    values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
+                };
+                let texel = texture.sample(lerp(us), lerp(vs)).to_array();
+                let mut src = [0u8; 4];
+                for (i, channel) in src.iter_mut().enumerate() {
+                    let tint = lerp(colors[i]);
+                    *channel = (tint * texel[i] as f32 / 255.0).round().min(255.0) as u8;
+                }
+                let offset = (y * self.width + x) * 4;
+                blend(&mut self.buf[offset..offset + 4], src);
+            }
+        }
+    }
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `p`.
+fn edge(a: Pos2, b: Pos2, p: Pos2) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+/// Draws premultiplied `src` over `dst`.
+fn blend(dst: &mut [u8], src: [u8; 4]) {
+    let keep = 255 - src[3] as u32;
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = (s as u32 + (*d as u32 * keep + 127) / 255).min(255) as u8;
+    }
+}
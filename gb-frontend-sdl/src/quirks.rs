@@ -0,0 +1,184 @@
+//! `quirks.toml`, the user's additions to the quirks built into the core:
+//!
+//! ```toml
+//! [[quirk]]
+//! title = "MY GAME"
+//! header-checksum = 0x3c
+//! global-checksum = 0x1a2b
+//! cartridge-type = 0x01
+//! force-dmg = true
+//! sp = 0xfffe
+//! ```
+//!
+//! Each `[[quirk]]` needs `header-checksum`. `global-checksum` tells apart
+//! games sharing a header checksum, `cartridge-type` replaces the MBC the
+//! header declares, `force-dmg` runs a CGB game as a DMG one and `a` to `l`,
+//! `sp` and `pc` set the registers the game starts with.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rust_gb::cpu::Registers;
+use rust_gb::quirks::{Quirk, QuirkDatabase};
+use serde::Deserialize;
+
+#[derive(Debug)]
+pub enum QuirkError {
+    Io(io::Error),
+    /// Not TOML, or a key is unknown or has a value it cannot take.
+    Toml(toml::de::Error),
+}
+
+impl fmt::Display for QuirkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QuirkError::Io(e) => write!(f, "{}", e),
+            QuirkError::Toml(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for QuirkError {}
+
+impl From<io::Error> for QuirkError {
+    fn from(e: io::Error) -> Self {
+        QuirkError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for QuirkError {
+    fn from(e: toml::de::Error) -> Self {
+        QuirkError::Toml(e)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QuirkFile {
+    #[serde(default)]
+    quirk: Vec<QuirkEntry>,
+}
+
+/// A `[[quirk]]` table. Registers it does not give start as zeroes, and
+/// pc at 0x100.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct QuirkEntry {
+    #[serde(default)]
+    title: String,
+    header_checksum: u8,
+    global_checksum: Option<u16>,
+    cartridge_type: Option<u8>,
+    #[serde(default)]
+    force_dmg: bool,
+    a: Option<u8>,
+    f: Option<u8>,
+    b: Option<u8>,
+    c: Option<u8>,
+    d: Option<u8>,
+    e: Option<u8>,
+    h: Option<u8>,
+    l: Option<u8>,
+    sp: Option<u16>,
+    pc: Option<u16>,
+}
+
+impl From<QuirkEntry> for Quirk {
+    fn from(entry: QuirkEntry) -> Self {
+        let bytes = [
+            entry.a, entry.f, entry.b, entry.c, entry.d, entry.e, entry.h, entry.l,
+        ];
+        let registers =
+            if bytes.iter().any(Option::is_some) || entry.sp.is_some() || entry.pc.is_some() {
+                let [a, f, b, c, d, e, h, l] = bytes.map(Option::unwrap_or_default);
+                Some(Registers {
+                    a,
+                    f,
+                    b,
+                    c,
+                    d,
+                    e,
+                    h,
+                    l,
+                    sp: entry.sp.unwrap_or_default(),
+                    pc: entry.pc.unwrap_or(0x100),
+                })
+            } else {
+                None
+            };
+        Quirk {
+            title: entry.title,
+            header_checksum: entry.header_checksum,
+            global_checksum: entry.global_checksum,
+            cartridge_type: entry.cartridge_type,
+            registers,
+            force_dmg: entry.force_dmg,
+        }
+    }
+}
+
+/// Reads a quirk file, empty if it does not exist.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<QuirkDatabase, QuirkError> {
+    match fs::read_to_string(path) {
+        Ok(text) => parse(&text),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(QuirkDatabase::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn parse(text: &str) -> Result<QuirkDatabase, QuirkError> {
+    let file: QuirkFile = toml::from_str(text)?;
+    Ok(QuirkDatabase::new(
+        file.quirk.into_iter().map(Quirk::from).collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "
+            # Overrides
+            [[quirk]]
+            title = \"BOOT # LEG\"
+            header-checksum = 0x42 # any global checksum
+            cartridge-type = 0x01
+
+            [[quirk]]
+            header-checksum = 66
+            sp = 0xfffe
+            force-dmg = true
+        ";
+        let quirks = parse(text).unwrap();
+        assert_eq!(quirks.len(), 2);
+        let mut rom = vec![0; 0x8000];
+        rom[0x14d] = 0x42;
+        // The later quirk wins
+        let quirk = quirks.lookup(&rom).unwrap();
+        assert_eq!(quirk.cartridge_type, None);
+        assert!(quirk.force_dmg);
+        assert_eq!(
+            quirk.registers,
+            Some(Registers {
+                sp: 0xfffe,
+                pc: 0x100,
+                ..Registers::default()
+            })
+        );
+        assert!(parse("").unwrap().is_empty());
+
+        for text in [
+            "header-checksum = 1",
+            "[[quirk]]\ncartridge-type = 1",
+            "[[quirk]]\nheader-checksum = 0x100",
+            "[[quirk]]\nheader-checksum = 1\nmodel = \"cgb\"",
+        ] {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rust_gb::palette::{self, BUILTIN_PALETTES};
+
+use crate::{MAX_AUDIO_LATENCY_MS, MIN_AUDIO_LATENCY_MS};
+
+/// Directory name under the platform's config directory.
+const APP_DIR: &str = "rust-gb";
+/// Environment variables overriding a setting are named after it with this
+/// prefix, e.g. `RUST_GB_SCALE` for `scale`.
+const ENV_PREFIX: &str = "RUST_GB_";
+/// Replaces the whole config directory, e.g. for a portable install.
+const CONFIG_DIR_VAR: &str = "RUST_GB_CONFIG_DIR";
+pub const SETTINGS_FILE: &str = "settings.cfg";
+pub const MAX_SCALE: u32 = 8;
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(io::Error),
+    /// A line is not `key = value`.
+    Syntax {
+        line: usize,
+    },
+    /// A line sets something that is not a setting, or to a value it
+    /// cannot take.
+    Value {
+        line: usize,
+        reason: String,
+    },
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingsError::Io(e) => write!(f, "{}", e),
+            SettingsError::Syntax { line } => write!(f, "line {}: expected key = value", line),
+            SettingsError::Value { line, reason } => write!(f, "line {}: {}", line, reason),
+        }
+    }
+}
+
+impl error::Error for SettingsError {}
+
+impl From<io::Error> for SettingsError {
+    fn from(e: io::Error) -> Self {
+        SettingsError::Io(e)
+    }
+}
+
+/// Choices that survive restarts. Each is read from the settings file
+/// first, then from its environment variable, and a command line flag
+/// beats both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Settings {
+    /// Window size in multiples of 160x144.
+    pub scale: u32,
+    /// Index into `BUILTIN_PALETTES`.
+    pub palette: usize,
+    /// Audio latency in milliseconds for games without their own.
+    pub audio_latency: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            scale: 3,
+            palette: 0,
+            audio_latency: 50,
+        }
+    }
+}
+
+impl Settings {
+    pub const KEYS: [&'static str; 3] = ["scale", "palette", "audio-latency"];
+
+    /// Reads a settings file, falling back to the defaults if it does not
+    /// exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SettingsError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Settings::parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Settings::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Reads `key = value` lines, `#` starting a comment. Settings not
+    /// mentioned keep their defaults.
+    pub fn parse(text: &str) -> Result<Self, SettingsError> {
+        let mut settings = Settings::default();
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(SettingsError::Syntax { line: line_number })?;
+            settings
+                .set(key.trim(), value.trim())
+                .map_err(|reason| SettingsError::Value {
+                    line: line_number,
+                    reason,
+                })?;
+        }
+        Ok(settings)
+    }
+
+    /// Sets the setting called `key` from its text form.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "scale" => {
+                self.scale = value
+                    .parse()
+                    .ok()
+                    .filter(|scale| (1..=MAX_SCALE).contains(scale))
+                    .ok_or_else(|| format!("scale {} is not 1 to {}", value, MAX_SCALE))?
+            }
+            "palette" => {
+                self.palette =
+                    palette::find(value).ok_or_else(|| format!("unknown palette {}", value))?
+            }
+            "audio-latency" => {
+                self.audio_latency = value
+                    .parse()
+                    .ok()
+                    .filter(|ms| (MIN_AUDIO_LATENCY_MS..=MAX_AUDIO_LATENCY_MS).contains(ms))
+                    .ok_or_else(|| {
+                        format!(
+                            "audio latency {} is not {} to {}",
+                            value, MIN_AUDIO_LATENCY_MS, MAX_AUDIO_LATENCY_MS
+                        )
+                    })?
+            }
+            _ => return Err(format!("unknown setting {}", key)),
+        }
+        Ok(())
+    }
+
+    /// The text form of the setting called `key`, as `set` takes it.
+    pub fn get(&self, key: &str) -> Option<String> {
+        match key {
+            "scale" => Some(self.scale.to_string()),
+            "palette" => Some(BUILTIN_PALETTES[self.palette].name.to_string()),
+            "audio-latency" => Some(self.audio_latency.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Applies the environment variables `lookup` finds, e.g. `RUST_GB_SCALE`
+    /// or `RUST_GB_AUDIO_LATENCY`. Returns a message for each one ignored
+    /// because its value is bad.
+    pub fn apply_env(&mut self, lookup: impl Fn(&str) -> Option<String>) -> Vec<String> {
+        let mut errors = Vec::new();
+        for key in Settings::KEYS {
+            let var = env_var(key);
+            if let Some(value) = lookup(&var) {
+                if let Err(e) = self.set(key, value.trim()) {
+                    errors.push(format!("{}: {}", var, e));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Writes every setting, creating the directory if needed.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_text())
+    }
+
+    fn to_text(self) -> String {
+        Settings::KEYS
+            .iter()
+            .map(|key| format!("{} = {}\n", key, self.get(key).unwrap()))
+            .collect()
+    }
+}
+
+/// Environment variable overriding `key`.
+fn env_var(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.replace('-', "_").to_uppercase())
+}
+
+/// Where settings and hotkeys live: `RUST_GB_CONFIG_DIR` if set, else
+/// `rust-gb` in the platform's config directory, see `dirs::config_dir`.
+/// `None` without a home.
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os(CONFIG_DIR_VAR) {
+        return Some(PathBuf::from(dir));
+    }
+    dirs::config_dir().map(|dir| dir.join(APP_DIR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse() {
+        let settings =
+            Settings::parse("# mine\nscale = 4\n\npalette = dmg-green # retro\n").unwrap();
+        assert_eq!(settings.scale, 4);
+        assert_eq!(BUILTIN_PALETTES[settings.palette].name, "dmg-green");
+        assert_eq!(settings.audio_latency, Settings::default().audio_latency);
+        assert_eq!(Settings::parse(&settings.to_text()).unwrap(), settings);
+
+        assert!(matches!(
+            Settings::parse("scale 4"),
+            Err(SettingsError::Syntax { line: 1 })
+        ));
+        assert!(matches!(
+            Settings::parse("\nscale = 9"),
+            Err(SettingsError::Value { line: 2, .. })
+        ));
+        assert!(matches!(
+            Settings::parse("volume = 3"),
+            Err(SettingsError::Value { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_apply_env() {
+        let vars: HashMap<&str, &str> = [
+            ("RUST_GB_AUDIO_LATENCY", "120"),
+            ("RUST_GB_PALETTE", "sepia-ish"),
+        ]
+        .iter()
+        .copied()
+        .collect();
+        let mut settings = Settings::default();
+        let errors = settings.apply_env(|var| vars.get(var).map(|v| v.to_string()));
+        assert_eq!(settings.audio_latency, 120);
+        assert_eq!(settings.palette, 0);
+        assert_eq!(errors, ["RUST_GB_PALETTE: unknown palette sepia-ish"]);
+    }
+}
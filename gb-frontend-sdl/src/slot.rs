@@ -0,0 +1,136 @@
+use chrono::{DateTime, Local, TimeZone};
+
+use rust_gb::cpu::Cpu;
+use rust_gb::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use rust_gb::state::{StateError, StateReader, StateWriter};
+
+/// Magic bytes at the start of slot metadata.
+const MAGIC: &[u8; 4] = b"RGBM";
+/// Thumbnails are the screen shrunk by this factor on each side.
+const THUMBNAIL_SCALE: usize = 2;
+
+/// Downscaled copy of the screen as RGB24, row-major.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Thumbnail {
+    /// Shrinks a 160x144 RGB24 frame, averaging each block of pixels.
+    pub fn from_frame(frame: &[u8]) -> Self {
+        let width = SCREEN_WIDTH / THUMBNAIL_SCALE;
+        let height = SCREEN_HEIGHT / THUMBNAIL_SCALE;
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                for channel in 0..3 {
+                    let mut sum = 0;
+                    for dy in 0..THUMBNAIL_SCALE {
+                        for dx in 0..THUMBNAIL_SCALE {
+                            let sx = x * THUMBNAIL_SCALE + dx;
+                            let sy = y * THUMBNAIL_SCALE + dy;
+                            sum += frame[(sy * SCREEN_WIDTH + sx) * 3 + channel] as usize;
+                        }
+                    }
+                    pixels.push((sum / (THUMBNAIL_SCALE * THUMBNAIL_SCALE)) as u8);
+                }
+            }
+        }
+        Thumbnail {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// What a state picker shows for a savestate slot, kept next to the state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotInfo {
+    pub saved_at: DateTime<Local>,
+    pub title: String,
+    pub thumbnail: Thumbnail,
+}
+
+impl SlotInfo {
+    /// Describes the state `cpu` is in now.
+    pub fn capture(cpu: &Cpu) -> Self {
+        SlotInfo {
+            saved_at: Local::now(),
+            title: cpu.mmu.cartridge.title(),
+            thumbnail: Thumbnail::from_frame(cpu.mmu.ppu.get_frame()),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.write_bytes(MAGIC);
+        w.write_i64(self.saved_at.timestamp());
+        w.write_vec(self.title.as_bytes());
+        w.write_u16(self.thumbnail.width as u16);
+        w.write_u16(self.thumbnail.height as u16);
+        w.write_vec(&self.thumbnail.pixels);
+        w.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, StateError> {
+        let mut r = StateReader::new(data);
+        let mut magic = [0; 4];
+        r.read_bytes(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let saved_at = Local
+            .timestamp_opt(r.read_i64()?, 0)
+            .single()
+            .ok_or(StateError::Corrupt)?;
+        let title = String::from_utf8(r.read_vec()?).map_err(|_| StateError::Corrupt)?;
+        let width = r.read_u16()? as usize;
+        let height = r.read_u16()? as usize;
+        let pixels = r.read_vec()?;
+        if pixels.len() != width * height * 3 {
+            return Err(StateError::Corrupt);
+        }
+        Ok(SlotInfo {
+            saved_at,
+            title,
+            thumbnail: Thumbnail {
+                width,
+                height,
+                pixels,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thumbnail_averages() {
+        let mut frame = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        // One white pixel in the top-left 2x2 block
+        frame[..3].copy_from_slice(&[0xff, 0xff, 0xff]);
+        let thumbnail = Thumbnail::from_frame(&frame);
+        assert_eq!((thumbnail.width, thumbnail.height), (80, 72));
+        assert_eq!(thumbnail.pixels[..6], [0x3f, 0x3f, 0x3f, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let info = SlotInfo {
+            saved_at: Local.timestamp_opt(1_700_000_000, 0).unwrap(),
+            title: "TETRIS".to_string(),
+            thumbnail: Thumbnail {
+                width: 1,
+                height: 2,
+                pixels: vec![1, 2, 3, 4, 5, 6],
+            },
+        };
+        assert_eq!(SlotInfo::from_bytes(&info.to_bytes()), Ok(info));
+        assert_eq!(SlotInfo::from_bytes(b"RGBS"), Err(StateError::BadMagic));
+    }
+}
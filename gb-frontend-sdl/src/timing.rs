@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Where the time of one trip through the frame loop went.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameTiming {
+    /// Running the emulator, leaving out the sleeps that spread the frame
+    /// over its real time.
+    pub emulation: Duration,
+    /// Copying the frame to the window and presenting it.
+    pub present: Duration,
+    /// How much later than asked the sleep at the end of the frame woke up.
+    pub sleep_error: Duration,
+    /// Since the previous frame started.
+    pub interval: Duration,
+}
+
+/// The latest `FrameTiming`s up to a fixed number, for stutter reports.
+pub struct FrameTimes {
+    frames: VecDeque<FrameTiming>,
+    capacity: usize,
+}
+
+impl FrameTimes {
+    /// Creates a new `FrameTimes` holding at most `capacity` frames.
+    pub fn new(capacity: usize) -> Self {
+        FrameTimes {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, timing: FrameTiming) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(timing);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Mean, 99th percentile and worst of each measurement, in ms.
+    pub fn summary(&self) -> String {
+        if self.frames.is_empty() {
+            return "no frames timed".to_string();
+        }
+        let columns = [
+            ("emulation", self.column(|t| t.emulation)),
+            ("present", self.column(|t| t.present)),
+            ("sleep error", self.column(|t| t.sleep_error)),
+            ("interval", self.column(|t| t.interval)),
+        ];
+        let mut summary = format!("{} frames:", self.frames.len());
+        for (name, mut times) in columns {
+            times.sort_by(f64::total_cmp);
+            let mean = times.iter().sum::<f64>() / times.len() as f64;
+            let p99 = times[(times.len() - 1) * 99 / 100];
+            let worst = times[times.len() - 1];
+            let _ = write!(summary, " {} {:.2}/{:.2}/{:.2}", name, mean, p99, worst);
+        }
+        summary + " ms (mean/p99/max)"
+    }
+
+    /// One measurement of every frame, in ms.
+    fn column(&self, get: impl Fn(&FrameTiming) -> Duration) -> Vec<f64> {
+        self.frames.iter().map(|t| ms(get(t))).collect()
+    }
+
+    /// One line per frame, oldest first, times in ms.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("frame,emulation_ms,present_ms,sleep_error_ms,interval_ms\n");
+        for (i, t) in self.frames.iter().enumerate() {
+            let _ = writeln!(
+                csv,
+                "{},{:.3},{:.3},{:.3},{:.3}",
+                i,
+                ms(t.emulation),
+                ms(t.present),
+                ms(t.sleep_error),
+                ms(t.interval)
+            );
+        }
+        csv
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(interval_ms: u64) -> FrameTiming {
+        FrameTiming {
+            interval: Duration::from_millis(interval_ms),
+            ..FrameTiming::default()
+        }
+    }
+
+    #[test]
+    fn test_keeps_latest_frames() {
+        let mut times = FrameTimes::new(2);
+        for interval in [10, 20, 30] {
+            times.push(frame(interval));
+        }
+        assert_eq!(times.len(), 2);
+        let csv = times.to_csv();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], "0,0.000,0.000,0.000,20.000");
+        assert_eq!(lines[2], "1,0.000,0.000,0.000,30.000");
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut times = FrameTimes::new(100);
+        assert_eq!(times.summary(), "no frames timed");
+        for interval in [16, 16, 16, 50] {
+            times.push(frame(interval));
+        }
+        assert!(times
+            .summary()
+            .ends_with("interval 24.50/16.00/50.00 ms (mean/p99/max)"));
+    }
+}
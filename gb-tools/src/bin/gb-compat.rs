@@ -0,0 +1,85 @@
+use std::fs;
+use std::io::{self, BufWriter};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::Parser;
+use gb_tools::compat::{self, Outcome};
+
+/// Runs every ROM in a directory headlessly for some frames and writes a
+/// compatibility report as tab-separated values: whether each one loaded,
+/// locked up on an illegal opcode or panicked, and the hash of its last
+/// frame. Diff reports of two releases to see what changed.
+#[derive(Parser)]
+struct Args {
+    /// Directory with the .gb and .gbc files
+    dir: PathBuf,
+    /// Frames to run each ROM for
+    #[arg(long, default_value_t = 600)]
+    frames: u64,
+    /// File to write the report to instead of stdout
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+fn list_roms(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut roms: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            matches!(
+                path.extension().and_then(|ext| ext.to_str()),
+                Some("gb") | Some("gbc")
+            )
+        })
+        .collect();
+    roms.sort();
+    Ok(roms)
+}
+
+fn main() {
+    let args = Args::parse();
+    let roms = list_roms(&args.dir).unwrap_or_else(|e| {
+        eprintln!("Cannot read {:?}: {}", args.dir, e);
+        process::exit(1);
+    });
+    // Panics end up in the report instead
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut results = Vec::new();
+    for path in &roms {
+        let name = path.file_name().unwrap().to_string_lossy();
+        let result = match fs::read(path) {
+            Ok(rom) => compat::run_rom(&name, rom, args.frames),
+            Err(e) => compat::CompatResult {
+                name: name.to_string(),
+                title: String::new(),
+                outcome: Outcome::LoadFailed(e.to_string()),
+                frames: 0,
+                frame_hash: None,
+            },
+        };
+        eprintln!("{}: {}", name, result.outcome);
+        results.push(result);
+    }
+
+    let written = match &args.output {
+        Some(path) => fs::File::create(path)
+            .and_then(|file| compat::write_report(&results, BufWriter::new(file))),
+        None => compat::write_report(&results, io::stdout().lock()),
+    };
+    if let Err(e) = written {
+        eprintln!("Cannot write the report: {}", e);
+        process::exit(1);
+    }
+    let ok = results
+        .iter()
+        .filter(|result| result.outcome == Outcome::Ok)
+        .count();
+    eprintln!(
+        "{} of {} ROMs ran {} frames",
+        ok,
+        results.len(),
+        args.frames
+    );
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::process;
+
+use clap::Parser;
+use gb_tools::disasm;
+
+/// Bytes per switchable ROM bank.
+const BANK_SIZE: usize = 0x4000;
+
+/// Disassembles part of a ROM file, one instruction per line.
+#[derive(Parser)]
+struct Args {
+    rom: String,
+    /// File offset to start at, e.g. 0x150
+    #[arg(long, default_value = "0x100", value_parser = parse_offset)]
+    start: usize,
+    /// Number of instructions to decode
+    #[arg(long, default_value_t = 32)]
+    count: usize,
+}
+
+fn parse_offset(s: &str) -> Result<usize, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| e.to_string())
+}
+
+/// Bank and CPU address of a file offset, with banks past the first mapped
+/// at 0x4000 as the MBCs do.
+fn bank_address(offset: usize) -> (usize, u16) {
+    let bank = offset / BANK_SIZE;
+    let addr = if bank == 0 {
+        offset
+    } else {
+        BANK_SIZE + offset % BANK_SIZE
+    };
+    (bank, addr as u16)
+}
+
+fn main() {
+    let args = Args::parse();
+    let rom = fs::read(&args.rom).unwrap_or_else(|e| {
+        eprintln!("Cannot read {}: {}", args.rom, e);
+        process::exit(1);
+    });
+
+    let mut offset = args.start;
+    for _ in 0..args.count {
+        let (bank, addr) = bank_address(offset);
+        let instruction = match rom
+            .get(offset..)
+            .and_then(|bytes| disasm::decode(bytes, addr))
+        {
+            Some(instruction) => instruction,
+            None => break,
+        };
+        let bytes: Vec<String> = rom[offset..offset + instruction.len]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        println!(
+            "{:02x}:{:04x}  {:<9} {}",
+            bank,
+            addr,
+            bytes.join(" "),
+            instruction.text
+        );
+        offset += instruction.len;
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs;
+use std::process;
+
+use clap::Parser;
+use rust_gb::cartridge;
+
+/// Prints the header of a ROM file and checks its checksums. With --fix the
+/// checksums of a homebrew ROM are rewritten to match.
+#[derive(Parser)]
+struct Args {
+    rom: String,
+    /// Writes both checksums into the header
+    #[arg(long)]
+    fix: bool,
+    /// File to write the fixed ROM to instead of the ROM itself
+    #[arg(long, value_name = "PATH", requires = "fix")]
+    output: Option<String>,
+}
+
+/// A size in KB, or the unknown size code.
+fn size(size: Option<usize>, code: u8) -> String {
+    match size {
+        Some(size) => format!("{}KB", size / 1024),
+        None => format!("unknown (code 0x{:02x})", code),
+    }
+}
+
+fn check(ok: bool) -> &'static str {
+    if ok {
+        "ok"
+    } else {
+        "MISMATCH"
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let mut rom = fs::read(&args.rom).unwrap_or_else(|e| {
+        eprintln!("Cannot read {}: {}", args.rom, e);
+        process::exit(1);
+    });
+    let report = cartridge::analyze(&rom).unwrap_or_else(|e| {
+        eprintln!("Cannot inspect {}: {}", args.rom, e);
+        process::exit(1);
+    });
+
+    println!("Title:           {}", report.title);
+    println!(
+        "Cartridge type:  0x{:02x} {}",
+        report.mbc_type, report.mbc_type_name
+    );
+    println!(
+        "ROM size:        {}",
+        size(report.declared_rom_size, rom[0x148])
+    );
+    println!("File size:       {} bytes", report.rom_size);
+    println!("RAM size:        {}", size(report.ram_size, rom[0x149]));
+    println!("CGB flag:        0x{:02x}", rom[0x143]);
+    println!("SGB flag:        0x{:02x}", rom[0x146]);
+    println!("Destination:     0x{:02x}", rom[0x14a]);
+    println!("Version:         0x{:02x}", rom[0x14c]);
+    println!(
+        "Header checksum: 0x{:02x} {} (computed 0x{:02x})",
+        rom[0x14d],
+        check(report.header_checksum_ok),
+        cartridge::header_checksum(&rom)
+    );
+    println!(
+        "Global checksum: 0x{:02x}{:02x} {} (computed 0x{:04x})",
+        rom[0x14e],
+        rom[0x14f],
+        check(report.global_checksum_ok),
+        cartridge::global_checksum(&rom)
+    );
+    for warning in &report.warnings {
+        println!("Warning: {}", warning);
+    }
+
+    let checksums_ok = report.header_checksum_ok && report.global_checksum_ok;
+    if !args.fix {
+        if !checksums_ok {
+            process::exit(1);
+        }
+        return;
+    }
+    let output = args.output.as_ref().unwrap_or(&args.rom);
+    if checksums_ok && output == &args.rom {
+        println!("Checksums already match");
+        return;
+    }
+    cartridge::fix_checksums(&mut rom);
+    if let Err(e) = fs::write(output, &rom) {
+        eprintln!("Cannot write {}: {}", output, e);
+        process::exit(1);
+    }
+    println!("Wrote fixed checksums to {}", output);
+}
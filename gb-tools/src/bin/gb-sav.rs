@@ -0,0 +1,44 @@
+use std::fs;
+use std::process;
+
+use clap::{Parser, ValueEnum};
+use rust_gb::battery::{self, SaveFormat};
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Plain RAM as other emulators write it
+    Raw,
+    /// Gzip with a checksum, as written with --compress-saves
+    Compressed,
+}
+
+/// Converts a battery save between the raw and the compressed format.
+/// Either format is accepted as input.
+#[derive(Parser)]
+struct Args {
+    input: String,
+    output: String,
+    /// Format to write
+    #[arg(long, value_enum, default_value = "raw")]
+    to: Format,
+}
+
+fn main() {
+    let args = Args::parse();
+    let data = fs::read(&args.input).unwrap_or_else(|e| {
+        eprintln!("Cannot read {}: {}", args.input, e);
+        process::exit(1);
+    });
+    let ram = battery::decode(&data, battery::ram_len(&data)).unwrap_or_else(|e| {
+        eprintln!("Cannot convert {}: {}", args.input, e);
+        process::exit(1);
+    });
+    let format = match args.to {
+        Format::Raw => SaveFormat::Raw,
+        Format::Compressed => SaveFormat::Compressed,
+    };
+    if let Err(e) = fs::write(&args.output, battery::encode(&ram, format)) {
+        eprintln!("Cannot write {}: {}", args.output, e);
+        process::exit(1);
+    }
+}
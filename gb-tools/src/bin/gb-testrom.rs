@@ -0,0 +1,31 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use clap::Parser;
+use gb_tools::testrom::TestRom;
+
+/// Writes the ROMs that change PPU registers mid-scanline (SCX, BGP and the
+/// window) to a directory, to compare frames with other emulators.
+#[derive(Parser)]
+struct Args {
+    /// Directory to write the ROMs to
+    #[arg(default_value = "test_roms")]
+    dir: PathBuf,
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = fs::create_dir_all(&args.dir) {
+        eprintln!("Cannot create {:?}: {}", args.dir, e);
+        process::exit(1);
+    }
+    for test in TestRom::ALL {
+        let path = args.dir.join(format!("{}.gb", test.name()));
+        if let Err(e) = fs::write(&path, test.build()) {
+            eprintln!("Cannot write {:?}: {}", path, e);
+            process::exit(1);
+        }
+        println!("{}", path.display());
+    }
+}
@@ -0,0 +1,189 @@
+//! Runs ROMs headlessly and reports how far each got, to compare
+//! compatibility between releases. The frame hash of the last frame tells
+//! when a game that used to run shows something else now.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+
+use rust_gb::cpu::Cpu;
+
+/// How a ROM ran.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Ran all the frames.
+    Ok,
+    /// The cartridge could not be built, with the reason.
+    LoadFailed(String),
+    /// An illegal opcode hung the CPU in `frame`, at `pc`.
+    LockedUp { frame: u64, pc: u16 },
+    /// The emulator panicked, with the message.
+    Panicked(String),
+}
+
+impl Outcome {
+    /// Short name for the report.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::LoadFailed(_) => "load-failed",
+            Outcome::LockedUp { .. } => "locked-up",
+            Outcome::Panicked(_) => "panicked",
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Ok => write!(f, "ok"),
+            Outcome::LoadFailed(reason) => write!(f, "cannot load: {}", reason),
+            Outcome::LockedUp { frame, pc } => {
+                write!(f, "illegal opcode at 0x{:04x} in frame {}", pc, frame)
+            }
+            Outcome::Panicked(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+/// The result of running one ROM.
+#[derive(Clone, Debug)]
+pub struct CompatResult {
+    /// File name of the ROM.
+    pub name: String,
+    /// Title from the header, empty if the ROM did not load.
+    pub title: String,
+    pub outcome: Outcome,
+    /// Frames run before it stopped.
+    pub frames: u64,
+    /// `Cpu::frame_hash` of the last frame, `None` if no frame ran to the
+    /// end.
+    pub frame_hash: Option<u64>,
+}
+
+/// Runs `rom` for `frames` frames without input, stopping early if the CPU
+/// locks up or the emulator panics.
+///
+/// Panics are caught, but the panic hook still runs; replace it with
+/// `panic::set_hook` to keep them off stderr.
+pub fn run_rom(name: &str, rom: Vec<u8>, frames: u64) -> CompatResult {
+    let mut result = CompatResult {
+        name: name.to_string(),
+        title: String::new(),
+        outcome: Outcome::Ok,
+        frames: 0,
+        frame_hash: None,
+    };
+    let mut cpu = match panic::catch_unwind(|| Cpu::with_rom(rom)) {
+        Ok(Ok(cpu)) => cpu,
+        Ok(Err(e)) => {
+            result.outcome = Outcome::LoadFailed(e.to_string());
+            return result;
+        }
+        Err(payload) => {
+            result.outcome = Outcome::Panicked(panic_message(payload));
+            return result;
+        }
+    };
+    result.title = cpu.mmu.cartridge.title();
+
+    let run = panic::catch_unwind(AssertUnwindSafe(|| {
+        while result.frames < frames {
+            cpu.run_frame_with(|_, _| {});
+            result.frames += 1;
+            result.frame_hash = Some(cpu.frame_hash());
+            if cpu.is_locked() {
+                result.outcome = Outcome::LockedUp {
+                    frame: cpu.frames(),
+                    pc: cpu.registers().pc.wrapping_sub(1),
+                };
+                break;
+            }
+        }
+    }));
+    if let Err(payload) = run {
+        result.outcome = Outcome::Panicked(panic_message(payload));
+    }
+    result
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+/// Writes the results as tab-separated values with a header line, one ROM
+/// per line, to diff between releases.
+pub fn write_report<W: Write>(results: &[CompatResult], mut w: W) -> io::Result<()> {
+    writeln!(w, "rom\ttitle\tresult\tframes\tframe hash\tdetail")?;
+    for result in results {
+        let hash = result
+            .frame_hash
+            .map_or(String::new(), |hash| format!("{:016x}", hash));
+        let detail = match result.outcome {
+            Outcome::Ok => String::new(),
+            ref outcome => outcome.to_string().replace(['\t', '\n'], " "),
+        };
+        writeln!(
+            w,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            result.name,
+            result.title,
+            result.outcome.name(),
+            result.frames,
+            hash,
+            detail
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testrom::TestRom;
+
+    #[test]
+    fn test_run_rom() {
+        let rom = TestRom::ScxMidLine.build();
+        let ok = run_rom("ok.gb", rom.clone(), 10);
+        assert_eq!(ok.outcome, Outcome::Ok);
+        assert_eq!(ok.title, "SCX MIDLINE");
+        assert_eq!(ok.frames, 10);
+        // The same ROM ends on the same frame
+        assert_eq!(run_rom("ok.gb", rom.clone(), 10).frame_hash, ok.frame_hash);
+
+        // The code starts with a jump to 0x150, make that an illegal opcode
+        let mut locked = rom.clone();
+        locked[0x150] = 0xd3;
+        let locked = run_rom("locked.gb", locked, 10);
+        assert_eq!(
+            locked.outcome,
+            Outcome::LockedUp {
+                frame: 1,
+                pc: 0x150
+            }
+        );
+        assert_eq!(locked.frames, 1);
+
+        let mut bad = rom;
+        bad[0x14d] ^= 0xff;
+        let bad = run_rom("bad.gb", bad, 10);
+        assert_eq!(bad.outcome.name(), "load-failed");
+        assert_eq!(bad.frame_hash, None);
+
+        let mut report = Vec::new();
+        write_report(&[ok, locked, bad], &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[2].starts_with("locked.gb\tSCX MIDLINE\tlocked-up\t1\t"));
+        assert!(lines[2].ends_with("\tillegal opcode at 0x0150 in frame 1"));
+        assert!(lines[3].ends_with("\tload-failed\t0\t\tcannot load: header checksum mismatch"));
+    }
+}
@@ -0,0 +1,181 @@
+const R: [&str; 8] = ["b", "c", "d", "e", "h", "l", "(hl)", "a"];
+const RP: [&str; 4] = ["bc", "de", "hl", "sp"];
+const RP2: [&str; 4] = ["bc", "de", "hl", "af"];
+const CC: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = [
+    "add a,", "adc a,", "sub ", "sbc a,", "and ", "xor ", "or ", "cp ",
+];
+const ROT: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+
+/// Operand following the opcode, written in place of its placeholder.
+#[derive(Clone, Copy)]
+enum Operand {
+    None,
+    /// `d8`, an immediate byte.
+    Byte,
+    /// `d16`, an immediate word.
+    Word,
+    /// `a8`, an offset into 0xff00.
+    HighPage,
+    /// `r8`, a jump relative to the next instruction.
+    Relative,
+    /// `s8`, a signed offset added to SP.
+    Signed,
+}
+
+impl Operand {
+    fn len(self) -> usize {
+        match self {
+            Operand::None => 0,
+            Operand::Word => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// One decoded instruction.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Instruction {
+    /// Bytes taken by the opcode and its operands.
+    pub len: usize,
+    /// Mnemonic and operands, e.g. `ld a,($ff44)`.
+    pub text: String,
+}
+
+/// Decodes the SM83 instruction at the start of `bytes`, which is at `addr`
+/// in the CPU address space. Relative jumps are shown with their target.
+///
+/// Returns `None` if `bytes` ends inside the instruction. Opcodes the CPU
+/// does not have are shown as `db`.
+pub fn decode(bytes: &[u8], addr: u16) -> Option<Instruction> {
+    let opcode = *bytes.first()?;
+    if opcode == 0xcb {
+        let op = *bytes.get(1)?;
+        let (y, z) = (((op >> 3) & 7) as usize, (op & 7) as usize);
+        let text = match op >> 6 {
+            0 => format!("{} {}", ROT[y], R[z]),
+            1 => format!("bit {},{}", y, R[z]),
+            2 => format!("res {},{}", y, R[z]),
+            _ => format!("set {},{}", y, R[z]),
+        };
+        return Some(Instruction { len: 2, text });
+    }
+
+    let (template, operand) = template(opcode);
+    let len = 1 + operand.len();
+    if bytes.len() < len {
+        return None;
+    }
+    let text = match operand {
+        Operand::None => template,
+        Operand::Byte => template.replace("d8", &format!("${:02x}", bytes[1])),
+        Operand::HighPage => template.replace("a8", &format!("$ff{:02x}", bytes[1])),
+        Operand::Word => {
+            let word = u16::from_le_bytes([bytes[1], bytes[2]]);
+            template.replace("d16", &format!("${:04x}", word))
+        }
+        Operand::Relative => {
+            let target = addr.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16);
+            template.replace("r8", &format!("${:04x}", target))
+        }
+        Operand::Signed => template.replace("s8", &format!("{}", bytes[1] as i8)),
+    };
+    Some(Instruction { len, text })
+}
+
+/// Text of an unprefixed opcode with a placeholder for its operand.
+fn template(opcode: u8) -> (String, Operand) {
+    let (x, y, z) = (opcode >> 6, ((opcode >> 3) & 7) as usize, opcode & 7);
+    let (p, q) = (y >> 1, y & 1);
+    let fixed = |text: &str| (text.to_string(), Operand::None);
+    match (x, z) {
+        (0, 0) => match y {
+            0 => fixed("nop"),
+            1 => ("ld (d16),sp".to_string(), Operand::Word),
+            2 => ("stop".to_string(), Operand::Byte),
+            3 => ("jr r8".to_string(), Operand::Relative),
+            _ => (format!("jr {},r8", CC[y - 4]), Operand::Relative),
+        },
+        (0, 1) if q == 0 => (format!("ld {},d16", RP[p]), Operand::Word),
+        (0, 1) => fixed(&format!("add hl,{}", RP[p])),
+        (0, 2) => {
+            let target = ["(bc)", "(de)", "(hl+)", "(hl-)"][p];
+            if q == 0 {
+                fixed(&format!("ld {},a", target))
+            } else {
+                fixed(&format!("ld a,{}", target))
+            }
+        }
+        (0, 3) if q == 0 => fixed(&format!("inc {}", RP[p])),
+        (0, 3) => fixed(&format!("dec {}", RP[p])),
+        (0, 4) => fixed(&format!("inc {}", R[y])),
+        (0, 5) => fixed(&format!("dec {}", R[y])),
+        (0, 6) => (format!("ld {},d8", R[y]), Operand::Byte),
+        (0, _) => fixed(["rlca", "rrca", "rla", "rra", "daa", "cpl", "scf", "ccf"][y]),
+        (1, 6) if y == 6 => fixed("halt"),
+        (1, _) => fixed(&format!("ld {},{}", R[y], R[z as usize])),
+        (2, _) => fixed(&format!("{}{}", ALU[y], R[z as usize])),
+        (_, 0) => match y {
+            0..=3 => fixed(&format!("ret {}", CC[y])),
+            4 => ("ldh (a8),a".to_string(), Operand::HighPage),
+            5 => ("add sp,s8".to_string(), Operand::Signed),
+            6 => ("ldh a,(a8)".to_string(), Operand::HighPage),
+            _ => ("ld hl,sp+s8".to_string(), Operand::Signed),
+        },
+        (_, 1) if q == 0 => fixed(&format!("pop {}", RP2[p])),
+        (_, 1) => fixed(["ret", "reti", "jp hl", "ld sp,hl"][p]),
+        (_, 2) => match y {
+            0..=3 => (format!("jp {},d16", CC[y]), Operand::Word),
+            4 => fixed("ld (c),a"),
+            5 => ("ld (d16),a".to_string(), Operand::Word),
+            6 => fixed("ld a,(c)"),
+            _ => ("ld a,(d16)".to_string(), Operand::Word),
+        },
+        (_, 3) => match y {
+            0 => ("jp d16".to_string(), Operand::Word),
+            6 => fixed("di"),
+            7 => fixed("ei"),
+            _ => fixed(&format!("db ${:02x}", opcode)),
+        },
+        (_, 4) if y < 4 => (format!("call {},d16", CC[y]), Operand::Word),
+        (_, 5) if q == 0 => fixed(&format!("push {}", RP2[p])),
+        (_, 5) if p == 0 => ("call d16".to_string(), Operand::Word),
+        (_, 6) => (format!("{}d8", ALU[y]), Operand::Byte),
+        (_, 7) => fixed(&format!("rst ${:02x}", y * 8)),
+        _ => fixed(&format!("db ${:02x}", opcode)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8], addr: u16) -> String {
+        decode(bytes, addr).unwrap().text
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(text(&[0x00], 0), "nop");
+        assert_eq!(text(&[0x3e, 0x12], 0), "ld a,$12");
+        assert_eq!(text(&[0xc3, 0x50, 0x01], 0x100), "jp $0150");
+        assert_eq!(text(&[0xf0, 0x44], 0), "ldh a,($ff44)");
+        assert_eq!(text(&[0x20, 0xfe], 0x150), "jr nz,$0150");
+        assert_eq!(text(&[0xf8, 0xff], 0), "ld hl,sp+-1");
+        assert_eq!(text(&[0x76], 0), "halt");
+        assert_eq!(text(&[0x7e], 0), "ld a,(hl)");
+        assert_eq!(text(&[0xae], 0), "xor (hl)");
+        assert_eq!(text(&[0xff], 0), "rst $38");
+        assert_eq!(text(&[0xcb, 0x7c], 0), "bit 7,h");
+        assert_eq!(text(&[0xcb, 0x37], 0), "swap a");
+        assert_eq!(text(&[0xd3], 0), "db $d3");
+        assert_eq!(decode(&[0xcd, 0x00, 0x40], 0).unwrap().len, 3);
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert_eq!(decode(&[], 0), None);
+        assert_eq!(decode(&[0xc3, 0x50], 0), None);
+        assert_eq!(decode(&[0xcb], 0), None);
+    }
+}
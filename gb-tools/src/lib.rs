@@ -0,0 +1,5 @@
+//! Command line tools around the emulation core, see `src/bin`.
+
+pub mod compat;
+pub mod disasm;
+pub mod testrom;
@@ -0,0 +1,242 @@
+//! Tiny ROMs that change a PPU register in the middle of every scanline, to
+//! compare this emulator's frames with other emulators' and real hardware.
+//!
+//! Each ROM draws 4-pixel vertical stripes over the whole background and
+//! then, on every line, waits for mode 3, waits some more depending on LY
+//! (0 to 7 loops of 4 M-cycles) and writes the register. The old value is
+//! put back in HBlank. The point where each line changes shows when the
+//! write took effect, so a staircase of 8 lines repeats down the screen.
+
+/// Where the tile data goes in the ROM.
+const TILES_ADDR: u16 = 0x1000;
+/// Blank, 4-pixel stripes of colors 1 and 2, and a checkerboard of colors
+/// 3 and 0, in VRAM tile format.
+const TILES: [[u8; 2]; 3] = [[0x00, 0x00], [0xf0, 0x0f], [0xaa, 0xaa]];
+/// Where the code starts, after the header.
+const CODE_ADDR: u16 = 0x150;
+const ROM_LEN: usize = 0x8000;
+
+/// The logo the boot ROM checks.
+const LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+/// A test ROM the generator can build.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestRom {
+    /// Scrolls the rest of the line by 4 pixels with SCX.
+    ScxMidLine,
+    /// Inverts BGP.
+    PaletteSwap,
+    /// Turns the window on, over the right half of the screen.
+    WindowToggle,
+}
+
+impl TestRom {
+    pub const ALL: [TestRom; 3] = [
+        TestRom::ScxMidLine,
+        TestRom::PaletteSwap,
+        TestRom::WindowToggle,
+    ];
+
+    /// File name without the extension.
+    pub fn name(self) -> &'static str {
+        match self {
+            TestRom::ScxMidLine => "scx-midline",
+            TestRom::PaletteSwap => "palette-swap",
+            TestRom::WindowToggle => "window-toggle",
+        }
+    }
+
+    fn title(self) -> &'static [u8] {
+        match self {
+            TestRom::ScxMidLine => b"SCX MIDLINE",
+            TestRom::PaletteSwap => b"PAL SWAP",
+            TestRom::WindowToggle => b"WIN TOGGLE",
+        }
+    }
+
+    /// The register written mid-line, as an offset into 0xff00, its value
+    /// from HBlank on and the value written mid-line.
+    fn effect(self) -> (u8, u8, u8) {
+        match self {
+            TestRom::ScxMidLine => (0x43, 0x00, 0x04),
+            TestRom::PaletteSwap => (0x47, 0xe4, 0x1b),
+            // LCD and BG on, tiles at 0x8000, window map at 0x9c00, with
+            // the window off and then on
+            TestRom::WindowToggle => (0x40, 0xd1, 0xf1),
+        }
+    }
+
+    /// Builds the 32 KiB ROM image, without a mapper.
+    pub fn build(self) -> Vec<u8> {
+        let mut rom = vec![0x00; ROM_LEN];
+        // nop; jp CODE_ADDR
+        let [low, high] = CODE_ADDR.to_le_bytes();
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, low, high]);
+        rom[0x104..0x134].copy_from_slice(&LOGO);
+        rom[0x134..0x134 + self.title().len()].copy_from_slice(self.title());
+        // Non-Japanese
+        rom[0x14a] = 0x01;
+
+        let code = self.code();
+        let start = CODE_ADDR as usize;
+        rom[start..start + code.len()].copy_from_slice(&code);
+        let tiles: Vec<u8> = TILES.iter().flat_map(|row| row.repeat(8)).collect();
+        let start = TILES_ADDR as usize;
+        rom[start..start + tiles.len()].copy_from_slice(&tiles);
+
+        let mut checksum: u8 = 0;
+        for &byte in &rom[0x134..0x14d] {
+            checksum = checksum.wrapping_sub(byte).wrapping_sub(1);
+        }
+        rom[0x14d] = checksum;
+        let global = rom
+            .iter()
+            .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        rom[0x14e..0x150].copy_from_slice(&global.to_be_bytes());
+        rom
+    }
+
+    fn code(self) -> Vec<u8> {
+        let (reg, normal, changed) = self.effect();
+        let lcdc = if reg == 0x40 { normal } else { 0x91 };
+        let tiles_len = (TILES.len() * 16) as u8;
+        let mut asm = Asm::default();
+
+        asm.emit(&[0xf3]); // di
+        asm.emit(&[0x31, 0xfe, 0xff]); // ld sp,$fffe
+
+        // The LCD may only be turned off in VBlank
+        let wait = asm.here();
+        asm.emit(&[0xf0, 0x44]); // ldh a,($44)
+        asm.emit(&[0xfe, 0x90]); // cp 144
+        asm.jr_back(0x20, wait); // jr nz,wait
+        asm.emit(&[0xaf]); // xor a
+        asm.emit(&[0xe0, 0x40]); // ldh ($40),a
+
+        asm.emit(&[0x21, 0x00, 0x80]); // ld hl,$8000
+        let [low, high] = TILES_ADDR.to_le_bytes();
+        asm.emit(&[0x11, low, high]); // ld de,TILES_ADDR
+        asm.emit(&[0x06, tiles_len]); // ld b,tiles_len
+        let copy = asm.here();
+        asm.emit(&[0x1a]); // ld a,(de)
+        asm.emit(&[0x22]); // ld (hl+),a
+        asm.emit(&[0x13]); // inc de
+        asm.emit(&[0x05]); // dec b
+        asm.jr_back(0x20, copy); // jr nz,copy
+
+        // Stripes on the BG map at 0x9800, checkers on the window map at
+        // 0x9c00 right after it
+        asm.emit(&[0x21, 0x00, 0x98]); // ld hl,$9800
+        for tile in 1..=2 {
+            asm.emit(&[0x01, 0x00, 0x04]); // ld bc,$0400
+            let fill = asm.here();
+            asm.emit(&[0x3e, tile]); // ld a,tile
+            asm.emit(&[0x22]); // ld (hl+),a
+            asm.emit(&[0x0b]); // dec bc
+            asm.emit(&[0x78]); // ld a,b
+            asm.emit(&[0xb1]); // or c
+            asm.jr_back(0x20, fill); // jr nz,fill
+        }
+
+        asm.emit(&[0x3e, 0xe4]); // ld a,$e4
+        asm.emit(&[0xe0, 0x47]); // ldh ($47),a
+        asm.emit(&[0xaf]); // xor a
+        asm.emit(&[0xe0, 0x42]); // ldh ($42),a
+        asm.emit(&[0xe0, 0x43]); // ldh ($43),a
+        asm.emit(&[0xe0, 0x4a]); // ldh ($4a),a
+        asm.emit(&[0x3e, 0x57]); // ld a,87
+        asm.emit(&[0xe0, 0x4b]); // ldh ($4b),a
+        asm.emit(&[0x3e, lcdc]); // ld a,lcdc
+        asm.emit(&[0xe0, 0x40]); // ldh ($40),a
+
+        let line = asm.here();
+        asm.emit(&[0xf0, 0x41]); // ldh a,($41)
+        asm.emit(&[0xe6, 0x03]); // and 3
+        asm.emit(&[0xfe, 0x03]); // cp 3
+        asm.jr_back(0x20, line); // jr nz,line
+        asm.emit(&[0xf0, 0x44]); // ldh a,($44)
+        asm.emit(&[0xe6, 0x07]); // and 7
+        asm.emit(&[0x3c]); // inc a
+        let delay = asm.here();
+        asm.emit(&[0x3d]); // dec a
+        asm.jr_back(0x20, delay); // jr nz,delay
+        asm.emit(&[0x3e, changed]); // ld a,changed
+        asm.emit(&[0xe0, reg]); // ldh (reg),a
+        let hblank = asm.here();
+        asm.emit(&[0xf0, 0x41]); // ldh a,($41)
+        asm.emit(&[0xe6, 0x03]); // and 3
+        asm.jr_back(0x20, hblank); // jr nz,hblank
+        asm.emit(&[0x3e, normal]); // ld a,normal
+        asm.emit(&[0xe0, reg]); // ldh (reg),a
+        asm.jr_back(0x18, line); // jr line
+        asm.code
+    }
+}
+
+/// Machine code being put together, starting at `CODE_ADDR`.
+#[derive(Default)]
+struct Asm {
+    code: Vec<u8>,
+}
+
+impl Asm {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn here(&self) -> usize {
+        self.code.len()
+    }
+
+    /// Emits the `jr` with `opcode`, e.g. 0x20 for `jr nz`, back to `target`.
+    fn jr_back(&mut self, opcode: u8, target: usize) {
+        let offset = target as isize - (self.here() + 2) as isize;
+        assert!(offset >= i8::MIN as isize, "jr out of range");
+        self.emit(&[opcode, offset as i8 as u8]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm;
+    use rust_gb::cpu::Cpu;
+    use rust_gb::ppu::PpuReg;
+
+    #[test]
+    fn test_build() {
+        for test in TestRom::ALL {
+            let rom = test.build();
+            assert_eq!(rom.len(), ROM_LEN);
+            let global = rom[..0x14e]
+                .iter()
+                .chain(&rom[0x150..])
+                .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+            assert_eq!(rom[0x14e..0x150], global.to_be_bytes());
+
+            // The main loop ends with a jump back to its start
+            let mut addr = CODE_ADDR as usize;
+            let mut last = String::new();
+            while rom[addr] != 0x00 {
+                let instruction = disasm::decode(&rom[addr..], addr as u16).unwrap();
+                assert!(!instruction.text.starts_with("db"), "{}", instruction.text);
+                addr += instruction.len;
+                last = instruction.text;
+            }
+            assert!(last.starts_with("jr $01"), "{}", last);
+
+            // The header checksum is checked on load
+            let mut cpu = Cpu::with_rom(rom).unwrap();
+            for _ in 0..10 {
+                cpu.run_frame_with(|_, _| {});
+            }
+            assert_eq!(cpu.mmu.ppu.get_register(PpuReg::Wx), 0x57);
+            let frame = cpu.mmu.ppu.get_frame();
+            assert!(frame.iter().any(|&c| c != frame[0]), "{:?} is blank", test);
+        }
+    }
+}
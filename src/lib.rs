@@ -1,10 +0,0 @@
-mod cartridge;
-pub mod cpu;
-pub mod joypad;
-pub mod mmu;
-mod ppu;
-pub mod register;
-mod rtc;
-mod serial;
-mod timer;
-pub mod utils;